@@ -0,0 +1,285 @@
+-- The schema as it stood before migrations were introduced
+
+CREATE SCHEMA base;
+
+CREATE TABLE base.address (
+    address_id integer NOT NULL,
+    street_address character varying(20),
+    postal_code character varying(20),
+    province character varying(10)
+);
+
+ALTER TABLE base.address ALTER COLUMN address_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.address_address_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+CREATE TABLE base.book (
+    isbn integer NOT NULL,
+    author_name character varying(20),
+    genre character varying(20),
+    publisher_id integer,
+    num_pages integer,
+    price numeric(6,2),
+    author_royalties numeric(3,2),
+    reorder_threshold integer,
+    title character varying(50),
+    stock integer,
+    discontinued boolean
+);
+
+CREATE TABLE base.book_collection (
+    collection_id integer NOT NULL,
+    curator_owner_id integer
+);
+
+ALTER TABLE base.book_collection ALTER COLUMN collection_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.book_collection_collection_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+CREATE TABLE base.customer (
+    customer_id integer NOT NULL,
+    name character varying(20),
+    email character varying(20),
+    password_hash character(60),
+    default_shipping_address integer,
+    default_payment_info_id integer
+);
+
+ALTER TABLE base.customer ALTER COLUMN customer_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.customer_customer_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+CREATE TABLE base.in_cart (
+    isbn integer NOT NULL,
+    customer_id integer NOT NULL,
+    quantity integer
+);
+
+CREATE TABLE base.in_collection (
+    collection_id integer NOT NULL,
+    isbn integer NOT NULL
+);
+
+CREATE TABLE base.in_order (
+    isbn integer NOT NULL,
+    order_id integer NOT NULL,
+    quantity integer
+);
+
+CREATE TABLE base.orders (
+    order_id integer NOT NULL,
+    customer_id integer,
+    shipping_address_id integer,
+    tracking_number character varying(30),
+    order_status character varying(10),
+    order_date date,
+    payment_info_id integer
+);
+
+ALTER TABLE base.orders ALTER COLUMN order_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.orders_order_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+CREATE TABLE base.owner (
+    owner_id integer NOT NULL,
+    name character varying(20),
+    email character varying(30),
+    password_hash character(60),
+    password_salt bytea
+);
+
+ALTER TABLE base.owner ALTER COLUMN owner_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.owner_owner_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+CREATE TABLE base.payment_info (
+    payment_info_id integer NOT NULL,
+    name_on_card character varying(30),
+    expiry character varying(10),
+    card_number character varying(30),
+    cvv character varying(10),
+    billing_address_id integer
+);
+
+ALTER TABLE base.payment_info ALTER COLUMN payment_info_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.payment_info_payment_info_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+CREATE TABLE base.publisher (
+    publisher_id integer NOT NULL,
+    company_name character varying(20),
+    phone_number character varying(20),
+    bank_number character varying(20),
+    address_id integer,
+    email character varying(30)
+);
+
+ALTER TABLE base.publisher ALTER COLUMN publisher_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.publisher_publisher_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+CREATE VIEW base.raw_sales_data AS
+ SELECT in_order.isbn,
+    orders.order_id,
+    orders.customer_id,
+    orders.shipping_address_id,
+    orders.tracking_number,
+    orders.order_status,
+    orders.order_date,
+    orders.payment_info_id,
+    in_order.quantity,
+    book.author_name,
+    book.genre,
+    book.publisher_id,
+    book.num_pages,
+    book.price,
+    book.author_royalties,
+    book.reorder_threshold,
+    book.title,
+    book.stock,
+    book.discontinued
+   FROM ((base.orders
+     JOIN base.in_order USING (order_id))
+     JOIN base.book USING (isbn));
+
+CREATE TABLE base.restock_order (
+    restock_order_id integer NOT NULL,
+    isbn integer,
+    quantity integer,
+    price_per_unit money,
+    order_date date,
+    order_status character varying(10)
+);
+
+ALTER TABLE base.restock_order ALTER COLUMN restock_order_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.restock_order_restock_order_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+CREATE VIEW base.sales AS
+ SELECT orders.order_date,
+    sum(in_order.quantity) AS quantity
+   FROM ((base.orders
+     JOIN base.in_order USING (order_id))
+     JOIN base.book USING (isbn))
+  GROUP BY orders.order_date;
+
+ALTER TABLE ONLY base.address
+    ADD CONSTRAINT address_pkey PRIMARY KEY (address_id);
+
+ALTER TABLE ONLY base.book_collection
+    ADD CONSTRAINT book_collection_pkey PRIMARY KEY (collection_id);
+
+ALTER TABLE ONLY base.book
+    ADD CONSTRAINT book_pkey PRIMARY KEY (isbn);
+
+ALTER TABLE ONLY base.customer
+    ADD CONSTRAINT customer_pkey PRIMARY KEY (customer_id);
+
+ALTER TABLE ONLY base.in_cart
+    ADD CONSTRAINT in_cart_pkey PRIMARY KEY (isbn, customer_id);
+
+ALTER TABLE ONLY base.in_collection
+    ADD CONSTRAINT in_collection_pkey PRIMARY KEY (collection_id, isbn);
+
+ALTER TABLE ONLY base.in_order
+    ADD CONSTRAINT in_order_pkey PRIMARY KEY (isbn, order_id);
+
+ALTER TABLE ONLY base.orders
+    ADD CONSTRAINT orders_pkey PRIMARY KEY (order_id);
+
+ALTER TABLE ONLY base.owner
+    ADD CONSTRAINT owner_pkey PRIMARY KEY (owner_id);
+
+ALTER TABLE ONLY base.payment_info
+    ADD CONSTRAINT payment_info_pkey PRIMARY KEY (payment_info_id);
+
+ALTER TABLE ONLY base.publisher
+    ADD CONSTRAINT publisher_pkey PRIMARY KEY (publisher_id);
+
+ALTER TABLE ONLY base.restock_order
+    ADD CONSTRAINT restock_order_pkey PRIMARY KEY (restock_order_id);
+
+ALTER TABLE ONLY base.book_collection
+    ADD CONSTRAINT book_collection_curator_owner_id_fkey FOREIGN KEY (curator_owner_id) REFERENCES base.owner(owner_id);
+
+ALTER TABLE ONLY base.book
+    ADD CONSTRAINT book_publisher_id_fkey FOREIGN KEY (publisher_id) REFERENCES base.publisher(publisher_id);
+
+ALTER TABLE ONLY base.customer
+    ADD CONSTRAINT customer_default_payment_info_id_fkey FOREIGN KEY (default_payment_info_id) REFERENCES base.payment_info(payment_info_id);
+
+ALTER TABLE ONLY base.customer
+    ADD CONSTRAINT customer_default_shipping_address_fkey FOREIGN KEY (default_shipping_address) REFERENCES base.address(address_id);
+
+ALTER TABLE ONLY base.in_cart
+    ADD CONSTRAINT in_cart_customer_id_fkey FOREIGN KEY (customer_id) REFERENCES base.customer(customer_id);
+
+ALTER TABLE ONLY base.in_cart
+    ADD CONSTRAINT in_cart_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+ALTER TABLE ONLY base.in_collection
+    ADD CONSTRAINT in_collection_collection_id_fkey FOREIGN KEY (collection_id) REFERENCES base.book_collection(collection_id);
+
+ALTER TABLE ONLY base.in_collection
+    ADD CONSTRAINT in_collection_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+ALTER TABLE ONLY base.in_order
+    ADD CONSTRAINT in_order_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+ALTER TABLE ONLY base.in_order
+    ADD CONSTRAINT in_order_order_id_fkey FOREIGN KEY (order_id) REFERENCES base.orders(order_id);
+
+ALTER TABLE ONLY base.orders
+    ADD CONSTRAINT orders_customer_id_fkey FOREIGN KEY (customer_id) REFERENCES base.customer(customer_id);
+
+ALTER TABLE ONLY base.orders
+    ADD CONSTRAINT orders_payment_info_id_fkey FOREIGN KEY (payment_info_id) REFERENCES base.payment_info(payment_info_id);
+
+ALTER TABLE ONLY base.payment_info
+    ADD CONSTRAINT payment_info_billing_address_id_fkey FOREIGN KEY (billing_address_id) REFERENCES base.address(address_id);
+
+ALTER TABLE ONLY base.publisher
+    ADD CONSTRAINT publisher_address_id_fkey FOREIGN KEY (address_id) REFERENCES base.address(address_id);
+
+ALTER TABLE ONLY base.restock_order
+    ADD CONSTRAINT restock_order_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
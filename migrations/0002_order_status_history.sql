@@ -0,0 +1,27 @@
+-- Records every status an order moves through
+
+CREATE TABLE base.order_status_history (
+    order_status_history_id integer NOT NULL,
+    order_id integer NOT NULL,
+    order_status character varying(10) NOT NULL,
+    changed_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE base.order_status_history ALTER COLUMN order_status_history_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.order_status_history_order_status_history_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+ALTER TABLE ONLY base.order_status_history
+    ADD CONSTRAINT order_status_history_pkey PRIMARY KEY (order_status_history_id);
+
+ALTER TABLE ONLY base.order_status_history
+    ADD CONSTRAINT order_status_history_order_id_fkey FOREIGN KEY (order_id) REFERENCES base.orders(order_id);
+
+-- Existing orders start their history at the status they are currently in
+INSERT INTO base.order_status_history (order_id, order_status, changed_at)
+SELECT order_id, order_status, order_date FROM base.orders;
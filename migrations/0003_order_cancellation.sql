@@ -0,0 +1,47 @@
+-- Why customers cancelled their orders, and keeping cancelled and returned orders out of the sales reports
+
+CREATE TABLE base.order_cancellation (
+    order_id integer NOT NULL,
+    reason character varying(200),
+    cancelled_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE ONLY base.order_cancellation
+    ADD CONSTRAINT order_cancellation_pkey PRIMARY KEY (order_id);
+
+ALTER TABLE ONLY base.order_cancellation
+    ADD CONSTRAINT order_cancellation_order_id_fkey FOREIGN KEY (order_id) REFERENCES base.orders(order_id);
+
+CREATE OR REPLACE VIEW base.raw_sales_data AS
+ SELECT in_order.isbn,
+    orders.order_id,
+    orders.customer_id,
+    orders.shipping_address_id,
+    orders.tracking_number,
+    orders.order_status,
+    orders.order_date,
+    orders.payment_info_id,
+    in_order.quantity,
+    book.author_name,
+    book.genre,
+    book.publisher_id,
+    book.num_pages,
+    book.price,
+    book.author_royalties,
+    book.reorder_threshold,
+    book.title,
+    book.stock,
+    book.discontinued
+   FROM ((base.orders
+     JOIN base.in_order USING (order_id))
+     JOIN base.book USING (isbn))
+  WHERE ((orders.order_status)::text <> ALL (ARRAY[('CA'::character varying)::text, ('RT'::character varying)::text]));
+
+CREATE OR REPLACE VIEW base.sales AS
+ SELECT orders.order_date,
+    sum(in_order.quantity) AS quantity
+   FROM ((base.orders
+     JOIN base.in_order USING (order_id))
+     JOIN base.book USING (isbn))
+  WHERE ((orders.order_status)::text <> ALL (ARRAY[('CA'::character varying)::text, ('RT'::character varying)::text]))
+  GROUP BY orders.order_date;
@@ -0,0 +1,13 @@
+-- Login sessions for the Postgres session store, keyed by the SHA-256 of their token
+
+CREATE TABLE base.session (
+    token_hash character varying(64) NOT NULL,
+    session_type character varying(10) NOT NULL,
+    account_id integer,
+    expires_at timestamp with time zone NOT NULL
+);
+
+ALTER TABLE ONLY base.session
+    ADD CONSTRAINT session_pkey PRIMARY KEY (token_hash);
+
+CREATE INDEX session_expires_at_idx ON base.session USING btree (expires_at);
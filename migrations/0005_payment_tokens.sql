@@ -0,0 +1,23 @@
+-- Replace stored card numbers and CVVs with payment provider tokens.
+--
+-- Cards saved before this migration were never tokenised, so they get a token
+-- no provider will accept and customers have to enter those cards again.
+
+ALTER TABLE base.payment_info
+    ADD COLUMN token character varying(64),
+    ADD COLUMN last_four character varying(4),
+    ADD COLUMN brand character varying(20);
+
+UPDATE base.payment_info SET
+    token = 'legacy_' || payment_info_id,
+    last_four = right(coalesce(card_number, ''), 4),
+    brand = 'Card';
+
+ALTER TABLE base.payment_info
+    DROP COLUMN card_number,
+    DROP COLUMN cvv,
+    ALTER COLUMN token SET NOT NULL,
+    ALTER COLUMN last_four SET NOT NULL,
+    ALTER COLUMN brand SET NOT NULL;
+
+ALTER TABLE base.orders ADD COLUMN payment_authorisation character varying(64);
@@ -0,0 +1,18 @@
+-- Named, ordered collections that owners curate for the storefront
+
+ALTER TABLE base.book_collection
+    ADD COLUMN name character varying(50),
+    ADD COLUMN sort_order integer DEFAULT 0 NOT NULL;
+
+UPDATE base.book_collection SET name = 'Collection ' || collection_id;
+
+ALTER TABLE base.book_collection ALTER COLUMN name SET NOT NULL;
+
+-- Deleting a collection takes its books with it, and deleting an owner keeps their collections
+ALTER TABLE ONLY base.in_collection
+    DROP CONSTRAINT in_collection_collection_id_fkey,
+    ADD CONSTRAINT in_collection_collection_id_fkey FOREIGN KEY (collection_id) REFERENCES base.book_collection(collection_id) ON DELETE CASCADE;
+
+ALTER TABLE ONLY base.book_collection
+    DROP CONSTRAINT book_collection_curator_owner_id_fkey,
+    ADD CONSTRAINT book_collection_curator_owner_id_fkey FOREIGN KEY (curator_owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
@@ -0,0 +1,47 @@
+-- Batches of author royalties paid out for a reporting period, and the sales each batch covered
+
+CREATE TABLE base.royalty_payout (
+    royalty_payout_id integer NOT NULL,
+    period_start date NOT NULL,
+    period_end date NOT NULL,
+    owner_id integer,
+    paid_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE base.royalty_payout ALTER COLUMN royalty_payout_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.royalty_payout_royalty_payout_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+-- A sale is only ever paid once, which the primary key on (order_id, isbn) enforces
+CREATE TABLE base.royalty_payout_line (
+    royalty_payout_id integer NOT NULL,
+    order_id integer NOT NULL,
+    isbn integer NOT NULL,
+    author_name character varying(20),
+    quantity integer NOT NULL,
+    price numeric(6,2) NOT NULL,
+    royalty_rate numeric(3,2) NOT NULL,
+    royalty numeric(10,2) NOT NULL
+);
+
+ALTER TABLE ONLY base.royalty_payout
+    ADD CONSTRAINT royalty_payout_pkey PRIMARY KEY (royalty_payout_id);
+
+ALTER TABLE ONLY base.royalty_payout_line
+    ADD CONSTRAINT royalty_payout_line_pkey PRIMARY KEY (order_id, isbn);
+
+CREATE INDEX royalty_payout_line_royalty_payout_id_idx ON base.royalty_payout_line USING btree (royalty_payout_id);
+
+ALTER TABLE ONLY base.royalty_payout
+    ADD CONSTRAINT royalty_payout_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
+
+ALTER TABLE ONLY base.royalty_payout_line
+    ADD CONSTRAINT royalty_payout_line_royalty_payout_id_fkey FOREIGN KEY (royalty_payout_id) REFERENCES base.royalty_payout(royalty_payout_id);
+
+ALTER TABLE ONLY base.royalty_payout_line
+    ADD CONSTRAINT royalty_payout_line_order_id_isbn_fkey FOREIGN KEY (order_id, isbn) REFERENCES base.in_order(order_id, isbn);
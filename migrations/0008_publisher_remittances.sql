@@ -0,0 +1,38 @@
+-- Payments to publishers for the restock orders they have delivered
+
+CREATE TABLE base.publisher_remittance (
+    publisher_remittance_id integer NOT NULL,
+    publisher_id integer NOT NULL,
+    period_start date NOT NULL,
+    period_end date NOT NULL,
+    amount numeric(12,2) NOT NULL,
+    bank_number character varying(20),
+    remittance_status character varying(10) DEFAULT 'PENDING'::character varying NOT NULL,
+    owner_id integer,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    remitted_at timestamp with time zone
+);
+
+ALTER TABLE base.publisher_remittance ALTER COLUMN publisher_remittance_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.publisher_remittance_publisher_remittance_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+ALTER TABLE ONLY base.publisher_remittance
+    ADD CONSTRAINT publisher_remittance_pkey PRIMARY KEY (publisher_remittance_id);
+
+ALTER TABLE ONLY base.publisher_remittance
+    ADD CONSTRAINT publisher_remittance_publisher_id_fkey FOREIGN KEY (publisher_id) REFERENCES base.publisher(publisher_id);
+
+ALTER TABLE ONLY base.publisher_remittance
+    ADD CONSTRAINT publisher_remittance_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
+
+-- A received restock order is paid for by at most one remittance
+ALTER TABLE base.restock_order ADD COLUMN publisher_remittance_id integer;
+
+ALTER TABLE ONLY base.restock_order
+    ADD CONSTRAINT restock_order_publisher_remittance_id_fkey FOREIGN KEY (publisher_remittance_id) REFERENCES base.publisher_remittance(publisher_remittance_id);
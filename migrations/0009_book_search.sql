@@ -0,0 +1,17 @@
+-- Ranked full-text search over the catalog, with trigram matching for typos
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm WITH SCHEMA public;
+
+ALTER TABLE base.book ADD COLUMN search_vector tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english'::regconfig, COALESCE(title, ''::character varying)::text), 'A'::"char")
+    || setweight(to_tsvector('english'::regconfig, COALESCE(author_name, ''::character varying)::text), 'B'::"char")
+    || setweight(to_tsvector('english'::regconfig, COALESCE(genre, ''::character varying)::text), 'C'::"char")
+) STORED;
+
+CREATE INDEX book_search_vector_idx ON base.book USING gin (search_vector);
+
+CREATE INDEX book_title_trgm_idx ON base.book USING gin (title public.gin_trgm_ops);
+
+CREATE INDEX book_author_name_trgm_idx ON base.book USING gin (author_name public.gin_trgm_ops);
+
+CREATE INDEX publisher_company_name_trgm_idx ON base.publisher USING gin (company_name public.gin_trgm_ops);
@@ -0,0 +1,90 @@
+-- Store ISBNs as the bigint of their ISBN-13, which an integer can't hold.
+--
+-- Books added before this migration were given made-up integer ISBNs, so each one
+-- becomes 978 followed by the old number padded to 9 digits and a fresh check digit.
+
+CREATE FUNCTION pg_temp.legacy_isbn13(legacy integer) RETURNS bigint
+    LANGUAGE plpgsql IMMUTABLE
+    AS $$
+DECLARE
+    body bigint := 978000000000 + legacy;
+    digits text := body::text;
+    total integer := 0;
+BEGIN
+    IF legacy < 0 OR legacy > 999999999 THEN
+        RAISE EXCEPTION 'ISBN % has too many digits to become an ISBN-13', legacy;
+    END IF;
+
+    FOR i IN 1..12 LOOP
+        total := total + substr(digits, i, 1)::integer * CASE WHEN i % 2 = 0 THEN 3 ELSE 1 END;
+    END LOOP;
+
+    RETURN body * 10 + (10 - total % 10) % 10;
+END
+$$;
+
+DROP VIEW base.sales;
+DROP VIEW base.raw_sales_data;
+
+ALTER TABLE base.in_cart DROP CONSTRAINT in_cart_isbn_fkey;
+ALTER TABLE base.in_collection DROP CONSTRAINT in_collection_isbn_fkey;
+ALTER TABLE base.in_order DROP CONSTRAINT in_order_isbn_fkey;
+ALTER TABLE base.restock_order DROP CONSTRAINT restock_order_isbn_fkey;
+ALTER TABLE base.royalty_payout_line DROP CONSTRAINT royalty_payout_line_order_id_isbn_fkey;
+
+ALTER TABLE base.book ALTER COLUMN isbn TYPE bigint USING pg_temp.legacy_isbn13(isbn);
+ALTER TABLE base.in_cart ALTER COLUMN isbn TYPE bigint USING pg_temp.legacy_isbn13(isbn);
+ALTER TABLE base.in_collection ALTER COLUMN isbn TYPE bigint USING pg_temp.legacy_isbn13(isbn);
+ALTER TABLE base.in_order ALTER COLUMN isbn TYPE bigint USING pg_temp.legacy_isbn13(isbn);
+ALTER TABLE base.restock_order ALTER COLUMN isbn TYPE bigint USING pg_temp.legacy_isbn13(isbn);
+ALTER TABLE base.royalty_payout_line ALTER COLUMN isbn TYPE bigint USING pg_temp.legacy_isbn13(isbn);
+
+ALTER TABLE base.book
+    ADD CONSTRAINT book_isbn_check CHECK (isbn BETWEEN 9780000000000 AND 9799999999999);
+
+ALTER TABLE ONLY base.in_cart
+    ADD CONSTRAINT in_cart_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+ALTER TABLE ONLY base.in_collection
+    ADD CONSTRAINT in_collection_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+ALTER TABLE ONLY base.in_order
+    ADD CONSTRAINT in_order_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+ALTER TABLE ONLY base.restock_order
+    ADD CONSTRAINT restock_order_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+ALTER TABLE ONLY base.royalty_payout_line
+    ADD CONSTRAINT royalty_payout_line_order_id_isbn_fkey FOREIGN KEY (order_id, isbn) REFERENCES base.in_order(order_id, isbn);
+
+CREATE VIEW base.raw_sales_data AS
+ SELECT in_order.isbn,
+    orders.order_id,
+    orders.customer_id,
+    orders.shipping_address_id,
+    orders.tracking_number,
+    orders.order_status,
+    orders.order_date,
+    orders.payment_info_id,
+    in_order.quantity,
+    book.author_name,
+    book.genre,
+    book.publisher_id,
+    book.num_pages,
+    book.price,
+    book.author_royalties,
+    book.reorder_threshold,
+    book.title,
+    book.stock,
+    book.discontinued
+   FROM ((base.orders
+     JOIN base.in_order USING (order_id))
+     JOIN base.book USING (isbn))
+  WHERE ((orders.order_status)::text <> ALL (ARRAY[('CA'::character varying)::text, ('RT'::character varying)::text]));
+
+CREATE VIEW base.sales AS
+ SELECT orders.order_date,
+    sum(in_order.quantity) AS quantity
+   FROM ((base.orders
+     JOIN base.in_order USING (order_id))
+     JOIN base.book USING (isbn))
+  WHERE ((orders.order_status)::text <> ALL (ARRAY[('CA'::character varying)::text, ('RT'::character varying)::text]))
+  GROUP BY orders.order_date;
+
+DROP FUNCTION pg_temp.legacy_isbn13(integer);
@@ -0,0 +1,29 @@
+-- Uploaded catalog files, kept so their preview can be reviewed before being applied
+
+CREATE TABLE base.catalog_import (
+    catalog_import_id integer NOT NULL,
+    file_name character varying(255) NOT NULL,
+    import_format character varying(4) NOT NULL,
+    contents text NOT NULL,
+    owner_id integer,
+    uploaded_at timestamp with time zone DEFAULT now() NOT NULL,
+    applied_at timestamp with time zone,
+    books_added integer,
+    books_updated integer,
+    records_rejected integer
+);
+
+ALTER TABLE base.catalog_import ALTER COLUMN catalog_import_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.catalog_import_catalog_import_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+ALTER TABLE ONLY base.catalog_import
+    ADD CONSTRAINT catalog_import_pkey PRIMARY KEY (catalog_import_id);
+
+ALTER TABLE ONLY base.catalog_import
+    ADD CONSTRAINT catalog_import_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
@@ -0,0 +1,34 @@
+-- Bumped by every change to a book, so an edit made from an out of date copy can be refused
+ALTER TABLE base.book ADD COLUMN version integer DEFAULT 1 NOT NULL;
+
+-- What each owner edit or catalog import changed about a book, one row per field
+CREATE TABLE base.book_edit (
+    book_edit_id integer NOT NULL,
+    isbn bigint NOT NULL,
+    version integer NOT NULL,
+    owner_id integer,
+    field_name character varying(20) NOT NULL,
+    old_value text NOT NULL,
+    new_value text NOT NULL,
+    edited_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE base.book_edit ALTER COLUMN book_edit_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.book_edit_book_edit_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+ALTER TABLE ONLY base.book_edit
+    ADD CONSTRAINT book_edit_pkey PRIMARY KEY (book_edit_id);
+
+CREATE INDEX book_edit_isbn_idx ON base.book_edit USING btree (isbn);
+
+ALTER TABLE ONLY base.book_edit
+    ADD CONSTRAINT book_edit_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+ALTER TABLE ONLY base.book_edit
+    ADD CONSTRAINT book_edit_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
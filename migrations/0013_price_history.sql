@@ -0,0 +1,77 @@
+-- The price each book was sold at, captured at checkout so later price changes leave past orders alone.
+-- Orders placed before this migration only have the book's current price to go on.
+ALTER TABLE base.in_order ADD COLUMN unit_price numeric(6,2);
+
+UPDATE base.in_order
+SET unit_price = book.price
+FROM base.book
+WHERE book.isbn = in_order.isbn;
+
+ALTER TABLE base.in_order ALTER COLUMN unit_price SET NOT NULL;
+
+-- Every price a book has had or is scheduled to have. `book.price` holds the one in effect,
+-- and a scheduled price is copied into it once its time comes, at which point it is marked applied.
+CREATE TABLE base.book_price (
+    book_price_id integer NOT NULL,
+    isbn bigint NOT NULL,
+    price numeric(6,2) NOT NULL,
+    effective_from timestamp with time zone NOT NULL,
+    owner_id integer,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    applied_at timestamp with time zone,
+    CONSTRAINT book_price_price_check CHECK ((price >= (0)::numeric))
+);
+
+ALTER TABLE base.book_price ALTER COLUMN book_price_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.book_price_book_price_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+ALTER TABLE ONLY base.book_price
+    ADD CONSTRAINT book_price_pkey PRIMARY KEY (book_price_id);
+
+CREATE INDEX book_price_isbn_effective_from_idx ON base.book_price USING btree (isbn, effective_from);
+
+CREATE INDEX book_price_pending_idx ON base.book_price USING btree (effective_from) WHERE (applied_at IS NULL);
+
+ALTER TABLE ONLY base.book_price
+    ADD CONSTRAINT book_price_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+ALTER TABLE ONLY base.book_price
+    ADD CONSTRAINT book_price_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
+
+-- Each book's history starts from the price it has now
+INSERT INTO base.book_price (isbn, price, effective_from, applied_at)
+SELECT isbn, price, now(), now()
+FROM base.book
+WHERE price IS NOT NULL;
+
+-- Sales are reported at the price they were sold at, not the book's current price
+CREATE OR REPLACE VIEW base.raw_sales_data AS
+ SELECT in_order.isbn,
+    orders.order_id,
+    orders.customer_id,
+    orders.shipping_address_id,
+    orders.tracking_number,
+    orders.order_status,
+    orders.order_date,
+    orders.payment_info_id,
+    in_order.quantity,
+    book.author_name,
+    book.genre,
+    book.publisher_id,
+    book.num_pages,
+    in_order.unit_price AS price,
+    book.author_royalties,
+    book.reorder_threshold,
+    book.title,
+    book.stock,
+    book.discontinued
+   FROM ((base.orders
+     JOIN base.in_order USING (order_id))
+     JOIN base.book USING (isbn))
+  WHERE ((orders.order_status)::text <> ALL (ARRAY[('CA'::character varying)::text, ('RT'::character varying)::text]));
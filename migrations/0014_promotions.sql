@@ -0,0 +1,62 @@
+-- Coupon codes taking a percentage or fixed amount off the books they cover. A promotion covers
+-- every book, or only one genre, publisher or ISBN.
+CREATE TABLE base.promotion (
+    promotion_id integer NOT NULL,
+    code character varying(30) NOT NULL,
+    discount_type character varying(7) NOT NULL,
+    discount_value numeric(8,2) NOT NULL,
+    min_spend numeric(8,2) DEFAULT 0.00 NOT NULL,
+    genre character varying(20),
+    publisher_id integer,
+    isbn bigint,
+    starts_at timestamp with time zone,
+    ends_at timestamp with time zone,
+    max_uses integer,
+    max_uses_per_customer integer,
+    active boolean DEFAULT true NOT NULL,
+    owner_id integer,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT promotion_code_check CHECK (((code)::text = upper((code)::text))),
+    CONSTRAINT promotion_discount_type_check CHECK (((discount_type)::text = ANY ((ARRAY['PERCENT'::character varying, 'FIXED'::character varying])::text[]))),
+    CONSTRAINT promotion_discount_value_check CHECK (((discount_value > (0)::numeric) AND (((discount_type)::text <> 'PERCENT'::text) OR (discount_value <= (100)::numeric)))),
+    CONSTRAINT promotion_min_spend_check CHECK ((min_spend >= (0)::numeric)),
+    CONSTRAINT promotion_scope_check CHECK ((num_nonnulls(genre, publisher_id, isbn) <= 1)),
+    CONSTRAINT promotion_period_check CHECK (((starts_at IS NULL) OR (ends_at IS NULL) OR (starts_at < ends_at))),
+    CONSTRAINT promotion_max_uses_check CHECK (((max_uses IS NULL) OR (max_uses > 0))),
+    CONSTRAINT promotion_max_uses_per_customer_check CHECK (((max_uses_per_customer IS NULL) OR (max_uses_per_customer > 0)))
+);
+
+ALTER TABLE base.promotion ALTER COLUMN promotion_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.promotion_promotion_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+ALTER TABLE ONLY base.promotion
+    ADD CONSTRAINT promotion_pkey PRIMARY KEY (promotion_id);
+
+ALTER TABLE ONLY base.promotion
+    ADD CONSTRAINT promotion_code_key UNIQUE (code);
+
+ALTER TABLE ONLY base.promotion
+    ADD CONSTRAINT promotion_publisher_id_fkey FOREIGN KEY (publisher_id) REFERENCES base.publisher(publisher_id);
+
+ALTER TABLE ONLY base.promotion
+    ADD CONSTRAINT promotion_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+ALTER TABLE ONLY base.promotion
+    ADD CONSTRAINT promotion_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
+
+-- The promotion used on an order and what it took off. The store absorbs discounts, so
+-- royalties and publisher settlements are still worked out from the line prices.
+ALTER TABLE base.orders
+    ADD COLUMN promotion_id integer,
+    ADD COLUMN discount numeric(8,2) DEFAULT 0.00 NOT NULL;
+
+ALTER TABLE ONLY base.orders
+    ADD CONSTRAINT orders_promotion_id_fkey FOREIGN KEY (promotion_id) REFERENCES base.promotion(promotion_id);
+
+CREATE INDEX orders_promotion_id_idx ON base.orders USING btree (promotion_id) WHERE (promotion_id IS NOT NULL);
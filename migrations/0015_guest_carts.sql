@@ -0,0 +1,31 @@
+-- Carts for visitors who haven't logged in, found by the token in their guest cart cookie.
+-- Carts nobody has touched in a while are swept out, taking their books with them.
+CREATE TABLE base.guest_cart (
+    cart_token character varying(64) NOT NULL,
+    last_active timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE ONLY base.guest_cart
+    ADD CONSTRAINT guest_cart_pkey PRIMARY KEY (cart_token);
+
+CREATE INDEX guest_cart_last_active_idx ON base.guest_cart USING btree (last_active);
+
+CREATE TABLE base.in_guest_cart (
+    isbn bigint NOT NULL,
+    cart_token character varying(64) NOT NULL,
+    quantity integer NOT NULL
+);
+
+ALTER TABLE ONLY base.in_guest_cart
+    ADD CONSTRAINT in_guest_cart_pkey PRIMARY KEY (isbn, cart_token);
+
+ALTER TABLE ONLY base.in_guest_cart
+    ADD CONSTRAINT in_guest_cart_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+ALTER TABLE ONLY base.in_guest_cart
+    ADD CONSTRAINT in_guest_cart_cart_token_fkey FOREIGN KEY (cart_token) REFERENCES base.guest_cart(cart_token) ON DELETE CASCADE;
+
+-- Guests leave an email instead of having an account, so an order has one or the other
+ALTER TABLE base.orders
+    ADD COLUMN guest_email character varying(254),
+    ADD CONSTRAINT orders_guest_email_check CHECK (((customer_id IS NULL) OR (guest_email IS NULL)));
@@ -0,0 +1,23 @@
+-- Books in a cart are held for it until `reserved_until`, so the copies can't be sold out from
+-- under the customer while they finish shopping. A NULL means the books aren't held.
+ALTER TABLE base.in_cart ADD COLUMN reserved_until timestamp with time zone;
+
+ALTER TABLE base.in_guest_cart ADD COLUMN reserved_until timestamp with time zone;
+
+-- The copies held in every cart right now. A book's available stock is its stock less these.
+CREATE VIEW base.stock_reservation AS
+ SELECT in_cart.isbn,
+    in_cart.quantity,
+    in_cart.reserved_until,
+    in_cart.customer_id,
+    NULL::character varying AS cart_token
+   FROM base.in_cart
+  WHERE (in_cart.reserved_until > now())
+UNION ALL
+ SELECT in_guest_cart.isbn,
+    in_guest_cart.quantity,
+    in_guest_cart.reserved_until,
+    NULL::integer AS customer_id,
+    in_guest_cart.cart_token
+   FROM base.in_guest_cart
+  WHERE (in_guest_cart.reserved_until > now());
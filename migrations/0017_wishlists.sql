@@ -0,0 +1,95 @@
+-- Named lists of books a customer wants. Anyone with a list's share token can look at it.
+CREATE TABLE base.wishlist (
+    wishlist_id integer NOT NULL,
+    customer_id integer NOT NULL,
+    name character varying(50) NOT NULL,
+    share_token character varying(64) NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE base.wishlist ALTER COLUMN wishlist_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.wishlist_wishlist_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+ALTER TABLE ONLY base.wishlist
+    ADD CONSTRAINT wishlist_pkey PRIMARY KEY (wishlist_id);
+
+ALTER TABLE ONLY base.wishlist
+    ADD CONSTRAINT wishlist_customer_id_name_key UNIQUE (customer_id, name);
+
+ALTER TABLE ONLY base.wishlist
+    ADD CONSTRAINT wishlist_share_token_key UNIQUE (share_token);
+
+ALTER TABLE ONLY base.wishlist
+    ADD CONSTRAINT wishlist_customer_id_fkey FOREIGN KEY (customer_id) REFERENCES base.customer(customer_id) ON DELETE CASCADE;
+
+CREATE TABLE base.in_wishlist (
+    wishlist_id integer NOT NULL,
+    isbn bigint NOT NULL,
+    added_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE ONLY base.in_wishlist
+    ADD CONSTRAINT in_wishlist_pkey PRIMARY KEY (wishlist_id, isbn);
+
+ALTER TABLE ONLY base.in_wishlist
+    ADD CONSTRAINT in_wishlist_wishlist_id_fkey FOREIGN KEY (wishlist_id) REFERENCES base.wishlist(wishlist_id) ON DELETE CASCADE;
+
+ALTER TABLE ONLY base.in_wishlist
+    ADD CONSTRAINT in_wishlist_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+-- Customers waiting for an out of stock book. A subscription is used up once the book is back.
+CREATE TABLE base.stock_subscription (
+    customer_id integer NOT NULL,
+    isbn bigint NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+ALTER TABLE ONLY base.stock_subscription
+    ADD CONSTRAINT stock_subscription_pkey PRIMARY KEY (customer_id, isbn);
+
+ALTER TABLE ONLY base.stock_subscription
+    ADD CONSTRAINT stock_subscription_customer_id_fkey FOREIGN KEY (customer_id) REFERENCES base.customer(customer_id) ON DELETE CASCADE;
+
+ALTER TABLE ONLY base.stock_subscription
+    ADD CONSTRAINT stock_subscription_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+CREATE INDEX stock_subscription_isbn_idx ON base.stock_subscription USING btree (isbn);
+
+-- Notifications queued for the notifier. Ones that fail are retried until they run out of attempts.
+CREATE TABLE base.notification (
+    notification_id integer NOT NULL,
+    customer_id integer NOT NULL,
+    isbn bigint NOT NULL,
+    kind character varying(20) NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    sent_at timestamp with time zone,
+    attempts integer DEFAULT 0 NOT NULL,
+    last_error text,
+    CONSTRAINT notification_kind_check CHECK (((kind)::text = 'BACK_IN_STOCK'::text))
+);
+
+ALTER TABLE base.notification ALTER COLUMN notification_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.notification_notification_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+ALTER TABLE ONLY base.notification
+    ADD CONSTRAINT notification_pkey PRIMARY KEY (notification_id);
+
+ALTER TABLE ONLY base.notification
+    ADD CONSTRAINT notification_customer_id_fkey FOREIGN KEY (customer_id) REFERENCES base.customer(customer_id) ON DELETE CASCADE;
+
+ALTER TABLE ONLY base.notification
+    ADD CONSTRAINT notification_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+CREATE INDEX notification_pending_idx ON base.notification USING btree (created_at) WHERE (sent_at IS NULL);
@@ -0,0 +1,29 @@
+-- Emails waiting to be sent, stored as the template to render and the values to render it with.
+-- Ones that fail are retried with a growing delay until they run out of attempts.
+CREATE TABLE base.mail_outbox (
+    mail_id integer NOT NULL,
+    template character varying(20) NOT NULL,
+    recipient_name character varying(50),
+    recipient_email character varying(254) NOT NULL,
+    context jsonb NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    next_attempt_at timestamp with time zone DEFAULT now() NOT NULL,
+    sent_at timestamp with time zone,
+    attempts integer DEFAULT 0 NOT NULL,
+    last_error text,
+    CONSTRAINT mail_outbox_template_check CHECK (((template)::text = ANY ((ARRAY['WELCOME'::character varying, 'ORDER_CONFIRMATION'::character varying, 'ORDER_STATUS'::character varying, 'PASSWORD_RESET'::character varying])::text[])))
+);
+
+ALTER TABLE base.mail_outbox ALTER COLUMN mail_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.mail_outbox_mail_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+ALTER TABLE ONLY base.mail_outbox
+    ADD CONSTRAINT mail_outbox_pkey PRIMARY KEY (mail_id);
+
+CREATE INDEX mail_outbox_pending_idx ON base.mail_outbox USING btree (next_attempt_at) WHERE (sent_at IS NULL);
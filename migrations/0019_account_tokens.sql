@@ -0,0 +1,64 @@
+-- Customers confirm their email by following a link. Accounts from before verification existed are
+-- treated as verified so they aren't locked out of checkout.
+ALTER TABLE base.customer ADD COLUMN email_verified_at timestamp with time zone;
+
+UPDATE base.customer SET email_verified_at = now();
+
+-- Single use tokens sent by email for resetting a password or verifying an email. Only a hash of
+-- each token is kept, so the table can't be used to take over accounts.
+CREATE TABLE base.account_token (
+    token_id integer NOT NULL,
+    purpose character varying(20) NOT NULL,
+    customer_id integer,
+    owner_id integer,
+    token_hash character(64) NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    expires_at timestamp with time zone NOT NULL,
+    used_at timestamp with time zone,
+    CONSTRAINT account_token_purpose_check CHECK (((purpose)::text = ANY ((ARRAY['PASSWORD_RESET'::character varying, 'VERIFY_EMAIL'::character varying])::text[]))),
+    CONSTRAINT account_token_account_check CHECK ((num_nonnulls(customer_id, owner_id) = 1))
+);
+
+ALTER TABLE base.account_token ALTER COLUMN token_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.account_token_token_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+ALTER TABLE ONLY base.account_token
+    ADD CONSTRAINT account_token_pkey PRIMARY KEY (token_id);
+
+ALTER TABLE ONLY base.account_token
+    ADD CONSTRAINT account_token_token_hash_key UNIQUE (token_hash);
+
+ALTER TABLE ONLY base.account_token
+    ADD CONSTRAINT account_token_customer_id_fkey FOREIGN KEY (customer_id) REFERENCES base.customer(customer_id) ON DELETE CASCADE;
+
+ALTER TABLE ONLY base.account_token
+    ADD CONSTRAINT account_token_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE CASCADE;
+
+-- Every time a token was asked for, whether or not the email belongs to an account, so requests
+-- can be limited per email
+CREATE TABLE base.account_token_request (
+    email character varying(254) NOT NULL,
+    purpose character varying(20) NOT NULL,
+    requested_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+CREATE INDEX account_token_request_email_idx ON base.account_token_request USING btree (email, purpose, requested_at);
+
+ALTER TABLE base.mail_outbox DROP CONSTRAINT mail_outbox_template_check;
+
+ALTER TABLE base.mail_outbox
+    ADD CONSTRAINT mail_outbox_template_check CHECK (((template)::text = ANY ((ARRAY['WELCOME'::character varying, 'ORDER_CONFIRMATION'::character varying, 'ORDER_STATUS'::character varying, 'PASSWORD_RESET'::character varying, 'VERIFY_EMAIL'::character varying])::text[])));
+
+-- Token emails point at their token rather than carrying its link. The link is only made when the
+-- email is sent, so the outbox never holds a working token. Emails for tokens that have been
+-- replaced go with them.
+ALTER TABLE base.mail_outbox ADD COLUMN account_token_id integer;
+
+ALTER TABLE ONLY base.mail_outbox
+    ADD CONSTRAINT mail_outbox_account_token_id_fkey FOREIGN KEY (account_token_id) REFERENCES base.account_token(token_id) ON DELETE CASCADE;
@@ -25,10 +25,71 @@ CREATE SCHEMA base;
 
 ALTER SCHEMA base OWNER TO steven;
 
+--
+-- Name: pg_trgm; Type: EXTENSION; Schema: -; Owner: -
+--
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm WITH SCHEMA public;
+
+
+--
+-- Name: EXTENSION pg_trgm; Type: COMMENT; Schema: -; Owner: 
+--
+
+COMMENT ON EXTENSION pg_trgm IS 'text similarity measurement and index searching based on trigrams';
+
+
 SET default_tablespace = '';
 
 SET default_table_access_method = heap;
 
+--
+-- Name: account_token; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.account_token (
+    token_id integer NOT NULL,
+    purpose character varying(20) NOT NULL,
+    customer_id integer,
+    owner_id integer,
+    token_hash character(64) NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    expires_at timestamp with time zone NOT NULL,
+    used_at timestamp with time zone,
+    CONSTRAINT account_token_account_check CHECK ((num_nonnulls(customer_id, owner_id) = 1)),
+    CONSTRAINT account_token_purpose_check CHECK (((purpose)::text = ANY (ARRAY[('PASSWORD_RESET'::character varying)::text, ('VERIFY_EMAIL'::character varying)::text])))
+);
+
+
+ALTER TABLE base.account_token OWNER TO steven;
+
+--
+-- Name: account_token_request; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.account_token_request (
+    email character varying(254) NOT NULL,
+    purpose character varying(20) NOT NULL,
+    requested_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+ALTER TABLE base.account_token_request OWNER TO steven;
+
+--
+-- Name: account_token_token_id_seq; Type: SEQUENCE; Schema: base; Owner: steven
+--
+
+ALTER TABLE base.account_token ALTER COLUMN token_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.account_token_token_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+
 --
 -- Name: address; Type: TABLE; Schema: base; Owner: steven
 --
@@ -62,7 +123,7 @@ ALTER TABLE base.address ALTER COLUMN address_id ADD GENERATED ALWAYS AS IDENTIT
 --
 
 CREATE TABLE base.book (
-    isbn integer NOT NULL,
+    isbn bigint NOT NULL,
     author_name character varying(20),
     genre character varying(20),
     publisher_id integer,
@@ -72,7 +133,10 @@ CREATE TABLE base.book (
     reorder_threshold integer,
     title character varying(50),
     stock integer,
-    discontinued boolean
+    discontinued boolean,
+    search_vector tsvector GENERATED ALWAYS AS (((setweight(to_tsvector('english'::regconfig, (COALESCE(title, ''::character varying))::text), 'A'::"char") || setweight(to_tsvector('english'::regconfig, (COALESCE(author_name, ''::character varying))::text), 'B'::"char")) || setweight(to_tsvector('english'::regconfig, (COALESCE(genre, ''::character varying))::text), 'C'::"char"))) STORED,
+    version integer DEFAULT 1 NOT NULL,
+    CONSTRAINT book_isbn_check CHECK (((isbn >= '9780000000000'::bigint) AND (isbn <= '9799999999999'::bigint)))
 );
 
 
@@ -84,7 +148,9 @@ ALTER TABLE base.book OWNER TO steven;
 
 CREATE TABLE base.book_collection (
     collection_id integer NOT NULL,
-    curator_owner_id integer
+    curator_owner_id integer,
+    name character varying(50) NOT NULL,
+    sort_order integer DEFAULT 0 NOT NULL
 );
 
 
@@ -104,6 +170,104 @@ ALTER TABLE base.book_collection ALTER COLUMN collection_id ADD GENERATED ALWAYS
 );
 
 
+--
+-- Name: book_edit; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.book_edit (
+    book_edit_id integer NOT NULL,
+    isbn bigint NOT NULL,
+    version integer NOT NULL,
+    owner_id integer,
+    field_name character varying(20) NOT NULL,
+    old_value text NOT NULL,
+    new_value text NOT NULL,
+    edited_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+ALTER TABLE base.book_edit OWNER TO steven;
+
+--
+-- Name: book_edit_book_edit_id_seq; Type: SEQUENCE; Schema: base; Owner: steven
+--
+
+ALTER TABLE base.book_edit ALTER COLUMN book_edit_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.book_edit_book_edit_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+
+--
+-- Name: book_price; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.book_price (
+    book_price_id integer NOT NULL,
+    isbn bigint NOT NULL,
+    price numeric(6,2) NOT NULL,
+    effective_from timestamp with time zone NOT NULL,
+    owner_id integer,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    applied_at timestamp with time zone,
+    CONSTRAINT book_price_price_check CHECK ((price >= (0)::numeric))
+);
+
+
+ALTER TABLE base.book_price OWNER TO steven;
+
+--
+-- Name: book_price_book_price_id_seq; Type: SEQUENCE; Schema: base; Owner: steven
+--
+
+ALTER TABLE base.book_price ALTER COLUMN book_price_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.book_price_book_price_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+
+--
+-- Name: catalog_import; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.catalog_import (
+    catalog_import_id integer NOT NULL,
+    file_name character varying(255) NOT NULL,
+    import_format character varying(4) NOT NULL,
+    contents text NOT NULL,
+    owner_id integer,
+    uploaded_at timestamp with time zone DEFAULT now() NOT NULL,
+    applied_at timestamp with time zone,
+    books_added integer,
+    books_updated integer,
+    records_rejected integer
+);
+
+
+ALTER TABLE base.catalog_import OWNER TO steven;
+
+--
+-- Name: catalog_import_catalog_import_id_seq; Type: SEQUENCE; Schema: base; Owner: steven
+--
+
+ALTER TABLE base.catalog_import ALTER COLUMN catalog_import_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.catalog_import_catalog_import_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+
 --
 -- Name: customer; Type: TABLE; Schema: base; Owner: steven
 --
@@ -114,7 +278,8 @@ CREATE TABLE base.customer (
     email character varying(20),
     password_hash character(60),
     default_shipping_address integer,
-    default_payment_info_id integer
+    default_payment_info_id integer,
+    email_verified_at timestamp with time zone
 );
 
 
@@ -134,14 +299,27 @@ ALTER TABLE base.customer ALTER COLUMN customer_id ADD GENERATED ALWAYS AS IDENT
 );
 
 
+--
+-- Name: guest_cart; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.guest_cart (
+    cart_token character varying(64) NOT NULL,
+    last_active timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+ALTER TABLE base.guest_cart OWNER TO steven;
+
 --
 -- Name: in_cart; Type: TABLE; Schema: base; Owner: steven
 --
 
 CREATE TABLE base.in_cart (
-    isbn integer NOT NULL,
+    isbn bigint NOT NULL,
     customer_id integer NOT NULL,
-    quantity integer
+    quantity integer,
+    reserved_until timestamp with time zone
 );
 
 
@@ -153,25 +331,163 @@ ALTER TABLE base.in_cart OWNER TO steven;
 
 CREATE TABLE base.in_collection (
     collection_id integer NOT NULL,
-    isbn integer NOT NULL
+    isbn bigint NOT NULL
 );
 
 
 ALTER TABLE base.in_collection OWNER TO steven;
 
+--
+-- Name: in_guest_cart; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.in_guest_cart (
+    isbn bigint NOT NULL,
+    cart_token character varying(64) NOT NULL,
+    quantity integer NOT NULL,
+    reserved_until timestamp with time zone
+);
+
+
+ALTER TABLE base.in_guest_cart OWNER TO steven;
+
 --
 -- Name: in_order; Type: TABLE; Schema: base; Owner: steven
 --
 
 CREATE TABLE base.in_order (
-    isbn integer NOT NULL,
+    isbn bigint NOT NULL,
     order_id integer NOT NULL,
-    quantity integer
+    quantity integer,
+    unit_price numeric(6,2) NOT NULL
 );
 
 
 ALTER TABLE base.in_order OWNER TO steven;
 
+--
+-- Name: in_wishlist; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.in_wishlist (
+    wishlist_id integer NOT NULL,
+    isbn bigint NOT NULL,
+    added_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+ALTER TABLE base.in_wishlist OWNER TO steven;
+
+--
+-- Name: mail_outbox; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.mail_outbox (
+    mail_id integer NOT NULL,
+    template character varying(20) NOT NULL,
+    recipient_name character varying(50),
+    recipient_email character varying(254) NOT NULL,
+    context jsonb NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    next_attempt_at timestamp with time zone DEFAULT now() NOT NULL,
+    sent_at timestamp with time zone,
+    attempts integer DEFAULT 0 NOT NULL,
+    last_error text,
+    account_token_id integer,
+    CONSTRAINT mail_outbox_template_check CHECK (((template)::text = ANY (ARRAY[('WELCOME'::character varying)::text, ('ORDER_CONFIRMATION'::character varying)::text, ('ORDER_STATUS'::character varying)::text, ('PASSWORD_RESET'::character varying)::text, ('VERIFY_EMAIL'::character varying)::text])))
+);
+
+
+ALTER TABLE base.mail_outbox OWNER TO steven;
+
+--
+-- Name: mail_outbox_mail_id_seq; Type: SEQUENCE; Schema: base; Owner: steven
+--
+
+ALTER TABLE base.mail_outbox ALTER COLUMN mail_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.mail_outbox_mail_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+
+--
+-- Name: notification; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.notification (
+    notification_id integer NOT NULL,
+    customer_id integer NOT NULL,
+    isbn bigint NOT NULL,
+    kind character varying(20) NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    sent_at timestamp with time zone,
+    attempts integer DEFAULT 0 NOT NULL,
+    last_error text,
+    CONSTRAINT notification_kind_check CHECK (((kind)::text = 'BACK_IN_STOCK'::text))
+);
+
+
+ALTER TABLE base.notification OWNER TO steven;
+
+--
+-- Name: notification_notification_id_seq; Type: SEQUENCE; Schema: base; Owner: steven
+--
+
+ALTER TABLE base.notification ALTER COLUMN notification_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.notification_notification_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+
+--
+-- Name: order_cancellation; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.order_cancellation (
+    order_id integer NOT NULL,
+    reason character varying(200),
+    cancelled_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+ALTER TABLE base.order_cancellation OWNER TO steven;
+
+--
+-- Name: order_status_history; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.order_status_history (
+    order_status_history_id integer NOT NULL,
+    order_id integer NOT NULL,
+    order_status character varying(10) NOT NULL,
+    changed_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+ALTER TABLE base.order_status_history OWNER TO steven;
+
+--
+-- Name: order_status_history_order_status_history_id_seq; Type: SEQUENCE; Schema: base; Owner: steven
+--
+
+ALTER TABLE base.order_status_history ALTER COLUMN order_status_history_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.order_status_history_order_status_history_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+
 --
 -- Name: orders; Type: TABLE; Schema: base; Owner: steven
 --
@@ -183,7 +499,12 @@ CREATE TABLE base.orders (
     tracking_number character varying(30),
     order_status character varying(10),
     order_date date,
-    payment_info_id integer
+    payment_info_id integer,
+    payment_authorisation character varying(64),
+    promotion_id integer,
+    discount numeric(8,2) DEFAULT 0.00 NOT NULL,
+    guest_email character varying(254),
+    CONSTRAINT orders_guest_email_check CHECK (((customer_id IS NULL) OR (guest_email IS NULL)))
 );
 
 
@@ -240,9 +561,10 @@ CREATE TABLE base.payment_info (
     payment_info_id integer NOT NULL,
     name_on_card character varying(30),
     expiry character varying(10),
-    card_number character varying(30),
-    cvv character varying(10),
-    billing_address_id integer
+    billing_address_id integer,
+    token character varying(64) NOT NULL,
+    last_four character varying(4) NOT NULL,
+    brand character varying(20) NOT NULL
 );
 
 
@@ -262,6 +584,53 @@ ALTER TABLE base.payment_info ALTER COLUMN payment_info_id ADD GENERATED ALWAYS
 );
 
 
+--
+-- Name: promotion; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.promotion (
+    promotion_id integer NOT NULL,
+    code character varying(30) NOT NULL,
+    discount_type character varying(7) NOT NULL,
+    discount_value numeric(8,2) NOT NULL,
+    min_spend numeric(8,2) DEFAULT 0.00 NOT NULL,
+    genre character varying(20),
+    publisher_id integer,
+    isbn bigint,
+    starts_at timestamp with time zone,
+    ends_at timestamp with time zone,
+    max_uses integer,
+    max_uses_per_customer integer,
+    active boolean DEFAULT true NOT NULL,
+    owner_id integer,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    CONSTRAINT promotion_code_check CHECK (((code)::text = upper((code)::text))),
+    CONSTRAINT promotion_discount_type_check CHECK (((discount_type)::text = ANY (ARRAY[('PERCENT'::character varying)::text, ('FIXED'::character varying)::text]))),
+    CONSTRAINT promotion_discount_value_check CHECK (((discount_value > (0)::numeric) AND (((discount_type)::text <> 'PERCENT'::text) OR (discount_value <= (100)::numeric)))),
+    CONSTRAINT promotion_max_uses_check CHECK (((max_uses IS NULL) OR (max_uses > 0))),
+    CONSTRAINT promotion_max_uses_per_customer_check CHECK (((max_uses_per_customer IS NULL) OR (max_uses_per_customer > 0))),
+    CONSTRAINT promotion_min_spend_check CHECK ((min_spend >= (0)::numeric)),
+    CONSTRAINT promotion_period_check CHECK (((starts_at IS NULL) OR (ends_at IS NULL) OR (starts_at < ends_at))),
+    CONSTRAINT promotion_scope_check CHECK ((num_nonnulls(genre, publisher_id, isbn) <= 1))
+);
+
+
+ALTER TABLE base.promotion OWNER TO steven;
+
+--
+-- Name: promotion_promotion_id_seq; Type: SEQUENCE; Schema: base; Owner: steven
+--
+
+ALTER TABLE base.promotion ALTER COLUMN promotion_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.promotion_promotion_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+
 --
 -- Name: publisher; Type: TABLE; Schema: base; Owner: steven
 --
@@ -292,6 +661,41 @@ ALTER TABLE base.publisher ALTER COLUMN publisher_id ADD GENERATED ALWAYS AS IDE
 );
 
 
+--
+-- Name: publisher_remittance; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.publisher_remittance (
+    publisher_remittance_id integer NOT NULL,
+    publisher_id integer NOT NULL,
+    period_start date NOT NULL,
+    period_end date NOT NULL,
+    amount numeric(12,2) NOT NULL,
+    bank_number character varying(20),
+    remittance_status character varying(10) DEFAULT 'PENDING'::character varying NOT NULL,
+    owner_id integer,
+    created_at timestamp with time zone DEFAULT now() NOT NULL,
+    remitted_at timestamp with time zone
+);
+
+
+ALTER TABLE base.publisher_remittance OWNER TO steven;
+
+
+--
+-- Name: publisher_remittance_publisher_remittance_id_seq; Type: SEQUENCE; Schema: base; Owner: steven
+--
+
+ALTER TABLE base.publisher_remittance ALTER COLUMN publisher_remittance_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.publisher_remittance_publisher_remittance_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+
 --
 -- Name: raw_sales_data; Type: VIEW; Schema: base; Owner: steven
 --
@@ -310,7 +714,7 @@ CREATE VIEW base.raw_sales_data AS
     book.genre,
     book.publisher_id,
     book.num_pages,
-    book.price,
+    in_order.unit_price AS price,
     book.author_royalties,
     book.reorder_threshold,
     book.title,
@@ -318,7 +722,8 @@ CREATE VIEW base.raw_sales_data AS
     book.discontinued
    FROM ((base.orders
      JOIN base.in_order USING (order_id))
-     JOIN base.book USING (isbn));
+     JOIN base.book USING (isbn))
+  WHERE ((orders.order_status)::text <> ALL (ARRAY[('CA'::character varying)::text, ('RT'::character varying)::text]));
 
 
 ALTER TABLE base.raw_sales_data OWNER TO steven;
@@ -329,11 +734,12 @@ ALTER TABLE base.raw_sales_data OWNER TO steven;
 
 CREATE TABLE base.restock_order (
     restock_order_id integer NOT NULL,
-    isbn integer,
+    isbn bigint,
     quantity integer,
     price_per_unit money,
     order_date date,
-    order_status character varying(10)
+    order_status character varying(10),
+    publisher_remittance_id integer
 );
 
 
@@ -363,302 +769,1013 @@ CREATE VIEW base.sales AS
    FROM ((base.orders
      JOIN base.in_order USING (order_id))
      JOIN base.book USING (isbn))
+  WHERE ((orders.order_status)::text <> ALL (ARRAY[('CA'::character varying)::text, ('RT'::character varying)::text]))
   GROUP BY orders.order_date;
 
 
 ALTER TABLE base.sales OWNER TO steven;
 
 --
--- Data for Name: address; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: royalty_payout; Type: TABLE; Schema: base; Owner: steven
 --
 
-COPY base.address (address_id, street_address, postal_code, province) FROM stdin;
-1	12 Main St	K2W0A8	ON
-2	777 U St.	K1S189	ON
-3	12831 W	1231	IW
-4	777 U St.	K1S189	ON
-5	12831 W	1231	IW
-6	777 U St.	K1S189	ON
-7	12831 W	1231	IW
-8	777 U St.	K1S189	ON
-9	12831 W	1231	IW
-10	777 U St.	K1S189	ON
-11	12831 W	1231	IW
-12	732 Main St	K192W9	ON
-13	829 Recipe Avenue	FOOD	ON
-\.
+CREATE TABLE base.royalty_payout (
+    royalty_payout_id integer NOT NULL,
+    period_start date NOT NULL,
+    period_end date NOT NULL,
+    owner_id integer,
+    paid_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+ALTER TABLE base.royalty_payout OWNER TO steven;
 
 
 --
--- Data for Name: book; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: royalty_payout_line; Type: TABLE; Schema: base; Owner: steven
 --
 
-COPY base.book (isbn, author_name, genre, publisher_id, num_pages, price, author_royalties, reorder_threshold, title, stock, discontinued) FROM stdin;
-82381902	B. Kenny	Sci-Fi	2	300	29.99	0.10	10	Jarjar Journeys	29	f
-82381967	B. BKenny	Sci-Fi	2	300	29.99	0.10	10	Jarjar Journeys 2	24	f
-82381990	B. BKenny	Sci-Fi	2	300	29.99	0.10	10	Jarjar Journeys 3: Jarjar Redux Complete	28	f
-312321	John Shefman	Cooking	4	25	50.00	0.10	5	Food For People Who Eat	7	f
-\.
+CREATE TABLE base.royalty_payout_line (
+    royalty_payout_id integer NOT NULL,
+    order_id integer NOT NULL,
+    isbn bigint NOT NULL,
+    author_name character varying(20),
+    quantity integer NOT NULL,
+    price numeric(6,2) NOT NULL,
+    royalty_rate numeric(3,2) NOT NULL,
+    royalty numeric(10,2) NOT NULL
+);
+
+
+ALTER TABLE base.royalty_payout_line OWNER TO steven;
 
 
 --
--- Data for Name: book_collection; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: royalty_payout_royalty_payout_id_seq; Type: SEQUENCE; Schema: base; Owner: steven
 --
 
-COPY base.book_collection (collection_id, curator_owner_id) FROM stdin;
-\.
+ALTER TABLE base.royalty_payout ALTER COLUMN royalty_payout_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.royalty_payout_royalty_payout_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
 
 
 --
--- Data for Name: customer; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: session; Type: TABLE; Schema: base; Owner: steven
 --
 
-COPY base.customer (customer_id, name, email, password_hash, default_shipping_address, default_payment_info_id) FROM stdin;
-2	Steve	test@local	$2b$10$BXeln024yC2PhT3J9jpuL.OYB9ciEUm0Lgvt3pDpTY0GlICWJGO4q	8	2
-3	Steve	test2@local	$2b$10$27lLyoOAxeJBQlGbh0ZL0eFZ80VwquejFQD1ecygB3dbsTkokb9sS	10	3
-\.
+CREATE TABLE base.session (
+    token_hash character varying(64) NOT NULL,
+    session_type character varying(10) NOT NULL,
+    account_id integer,
+    expires_at timestamp with time zone NOT NULL
+);
+
+
+ALTER TABLE base.session OWNER TO steven;
+
+--
+-- Name: stock_reservation; Type: VIEW; Schema: base; Owner: steven
+--
+
+CREATE VIEW base.stock_reservation AS
+ SELECT in_cart.isbn,
+    in_cart.quantity,
+    in_cart.reserved_until,
+    in_cart.customer_id,
+    NULL::character varying AS cart_token
+   FROM base.in_cart
+  WHERE (in_cart.reserved_until > now())
+UNION ALL
+ SELECT in_guest_cart.isbn,
+    in_guest_cart.quantity,
+    in_guest_cart.reserved_until,
+    NULL::integer AS customer_id,
+    in_guest_cart.cart_token
+   FROM base.in_guest_cart
+  WHERE (in_guest_cart.reserved_until > now());
+
+
+ALTER TABLE base.stock_reservation OWNER TO steven;
+
+--
+-- Name: stock_subscription; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.stock_subscription (
+    customer_id integer NOT NULL,
+    isbn bigint NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+ALTER TABLE base.stock_subscription OWNER TO steven;
+
+--
+-- Name: wishlist; Type: TABLE; Schema: base; Owner: steven
+--
+
+CREATE TABLE base.wishlist (
+    wishlist_id integer NOT NULL,
+    customer_id integer NOT NULL,
+    name character varying(50) NOT NULL,
+    share_token character varying(64) NOT NULL,
+    created_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+ALTER TABLE base.wishlist OWNER TO steven;
+
+--
+-- Name: wishlist_wishlist_id_seq; Type: SEQUENCE; Schema: base; Owner: steven
+--
+
+ALTER TABLE base.wishlist ALTER COLUMN wishlist_id ADD GENERATED ALWAYS AS IDENTITY (
+    SEQUENCE NAME base.wishlist_wishlist_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1
+);
+
+
+--
+-- Name: schema_migrations; Type: TABLE; Schema: public; Owner: steven
+--
+
+CREATE TABLE public.schema_migrations (
+    version integer NOT NULL,
+    name character varying(100) NOT NULL,
+    applied_at timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+ALTER TABLE public.schema_migrations OWNER TO steven;
+
+--
+-- Data for Name: account_token; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.account_token (token_id, purpose, customer_id, owner_id, token_hash, created_at, expires_at, used_at) FROM stdin;
+\.
+
+
+--
+-- Data for Name: account_token_request; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.account_token_request (email, purpose, requested_at) FROM stdin;
+\.
+
+
+--
+-- Data for Name: address; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.address (address_id, street_address, postal_code, province) FROM stdin;
+1	12 Main St	K2W0A8	ON
+2	777 U St.	K1S189	ON
+3	12831 W	1231	IW
+4	777 U St.	K1S189	ON
+5	12831 W	1231	IW
+6	777 U St.	K1S189	ON
+7	12831 W	1231	IW
+8	777 U St.	K1S189	ON
+9	12831 W	1231	IW
+10	777 U St.	K1S189	ON
+11	12831 W	1231	IW
+12	732 Main St	K192W9	ON
+13	829 Recipe Avenue	FOOD	ON
+\.
+
+
+--
+-- Data for Name: book; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.book (isbn, author_name, genre, publisher_id, num_pages, price, author_royalties, reorder_threshold, title, stock, discontinued, version) FROM stdin;
+9780823819027	B. Kenny	Sci-Fi	2	300	29.99	0.10	10	Jarjar Journeys	29	f	1
+9780823819676	B. BKenny	Sci-Fi	2	300	29.99	0.10	10	Jarjar Journeys 2	24	f	1
+9780823819904	B. BKenny	Sci-Fi	2	300	29.99	0.10	10	Jarjar Journeys 3: Jarjar Redux Complete	28	f	1
+9780003123210	John Shefman	Cooking	4	25	50.00	0.10	5	Food For People Who Eat	7	f	1
+\.
+
+
+--
+-- Data for Name: book_collection; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.book_collection (collection_id, curator_owner_id, name, sort_order) FROM stdin;
+\.
+
+
+--
+-- Data for Name: book_edit; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.book_edit (book_edit_id, isbn, version, owner_id, field_name, old_value, new_value, edited_at) FROM stdin;
+\.
+
+
+--
+-- Data for Name: book_price; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.book_price (book_price_id, isbn, price, effective_from, owner_id, created_at, applied_at) FROM stdin;
+1	9780823819027	29.99	2021-12-10 00:00:00-05	\N	2021-12-10 00:00:00-05	2021-12-10 00:00:00-05
+2	9780823819676	29.99	2021-12-10 00:00:00-05	\N	2021-12-10 00:00:00-05	2021-12-10 00:00:00-05
+3	9780823819904	29.99	2021-12-10 00:00:00-05	\N	2021-12-10 00:00:00-05	2021-12-10 00:00:00-05
+4	9780003123210	50.00	2021-12-10 00:00:00-05	\N	2021-12-10 00:00:00-05	2021-12-10 00:00:00-05
+\.
+
+
+--
+-- Data for Name: catalog_import; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.catalog_import (catalog_import_id, file_name, import_format, contents, owner_id, uploaded_at, applied_at, books_added, books_updated, records_rejected) FROM stdin;
+\.
+
+
+--
+-- Data for Name: customer; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.customer (customer_id, name, email, password_hash, default_shipping_address, default_payment_info_id, email_verified_at) FROM stdin;
+2	Steve	test@local	$2b$10$BXeln024yC2PhT3J9jpuL.OYB9ciEUm0Lgvt3pDpTY0GlICWJGO4q	8	2	2026-10-17 20:24:39.589407+00
+3	Steve	test2@local	$2b$10$27lLyoOAxeJBQlGbh0ZL0eFZ80VwquejFQD1ecygB3dbsTkokb9sS	10	3	2026-10-17 20:24:39.589407+00
+\.
+
+
+--
+-- Data for Name: guest_cart; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.guest_cart (cart_token, last_active) FROM stdin;
+\.
+
+
+--
+-- Data for Name: in_cart; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.in_cart (isbn, customer_id, quantity, reserved_until) FROM stdin;
+\.
+
+
+--
+-- Data for Name: in_collection; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.in_collection (collection_id, isbn) FROM stdin;
+\.
+
+
+--
+-- Data for Name: in_guest_cart; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.in_guest_cart (isbn, cart_token, quantity, reserved_until) FROM stdin;
+\.
+
+
+--
+-- Data for Name: in_order; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.in_order (isbn, order_id, quantity, unit_price) FROM stdin;
+9780823819676	3	2	29.99
+9780823819904	3	1	29.99
+9780823819676	4	1	29.99
+9780823819676	5	1	29.99
+9780823819027	6	1	29.99
+9780823819676	7	1	29.99
+9780823819904	8	1	29.99
+9780823819904	9	1	29.99
+9780823819676	10	4	29.99
+9780003123210	11	3	50.00
+\.
+
+
+--
+-- Data for Name: in_wishlist; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.in_wishlist (wishlist_id, isbn, added_at) FROM stdin;
+\.
+
+
+--
+-- Data for Name: mail_outbox; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.mail_outbox (mail_id, template, recipient_name, recipient_email, context, created_at, next_attempt_at, sent_at, attempts, last_error, account_token_id) FROM stdin;
+\.
+
+
+--
+-- Data for Name: notification; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.notification (notification_id, customer_id, isbn, kind, created_at, sent_at, attempts, last_error) FROM stdin;
+\.
+
+
+--
+-- Data for Name: order_cancellation; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.order_cancellation (order_id, reason, cancelled_at) FROM stdin;
+\.
+
+
+--
+-- Data for Name: order_status_history; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.order_status_history (order_status_history_id, order_id, order_status, changed_at) FROM stdin;
+1	2	PR	2021-12-08 00:00:00-05
+2	3	PR	2021-12-08 00:00:00-05
+3	4	PR	2021-12-08 00:00:00-05
+4	5	PR	2021-12-08 00:00:00-05
+5	6	PR	2021-12-08 00:00:00-05
+6	7	PR	2021-12-08 00:00:00-05
+7	8	PR	2021-12-08 00:00:00-05
+8	9	PR	2021-12-09 00:00:00-05
+9	10	PR	2021-12-09 00:00:00-05
+10	11	PR	2021-12-10 00:00:00-05
+\.
+
+
+--
+-- Data for Name: orders; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.orders (order_id, customer_id, shipping_address_id, tracking_number, order_status, order_date, payment_info_id) FROM stdin;
+2	3	10	953146797	PR	2021-12-08	3
+3	3	10	661630750	PR	2021-12-08	3
+4	3	10	1290554229	PR	2021-12-08	3
+5	3	10	3305752897	PR	2021-12-08	3
+6	3	10	368815587	PR	2021-12-08	3
+7	3	10	1887640785	PR	2021-12-08	3
+8	3	10	1118788729	PR	2021-12-08	3
+9	3	10	1910357174	PR	2021-12-09	3
+10	3	10	2235082141	PR	2021-12-09	3
+11	3	10	953908047	PR	2021-12-10	3
+\.
+
+
+--
+-- Data for Name: owner; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.owner (owner_id, name, email, password_hash, password_salt) FROM stdin;
+\.
+
+
+--
+-- Data for Name: payment_info; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.payment_info (payment_info_id, name_on_card, expiry, billing_address_id, token, last_four, brand) FROM stdin;
+1	Steven Pham	1/23	7	tok_mock_q6fVB9p7FmGBEM_bR5JGiPqcGCJMt7q_6NkEAvfZSjY	2313	Card
+2	Steven Pham	1/23	9	tok_mock_q6fVB9p7FmGBEM_bR5JGiPqcGCJMt7q_6NkEAvfZSjY	2313	Card
+3	Steven Pham	1/23	11	tok_mock_q6fVB9p7FmGBEM_bR5JGiPqcGCJMt7q_6NkEAvfZSjY	2313	Card
+\.
+
+
+--
+-- Data for Name: promotion; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.promotion (promotion_id, code, discount_type, discount_value, min_spend, genre, publisher_id, isbn, starts_at, ends_at, max_uses, max_uses_per_customer, active, owner_id, created_at) FROM stdin;
+\.
+
+
+--
+-- Data for Name: publisher; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.publisher (publisher_id, company_name, phone_number, bank_number, address_id, email) FROM stdin;
+2	ABC Books	888-999-0984	772823	1	publishing@abcbooks.com
+3	CDE Books	613772222	77282890283901	12	publisher@cdebooks.com
+4	Food Incorporated	2223334444	723819372193	13	publishing@foodcorp.com
+\.
+
+
+--
+-- Data for Name: publisher_remittance; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.publisher_remittance (publisher_remittance_id, publisher_id, period_start, period_end, amount, bank_number, remittance_status, owner_id, created_at, remitted_at) FROM stdin;
+\.
+
+
+--
+-- Data for Name: restock_order; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.restock_order (restock_order_id, isbn, quantity, price_per_unit, order_date, order_status, publisher_remittance_id) FROM stdin;
+\.
+
+
+--
+-- Data for Name: royalty_payout; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.royalty_payout (royalty_payout_id, period_start, period_end, owner_id, paid_at) FROM stdin;
+\.
+
+
+--
+-- Data for Name: royalty_payout_line; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.royalty_payout_line (royalty_payout_id, order_id, isbn, author_name, quantity, price, royalty_rate, royalty) FROM stdin;
+\.
+
+
+--
+-- Data for Name: session; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.session (token_hash, session_type, account_id, expires_at) FROM stdin;
+\.
+
+
+--
+-- Data for Name: stock_subscription; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.stock_subscription (customer_id, isbn, created_at) FROM stdin;
+\.
+
+
+--
+-- Data for Name: wishlist; Type: TABLE DATA; Schema: base; Owner: steven
+--
+
+COPY base.wishlist (wishlist_id, customer_id, name, share_token, created_at) FROM stdin;
+\.
+
+
+--
+-- Data for Name: schema_migrations; Type: TABLE DATA; Schema: public; Owner: steven
+--
+
+COPY public.schema_migrations (version, name, applied_at) FROM stdin;
+1	baseline	2021-12-10 00:00:00-05
+2	order_status_history	2021-12-10 00:00:00-05
+3	order_cancellation	2021-12-10 00:00:00-05
+4	session	2021-12-10 00:00:00-05
+5	payment_tokens	2021-12-10 00:00:00-05
+6	collection_names	2021-12-10 00:00:00-05
+7	royalty_payouts	2021-12-10 00:00:00-05
+8	publisher_remittances	2021-12-10 00:00:00-05
+9	book_search	2021-12-10 00:00:00-05
+10	isbn13	2021-12-10 00:00:00-05
+11	catalog_imports	2021-12-10 00:00:00-05
+12	book_edits	2021-12-10 00:00:00-05
+13	price_history	2021-12-10 00:00:00-05
+14	promotions	2021-12-10 00:00:00-05
+15	guest_carts	2021-12-10 00:00:00-05
+16	stock_reservations	2021-12-10 00:00:00-05
+17	wishlists	2021-12-10 00:00:00-05
+18	mail_outbox	2021-12-10 00:00:00-05
+19	account_tokens	2021-12-10 00:00:00-05
+\.
+
+
+--
+-- Name: address_address_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.address_address_id_seq', 13, true);
+
+
+--
+-- Name: book_collection_collection_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.book_collection_collection_id_seq', 1, false);
+
+
+--
+-- Name: book_edit_book_edit_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.book_edit_book_edit_id_seq', 1, false);
+
+
+--
+-- Name: book_price_book_price_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.book_price_book_price_id_seq', 4, true);
+
+
+--
+-- Name: catalog_import_catalog_import_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.catalog_import_catalog_import_id_seq', 1, false);
+
+
+--
+-- Name: customer_customer_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.customer_customer_id_seq', 3, true);
+
+
+--
+-- Name: mail_outbox_mail_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.mail_outbox_mail_id_seq', 1, false);
+
+
+--
+-- Name: notification_notification_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.notification_notification_id_seq', 1, false);
+
+
+--
+-- Name: order_status_history_order_status_history_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.order_status_history_order_status_history_id_seq', 10, true);
+
+
+--
+-- Name: orders_order_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.orders_order_id_seq', 11, true);
+
+
+--
+-- Name: owner_owner_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.owner_owner_id_seq', 1, false);
+
+
+--
+-- Name: payment_info_payment_info_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.payment_info_payment_info_id_seq', 3, true);
+
+
+--
+-- Name: promotion_promotion_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.promotion_promotion_id_seq', 1, false);
+
+
+--
+-- Name: publisher_publisher_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.publisher_publisher_id_seq', 4, true);
+
+
+--
+-- Name: publisher_remittance_publisher_remittance_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.publisher_remittance_publisher_remittance_id_seq', 1, false);
+
+
+--
+-- Name: restock_order_restock_order_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.restock_order_restock_order_id_seq', 1, false);
+
+
+--
+-- Name: royalty_payout_royalty_payout_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.royalty_payout_royalty_payout_id_seq', 1, false);
+
+
+--
+-- Name: wishlist_wishlist_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+--
+
+SELECT pg_catalog.setval('base.wishlist_wishlist_id_seq', 1, false);
+
+
+--
+-- Name: account_token account_token_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.account_token
+    ADD CONSTRAINT account_token_pkey PRIMARY KEY (token_id);
+
+
+--
+-- Name: account_token account_token_token_hash_key; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.account_token
+    ADD CONSTRAINT account_token_token_hash_key UNIQUE (token_hash);
+
+
+--
+-- Name: address address_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.address
+    ADD CONSTRAINT address_pkey PRIMARY KEY (address_id);
+
+
+--
+-- Name: book_collection book_collection_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.book_collection
+    ADD CONSTRAINT book_collection_pkey PRIMARY KEY (collection_id);
+
+
+--
+-- Name: book_edit book_edit_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.book_edit
+    ADD CONSTRAINT book_edit_pkey PRIMARY KEY (book_edit_id);
+
+
+--
+-- Name: book book_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.book
+    ADD CONSTRAINT book_pkey PRIMARY KEY (isbn);
+
+
+--
+-- Name: book_price book_price_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.book_price
+    ADD CONSTRAINT book_price_pkey PRIMARY KEY (book_price_id);
+
+
+--
+-- Name: catalog_import catalog_import_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.catalog_import
+    ADD CONSTRAINT catalog_import_pkey PRIMARY KEY (catalog_import_id);
+
+
+--
+-- Name: customer customer_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.customer
+    ADD CONSTRAINT customer_pkey PRIMARY KEY (customer_id);
+
+
+--
+-- Name: guest_cart guest_cart_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.guest_cart
+    ADD CONSTRAINT guest_cart_pkey PRIMARY KEY (cart_token);
+
+
+--
+-- Name: in_cart in_cart_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.in_cart
+    ADD CONSTRAINT in_cart_pkey PRIMARY KEY (isbn, customer_id);
+
+
+--
+-- Name: in_collection in_collection_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.in_collection
+    ADD CONSTRAINT in_collection_pkey PRIMARY KEY (collection_id, isbn);
+
+
+--
+-- Name: in_guest_cart in_guest_cart_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.in_guest_cart
+    ADD CONSTRAINT in_guest_cart_pkey PRIMARY KEY (isbn, cart_token);
+
+
+--
+-- Name: in_order in_order_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.in_order
+    ADD CONSTRAINT in_order_pkey PRIMARY KEY (isbn, order_id);
+
+
+--
+-- Name: in_wishlist in_wishlist_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.in_wishlist
+    ADD CONSTRAINT in_wishlist_pkey PRIMARY KEY (wishlist_id, isbn);
+
+
+--
+-- Name: mail_outbox mail_outbox_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.mail_outbox
+    ADD CONSTRAINT mail_outbox_pkey PRIMARY KEY (mail_id);
+
+
+--
+-- Name: notification notification_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.notification
+    ADD CONSTRAINT notification_pkey PRIMARY KEY (notification_id);
+
+
+--
+-- Name: order_cancellation order_cancellation_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.order_cancellation
+    ADD CONSTRAINT order_cancellation_pkey PRIMARY KEY (order_id);
+
+
+--
+-- Name: order_status_history order_status_history_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.order_status_history
+    ADD CONSTRAINT order_status_history_pkey PRIMARY KEY (order_status_history_id);
+
+
+--
+-- Name: orders orders_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.orders
+    ADD CONSTRAINT orders_pkey PRIMARY KEY (order_id);
 
 
 --
--- Data for Name: in_cart; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: owner owner_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-COPY base.in_cart (isbn, customer_id, quantity) FROM stdin;
-\.
+ALTER TABLE ONLY base.owner
+    ADD CONSTRAINT owner_pkey PRIMARY KEY (owner_id);
 
 
 --
--- Data for Name: in_collection; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: payment_info payment_info_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-COPY base.in_collection (collection_id, isbn) FROM stdin;
-\.
+ALTER TABLE ONLY base.payment_info
+    ADD CONSTRAINT payment_info_pkey PRIMARY KEY (payment_info_id);
 
 
 --
--- Data for Name: in_order; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: promotion promotion_code_key; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-COPY base.in_order (isbn, order_id, quantity) FROM stdin;
-82381967	3	2
-82381990	3	1
-82381967	4	1
-82381967	5	1
-82381902	6	1
-82381967	7	1
-82381990	8	1
-82381990	9	1
-82381967	10	4
-312321	11	3
-\.
+ALTER TABLE ONLY base.promotion
+    ADD CONSTRAINT promotion_code_key UNIQUE (code);
 
 
 --
--- Data for Name: orders; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: promotion promotion_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-COPY base.orders (order_id, customer_id, shipping_address_id, tracking_number, order_status, order_date, payment_info_id) FROM stdin;
-2	3	10	953146797	PR	2021-12-08	3
-3	3	10	661630750	PR	2021-12-08	3
-4	3	10	1290554229	PR	2021-12-08	3
-5	3	10	3305752897	PR	2021-12-08	3
-6	3	10	368815587	PR	2021-12-08	3
-7	3	10	1887640785	PR	2021-12-08	3
-8	3	10	1118788729	PR	2021-12-08	3
-9	3	10	1910357174	PR	2021-12-09	3
-10	3	10	2235082141	PR	2021-12-09	3
-11	3	10	953908047	PR	2021-12-10	3
-\.
+ALTER TABLE ONLY base.promotion
+    ADD CONSTRAINT promotion_pkey PRIMARY KEY (promotion_id);
 
 
 --
--- Data for Name: owner; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: publisher publisher_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-COPY base.owner (owner_id, name, email, password_hash, password_salt) FROM stdin;
-\.
+ALTER TABLE ONLY base.publisher
+    ADD CONSTRAINT publisher_pkey PRIMARY KEY (publisher_id);
 
 
 --
--- Data for Name: payment_info; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: publisher_remittance publisher_remittance_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-COPY base.payment_info (payment_info_id, name_on_card, expiry, card_number, cvv, billing_address_id) FROM stdin;
-1	Steven Pham	1/23	12312313	199	7
-2	Steven Pham	1/23	12312313	199	9
-3	Steven Pham	1/23	12312313	199	11
-\.
+ALTER TABLE ONLY base.publisher_remittance
+    ADD CONSTRAINT publisher_remittance_pkey PRIMARY KEY (publisher_remittance_id);
 
 
 --
--- Data for Name: publisher; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: restock_order restock_order_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-COPY base.publisher (publisher_id, company_name, phone_number, bank_number, address_id, email) FROM stdin;
-2	ABC Books	888-999-0984	772823	1	publishing@abcbooks.com
-3	CDE Books	613772222	77282890283901	12	publisher@cdebooks.com
-4	Food Incorporated	2223334444	723819372193	13	publishing@foodcorp.com
-\.
+ALTER TABLE ONLY base.restock_order
+    ADD CONSTRAINT restock_order_pkey PRIMARY KEY (restock_order_id);
 
 
 --
--- Data for Name: restock_order; Type: TABLE DATA; Schema: base; Owner: steven
+-- Name: royalty_payout royalty_payout_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-COPY base.restock_order (restock_order_id, isbn, quantity, price_per_unit, order_date, order_status) FROM stdin;
-\.
+ALTER TABLE ONLY base.royalty_payout
+    ADD CONSTRAINT royalty_payout_pkey PRIMARY KEY (royalty_payout_id);
 
 
 --
--- Name: address_address_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+-- Name: royalty_payout_line royalty_payout_line_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-SELECT pg_catalog.setval('base.address_address_id_seq', 13, true);
+ALTER TABLE ONLY base.royalty_payout_line
+    ADD CONSTRAINT royalty_payout_line_pkey PRIMARY KEY (order_id, isbn);
 
 
 --
--- Name: book_collection_collection_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+-- Name: session session_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-SELECT pg_catalog.setval('base.book_collection_collection_id_seq', 1, false);
+ALTER TABLE ONLY base.session
+    ADD CONSTRAINT session_pkey PRIMARY KEY (token_hash);
 
 
 --
--- Name: customer_customer_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+-- Name: stock_subscription stock_subscription_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-SELECT pg_catalog.setval('base.customer_customer_id_seq', 3, true);
+ALTER TABLE ONLY base.stock_subscription
+    ADD CONSTRAINT stock_subscription_pkey PRIMARY KEY (customer_id, isbn);
 
 
 --
--- Name: orders_order_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+-- Name: wishlist wishlist_customer_id_name_key; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-SELECT pg_catalog.setval('base.orders_order_id_seq', 11, true);
+ALTER TABLE ONLY base.wishlist
+    ADD CONSTRAINT wishlist_customer_id_name_key UNIQUE (customer_id, name);
 
 
 --
--- Name: owner_owner_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+-- Name: wishlist wishlist_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-SELECT pg_catalog.setval('base.owner_owner_id_seq', 1, false);
+ALTER TABLE ONLY base.wishlist
+    ADD CONSTRAINT wishlist_pkey PRIMARY KEY (wishlist_id);
 
 
 --
--- Name: payment_info_payment_info_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+-- Name: wishlist wishlist_share_token_key; Type: CONSTRAINT; Schema: base; Owner: steven
 --
 
-SELECT pg_catalog.setval('base.payment_info_payment_info_id_seq', 3, true);
+ALTER TABLE ONLY base.wishlist
+    ADD CONSTRAINT wishlist_share_token_key UNIQUE (share_token);
 
 
 --
--- Name: publisher_publisher_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+-- Name: schema_migrations schema_migrations_pkey; Type: CONSTRAINT; Schema: public; Owner: steven
 --
 
-SELECT pg_catalog.setval('base.publisher_publisher_id_seq', 4, true);
+ALTER TABLE ONLY public.schema_migrations
+    ADD CONSTRAINT schema_migrations_pkey PRIMARY KEY (version);
 
 
 --
--- Name: restock_order_restock_order_id_seq; Type: SEQUENCE SET; Schema: base; Owner: steven
+-- Name: account_token_request_email_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-SELECT pg_catalog.setval('base.restock_order_restock_order_id_seq', 1, false);
+CREATE INDEX account_token_request_email_idx ON base.account_token_request USING btree (email, purpose, requested_at);
 
 
 --
--- Name: address address_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: book_author_name_trgm_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.address
-    ADD CONSTRAINT address_pkey PRIMARY KEY (address_id);
+CREATE INDEX book_author_name_trgm_idx ON base.book USING gin (author_name public.gin_trgm_ops);
 
 
 --
--- Name: book_collection book_collection_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: book_edit_isbn_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.book_collection
-    ADD CONSTRAINT book_collection_pkey PRIMARY KEY (collection_id);
+CREATE INDEX book_edit_isbn_idx ON base.book_edit USING btree (isbn);
 
 
 --
--- Name: book book_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: book_price_isbn_effective_from_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.book
-    ADD CONSTRAINT book_pkey PRIMARY KEY (isbn);
+CREATE INDEX book_price_isbn_effective_from_idx ON base.book_price USING btree (isbn, effective_from);
 
 
 --
--- Name: customer customer_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: book_price_pending_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.customer
-    ADD CONSTRAINT customer_pkey PRIMARY KEY (customer_id);
+CREATE INDEX book_price_pending_idx ON base.book_price USING btree (effective_from) WHERE (applied_at IS NULL);
 
 
 --
--- Name: in_cart in_cart_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: book_search_vector_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.in_cart
-    ADD CONSTRAINT in_cart_pkey PRIMARY KEY (isbn, customer_id);
+CREATE INDEX book_search_vector_idx ON base.book USING gin (search_vector);
 
 
 --
--- Name: in_collection in_collection_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: book_title_trgm_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.in_collection
-    ADD CONSTRAINT in_collection_pkey PRIMARY KEY (collection_id, isbn);
+CREATE INDEX book_title_trgm_idx ON base.book USING gin (title public.gin_trgm_ops);
 
 
 --
--- Name: in_order in_order_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: guest_cart_last_active_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.in_order
-    ADD CONSTRAINT in_order_pkey PRIMARY KEY (isbn, order_id);
+CREATE INDEX guest_cart_last_active_idx ON base.guest_cart USING btree (last_active);
 
 
 --
--- Name: orders orders_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: mail_outbox_pending_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.orders
-    ADD CONSTRAINT orders_pkey PRIMARY KEY (order_id);
+CREATE INDEX mail_outbox_pending_idx ON base.mail_outbox USING btree (next_attempt_at) WHERE (sent_at IS NULL);
 
 
 --
--- Name: owner owner_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: notification_pending_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.owner
-    ADD CONSTRAINT owner_pkey PRIMARY KEY (owner_id);
+CREATE INDEX notification_pending_idx ON base.notification USING btree (created_at) WHERE (sent_at IS NULL);
 
 
 --
--- Name: payment_info payment_info_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: orders_promotion_id_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.payment_info
-    ADD CONSTRAINT payment_info_pkey PRIMARY KEY (payment_info_id);
+CREATE INDEX orders_promotion_id_idx ON base.orders USING btree (promotion_id) WHERE (promotion_id IS NOT NULL);
 
 
 --
--- Name: publisher publisher_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: publisher_company_name_trgm_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.publisher
-    ADD CONSTRAINT publisher_pkey PRIMARY KEY (publisher_id);
+CREATE INDEX publisher_company_name_trgm_idx ON base.publisher USING gin (company_name public.gin_trgm_ops);
 
 
 --
--- Name: restock_order restock_order_pkey; Type: CONSTRAINT; Schema: base; Owner: steven
+-- Name: royalty_payout_line_royalty_payout_id_idx; Type: INDEX; Schema: base; Owner: steven
 --
 
-ALTER TABLE ONLY base.restock_order
-    ADD CONSTRAINT restock_order_pkey PRIMARY KEY (restock_order_id);
+CREATE INDEX royalty_payout_line_royalty_payout_id_idx ON base.royalty_payout_line USING btree (royalty_payout_id);
+
+
+--
+-- Name: session_expires_at_idx; Type: INDEX; Schema: base; Owner: steven
+--
+
+CREATE INDEX session_expires_at_idx ON base.session USING btree (expires_at);
+
+
+--
+-- Name: stock_subscription_isbn_idx; Type: INDEX; Schema: base; Owner: steven
+--
+
+CREATE INDEX stock_subscription_isbn_idx ON base.stock_subscription USING btree (isbn);
+
+
+--
+-- Name: account_token account_token_customer_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.account_token
+    ADD CONSTRAINT account_token_customer_id_fkey FOREIGN KEY (customer_id) REFERENCES base.customer(customer_id) ON DELETE CASCADE;
+
+
+--
+-- Name: account_token account_token_owner_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.account_token
+    ADD CONSTRAINT account_token_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE CASCADE;
 
 
 --
@@ -666,7 +1783,39 @@ ALTER TABLE ONLY base.restock_order
 --
 
 ALTER TABLE ONLY base.book_collection
-    ADD CONSTRAINT book_collection_curator_owner_id_fkey FOREIGN KEY (curator_owner_id) REFERENCES base.owner(owner_id);
+    ADD CONSTRAINT book_collection_curator_owner_id_fkey FOREIGN KEY (curator_owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
+
+
+--
+-- Name: book_edit book_edit_isbn_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.book_edit
+    ADD CONSTRAINT book_edit_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+
+--
+-- Name: book_edit book_edit_owner_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.book_edit
+    ADD CONSTRAINT book_edit_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
+
+
+--
+-- Name: book_price book_price_isbn_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.book_price
+    ADD CONSTRAINT book_price_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+
+--
+-- Name: book_price book_price_owner_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.book_price
+    ADD CONSTRAINT book_price_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
 
 
 --
@@ -677,6 +1826,14 @@ ALTER TABLE ONLY base.book
     ADD CONSTRAINT book_publisher_id_fkey FOREIGN KEY (publisher_id) REFERENCES base.publisher(publisher_id);
 
 
+--
+-- Name: catalog_import catalog_import_owner_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.catalog_import
+    ADD CONSTRAINT catalog_import_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
+
+
 --
 -- Name: customer customer_default_payment_info_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
 --
@@ -714,7 +1871,7 @@ ALTER TABLE ONLY base.in_cart
 --
 
 ALTER TABLE ONLY base.in_collection
-    ADD CONSTRAINT in_collection_collection_id_fkey FOREIGN KEY (collection_id) REFERENCES base.book_collection(collection_id);
+    ADD CONSTRAINT in_collection_collection_id_fkey FOREIGN KEY (collection_id) REFERENCES base.book_collection(collection_id) ON DELETE CASCADE;
 
 
 --
@@ -725,6 +1882,22 @@ ALTER TABLE ONLY base.in_collection
     ADD CONSTRAINT in_collection_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
 
 
+--
+-- Name: in_guest_cart in_guest_cart_cart_token_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.in_guest_cart
+    ADD CONSTRAINT in_guest_cart_cart_token_fkey FOREIGN KEY (cart_token) REFERENCES base.guest_cart(cart_token) ON DELETE CASCADE;
+
+
+--
+-- Name: in_guest_cart in_guest_cart_isbn_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.in_guest_cart
+    ADD CONSTRAINT in_guest_cart_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+
 --
 -- Name: in_order in_order_isbn_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
 --
@@ -741,6 +1914,62 @@ ALTER TABLE ONLY base.in_order
     ADD CONSTRAINT in_order_order_id_fkey FOREIGN KEY (order_id) REFERENCES base.orders(order_id);
 
 
+--
+-- Name: in_wishlist in_wishlist_isbn_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.in_wishlist
+    ADD CONSTRAINT in_wishlist_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+
+--
+-- Name: in_wishlist in_wishlist_wishlist_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.in_wishlist
+    ADD CONSTRAINT in_wishlist_wishlist_id_fkey FOREIGN KEY (wishlist_id) REFERENCES base.wishlist(wishlist_id) ON DELETE CASCADE;
+
+
+--
+-- Name: mail_outbox mail_outbox_account_token_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.mail_outbox
+    ADD CONSTRAINT mail_outbox_account_token_id_fkey FOREIGN KEY (account_token_id) REFERENCES base.account_token(token_id) ON DELETE CASCADE;
+
+
+--
+-- Name: notification notification_customer_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.notification
+    ADD CONSTRAINT notification_customer_id_fkey FOREIGN KEY (customer_id) REFERENCES base.customer(customer_id) ON DELETE CASCADE;
+
+
+--
+-- Name: notification notification_isbn_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.notification
+    ADD CONSTRAINT notification_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+
+--
+-- Name: order_cancellation order_cancellation_order_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.order_cancellation
+    ADD CONSTRAINT order_cancellation_order_id_fkey FOREIGN KEY (order_id) REFERENCES base.orders(order_id);
+
+
+--
+-- Name: order_status_history order_status_history_order_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.order_status_history
+    ADD CONSTRAINT order_status_history_order_id_fkey FOREIGN KEY (order_id) REFERENCES base.orders(order_id);
+
+
 --
 -- Name: orders orders_customer_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
 --
@@ -757,6 +1986,14 @@ ALTER TABLE ONLY base.orders
     ADD CONSTRAINT orders_payment_info_id_fkey FOREIGN KEY (payment_info_id) REFERENCES base.payment_info(payment_info_id);
 
 
+--
+-- Name: orders orders_promotion_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.orders
+    ADD CONSTRAINT orders_promotion_id_fkey FOREIGN KEY (promotion_id) REFERENCES base.promotion(promotion_id);
+
+
 --
 -- Name: payment_info payment_info_billing_address_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
 --
@@ -765,6 +2002,30 @@ ALTER TABLE ONLY base.payment_info
     ADD CONSTRAINT payment_info_billing_address_id_fkey FOREIGN KEY (billing_address_id) REFERENCES base.address(address_id);
 
 
+--
+-- Name: promotion promotion_isbn_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.promotion
+    ADD CONSTRAINT promotion_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+
+--
+-- Name: promotion promotion_owner_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.promotion
+    ADD CONSTRAINT promotion_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
+
+
+--
+-- Name: promotion promotion_publisher_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.promotion
+    ADD CONSTRAINT promotion_publisher_id_fkey FOREIGN KEY (publisher_id) REFERENCES base.publisher(publisher_id);
+
+
 --
 -- Name: publisher publisher_address_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
 --
@@ -773,6 +2034,22 @@ ALTER TABLE ONLY base.publisher
     ADD CONSTRAINT publisher_address_id_fkey FOREIGN KEY (address_id) REFERENCES base.address(address_id);
 
 
+--
+-- Name: publisher_remittance publisher_remittance_owner_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.publisher_remittance
+    ADD CONSTRAINT publisher_remittance_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
+
+
+--
+-- Name: publisher_remittance publisher_remittance_publisher_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.publisher_remittance
+    ADD CONSTRAINT publisher_remittance_publisher_id_fkey FOREIGN KEY (publisher_id) REFERENCES base.publisher(publisher_id);
+
+
 --
 -- Name: restock_order restock_order_isbn_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
 --
@@ -781,6 +2058,62 @@ ALTER TABLE ONLY base.restock_order
     ADD CONSTRAINT restock_order_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
 
 
+--
+-- Name: restock_order restock_order_publisher_remittance_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.restock_order
+    ADD CONSTRAINT restock_order_publisher_remittance_id_fkey FOREIGN KEY (publisher_remittance_id) REFERENCES base.publisher_remittance(publisher_remittance_id);
+
+
+--
+-- Name: royalty_payout royalty_payout_owner_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.royalty_payout
+    ADD CONSTRAINT royalty_payout_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES base.owner(owner_id) ON DELETE SET NULL;
+
+
+--
+-- Name: royalty_payout_line royalty_payout_line_order_id_isbn_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.royalty_payout_line
+    ADD CONSTRAINT royalty_payout_line_order_id_isbn_fkey FOREIGN KEY (order_id, isbn) REFERENCES base.in_order(order_id, isbn);
+
+
+--
+-- Name: royalty_payout_line royalty_payout_line_royalty_payout_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.royalty_payout_line
+    ADD CONSTRAINT royalty_payout_line_royalty_payout_id_fkey FOREIGN KEY (royalty_payout_id) REFERENCES base.royalty_payout(royalty_payout_id);
+
+
+--
+-- Name: stock_subscription stock_subscription_customer_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.stock_subscription
+    ADD CONSTRAINT stock_subscription_customer_id_fkey FOREIGN KEY (customer_id) REFERENCES base.customer(customer_id) ON DELETE CASCADE;
+
+
+--
+-- Name: stock_subscription stock_subscription_isbn_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.stock_subscription
+    ADD CONSTRAINT stock_subscription_isbn_fkey FOREIGN KEY (isbn) REFERENCES base.book(isbn);
+
+
+--
+-- Name: wishlist wishlist_customer_id_fkey; Type: FK CONSTRAINT; Schema: base; Owner: steven
+--
+
+ALTER TABLE ONLY base.wishlist
+    ADD CONSTRAINT wishlist_customer_id_fkey FOREIGN KEY (customer_id) REFERENCES base.customer(customer_id) ON DELETE CASCADE;
+
+
 --
 -- PostgreSQL database dump complete
 --
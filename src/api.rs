@@ -0,0 +1,638 @@
+//! The JSON API mounted at `/api/v1`, for clients that would otherwise have to scrape the pages.
+//!
+//! Clients log in for a bearer token, which is a session just like the ones behind the login
+//! cookies, and send it back as `Authorization: Bearer <token>`.
+
+use chrono::{Duration, Local};
+use rocket::http::Status;
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::{Catcher, Request, Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ReservationConfig, RestockPolicy};
+use crate::db::conn::DbConn;
+use crate::db::error::{
+    CancelOrderError, CartError, LoginError, OrderError, OrderStatusError, RestockError,
+    SessionError,
+};
+use crate::db::query::{
+    cancel_order, cart_set_book_quantity, create_order, discontinue_books, get_cart,
+    get_customer_accounts, get_customer_orders_info, get_fulfilment_orders,
+    get_order_status_history, get_owner_accounts, get_restock_orders, set_order_status,
+    set_restock_order_status, undiscontinue_books, validate_customer_login, validate_owner_login,
+    Buyer, Expiry, OwnerLoginType,
+};
+use crate::db::search::{search_books, suggest_books, BookQuery, SearchHit, Suggestion};
+use crate::endpoints::{
+    book_query, cancel_order_error_status, cart_error_status, censor_order, create_session_token,
+    order_error_status, order_status_error_status, restock_error_status, ApproveRestock,
+    BookWithQuantity, CancelOrder, CensoredOrder, Search, UpdateOrderStatus, SUGGESTION_LIMIT,
+};
+use crate::payment::CardDetails;
+use crate::request_guards::state::SessionType;
+use crate::request_guards::{BearerToken, CartOwner, Customer, Owner};
+use crate::schema::entities::{
+    BookWithPublisherName, CustomerLogin, Isbn, OrderStatus, OwnerLogin, PostgresInt, RestockStatus,
+};
+use crate::schema::joined::{FulfilmentOrder, OrderStatusChange, RestockOrder};
+use crate::schema::no_id::{Address, PaymentInfo};
+use crate::{PaymentProviderState, SessionStoreState};
+
+pub const BASE: &str = "/api/v1";
+
+const DEFAULT_PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        customer_login,
+        owner_login,
+        logout,
+        books,
+        book_suggestions,
+        book,
+        cart,
+        set_cart_quantity,
+        remove_from_cart,
+        checkout,
+        orders,
+        order,
+        cancel,
+        fulfilment_orders,
+        update_order_status,
+        discontinue,
+        undiscontinue,
+        restock_orders,
+        approve_restock_order,
+        receive_restock_order,
+        cancel_restock_order,
+        customer_accounts,
+        owner_accounts,
+    ]
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers![default_catcher]
+}
+
+/// Every API error has the same body, `{"status": 404, "message": "..."}`
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new<T: AsRef<str>>(status: Status, message: T) -> ApiError {
+        ApiError {
+            status: status.code,
+            message: message.as_ref().to_string(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = Status::from_code(self.status).unwrap_or(Status::InternalServerError);
+
+        (status, Json(self)).respond_to(request)
+    }
+}
+
+impl From<(Status, String)> for ApiError {
+    fn from((status, message): (Status, String)) -> ApiError {
+        ApiError::new(status, message)
+    }
+}
+
+impl From<postgres::error::Error> for ApiError {
+    fn from(e: postgres::error::Error) -> ApiError {
+        ApiError::new(
+            Status::InternalServerError,
+            format!("Internal DB error: `{e}`"),
+        )
+    }
+}
+
+impl From<SessionError> for ApiError {
+    fn from(e: SessionError) -> ApiError {
+        match e {
+            SessionError::NoConnection => ApiError::new(Status::ServiceUnavailable, e.to_string()),
+            SessionError::DBError(e) => e.into(),
+        }
+    }
+}
+
+impl From<LoginError> for ApiError {
+    fn from(e: LoginError) -> ApiError {
+        match e {
+            LoginError::CredentialError => ApiError::new(Status::Unauthorized, e.to_string()),
+            LoginError::DBError(_) | LoginError::BCryptError(_) => {
+                ApiError::new(Status::InternalServerError, e.to_string())
+            }
+        }
+    }
+}
+
+impl From<CartError> for ApiError {
+    fn from(e: CartError) -> ApiError {
+        cart_error_status(e).into()
+    }
+}
+
+impl From<OrderError> for ApiError {
+    fn from(e: OrderError) -> ApiError {
+        order_error_status(e).into()
+    }
+}
+
+impl From<CancelOrderError> for ApiError {
+    fn from(e: CancelOrderError) -> ApiError {
+        cancel_order_error_status(e).into()
+    }
+}
+
+impl From<OrderStatusError> for ApiError {
+    fn from(e: OrderStatusError) -> ApiError {
+        order_status_error_status(e).into()
+    }
+}
+
+impl From<RestockError> for ApiError {
+    fn from(e: RestockError) -> ApiError {
+        restock_error_status(e).into()
+    }
+}
+
+pub type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[catch(default)]
+fn default_catcher(status: Status, _request: &Request) -> ApiError {
+    ApiError::new(status, status.reason().unwrap_or("Unknown error"))
+}
+
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    items: Vec<T>,
+    page: usize,
+    per_page: usize,
+    total_items: usize,
+    total_pages: usize,
+}
+
+/// Slices out the requested page, counting pages from 1
+fn paginate<T>(items: Vec<T>, page: Option<usize>, per_page: Option<usize>) -> Page<T> {
+    let page = page.unwrap_or(1).max(1);
+    let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+    let total_items = items.len();
+
+    Page {
+        items: items
+            .into_iter()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .collect(),
+        page,
+        per_page,
+        total_items,
+        total_pages: total_items.div_ceil(per_page),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Credentials {
+    email: String,
+    password: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionToken {
+    token: String,
+    token_type: &'static str,
+    expires_at: String,
+}
+
+async fn start_session(
+    session_store: &SessionStoreState,
+    session_type: SessionType,
+) -> ApiResult<SessionToken> {
+    let token = create_session_token();
+    let expiry = Local::now() + Duration::days(30);
+
+    session_store
+        .insert(token.clone(), session_type, expiry)
+        .await?;
+
+    Ok(Json(SessionToken {
+        token,
+        token_type: "Bearer",
+        expires_at: expiry.to_rfc3339(),
+    }))
+}
+
+#[post("/auth/customer", data = "<credentials>")]
+async fn customer_login(
+    conn: DbConn,
+    credentials: Json<Credentials>,
+    session_store: &State<SessionStoreState>,
+) -> ApiResult<SessionToken> {
+    let customer_id =
+        validate_customer_login(&conn, &credentials.email, &credentials.password).await?;
+
+    start_session(session_store, SessionType::Customer(customer_id)).await
+}
+
+#[post("/auth/owner", data = "<credentials>")]
+async fn owner_login(
+    conn: DbConn,
+    credentials: Json<Credentials>,
+    session_store: &State<SessionStoreState>,
+) -> ApiResult<SessionToken> {
+    let session_type =
+        match validate_owner_login(&conn, &credentials.email, &credentials.password).await? {
+            OwnerLoginType::DefaultOwner => SessionType::DefaultOwner,
+            OwnerLoginType::OwnerAccount(owner_id) => SessionType::Owner(owner_id),
+        };
+
+    start_session(session_store, session_type).await
+}
+
+#[delete("/auth")]
+async fn logout(
+    token: BearerToken,
+    session_store: &State<SessionStoreState>,
+) -> Result<(), ApiError> {
+    Ok(session_store.remove(&token.0).await?)
+}
+
+#[derive(Serialize, Debug)]
+pub struct BookSearchPage {
+    #[serde(flatten)]
+    page: Page<SearchHit>,
+    suggestion: Option<String>,
+}
+
+#[get("/books?<per_page>&<search..>")]
+async fn books(
+    conn: DbConn,
+    search: Search<'_>,
+    per_page: Option<usize>,
+) -> ApiResult<BookSearchPage> {
+    let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+    let results = search_books(&conn, book_query(&search, per_page as u32)).await?;
+    let total_items = results.total_books as usize;
+
+    Ok(Json(BookSearchPage {
+        page: Page {
+            items: results.books,
+            page: search.page.unwrap_or(1).max(1) as usize,
+            per_page,
+            total_items,
+            total_pages: total_items.div_ceil(per_page),
+        },
+        suggestion: results.suggestion,
+    }))
+}
+
+#[get("/books/suggest?<q>")]
+async fn book_suggestions(conn: DbConn, q: Option<&str>) -> ApiResult<Vec<Suggestion>> {
+    let q = q.unwrap_or("").trim();
+    if q.is_empty() {
+        return Ok(Json(vec![]));
+    }
+
+    Ok(Json(
+        suggest_books(&conn, q.to_string(), SUGGESTION_LIMIT).await?,
+    ))
+}
+
+#[get("/books/<isbn>")]
+async fn book(conn: DbConn, isbn: Isbn) -> ApiResult<BookWithPublisherName> {
+    let query = BookQuery::new()
+        .isbn(isbn)
+        .include_discontinued(true)
+        .include_no_stock(true);
+
+    search_books(&conn, query)
+        .await?
+        .books
+        .pop()
+        .map(|hit| Json(hit.book))
+        .ok_or_else(|| ApiError::new(Status::NotFound, format!("No book with ISBN: {isbn}")))
+}
+
+async fn customer_cart(conn: &DbConn, customer: Customer) -> ApiResult<Vec<BookWithQuantity>> {
+    let cart = get_cart(conn, &CartOwner::Customer(customer.customer_id)).await?;
+    let books = crate::db::query::get_books(conn).await?;
+
+    Ok(Json(
+        cart.into_iter()
+            .filter_map(|(isbn, quantity)| {
+                books
+                    .iter()
+                    .find(|book| book.isbn == isbn)
+                    .map(|book| BookWithQuantity {
+                        book: book.clone(),
+                        quantity,
+                    })
+            })
+            .collect(),
+    ))
+}
+
+#[get("/cart")]
+async fn cart(conn: DbConn, customer: Customer) -> ApiResult<Vec<BookWithQuantity>> {
+    customer_cart(&conn, customer).await
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CartQuantity {
+    quantity: u32,
+}
+
+#[put("/cart/<isbn>", data = "<quantity>")]
+async fn set_cart_quantity(
+    conn: DbConn,
+    customer: Customer,
+    isbn: Isbn,
+    quantity: Json<CartQuantity>,
+    reservations: &State<ReservationConfig>,
+) -> ApiResult<Vec<BookWithQuantity>> {
+    let books = crate::db::query::get_books(&conn).await?;
+    if !books.iter().any(|book| book.isbn == isbn) {
+        Err(ApiError::new(
+            Status::NotFound,
+            format!("No book with ISBN: {isbn}"),
+        ))?;
+    }
+
+    // Books not in the cart yet are added, with their copies held like any other change
+    cart_set_book_quantity(
+        &conn,
+        &CartOwner::Customer(customer.customer_id),
+        isbn,
+        quantity.quantity,
+        reservations.reserved_until(),
+    )
+    .await?;
+
+    customer_cart(&conn, customer).await
+}
+
+#[delete("/cart/<isbn>")]
+async fn remove_from_cart(
+    conn: DbConn,
+    customer: Customer,
+    isbn: Isbn,
+    reservations: &State<ReservationConfig>,
+) -> ApiResult<Vec<BookWithQuantity>> {
+    cart_set_book_quantity(
+        &conn,
+        &CartOwner::Customer(customer.customer_id),
+        isbn,
+        0,
+        reservations.reserved_until(),
+    )
+    .await?;
+
+    customer_cart(&conn, customer).await
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Card {
+    name_on_card: String,
+    card_number: String,
+    expiry: String,
+    cvv: String,
+    billing_address: Address,
+}
+
+/// Leaving out the shipping address or card uses the customer's defaults, and the promotion code
+/// is optional
+#[derive(Deserialize, Debug)]
+pub struct Checkout {
+    shipping_address: Option<Address>,
+    card: Option<Card>,
+    promotion_code: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OrderCreated {
+    order_id: PostgresInt,
+}
+
+#[post("/orders", data = "<checkout>")]
+async fn checkout(
+    conn: DbConn,
+    customer: Customer,
+    checkout: Json<Checkout>,
+    restock_policy: &State<RestockPolicy>,
+    payment_provider: &State<PaymentProviderState>,
+) -> Result<(Status, Json<OrderCreated>), ApiError> {
+    let Checkout {
+        shipping_address,
+        card,
+        promotion_code,
+    } = checkout.into_inner();
+
+    let payment_info = match card {
+        Some(card) => {
+            let expiry = Expiry::from_str(&card.expiry)
+                .ok_or_else(|| ApiError::new(Status::BadRequest, "Invalid Credit Card Expiry"))?;
+            let tokenised = payment_provider
+                .tokenise(&CardDetails::new(
+                    &card.name_on_card,
+                    expiry,
+                    &card.card_number,
+                    &card.cvv,
+                ))
+                .map_err(|e| ApiError::new(Status::BadRequest, e.to_string()))?;
+
+            Some(PaymentInfo::new(
+                card.name_on_card,
+                expiry,
+                tokenised,
+                card.billing_address,
+            ))
+        }
+        None => None,
+    };
+
+    let order_id = create_order(
+        &conn,
+        Buyer::Customer(customer.customer_id),
+        shipping_address,
+        payment_info,
+        promotion_code,
+        *restock_policy.inner(),
+        payment_provider.inner().clone(),
+    )
+    .await?;
+
+    Ok((Status::Created, Json(OrderCreated { order_id })))
+}
+
+#[get("/orders?<page>&<per_page>")]
+async fn orders(
+    conn: DbConn,
+    customer: Customer,
+    page: Option<usize>,
+    per_page: Option<usize>,
+) -> ApiResult<Page<CensoredOrder>> {
+    let orders = get_customer_orders_info(&conn, customer.customer_id)
+        .await?
+        .into_iter()
+        .map(censor_order)
+        .collect();
+
+    Ok(Json(paginate(orders, page, per_page)))
+}
+
+#[derive(Serialize, Debug)]
+pub struct OrderDetail {
+    order: CensoredOrder,
+    status_history: Vec<OrderStatusChange>,
+}
+
+#[get("/orders/<order_id>")]
+async fn order(conn: DbConn, customer: Customer, order_id: PostgresInt) -> ApiResult<OrderDetail> {
+    // Looking the order up among the customer's own keeps other customers' orders private
+    let order = get_customer_orders_info(&conn, customer.customer_id)
+        .await?
+        .into_iter()
+        .find(|order| order.order_id == order_id)
+        .ok_or_else(|| {
+            ApiError::new(
+                Status::NotFound,
+                format!("No order with the ID ({order_id})"),
+            )
+        })?;
+    let status_history = get_order_status_history(&conn, order_id).await?;
+
+    Ok(Json(OrderDetail {
+        order: censor_order(order),
+        status_history,
+    }))
+}
+
+#[post("/orders/<order_id>/cancel", data = "<cancellation>")]
+async fn cancel(
+    conn: DbConn,
+    customer: Customer,
+    order_id: PostgresInt,
+    cancellation: Json<CancelOrder>,
+) -> Result<(), ApiError> {
+    Ok(cancel_order(
+        &conn,
+        customer.customer_id,
+        order_id,
+        cancellation.into_inner().reason,
+    )
+    .await?)
+}
+
+#[get("/owner/orders?<status>&<page>&<per_page>")]
+async fn fulfilment_orders(
+    conn: DbConn,
+    _owner: Owner,
+    status: Option<OrderStatus>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+) -> ApiResult<Page<FulfilmentOrder>> {
+    let orders = get_fulfilment_orders(&conn, status).await?;
+
+    Ok(Json(paginate(orders, page, per_page)))
+}
+
+#[put("/owner/orders/<order_id>/status", data = "<update>")]
+async fn update_order_status(
+    conn: DbConn,
+    _owner: Owner,
+    order_id: PostgresInt,
+    update: Json<UpdateOrderStatus>,
+) -> Result<(), ApiError> {
+    let update = update.into_inner();
+
+    Ok(set_order_status(&conn, order_id, update.order_status, update.reason).await?)
+}
+
+#[put("/owner/books/discontinue", data = "<books>")]
+async fn discontinue(conn: DbConn, owner: Owner, books: Json<Vec<Isbn>>) -> Result<(), ApiError> {
+    Ok(discontinue_books(&conn, books.into_inner(), owner.owner_id()).await?)
+}
+
+#[put("/owner/books/undiscontinue", data = "<books>")]
+async fn undiscontinue(conn: DbConn, owner: Owner, books: Json<Vec<Isbn>>) -> Result<(), ApiError> {
+    Ok(undiscontinue_books(&conn, books.into_inner(), owner.owner_id()).await?)
+}
+
+#[get("/owner/restock?<page>&<per_page>")]
+async fn restock_orders(
+    conn: DbConn,
+    _owner: Owner,
+    page: Option<usize>,
+    per_page: Option<usize>,
+) -> ApiResult<Page<RestockOrder>> {
+    let restock_orders = get_restock_orders(&conn).await?;
+
+    Ok(Json(paginate(restock_orders, page, per_page)))
+}
+
+#[put("/owner/restock/<restock_order_id>/approve", data = "<approval>")]
+async fn approve_restock_order(
+    conn: DbConn,
+    _owner: Owner,
+    restock_order_id: PostgresInt,
+    approval: Json<ApproveRestock>,
+) -> Result<(), ApiError> {
+    Ok(set_restock_order_status(
+        &conn,
+        restock_order_id,
+        RestockStatus::Approved,
+        approval.price_per_unit,
+    )
+    .await?)
+}
+
+#[put("/owner/restock/<restock_order_id>/receive")]
+async fn receive_restock_order(
+    conn: DbConn,
+    _owner: Owner,
+    restock_order_id: PostgresInt,
+) -> Result<(), ApiError> {
+    Ok(set_restock_order_status(&conn, restock_order_id, RestockStatus::Received, None).await?)
+}
+
+#[put("/owner/restock/<restock_order_id>/cancel")]
+async fn cancel_restock_order(
+    conn: DbConn,
+    _owner: Owner,
+    restock_order_id: PostgresInt,
+) -> Result<(), ApiError> {
+    Ok(set_restock_order_status(&conn, restock_order_id, RestockStatus::Cancelled, None).await?)
+}
+
+#[get("/owner/customers?<page>&<per_page>")]
+async fn customer_accounts(
+    conn: DbConn,
+    _owner: Owner,
+    page: Option<usize>,
+    per_page: Option<usize>,
+) -> ApiResult<Page<CustomerLogin>> {
+    let customers = get_customer_accounts(&conn).await?;
+
+    Ok(Json(paginate(customers, page, per_page)))
+}
+
+#[get("/owner/owners?<page>&<per_page>")]
+async fn owner_accounts(
+    conn: DbConn,
+    _owner: Owner,
+    page: Option<usize>,
+    per_page: Option<usize>,
+) -> ApiResult<Page<OwnerLogin>> {
+    let owners = get_owner_accounts(&conn).await?;
+
+    Ok(Json(paginate(owners, page, per_page)))
+}
@@ -0,0 +1,798 @@
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::SmtpTransport;
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::figment::Profile;
+use rocket::serde::{Deserialize, DeserializeOwned};
+use rocket::{Build, Config, Rocket};
+
+use crate::db;
+use crate::db::conn::{DbConn, DbPool};
+use crate::mail::{MailError, MaildirTransport, Mailer, SmtpMailTransport};
+use crate::notify::{FileNotifier, LogNotifier, MailNotifier};
+use crate::payment::MockPaymentProvider;
+use crate::request_guards::state::{MemorySessionStore, PostgresSessionStore};
+use crate::schema::entities::{AccountTokenPurpose, PostgresInt};
+use crate::{MailTransportState, NotifierState, PaymentProviderState, SessionStoreState};
+
+/// Reads the config under `key`, or its default if there isn't any. An invalid config is logged
+/// as `what` and gives `None`, which should stop the launch.
+fn config_or_default<T: DeserializeOwned + Default>(
+    rocket: &Rocket<Build>,
+    key: &str,
+    what: &str,
+) -> Option<T> {
+    if rocket.figment().find_value(key).is_err() {
+        return Some(T::default());
+    }
+
+    match rocket.figment().extract_inner(key) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            error!("Invalid {}: {}", what, e);
+            None
+        }
+    }
+}
+
+/// Runs `job` every `interval_secs` once launched. Each run takes a connection from the pool
+/// and gives it back when it's done, so no connection is held between runs.
+fn spawn_periodic<F, Fut>(name: &'static str, interval_secs: u64, job: F) -> impl Fairing
+where
+    F: Fn(DbConn) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    AdHoc::on_liftoff(name, move |rocket| {
+        Box::pin(async move {
+            let pool = match DbPool::from_rocket(rocket).await {
+                Some(pool) => pool,
+                None => {
+                    warn!("{} will not run without a database pool", name);
+                    return;
+                }
+            };
+
+            rocket::tokio::spawn(async move {
+                let mut interval =
+                    rocket::tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+
+                loop {
+                    interval.tick().await;
+
+                    match pool.get().await {
+                        Some(conn) => job(conn).await,
+                        None => warn!("{} could not get a database connection", name),
+                    }
+                }
+            });
+        })
+    })
+}
+
+/// Decides how many copies are ordered when a book's stock falls below its reorder threshold.
+///
+/// Configured under the `restock` key, e.g. in `Rocket.toml`:
+///
+/// ```toml
+/// [default.restock]
+/// policy = "top_up"
+/// target_multiple = 2
+/// ```
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(crate = "rocket::serde", tag = "policy", rename_all = "snake_case")]
+pub enum RestockPolicy {
+    /// Always order the same number of copies
+    Fixed { quantity: PostgresInt },
+    /// Order enough copies to bring the stock up to `target_multiple` times the reorder threshold
+    TopUp { target_multiple: PostgresInt },
+}
+
+impl Default for RestockPolicy {
+    fn default() -> RestockPolicy {
+        RestockPolicy::TopUp { target_multiple: 2 }
+    }
+}
+
+impl RestockPolicy {
+    pub fn quantity_for(&self, stock: PostgresInt, reorder_threshold: PostgresInt) -> PostgresInt {
+        let quantity = match *self {
+            RestockPolicy::Fixed { quantity } => quantity,
+            RestockPolicy::TopUp { target_multiple } => reorder_threshold * target_multiple - stock,
+        };
+
+        // Always order at least one copy so the restock order is meaningful
+        PostgresInt::max(quantity, 1)
+    }
+
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Restock Policy", |rocket| async {
+            match config_or_default::<RestockPolicy>(&rocket, "restock", "restock policy") {
+                Some(policy) => Ok(rocket.manage(policy)),
+                None => Err(rocket),
+            }
+        })
+    }
+}
+
+/// Where login sessions are kept.
+///
+/// Configured under the `sessions` key, e.g. in `Rocket.toml`:
+///
+/// ```toml
+/// [default.sessions]
+/// store = "postgres"
+/// sweep_interval_secs = 600
+/// guest_cart_days = 7
+/// ```
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum SessionStoreKind {
+    Memory,
+    Postgres,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SessionConfig {
+    pub store: SessionStoreKind,
+    /// How often expired sessions are swept out of the store
+    pub sweep_interval_secs: u64,
+    /// How long a guest cart is kept after it was last changed. Browsers keep the guest cart
+    /// cookie for a week after that, so keeping the cart any longer doesn't help anyone.
+    pub guest_cart_days: u32,
+}
+
+impl Default for SessionConfig {
+    fn default() -> SessionConfig {
+        SessionConfig {
+            store: SessionStoreKind::Postgres,
+            sweep_interval_secs: 600,
+            guest_cart_days: 7,
+        }
+    }
+}
+
+impl SessionConfig {
+    async fn store(&self, rocket: &Rocket<Build>) -> Option<SessionStoreState> {
+        Some(match self.store {
+            SessionStoreKind::Memory => Arc::new(MemorySessionStore::default()),
+            SessionStoreKind::Postgres => Arc::new(PostgresSessionStore::new(
+                DbPool::from_rocket(rocket).await?,
+            )),
+        })
+    }
+
+    /// Manages the configured session store, and sweeps expired sessions and stale guest carts
+    /// out once launched
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Session Store", |rocket| async {
+            let config =
+                match config_or_default::<SessionConfig>(&rocket, "sessions", "session config") {
+                    Some(config) => config,
+                    None => return Err(rocket),
+                };
+
+            let store = match config.store(&rocket).await {
+                Some(store) => store,
+                None => {
+                    error!("The Postgres session store needs the database pool");
+                    return Err(rocket);
+                }
+            };
+
+            Ok(rocket
+                .manage(store.clone())
+                .manage(config)
+                .attach(spawn_periodic(
+                    "Session Sweeper",
+                    config.sweep_interval_secs,
+                    move |conn| sweep_sessions(store.clone(), config, conn),
+                )))
+        })
+    }
+}
+
+async fn sweep_sessions(session_store: SessionStoreState, config: SessionConfig, conn: DbConn) {
+    if let Err(e) = session_store.remove_expired().await {
+        warn!("Could not sweep expired sessions: {}", e);
+    }
+
+    if let Err(e) = db::query::delete_stale_guest_carts(&conn, config.guest_cart_days).await {
+        warn!("Could not sweep stale guest carts: {}", e);
+    }
+}
+
+/// Which payment provider to use.
+///
+/// Configured under the `payment` key, e.g. in `Rocket.toml`:
+///
+/// ```toml
+/// [default.payment]
+/// provider = "mock"
+/// secret = "change me"
+/// ```
+///
+/// The secret is best kept out of `Rocket.toml`, e.g. in `ROCKET_PAYMENT={secret="..."}`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", tag = "provider", rename_all = "snake_case")]
+pub enum PaymentConfig {
+    /// Without a secret the debug profile uses a random one, so cards get new tokens after
+    /// every restart, and any other profile refuses to start
+    Mock { secret: Option<String> },
+}
+
+impl Default for PaymentConfig {
+    fn default() -> PaymentConfig {
+        PaymentConfig::Mock { secret: None }
+    }
+}
+
+impl PaymentConfig {
+    fn provider(&self, profile: &Profile) -> Option<PaymentProviderState> {
+        match self {
+            PaymentConfig::Mock {
+                secret: Some(secret),
+            } => Some(Arc::new(MockPaymentProvider::new(secret))),
+            PaymentConfig::Mock { secret: None } if *profile == Config::DEBUG_PROFILE => {
+                warn!("No `payment.secret` is set, so a random one is used until the next restart");
+
+                let mut secret = [0u8; 32];
+                rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut secret);
+
+                Some(Arc::new(MockPaymentProvider::new(base64::encode(secret))))
+            }
+            PaymentConfig::Mock { secret: None } => {
+                error!(
+                    "No `payment.secret` is set, which only the {} profile allows",
+                    Config::DEBUG_PROFILE
+                );
+                None
+            }
+        }
+    }
+
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Payment Provider", |rocket| async {
+            let provider = config_or_default::<PaymentConfig>(&rocket, "payment", "payment config")
+                .and_then(|config| config.provider(rocket.figment().profile()));
+
+            match provider {
+                Some(provider) => Ok(rocket.manage(provider)),
+                None => Err(rocket),
+            }
+        })
+    }
+}
+
+/// How often scheduled price changes are checked for ones whose time has come.
+///
+/// Configured under the `prices` key, e.g. in `Rocket.toml`:
+///
+/// ```toml
+/// [default.prices]
+/// apply_interval_secs = 60
+/// ```
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(crate = "rocket::serde")]
+pub struct PriceConfig {
+    pub apply_interval_secs: u64,
+}
+
+impl Default for PriceConfig {
+    fn default() -> PriceConfig {
+        PriceConfig {
+            apply_interval_secs: 60,
+        }
+    }
+}
+
+impl PriceConfig {
+    /// Applies scheduled price changes in the background once launched
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Price Scheduler", |rocket| async {
+            match config_or_default::<PriceConfig>(&rocket, "prices", "price config") {
+                Some(config) => Ok(rocket.attach(spawn_periodic(
+                    "Price Scheduler",
+                    config.apply_interval_secs,
+                    apply_scheduled_prices,
+                ))),
+                None => Err(rocket),
+            }
+        })
+    }
+}
+
+async fn apply_scheduled_prices(conn: DbConn) {
+    if let Err(e) = db::query::apply_scheduled_prices(&conn).await {
+        warn!("Could not apply scheduled price changes: {}", e);
+    }
+}
+
+/// How long books in carts are held for, and how often holds that have run out are released.
+///
+/// Configured under the `reservations` key, e.g. in `Rocket.toml`:
+///
+/// ```toml
+/// [default.reservations]
+/// hold_minutes = 15
+/// release_interval_secs = 60
+/// ```
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ReservationConfig {
+    pub hold_minutes: u32,
+    pub release_interval_secs: u64,
+}
+
+impl Default for ReservationConfig {
+    fn default() -> ReservationConfig {
+        ReservationConfig {
+            hold_minutes: 15,
+            release_interval_secs: 60,
+        }
+    }
+}
+
+impl ReservationConfig {
+    /// When a hold placed now runs out
+    pub fn reserved_until(&self) -> DateTime<Local> {
+        Local::now() + chrono::Duration::minutes(self.hold_minutes as i64)
+    }
+
+    /// Manages the reservation config, and releases holds that have run out once launched
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Stock Reservations", |rocket| async {
+            match config_or_default::<ReservationConfig>(
+                &rocket,
+                "reservations",
+                "reservation config",
+            ) {
+                Some(config) => Ok(rocket.manage(config).attach(spawn_periodic(
+                    "Reservation Releaser",
+                    config.release_interval_secs,
+                    release_expired_reservations,
+                ))),
+                None => Err(rocket),
+            }
+        })
+    }
+}
+
+async fn release_expired_reservations(conn: DbConn) {
+    if let Err(e) = db::query::release_expired_reservations(&conn).await {
+        warn!("Could not release expired stock reservations: {}", e);
+    }
+}
+
+/// Which notifier delivers notifications to customers.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(crate = "rocket::serde", tag = "notifier", rename_all = "snake_case")]
+pub enum NotifierConfig {
+    /// Writes notifications to the application log
+    #[default]
+    Log,
+    /// Appends notifications to a local file
+    File { path: PathBuf },
+    /// Emails notifications with the mail transport
+    Mail,
+}
+
+/// How notifications are delivered, how often queued ones are sent, and how many times one is
+/// tried before it's given up on.
+///
+/// Configured under the `notifications` key, e.g. in `Rocket.toml`:
+///
+/// ```toml
+/// [default.notifications]
+/// notifier = "file"
+/// path = "notifications.log"
+/// dispatch_interval_secs = 30
+/// max_attempts = 5
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct NotificationConfig {
+    #[serde(flatten)]
+    pub notifier: NotifierConfig,
+    pub dispatch_interval_secs: u64,
+    pub max_attempts: PostgresInt,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> NotificationConfig {
+        NotificationConfig {
+            notifier: NotifierConfig::default(),
+            dispatch_interval_secs: 30,
+            max_attempts: 5,
+        }
+    }
+}
+
+impl NotificationConfig {
+    /// The configured notifier. The mail notifier uses the mailer and transport the mail fairing
+    /// manages, so it's `None` if that hasn't been attached first.
+    fn notifier(&self, rocket: &Rocket<Build>) -> Option<NotifierState> {
+        Some(match &self.notifier {
+            NotifierConfig::Log => Arc::new(LogNotifier),
+            NotifierConfig::File { path } => Arc::new(FileNotifier::new(path)),
+            NotifierConfig::Mail => Arc::new(MailNotifier::new(
+                rocket.state::<Arc<Mailer>>()?.clone(),
+                rocket.state::<MailTransportState>()?.clone(),
+            )),
+        })
+    }
+
+    /// Checks the notification config, and sends queued notifications once launched
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Notifications", |rocket| async {
+            let config = match config_or_default::<NotificationConfig>(
+                &rocket,
+                "notifications",
+                "notification config",
+            ) {
+                Some(config) => config,
+                None => return Err(rocket),
+            };
+
+            let notifier = match config.notifier(&rocket) {
+                Some(notifier) => notifier,
+                None => {
+                    error!("The mail notifier needs the mail fairing attached before it");
+                    return Err(rocket);
+                }
+            };
+
+            Ok(rocket.attach(spawn_periodic(
+                "Notification Dispatcher",
+                config.dispatch_interval_secs,
+                move |conn| dispatch_notifications(config.max_attempts, notifier.clone(), conn),
+            )))
+        })
+    }
+}
+
+async fn dispatch_notifications(max_attempts: PostgresInt, notifier: NotifierState, conn: DbConn) {
+    let notifications = match db::query::get_pending_notifications(&conn, max_attempts).await {
+        Ok(notifications) => notifications,
+        Err(e) => {
+            warn!("Could not fetch queued notifications: {}", e);
+            return;
+        }
+    };
+
+    for notification in notifications {
+        let notifier = notifier.clone();
+        let notification_id = notification.notification_id;
+
+        // The mail notifier can block on the network, so notifying is kept off the async workers
+        let sent =
+            rocket::tokio::task::spawn_blocking(move || notifier.notify(&notification)).await;
+
+        let error = match sent {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e.to_string()),
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(error) = &error {
+            warn!("Could not send notification {}: {}", notification_id, error);
+        }
+
+        if let Err(e) = db::query::record_notification_attempt(&conn, notification_id, error).await
+        {
+            warn!("Could not record a notification attempt: {}", e);
+        }
+    }
+}
+
+/// Where links in account emails point, how long password reset and email verification links
+/// last, and how often each email can ask for one.
+///
+/// Configured under the `accounts` key, e.g. in `Rocket.toml`:
+///
+/// ```toml
+/// [default.accounts]
+/// base_url = "https://lookinnabook.example.com"
+/// reset_token_minutes = 60
+/// verification_token_hours = 48
+/// max_token_requests = 3
+/// token_request_window_minutes = 60
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AccountConfig {
+    /// The address the site is reached at, without a trailing slash
+    pub base_url: String,
+    pub reset_token_minutes: i64,
+    pub verification_token_hours: i64,
+    /// How many links an email can ask for within the window
+    pub max_token_requests: i64,
+    pub token_request_window_minutes: i64,
+}
+
+impl Default for AccountConfig {
+    fn default() -> AccountConfig {
+        AccountConfig {
+            base_url: "http://localhost:8000".to_string(),
+            reset_token_minutes: 60,
+            verification_token_hours: 48,
+            max_token_requests: 3,
+            token_request_window_minutes: 60,
+        }
+    }
+}
+
+impl AccountConfig {
+    pub fn reset_link(&self, token: &str) -> String {
+        format!(
+            "{}/password/reset/{}",
+            self.base_url.trim_end_matches('/'),
+            token
+        )
+    }
+
+    pub fn verify_link(&self, token: &str) -> String {
+        format!("{}/verify/{}", self.base_url.trim_end_matches('/'), token)
+    }
+
+    /// Manages the account config
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Accounts", |rocket| async {
+            match config_or_default::<AccountConfig>(&rocket, "accounts", "account config") {
+                Some(config) => Ok(rocket.manage(config)),
+                None => Err(rocket),
+            }
+        })
+    }
+}
+
+/// How the connection to an SMTP server is secured
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Connects over TLS from the start
+    Tls,
+    /// Upgrades a plain connection with `STARTTLS`
+    #[default]
+    StartTls,
+    /// Sends everything in the clear, only for local test servers
+    None,
+}
+
+/// Which transport delivers emails.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", tag = "transport", rename_all = "snake_case")]
+pub enum MailTransportConfig {
+    /// Writes emails into a local maildir
+    Maildir { path: PathBuf },
+    /// Sends emails through an SMTP server, logging in if a username is given
+    Smtp {
+        host: String,
+        port: Option<u16>,
+        #[serde(default)]
+        security: SmtpSecurity,
+        username: Option<String>,
+        password: Option<String>,
+    },
+}
+
+impl Default for MailTransportConfig {
+    fn default() -> MailTransportConfig {
+        MailTransportConfig::Maildir {
+            path: PathBuf::from("maildir"),
+        }
+    }
+}
+
+/// Where emails are rendered from and sent with, how often the outbox is checked, and how
+/// failed emails are retried.
+///
+/// Configured under the `mail` key, e.g. in `Rocket.toml`:
+///
+/// ```toml
+/// [default.mail]
+/// transport = "smtp"
+/// host = "smtp.example.com"
+/// port = 587
+/// security = "start_tls"
+/// username = "lookinnabook"
+/// password = "..."
+/// from = "Look Inna Book <orders@example.com>"
+/// template_dir = "templates/email"
+/// dispatch_interval_secs = 30
+/// max_attempts = 5
+/// retry_delay_secs = 60
+/// batch_size = 50
+/// lease_secs = 300
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MailConfig {
+    #[serde(flatten)]
+    pub transport: MailTransportConfig,
+    pub from: String,
+    pub template_dir: PathBuf,
+    pub dispatch_interval_secs: u64,
+    pub max_attempts: PostgresInt,
+    /// How long to wait before retrying a failed email, doubled after each further failure
+    pub retry_delay_secs: u64,
+    /// How many emails a dispatcher claims from the outbox at a time
+    pub batch_size: i64,
+    /// How long claimed emails are left to their dispatcher before another one may send them,
+    /// which should be longer than sending a batch takes
+    pub lease_secs: u64,
+}
+
+impl Default for MailConfig {
+    fn default() -> MailConfig {
+        MailConfig {
+            transport: MailTransportConfig::default(),
+            from: "Look Inna Book <noreply@lookinnabook.local>".to_string(),
+            template_dir: PathBuf::from("templates/email"),
+            dispatch_interval_secs: 30,
+            max_attempts: 5,
+            retry_delay_secs: 60,
+            batch_size: 50,
+            lease_secs: 300,
+        }
+    }
+}
+
+impl MailConfig {
+    fn transport(&self) -> Result<MailTransportState, MailError> {
+        Ok(match &self.transport {
+            MailTransportConfig::Maildir { path } => Arc::new(MaildirTransport::new(path)?),
+            MailTransportConfig::Smtp {
+                host,
+                port,
+                security,
+                username,
+                password,
+            } => {
+                let mut builder = match security {
+                    SmtpSecurity::Tls => SmtpTransport::relay(host)?,
+                    SmtpSecurity::StartTls => SmtpTransport::starttls_relay(host)?,
+                    SmtpSecurity::None => SmtpTransport::builder_dangerous(host),
+                };
+                if let Some(port) = port {
+                    builder = builder.port(*port);
+                }
+                if let Some(username) = username {
+                    builder = builder.credentials(Credentials::new(
+                        username.clone(),
+                        password.clone().unwrap_or_default(),
+                    ));
+                }
+
+                Arc::new(SmtpMailTransport::new(builder.build()))
+            }
+        })
+    }
+
+    /// Checks the mail config and templates, manages the mailer and transport for anything else
+    /// sending email, and sends emails from the outbox once launched
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Mail", |rocket| async {
+            let config = match config_or_default::<MailConfig>(&rocket, "mail", "mail config") {
+                Some(config) => config,
+                None => return Err(rocket),
+            };
+
+            // Links in account emails are made when they're sent
+            let accounts =
+                match config_or_default::<AccountConfig>(&rocket, "accounts", "account config") {
+                    Some(accounts) => Arc::new(accounts),
+                    None => return Err(rocket),
+                };
+
+            let mailer = match Mailer::new(&config.template_dir, &config.from) {
+                Ok(mailer) => Arc::new(mailer),
+                Err(e) => {
+                    error!("Could not set up the mailer: {}", e);
+                    return Err(rocket);
+                }
+            };
+
+            let transport = match config.transport() {
+                Ok(transport) => transport,
+                Err(e) => {
+                    error!("Could not set up the mail transport: {}", e);
+                    return Err(rocket);
+                }
+            };
+
+            let config = Arc::new(config);
+
+            Ok(rocket
+                .manage(mailer.clone())
+                .manage(transport.clone())
+                .attach(spawn_periodic(
+                    "Mail Dispatcher",
+                    config.dispatch_interval_secs,
+                    move |conn| {
+                        dispatch_mail(
+                            config.clone(),
+                            accounts.clone(),
+                            mailer.clone(),
+                            transport.clone(),
+                            conn,
+                        )
+                    },
+                )))
+        })
+    }
+}
+
+async fn dispatch_mail(
+    config: Arc<MailConfig>,
+    accounts: Arc<AccountConfig>,
+    mailer: Arc<Mailer>,
+    transport: MailTransportState,
+    conn: DbConn,
+) {
+    let outbox = match db::query::claim_due_mail(
+        &conn,
+        config.max_attempts,
+        config.batch_size,
+        config.lease_secs,
+    )
+    .await
+    {
+        Ok(outbox) => outbox,
+        Err(e) => {
+            warn!("Could not fetch the mail outbox: {}", e);
+            return;
+        }
+    };
+
+    for mut mail in outbox {
+        let mail_id = mail.mail_id;
+
+        if let Some(account_token_id) = mail.account_token_id {
+            match db::query::renew_mail_token(&conn, mail_id, account_token_id).await {
+                Ok(Some((purpose, token))) => {
+                    let (field, link) = match purpose {
+                        AccountTokenPurpose::PasswordReset => {
+                            ("reset_link", accounts.reset_link(&token))
+                        }
+                        AccountTokenPurpose::VerifyEmail => {
+                            ("verify_link", accounts.verify_link(&token))
+                        }
+                    };
+                    mail.context[field] = link.into();
+                }
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Could not make the link for email {}: {}", mail_id, e);
+                    continue;
+                }
+            }
+        }
+        let mailer = mailer.clone();
+        let transport = transport.clone();
+
+        // Sending can block on the network, so it's kept off the async workers
+        let sent =
+            rocket::tokio::task::spawn_blocking(move || transport.send(&mailer.render(&mail)?))
+                .await;
+
+        let error = match sent {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e.to_string()),
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(error) = &error {
+            warn!("Could not send email {}: {}", mail_id, error);
+        }
+
+        if let Err(e) =
+            db::query::record_mail_attempt(&conn, mail_id, error, config.retry_delay_secs).await
+        {
+            warn!("Could not record a mail attempt: {}", e);
+        }
+    }
+}
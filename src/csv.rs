@@ -0,0 +1,319 @@
+use std::fmt::Display;
+
+use rocket::http::Header;
+use thiserror::Error;
+
+/// A CSV document served as a download
+#[derive(Responder)]
+#[response(content_type = "text/csv")]
+pub struct CsvFile {
+    body: String,
+    disposition: Header<'static>,
+}
+
+impl CsvFile {
+    pub fn new<T: AsRef<str>>(filename: T, body: String) -> CsvFile {
+        CsvFile {
+            body,
+            disposition: Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", filename.as_ref()),
+            ),
+        }
+    }
+}
+
+/// Builds CSV text a row at a time, quoting fields as RFC 4180 requires.
+///
+/// The files are meant to be opened in spreadsheets, so text that a spreadsheet would run as a
+/// formula is written with a leading `'`, which makes it plain text instead.
+#[derive(Default)]
+pub struct CsvWriter {
+    body: String,
+}
+
+impl CsvWriter {
+    pub fn new() -> CsvWriter {
+        CsvWriter::default()
+    }
+
+    pub fn row(&mut self, fields: &[&dyn Display]) -> &mut CsvWriter {
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| {
+                let field = neutralise_formula(field.to_string());
+
+                if field.contains([',', '"', '\r', '\n']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field
+                }
+            })
+            .collect();
+
+        self.body.push_str(&fields.join(","));
+        self.body.push_str("\r\n");
+        self
+    }
+
+    pub fn finish(self) -> String {
+        self.body
+    }
+}
+
+/// Characters that make a spreadsheet treat a cell as a formula
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// Prefixes text that would be run as a formula with `'`. Numbers, like negative amounts, are
+/// left alone.
+fn neutralise_formula(field: String) -> String {
+    if field.starts_with(FORMULA_PREFIXES) && field.parse::<f64>().is_err() {
+        format!("'{field}")
+    } else {
+        field
+    }
+}
+
+/// Undoes [`CsvWriter`]'s formula prefix, so a field written by it reads back as it was
+pub fn strip_formula_prefix(field: &str) -> &str {
+    match field.strip_prefix('\'') {
+        Some(rest) if rest.starts_with(FORMULA_PREFIXES) => rest,
+        _ => field,
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CsvError {
+    #[error("Line {0} has a quoted field that is never closed")]
+    UnclosedQuote(usize),
+    #[error("Line {0} has text after a closing quote")]
+    TextAfterQuote(usize),
+}
+
+/// A row read from a CSV file, along with the line it started on (counting from 1)
+#[derive(Debug)]
+pub struct CsvRow {
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+/// Reads RFC 4180 CSV text, which may quote fields containing commas, quotes and line breaks
+///
+/// Blank lines are skipped, and either CRLF or LF line endings are accepted.
+pub fn read_csv(text: &str) -> Result<Vec<CsvRow>, CsvError> {
+    let mut rows = vec![];
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut quoted = false;
+    let mut after_quote = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => {
+                    quoted = false;
+                    after_quote = true;
+                }
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() && !after_quote => quoted = true,
+            ',' => {
+                fields.push(std::mem::take(&mut field));
+                after_quote = false;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                if fields.iter().any(|field| !field.is_empty()) {
+                    rows.push(CsvRow {
+                        line: row_line,
+                        fields: std::mem::take(&mut fields),
+                    });
+                }
+                fields.clear();
+                after_quote = false;
+                line += 1;
+                row_line = line;
+            }
+            _ if after_quote => return Err(CsvError::TextAfterQuote(line)),
+            _ => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(CsvError::UnclosedQuote(row_line));
+    }
+
+    fields.push(field);
+    if fields.iter().any(|field| !field.is_empty()) {
+        rows.push(CsvRow {
+            line: row_line,
+            fields,
+        });
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(fields: &[&dyn Display]) -> String {
+        let mut csv = CsvWriter::new();
+        csv.row(fields);
+        csv.finish()
+    }
+
+    #[test]
+    fn writes_plain_fields() {
+        assert_eq!(write(&[&"Optics", &320, &24.99]), "Optics,320,24.99\r\n");
+    }
+
+    #[test]
+    fn quotes_fields_with_separators_quotes_and_line_breaks() {
+        assert_eq!(
+            write(&[&"Smith, Jane", &"She said \"hi\"", &"two\nlines", &"cr\rlf"]),
+            "\"Smith, Jane\",\"She said \"\"hi\"\"\",\"two\nlines\",\"cr\rlf\"\r\n"
+        );
+    }
+
+    #[test]
+    fn writes_empty_rows_and_fields() {
+        assert_eq!(write(&[]), "\r\n");
+        assert_eq!(write(&[&"", &""]), ",\r\n");
+    }
+
+    #[test]
+    fn prefixes_formulas() {
+        assert_eq!(
+            write(&[&"=HYPERLINK(\"x\")", &"+A1", &"-2+3", &"@SUM(A1)"]),
+            "\"'=HYPERLINK(\"\"x\"\")\",'+A1,'-2+3,'@SUM(A1)\r\n"
+        );
+        assert_eq!(write(&[&"\t=1"]), "'\t=1\r\n");
+    }
+
+    #[test]
+    fn leaves_numbers_and_inner_formula_characters_alone() {
+        assert_eq!(
+            write(&[&"-12.50", &-3, &"a=b", &"x@y.com"]),
+            "-12.50,-3,a=b,x@y.com\r\n"
+        );
+    }
+
+    #[test]
+    fn written_fields_read_back_the_same() {
+        let originals = [
+            "=1+1",
+            "Smith, \"Jane\"",
+            "-Dash",
+            "'quoted'",
+            "multi\r\nline",
+        ];
+        let fields: Vec<&dyn Display> = originals.iter().map(|f| f as &dyn Display).collect();
+
+        let rows = read_csv(&write(&fields)).unwrap();
+        let read: Vec<&str> = rows[0]
+            .fields
+            .iter()
+            .map(|field| strip_formula_prefix(field))
+            .collect();
+        assert_eq!(read, originals);
+    }
+
+    fn fields(text: &str) -> Vec<Vec<String>> {
+        read_csv(text)
+            .unwrap()
+            .into_iter()
+            .map(|row| row.fields)
+            .collect()
+    }
+
+    #[test]
+    fn reads_plain_rows() {
+        assert_eq!(
+            fields("isbn,title\n9780306406157,Optics\n"),
+            [["isbn", "title"], ["9780306406157", "Optics"]]
+        );
+    }
+
+    #[test]
+    fn reads_quoted_fields() {
+        assert_eq!(
+            fields("\"Smith, Jane\",\"She said \"\"hi\"\"\",\"\"\n"),
+            [["Smith, Jane", "She said \"hi\"", ""]]
+        );
+    }
+
+    #[test]
+    fn keeps_line_breaks_inside_quotes() {
+        let rows = read_csv("title,note\r\n\"Two\r\nlines\",x\r\nnext,y\r\n").unwrap();
+
+        assert_eq!(rows[1].fields, ["Two\r\nlines", "x"]);
+        assert_eq!(rows[1].line, 2);
+        assert_eq!(rows[2].fields, ["next", "y"]);
+        assert_eq!(rows[2].line, 4);
+    }
+
+    #[test]
+    fn accepts_crlf_and_lf_line_endings() {
+        assert_eq!(
+            fields("a,b\r\nc,d\ne,f"),
+            [["a", "b"], ["c", "d"], ["e", "f"]]
+        );
+    }
+
+    #[test]
+    fn skips_blank_lines_but_counts_them() {
+        let rows = read_csv("a\n\n\nb\n").unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].line, 4);
+    }
+
+    #[test]
+    fn keeps_empty_fields() {
+        assert_eq!(fields(",x,\n"), [["", "x", ""]]);
+    }
+
+    #[test]
+    fn strips_a_byte_order_mark() {
+        assert_eq!(fields("\u{feff}isbn,title\n"), [["isbn", "title"]]);
+    }
+
+    #[test]
+    fn leaves_quotes_inside_unquoted_fields() {
+        assert_eq!(fields("12\" ruler,x\n"), [["12\" ruler", "x"]]);
+    }
+
+    #[test]
+    fn rejects_an_unclosed_quote() {
+        assert_eq!(
+            read_csv("a,b\n\"never\nclosed,c\n").unwrap_err(),
+            CsvError::UnclosedQuote(2)
+        );
+    }
+
+    #[test]
+    fn rejects_text_after_a_closing_quote() {
+        assert_eq!(
+            read_csv("a\n\"quoted\"tail,b\n").unwrap_err(),
+            CsvError::TextAfterQuote(2)
+        );
+    }
+}
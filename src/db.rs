@@ -1,8 +1,31 @@
 pub mod conn {
-    use rocket_sync_db_pools::{database, postgres};
+    use rocket::figment::Figment;
+    use rocket::{Build, Phase, Rocket};
+    use rocket_sync_db_pools::{database, postgres, ConnectionPool};
 
     #[database("postgres")]
     pub struct DbConn(postgres::Client);
+
+    /// Takes connections from the pool outside of requests, for background jobs and for stores
+    /// that keep their data in the database.
+    ///
+    /// Rocket only hands out connections through a `Rocket`, so this keeps an unlaunched one that
+    /// manages nothing but the pool.
+    pub struct DbPool(Rocket<Build>);
+
+    impl DbPool {
+        /// The pool `rocket` manages, once the `DbConn` fairing has set it up
+        pub async fn from_rocket<P: Phase>(rocket: &Rocket<P>) -> Option<DbPool> {
+            let pool = ConnectionPool::<DbConn, postgres::Client>::get_pool(rocket).await?;
+
+            Some(DbPool(rocket::custom(Figment::new()).manage(pool)))
+        }
+
+        /// Waits for a free connection, which goes back to the pool when it's dropped
+        pub async fn get(&self) -> Option<DbConn> {
+            DbConn::get_one(&self.0).await
+        }
+    }
 }
 
 pub mod error {
@@ -27,18 +50,268 @@ pub mod error {
         }
     }
 
+    #[derive(Debug, Error)]
+    pub enum SessionError {
+        #[error("No database connection is available")]
+        NoConnection,
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+    }
+
     #[derive(Debug, Error)]
     pub enum CartError {
-        #[error("Insufficient stock for quantity change")]
+        #[error("Not enough copies are available, some may be held in other carts")]
         NotEnoughStock,
         #[error("Internal DB error: `{0}`")]
         DBError(#[from] postgres::error::Error),
     }
 
+    #[derive(Debug, Error)]
+    pub enum CollectionError {
+        #[error("No collection with the ID ({0})")]
+        NoSuchCollection(crate::schema::entities::PostgresInt),
+        #[error("No book with the ISBN ({0})")]
+        NoSuchBook(crate::schema::entities::Isbn),
+        #[error("Collection names must be between 1 and 50 characters")]
+        InvalidName,
+        #[error("The new order must list every collection exactly once")]
+        InvalidOrder,
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum WishlistError {
+        #[error("No wishlist with the ID ({0})")]
+        NoSuchWishlist(crate::schema::entities::PostgresInt),
+        #[error("No book with the ISBN ({0})")]
+        NoSuchBook(crate::schema::entities::Isbn),
+        #[error("Wishlist names must be between 1 and 50 characters")]
+        InvalidName,
+        #[error("You already have a wishlist called `{0}`")]
+        DuplicateName(String),
+        #[error("The book ({0}) is in stock, so there's nothing to wait for")]
+        InStock(crate::schema::entities::Isbn),
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum NotificationError {
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+        #[error("Internal state error: `{0}`")]
+        StateError(#[from] StateError),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum AccountTokenError {
+        #[error("Too many links have been asked for with this email, please try again later")]
+        RateLimited,
+        #[error("This link is invalid, has expired or has already been used")]
+        InvalidToken,
+        #[error("Your email is already verified")]
+        AlreadyVerified,
+        #[error("The new password can't be empty")]
+        EmptyPassword,
+        #[error("The passwords don't match")]
+        PasswordMismatch,
+        #[error("{0}")]
+        Outbox(#[from] OutboxError),
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+        #[error("Internal bcrypt error")]
+        BCryptError(#[from] bcrypt::BcryptError),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum OutboxError {
+        #[error("Invalid mail context: `{0}`")]
+        Context(#[from] serde_json::Error),
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+        #[error("Internal state error: `{0}`")]
+        StateError(#[from] StateError),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum RoyaltyError {
+        #[error("The reporting period must start on or before the day it ends")]
+        InvalidPeriod,
+        #[error("Every shipped sale in this period has already been paid")]
+        NothingToPay,
+        #[error("No royalty payout with the ID ({0})")]
+        NoSuchPayout(crate::schema::entities::PostgresInt),
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum SettlementError {
+        #[error("The reporting period must start on or before the day it ends")]
+        InvalidPeriod,
+        #[error("No publisher with the ID ({0})")]
+        NoSuchPublisher(crate::schema::entities::PostgresInt),
+        #[error("Every priced restock order received in this period has already been remitted")]
+        NothingToRemit,
+        #[error("No remittance with the ID ({0})")]
+        NoSuchRemittance(crate::schema::entities::PostgresInt),
+        #[error("Cannot change a remittance from {0} to {1}")]
+        InvalidTransition(
+            crate::schema::entities::RemittanceStatus,
+            crate::schema::entities::RemittanceStatus,
+        ),
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+        #[error("Internal state error: `{0}`")]
+        StateError(#[from] StateError),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum MigrationError {
+        #[error("Migration {version} ({name}) failed: `{source}`")]
+        Failed {
+            version: i32,
+            name: &'static str,
+            source: postgres::error::Error,
+        },
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+    }
+
     #[derive(Debug, Error)]
     pub enum OrderError {
-        #[error("Insufficient stock for order")]
+        #[error("Not enough copies are available for the order, some may be held in other carts")]
         NotEnoughStock,
+        #[error("There are no books in the cart")]
+        EmptyCart,
+        #[error("The order conflicted with another checkout, please try again")]
+        Conflict,
+        #[error("Guests need to give an email, a shipping address and a card to check out")]
+        GuestDetailsRequired,
+        #[error("`{0}` isn't an email address")]
+        InvalidEmail(String),
+        #[error("Verify your email address before checking out")]
+        EmailNotVerified,
+        #[error("{0}")]
+        PaymentError(#[from] crate::payment::PaymentError),
+        #[error("{0}")]
+        Discount(#[from] crate::promotion::DiscountError),
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+        #[error("Internal state error: `{0}`")]
+        StateError(#[from] StateError),
+        #[error("{0}")]
+        Outbox(#[from] OutboxError),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum CancelOrderError {
+        #[error("No order with the ID ({0})")]
+        NoSuchOrder(crate::schema::entities::PostgresInt),
+        #[error("Orders that are {0} can no longer be cancelled")]
+        NotCancellable(crate::schema::entities::OrderStatus),
+        #[error("A reason is needed to cancel an order")]
+        MissingReason,
+        #[error("The cancellation reason is too long")]
+        ReasonTooLong,
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+        #[error("Internal state error: `{0}`")]
+        StateError(#[from] StateError),
+        #[error("{0}")]
+        Outbox(#[from] OutboxError),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum RestockError {
+        #[error("No restock order with the ID ({0})")]
+        NoSuchOrder(crate::schema::entities::PostgresInt),
+        #[error("Cannot change a restock order from {0} to {1}")]
+        InvalidTransition(
+            crate::schema::entities::RestockStatus,
+            crate::schema::entities::RestockStatus,
+        ),
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+        #[error("Internal state error: `{0}`")]
+        StateError(#[from] StateError),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum OrderStatusError {
+        #[error("No order with the ID ({0})")]
+        NoSuchOrder(crate::schema::entities::PostgresInt),
+        #[error("Cannot change an order from {0} to {1}")]
+        InvalidTransition(
+            crate::schema::entities::OrderStatus,
+            crate::schema::entities::OrderStatus,
+        ),
+        #[error("A reason is needed to cancel an order")]
+        MissingReason,
+        #[error("The cancellation reason is too long")]
+        ReasonTooLong,
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+        #[error("Internal state error: `{0}`")]
+        StateError(#[from] StateError),
+        #[error("{0}")]
+        Outbox(#[from] OutboxError),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum BookEditError {
+        #[error("No book with the ISBN ({0})")]
+        NoSuchBook(crate::schema::entities::Isbn),
+        #[error("No publisher with the ID ({0})")]
+        NoSuchPublisher(crate::schema::entities::PostgresInt),
+        #[error("This book was changed by someone else after you opened it, reload to see their changes")]
+        Conflict,
+        #[error("Stock can't go below the {reserved} copies held in carts (it would be {stock})")]
+        StockBelowReserved {
+            stock: crate::schema::entities::PostgresInt,
+            reserved: i64,
+        },
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum PromotionError {
+        #[error("No promotion with the ID ({0})")]
+        NoSuchPromotion(crate::schema::entities::PostgresInt),
+        #[error("There's already a promotion with the code `{0}`")]
+        DuplicateCode(String),
+        #[error("No book with the ISBN ({0})")]
+        NoSuchBook(crate::schema::entities::Isbn),
+        #[error("No publisher with the ID ({0})")]
+        NoSuchPublisher(crate::schema::entities::PostgresInt),
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+        #[error("Internal state error: `{0}`")]
+        StateError(#[from] StateError),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum PriceError {
+        #[error("No book with the ISBN ({0})")]
+        NoSuchBook(crate::schema::entities::Isbn),
+        #[error("No scheduled price change with the ID ({0})")]
+        NoSuchPriceChange(crate::schema::entities::PostgresInt),
+        #[error("Price changes can only be scheduled for the future")]
+        NotInFuture,
+        #[error("Internal DB error: `{0}`")]
+        DBError(#[from] postgres::error::Error),
+    }
+
+    #[derive(Debug, Error)]
+    pub enum ImportError {
+        #[error("No catalog import with the ID ({0})")]
+        NoSuchImport(crate::schema::entities::PostgresInt),
+        #[error("This catalog import has already been applied")]
+        AlreadyApplied,
+        #[error("{0}")]
+        InvalidFile(#[from] crate::import::ImportFileError),
         #[error("Internal DB error: `{0}`")]
         DBError(#[from] postgres::error::Error),
         #[error("Internal state error: `{0}`")]
@@ -67,6 +340,10 @@ pub mod error {
         DBError(#[from] postgres::error::Error),
         #[error("Internal bcrypt error")]
         BCryptError(#[from] bcrypt::BcryptError),
+        #[error("{0}")]
+        Outbox(#[from] OutboxError),
+        #[error("{0}")]
+        AccountToken(#[from] AccountTokenError),
     }
 
     #[derive(Debug, Error)]
@@ -78,940 +355,5467 @@ pub mod error {
     }
 }
 
-pub mod query {
+pub mod migrations {
     use super::conn::DbConn;
-    use super::error::CartError;
-    use super::error::CreateCustomerError;
-    use super::error::CreateOwnerError;
-    use super::error::CreatePublisherError;
-    use super::error::LoginError;
-    use super::error::OrderError;
-    use super::error::StateError;
-    use crate::schema;
-    use crate::schema::entities::*;
-    use crate::schema::joined::Order;
-    use crate::schema::joined::OrderNoBooks;
-    use crate::schema::no_id;
-    use crate::schema::no_id::Address;
-    use crate::schema::no_id::PaymentInfo;
-    use chrono::Local;
-    use chrono::NaiveDate;
-    use rand::RngCore;
-    use serde::Serialize;
+    use super::error::MigrationError;
+    use rocket::fairing::{AdHoc, Fairing};
+    use rocket_sync_db_pools::postgres;
 
-    pub async fn get_books(conn: &DbConn) -> Result<Vec<Book>, postgres::error::Error> {
-        let rows = conn
-            .run(|c| c.query("SELECT * FROM base.book", &[]))
-            .await?;
-        Ok(rows.iter().flat_map(|row| Book::from_row(row)).collect())
+    pub struct Migration {
+        pub version: i32,
+        pub name: &'static str,
+        pub sql: &'static str,
     }
 
-    pub async fn get_books_with_publisher_name(
-        conn: &DbConn,
-    ) -> Result<Vec<BookWithPublisherName>, postgres::error::Error> {
-        let rows = conn
-            .run(|c| c.query("
-                            SELECT
-                            isbn,
-                            title,
-                            author_name,
-                            genre,
-                            base.book.publisher_id,
-                            company_name AS publisher_name,
-                            num_pages,
-                            price,
-                            author_royalties,
-                            reorder_threshold,
-                            stock,
-                            discontinued,
-                            company_name AS publisher_name
-                            FROM base.book INNER JOIN base.publisher ON base.book.publisher_id = base.publisher.publisher_id;",
-                             &[]))
-            .await?;
-        Ok(rows
+    /// Every migration, in the order they are applied. New migrations go at the end.
+    pub const MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            name: "baseline",
+            sql: include_str!("../migrations/0001_baseline.sql"),
+        },
+        Migration {
+            version: 2,
+            name: "order_status_history",
+            sql: include_str!("../migrations/0002_order_status_history.sql"),
+        },
+        Migration {
+            version: 3,
+            name: "order_cancellation",
+            sql: include_str!("../migrations/0003_order_cancellation.sql"),
+        },
+        Migration {
+            version: 4,
+            name: "session",
+            sql: include_str!("../migrations/0004_session.sql"),
+        },
+        Migration {
+            version: 5,
+            name: "payment_tokens",
+            sql: include_str!("../migrations/0005_payment_tokens.sql"),
+        },
+        Migration {
+            version: 6,
+            name: "collection_names",
+            sql: include_str!("../migrations/0006_collection_names.sql"),
+        },
+        Migration {
+            version: 7,
+            name: "royalty_payouts",
+            sql: include_str!("../migrations/0007_royalty_payouts.sql"),
+        },
+        Migration {
+            version: 8,
+            name: "publisher_remittances",
+            sql: include_str!("../migrations/0008_publisher_remittances.sql"),
+        },
+        Migration {
+            version: 9,
+            name: "book_search",
+            sql: include_str!("../migrations/0009_book_search.sql"),
+        },
+        Migration {
+            version: 10,
+            name: "isbn13",
+            sql: include_str!("../migrations/0010_isbn13.sql"),
+        },
+        Migration {
+            version: 11,
+            name: "catalog_imports",
+            sql: include_str!("../migrations/0011_catalog_imports.sql"),
+        },
+        Migration {
+            version: 12,
+            name: "book_edits",
+            sql: include_str!("../migrations/0012_book_edits.sql"),
+        },
+        Migration {
+            version: 13,
+            name: "price_history",
+            sql: include_str!("../migrations/0013_price_history.sql"),
+        },
+        Migration {
+            version: 14,
+            name: "promotions",
+            sql: include_str!("../migrations/0014_promotions.sql"),
+        },
+        Migration {
+            version: 15,
+            name: "guest_carts",
+            sql: include_str!("../migrations/0015_guest_carts.sql"),
+        },
+        Migration {
+            version: 16,
+            name: "stock_reservations",
+            sql: include_str!("../migrations/0016_stock_reservations.sql"),
+        },
+        Migration {
+            version: 17,
+            name: "wishlists",
+            sql: include_str!("../migrations/0017_wishlists.sql"),
+        },
+        Migration {
+            version: 18,
+            name: "mail_outbox",
+            sql: include_str!("../migrations/0018_mail_outbox.sql"),
+        },
+        Migration {
+            version: 19,
+            name: "account_tokens",
+            sql: include_str!("../migrations/0019_account_tokens.sql"),
+        },
+    ];
+
+    /// Brings the database up to date, returning the versions that were applied.
+    ///
+    /// Everything runs in one transaction holding an advisory lock, so instances
+    /// starting together don't race each other and a failed migration leaves the
+    /// schema untouched.
+    pub fn run_migrations(client: &mut postgres::Client) -> Result<Vec<i32>, MigrationError> {
+        let mut transaction = client.transaction()?;
+
+        transaction.batch_execute(
+            "
+            SELECT pg_advisory_xact_lock(hashtext('look_inna_book.schema_migrations'));
+
+            CREATE TABLE IF NOT EXISTS public.schema_migrations (
+                version integer PRIMARY KEY,
+                name character varying(100) NOT NULL,
+                applied_at timestamp with time zone DEFAULT now() NOT NULL
+            );
+            ",
+        )?;
+
+        let mut applied: Vec<i32> = transaction
+            .query("SELECT version FROM public.schema_migrations;", &[])?
             .iter()
-            .flat_map(|row| BookWithPublisherName::from_row(row))
-            .collect())
-    }
+            .map(|row| row.try_get("version"))
+            .collect::<Result<_, _>>()?;
 
-    pub async fn validate_customer_login<T: AsRef<str>>(
-        conn: &DbConn,
-        email: T,
-        password: T,
-    ) -> Result<PostgresInt, LoginError> {
-        let email = email.as_ref().to_owned();
-        let password = password.as_ref().to_owned();
+        // Databases restored from a dump taken before migrations existed already have the baseline
+        let is_untracked_baseline: bool = transaction
+            .query_one(
+                "SELECT to_regclass('base.book') IS NOT NULL AS has_baseline;",
+                &[],
+            )?
+            .try_get("has_baseline")?;
 
-        let customer = conn
-            .run(move |c| {
-                c.query_opt(
-                    "SELECT customer_id, password_hash FROM base.customer WHERE email = $1",
-                    &[&email],
-                )
-            })
-            .await?;
+        if applied.is_empty() && is_untracked_baseline {
+            let baseline = &MIGRATIONS[0];
+            transaction.execute(
+                "INSERT INTO public.schema_migrations (version, name) VALUES ($1, $2);",
+                &[&baseline.version, &baseline.name],
+            )?;
+            applied.push(baseline.version);
+        }
 
-        match customer {
-            Some(customer) => {
-                let customer_id = customer.get("customer_id");
-                let password_hash = customer.get("password_hash");
+        let mut newly_applied = vec![];
 
-                if bcrypt::verify(password, password_hash)? {
-                    Ok(customer_id)
-                } else {
-                    Err(LoginError::CredentialError)?
-                }
-            }
-            None => Err(LoginError::CredentialError)?,
+        for migration in MIGRATIONS
+            .iter()
+            .filter(|migration| !applied.contains(&migration.version))
+        {
+            transaction
+                .batch_execute(migration.sql)
+                .map_err(|source| MigrationError::Failed {
+                    version: migration.version,
+                    name: migration.name,
+                    source,
+                })?;
+
+            transaction.execute(
+                "INSERT INTO public.schema_migrations (version, name) VALUES ($1, $2);",
+                &[&migration.version, &migration.name],
+            )?;
+
+            newly_applied.push(migration.version);
         }
-    }
 
-    pub async fn try_add_address(
-        conn: &DbConn,
-        address: no_id::Address,
-    ) -> Result<PostgresInt, postgres::error::Error> {
-        let no_id::Address {
-            street_address,
-            postal_code,
-            province,
-        } = address;
-        Ok(conn.run(move |c| {
-            c.query_one(
-                "INSERT INTO base.address (street_address, postal_code, province) VALUES ($1, $2, $3) RETURNING address_id",
-                &[&street_address, &postal_code, &province],
-            )
-        }).await?.get("address_id"))
-    }
+        transaction.commit()?;
 
-    #[derive(Debug, Clone, Copy, Serialize)]
-    pub struct Expiry {
-        month: u32,
-        year: u32,
+        Ok(newly_applied)
     }
 
-    impl Expiry {
-        pub fn from_str<T: AsRef<str>>(s: T) -> Option<Expiry> {
-            let mut split = s.as_ref().split('/');
-            let month = split.next()?.parse().ok()?;
-            if month < 1 || month > 12 {
-                return None;
+    /// Runs any outstanding migrations before the rocket launches. Must be attached after `DbConn::fairing()`.
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Database Migrations", |rocket| async {
+            let conn = match DbConn::get_one(&rocket).await {
+                Some(conn) => conn,
+                None => {
+                    error!("No database connection to run migrations with");
+                    return Err(rocket);
+                }
+            };
+
+            match conn.run(run_migrations).await {
+                Ok(applied) => {
+                    for version in applied {
+                        info!("Applied migration {:04}", version);
+                    }
+
+                    Ok(rocket)
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    Err(rocket)
+                }
             }
+        })
+    }
+}
 
-            let year = split.next()?.parse().ok()?;
+pub mod search {
+    use super::conn::DbConn;
+    use crate::export::ExportBatch;
+    use crate::schema::entities::{BookWithPublisherName, Isbn, PostgresInt, PostgresNumeric};
+    use postgres::types::ToSql;
+    use rocket::tokio::sync::mpsc;
+    use serde::Serialize;
 
-            if split.next().is_some() {
-                return None;
+    #[derive(Serialize, FromFormField, Default, Clone, Copy, Debug, PartialEq, Eq)]
+    #[serde(crate = "rocket::serde")]
+    pub enum BookSort {
+        /// Best match first, falling back to title order when there is no search text
+        #[field(value = "relevance")]
+        Relevance,
+        #[default]
+        #[field(value = "title")]
+        Title,
+        #[field(value = "title_desc")]
+        TitleDesc,
+        #[field(value = "author")]
+        Author,
+        #[field(value = "price")]
+        Price,
+        #[field(value = "price_desc")]
+        PriceDesc,
+        #[field(value = "pages")]
+        Pages,
+        #[field(value = "pages_desc")]
+        PagesDesc,
+    }
+
+    impl BookSort {
+        pub const ALL: [BookSort; 8] = [
+            BookSort::Relevance,
+            BookSort::Title,
+            BookSort::TitleDesc,
+            BookSort::Author,
+            BookSort::Price,
+            BookSort::PriceDesc,
+            BookSort::Pages,
+            BookSort::PagesDesc,
+        ];
+
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                BookSort::Relevance => "relevance",
+                BookSort::Title => "title",
+                BookSort::TitleDesc => "title_desc",
+                BookSort::Author => "author",
+                BookSort::Price => "price",
+                BookSort::PriceDesc => "price_desc",
+                BookSort::Pages => "pages",
+                BookSort::PagesDesc => "pages_desc",
             }
+        }
 
-            Some(Expiry { month, year })
+        pub fn label(&self) -> &'static str {
+            match self {
+                BookSort::Relevance => "Best Match",
+                BookSort::Title => "Title (A-Z)",
+                BookSort::TitleDesc => "Title (Z-A)",
+                BookSort::Author => "Author",
+                BookSort::Price => "Price (Low to High)",
+                BookSort::PriceDesc => "Price (High to Low)",
+                BookSort::Pages => "Length (Shortest)",
+                BookSort::PagesDesc => "Length (Longest)",
+            }
         }
-    }
 
-    impl ToString for Expiry {
-        fn to_string(&self) -> String {
-            format!("{}/{}", self.month, self.year)
+        /// The ISBN tiebreaker keeps the order stable, so no book shows up on two pages
+        fn order_by(&self) -> &'static str {
+            match self {
+                BookSort::Relevance | BookSort::Title => "title ASC, isbn ASC",
+                BookSort::TitleDesc => "title DESC, isbn ASC",
+                BookSort::Author => "author_name ASC, title ASC, isbn ASC",
+                BookSort::Price => "price ASC, isbn ASC",
+                BookSort::PriceDesc => "price DESC, isbn ASC",
+                BookSort::Pages => "num_pages ASC, isbn ASC",
+                BookSort::PagesDesc => "num_pages DESC, isbn ASC",
+            }
         }
     }
 
-    pub async fn try_add_payment_info(
-        conn: &DbConn,
-        payment_info: no_id::PaymentInfo,
-    ) -> Result<PostgresInt, postgres::error::Error> {
-        let no_id::PaymentInfo {
-            name_on_card,
-            expiry,
-            card_number,
-            cvv,
-            billing_address,
-        } = payment_info;
+    /// The book's own indexed `search_vector`, plus its publisher's name at the lowest weight
+    const SEARCH_VECTOR: &str =
+        "(search_vector || setweight(to_tsvector('english', coalesce(company_name, '')), 'D'))";
 
-        let billing_address_id = try_add_address(conn, billing_address).await?;
-        Ok(conn.run(move |c| {
-            c.query_one(
-                "INSERT INTO base.payment_info (name_on_card, expiry, card_number, cvv, billing_address_id) VALUES ($1, $2, $3, $4, $5) RETURNING payment_info_id",
-                &[&name_on_card, &expiry.to_string(), &card_number, &cvv, &billing_address_id],
-            )
-        }).await?.get("payment_info_id"))
-    }
+    /// How alike a search word and part of a title (or name) have to be to count as a typo.
+    ///
+    /// Lower than pg_trgm's default of 0.6, which misses one-letter typos in short words.
+    const WORD_SIMILARITY_THRESHOLD: &str = "0.4";
 
-    pub async fn try_create_new_customer<'a, T: AsRef<str>>(
-        conn: &DbConn,
-        email: T,
-        password: T,
-        name: T,
-        address: no_id::Address,
-        payment_info: no_id::PaymentInfo,
-    ) -> Result<PostgresInt, CreateCustomerError> {
-        let name = name.as_ref().to_string();
-        let email = email.as_ref().to_string();
-        let password = password.as_ref().to_string();
+    /// `ts_headline` wraps matches in these, which can't turn up in a title or name
+    const HIGHLIGHT_START: char = '\u{2}';
+    const HIGHLIGHT_STOP: char = '\u{3}';
 
-        let address_id = try_add_address(conn, address).await?;
-        let payment_info_id = try_add_payment_info(conn, payment_info).await?;
+    type Param = Box<dyn ToSql + Sync + Send>;
 
-        let password_hash = bcrypt::hash(password, 10)?;
+    /// Every column of a `BookWithPublisherName`, for a query joining books to publishers
+    const BOOK_COLUMNS: &str = "isbn, title, author_name, genre, base.book.publisher_id, company_name AS publisher_name, num_pages, price, author_royalties, reorder_threshold, stock, discontinued";
 
-        Ok(conn
-            .run(move |c| {
-                c.query_one(
-                    "INSERT INTO base.customer (name, email, password_hash, default_shipping_address, default_payment_info_id) VALUES ($1, $2, $3, $4, $5) RETURNING customer_id;",
-                    &[&name, &email, &password_hash, &address_id, &payment_info_id],
+    /// How many books an export reads from the database at a time
+    const EXPORT_BATCH_SIZE: i32 = 500;
+
+    /// Builds up a query over books (with their publisher's name), one filter at a time
+    ///
+    /// Every filter is ANDed together, and values are always passed as query parameters.
+    /// Discontinued and out of stock books are left out unless asked for.
+    pub struct BookQuery {
+        conditions: Vec<String>,
+        params: Vec<Param>,
+        /// The parameter number and words of the free text search, if there is one
+        text: Option<(usize, Vec<String>)>,
+        include_discontinued: bool,
+        include_no_stock: bool,
+        sort: BookSort,
+        limit: Option<i64>,
+        offset: i64,
+    }
+
+    impl BookQuery {
+        pub fn new() -> BookQuery {
+            BookQuery {
+                conditions: vec![],
+                params: vec![],
+                text: None,
+                include_discontinued: false,
+                include_no_stock: false,
+                sort: BookSort::default(),
+                limit: None,
+                offset: 0,
+            }
+        }
+
+        /// Adds `condition`, where every `$` stands in for the parameter holding `value`
+        fn filter<T: ToSql + Sync + Send + 'static>(
+            mut self,
+            condition: &str,
+            value: T,
+        ) -> BookQuery {
+            self.params.push(Box::new(value));
+            self.conditions
+                .push(condition.replace('$', &format!("${}", self.params.len())));
+            self
+        }
+
+        /// Matches `column` containing `value`, or something close to it, ignoring case
+        fn fuzzy_filter(self, column: &str, value: &str) -> BookQuery {
+            self.filter(
+                &format!("(strpos(lower({column}), lower($)) > 0 OR $ <% {column})"),
+                value.to_string(),
+            )
+        }
+
+        pub fn isbn(self, isbn: Isbn) -> BookQuery {
+            self.filter("isbn = $", isbn)
+        }
+
+        /// Searches titles, authors, genres and publishers, tolerating typos
+        ///
+        /// Results are ranked by how well they match when sorting by relevance.
+        pub fn text(self, text: &str) -> BookQuery {
+            let words = text
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .map(str::to_lowercase)
+                .collect();
+
+            let mut query = self.filter(
+                &format!(
+                    "({SEARCH_VECTOR} @@ websearch_to_tsquery('english', $) OR $ <% title OR $ <% author_name OR $ <% company_name)"
+                ),
+                text.to_string(),
+            );
+            query.text = Some((query.params.len(), words));
+            query
+        }
+
+        pub fn title(self, title: &str) -> BookQuery {
+            self.fuzzy_filter("title", title)
+        }
+
+        pub fn genre(self, genre: &str) -> BookQuery {
+            self.filter("genre = $", genre.to_string())
+        }
+
+        pub fn author(self, author: &str) -> BookQuery {
+            self.fuzzy_filter("author_name", author)
+        }
+
+        pub fn publisher(self, publisher: &str) -> BookQuery {
+            self.fuzzy_filter("company_name", publisher)
+        }
+
+        pub fn min_pages(self, min_pages: PostgresInt) -> BookQuery {
+            self.filter("num_pages >= $", min_pages)
+        }
+
+        pub fn max_pages(self, max_pages: PostgresInt) -> BookQuery {
+            self.filter("num_pages <= $", max_pages)
+        }
+
+        pub fn min_price(self, min_price: PostgresNumeric) -> BookQuery {
+            self.filter("price >= $", min_price)
+        }
+
+        pub fn max_price(self, max_price: PostgresNumeric) -> BookQuery {
+            self.filter("price <= $", max_price)
+        }
+
+        pub fn include_discontinued(mut self, include: bool) -> BookQuery {
+            self.include_discontinued = include;
+            self
+        }
+
+        pub fn include_no_stock(mut self, include: bool) -> BookQuery {
+            self.include_no_stock = include;
+            self
+        }
+
+        pub fn sort(mut self, sort: BookSort) -> BookQuery {
+            self.sort = sort;
+            self
+        }
+
+        /// Only fetches the given page, counting pages from 1
+        pub fn page(mut self, page: u32, per_page: u32) -> BookQuery {
+            let per_page = per_page.max(1) as i64;
+            self.limit = Some(per_page);
+            self.offset = (page.max(1) as i64 - 1) * per_page;
+            self
+        }
+
+        fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+            self.params
+                .iter()
+                .map(|param| param.as_ref() as &(dyn ToSql + Sync))
+                .collect()
+        }
+
+        fn joined_from(&self) -> String {
+            format!(
+                "FROM base.book INNER JOIN base.publisher ON base.book.publisher_id = base.publisher.publisher_id {}",
+                self.where_clause()
+            )
+        }
+
+        fn where_clause(&self) -> String {
+            let mut conditions = self.conditions.clone();
+            if !self.include_discontinued {
+                conditions.push("NOT discontinued".to_string());
+            }
+            if !self.include_no_stock {
+                conditions.push("stock <> 0".to_string());
+            }
+
+            if conditions.is_empty() {
+                String::new()
+            } else {
+                format!("WHERE {}", conditions.join(" AND "))
+            }
+        }
+
+        fn order_by(&self) -> String {
+            match (self.sort, &self.text) {
+                (BookSort::Relevance, Some((param, _))) => format!(
+                    "ts_rank({SEARCH_VECTOR}, websearch_to_tsquery('english', ${param}))
+                    + greatest(word_similarity(${param}, title), word_similarity(${param}, author_name), word_similarity(${param}, company_name)) DESC,
+                    title ASC, isbn ASC"
+                ),
+                (sort, _) => sort.order_by().to_string(),
+            }
+        }
+
+        /// Selects `column` with the words matching the search text marked, as `<column>_highlight`
+        fn highlight(&self, column: &str) -> String {
+            match &self.text {
+                Some((param, _)) => format!(
+                    "ts_headline('english', coalesce({column}, ''), websearch_to_tsquery('english', ${param}), E'StartSel=\\x02, StopSel=\\x03, HighlightAll=true') AS {column}_highlight"
+                ),
+                None => format!("coalesce({column}, '') AS {column}_highlight"),
+            }
+        }
+    }
+
+    impl Default for BookQuery {
+        fn default() -> BookQuery {
+            BookQuery::new()
+        }
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct HighlightPart {
+        pub text: String,
+        pub matched: bool,
+    }
+
+    /// Splits a `ts_headline` into the parts that did and didn't match
+    fn highlight_parts(headline: &str) -> Vec<HighlightPart> {
+        let mut parts = vec![];
+
+        for (i, piece) in headline.split(HIGHLIGHT_START).enumerate() {
+            let (matched, rest) = match piece.split_once(HIGHLIGHT_STOP) {
+                Some((matched, rest)) if i > 0 => (matched, rest),
+                _ => ("", piece),
+            };
+
+            if !matched.is_empty() {
+                parts.push(HighlightPart {
+                    text: matched.to_string(),
+                    matched: true,
+                });
+            }
+            if !rest.is_empty() {
+                parts.push(HighlightPart {
+                    text: rest.to_string(),
+                    matched: false,
+                });
+            }
+        }
+
+        parts
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct SearchHit {
+        #[serde(flatten)]
+        pub book: BookWithPublisherName,
+        pub title_highlight: Vec<HighlightPart>,
+        pub author_highlight: Vec<HighlightPart>,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct BookSearchResults {
+        pub books: Vec<SearchHit>,
+        /// How many books matched, across every page
+        pub total_books: i64,
+        /// The search text respelled with the closest words in the catalog, if any were off
+        pub suggestion: Option<String>,
+    }
+
+    /// Finds the closest word in the catalog to each of `words`, or `None` if they're all in it
+    fn suggest_spelling(
+        c: &mut impl postgres::GenericClient,
+        words: &[String],
+    ) -> Result<Option<String>, postgres::error::Error> {
+        let rows = c.query(
+            "
+            WITH catalog_words AS (
+                SELECT DISTINCT word FROM (
+                    SELECT regexp_split_to_table(lower(concat_ws(' ', title, author_name, genre)), '[^[:alnum:]]+') AS word
+                    FROM base.book
+                    UNION ALL
+                    SELECT regexp_split_to_table(lower(company_name), '[^[:alnum:]]+')
+                    FROM base.publisher
+                ) words
+                WHERE word <> ''
+            )
+            SELECT coalesce(
+                (SELECT word FROM catalog_words
+                 WHERE catalog_words.word % typed.word
+                 ORDER BY similarity(catalog_words.word, typed.word) DESC, catalog_words.word
+                 LIMIT 1),
+                typed.word
+            ) AS suggestion
+            FROM unnest($1::text[]) WITH ORDINALITY AS typed(word, n)
+            ORDER BY n",
+            &[&words],
+        )?;
+        let suggestion = rows
+            .iter()
+            .map(|row| row.try_get("suggestion"))
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(if suggestion == words {
+            None
+        } else {
+            Some(suggestion.join(" "))
+        })
+    }
+
+    #[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum SuggestionKind {
+        Title,
+        Author,
+        Publisher,
+        Genre,
+        Isbn,
+    }
+
+    impl SuggestionKind {
+        fn from_str(s: &str) -> Option<SuggestionKind> {
+            match s {
+                "title" => Some(SuggestionKind::Title),
+                "author" => Some(SuggestionKind::Author),
+                "publisher" => Some(SuggestionKind::Publisher),
+                "genre" => Some(SuggestionKind::Genre),
+                "isbn" => Some(SuggestionKind::Isbn),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct Suggestion {
+        pub kind: SuggestionKind,
+        pub value: String,
+        /// How many books in the storefront have this title, author, etc.
+        pub books: i64,
+    }
+
+    /// Suggests titles, authors, publishers, genres and ISBNs for what's been typed so far
+    ///
+    /// Values starting with `prefix` come first, then close matches for typos. Only books
+    /// shown in the storefront by default (not discontinued, in stock) are counted.
+    pub async fn suggest_books(
+        conn: &DbConn,
+        prefix: String,
+        limit: i64,
+    ) -> Result<Vec<Suggestion>, postgres::error::Error> {
+        conn.run(move |c| {
+            let mut t = c.transaction()?;
+            t.batch_execute(&format!(
+                "SET LOCAL pg_trgm.word_similarity_threshold = {WORD_SIMILARITY_THRESHOLD}"
+            ))?;
+
+            let candidates = [
+                ("title", "title", true),
+                ("author", "author_name", true),
+                ("publisher", "company_name", true),
+                ("genre", "genre", false),
+                ("isbn", "isbn::text", false),
+            ]
+            .iter()
+            .map(|(kind, column, fuzzy)| {
+                format!(
+                    "SELECT '{0}' AS kind, {1} AS value
+                    FROM base.book INNER JOIN base.publisher ON base.book.publisher_id = base.publisher.publisher_id
+                    WHERE NOT discontinued AND stock <> 0
+                    AND (starts_with(lower({1}), lower($1)){2})",
+                    kind,
+                    column,
+                    if *fuzzy {
+                        format!(" OR $1 <% {column}")
+                    } else {
+                        String::new()
+                    }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" UNION ALL ");
+
+            let rows = t.query(
+                format!(
+                    "SELECT kind, value, count(*) AS books
+                    FROM ({candidates}) candidates
+                    WHERE value IS NOT NULL
+                    GROUP BY kind, value
+                    ORDER BY bool_or(starts_with(lower(value), lower($1))) DESC,
+                    max(word_similarity($1, value)) DESC, books DESC, value
+                    LIMIT $2"
+                )
+                .as_str(),
+                &[&prefix, &limit],
+            )?;
+            t.commit()?;
+
+            Ok(rows
+                .iter()
+                .filter_map(|row| {
+                    Some(Suggestion {
+                        kind: SuggestionKind::from_str(row.try_get("kind").ok()?)?,
+                        value: row.try_get("value").ok()?,
+                        books: row.try_get("books").ok()?,
+                    })
+                })
+                .collect())
+        })
+        .await
+    }
+
+    pub async fn search_books(
+        conn: &DbConn,
+        query: BookQuery,
+    ) -> Result<BookSearchResults, postgres::error::Error> {
+        conn.run(move |c| {
+            let mut t = c.transaction()?;
+            t.batch_execute(&format!(
+                "SET LOCAL pg_trgm.word_similarity_threshold = {WORD_SIMILARITY_THRESHOLD}"
+            ))?;
+
+            let from = query.joined_from();
+            let params = query.params();
+
+            let total_books: i64 = t
+                .query_one(format!("SELECT count(*) {from}").as_str(), &params)?
+                .try_get(0)?;
+
+            let limit = match query.limit {
+                Some(limit) => format!("LIMIT {} OFFSET {}", limit, query.offset),
+                None => String::new(),
+            };
+            let rows = t.query(
+                format!(
+                    "SELECT {}, {}, {} {} ORDER BY {} {}",
+                    BOOK_COLUMNS,
+                    query.highlight("title"),
+                    query.highlight("author_name"),
+                    from,
+                    query.order_by(),
+                    limit
+                )
+                .as_str(),
+                &params,
+            )?;
+
+            let books = rows
+                .iter()
+                .map(|row| {
+                    Ok(SearchHit {
+                        book: BookWithPublisherName::from_row(row)?,
+                        title_highlight: highlight_parts(row.try_get("title_highlight")?),
+                        author_highlight: highlight_parts(row.try_get("author_name_highlight")?),
+                    })
+                })
+                .collect::<Result<_, postgres::error::Error>>()?;
+
+            let suggestion = match &query.text {
+                Some((_, words)) if !words.is_empty() => suggest_spelling(&mut t, words)?,
+                _ => None,
+            };
+
+            t.commit()?;
+
+            Ok(BookSearchResults {
+                books,
+                total_books,
+                suggestion,
+            })
+        })
+        .await
+    }
+
+    /// Sends every book matching `query` to `batches` a few hundred at a time, reading them
+    /// through a cursor so the whole catalog is never held in memory at once
+    ///
+    /// Stops early, without an error, if `batches` is closed.
+    pub async fn export_books(
+        conn: &DbConn,
+        query: BookQuery,
+        batches: mpsc::Sender<ExportBatch>,
+    ) -> Result<(), postgres::error::Error> {
+        conn.run(move |c| {
+            let mut t = c.transaction()?;
+            t.batch_execute(&format!(
+                "SET LOCAL pg_trgm.word_similarity_threshold = {WORD_SIMILARITY_THRESHOLD}"
+            ))?;
+
+            let cursor = t.bind(
+                format!(
+                    "SELECT {} {} ORDER BY {}",
+                    BOOK_COLUMNS,
+                    query.joined_from(),
+                    query.order_by()
+                )
+                .as_str(),
+                &query.params(),
+            )?;
+
+            loop {
+                let books = t
+                    .query_portal(&cursor, EXPORT_BATCH_SIZE)?
+                    .iter()
+                    .map(BookWithPublisherName::from_row)
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if books.is_empty() || batches.blocking_send(Ok(books)).is_err() {
+                    break;
+                }
+            }
+
+            t.commit()
+        })
+        .await
+    }
+}
+
+pub mod query {
+    use super::conn::DbConn;
+    use super::error::AccountTokenError;
+    use super::error::BookEditError;
+    use super::error::CancelOrderError;
+    use super::error::CartError;
+    use super::error::CollectionError;
+    use super::error::CreateCustomerError;
+    use super::error::CreateOwnerError;
+    use super::error::CreatePublisherError;
+    use super::error::ImportError;
+    use super::error::LoginError;
+    use super::error::NotificationError;
+    use super::error::OrderError;
+    use super::error::OrderStatusError;
+    use super::error::OutboxError;
+    use super::error::PriceError;
+    use super::error::PromotionError;
+    use super::error::RestockError;
+    use super::error::RoyaltyError;
+    use super::error::SettlementError;
+    use super::error::StateError;
+    use super::error::WishlistError;
+    use crate::config::{AccountConfig, RestockPolicy};
+    use crate::import::{
+        plan_import, read_catalog, ImportFormat, ImportPlan, PlannedBook, RejectedRecord,
+    };
+    use crate::mail::{MailTemplate, OutgoingMail};
+    use crate::notify::{Notification, NotificationKind};
+    use crate::payment::{PaymentProvider, TokenisedCard};
+    use crate::promotion::{
+        normalise_code, DiscountError, Promotion, PromotionLine, PromotionScope, PromotionTerms,
+    };
+    use crate::request_guards::state::{ExpirationTime, SessionType};
+    use crate::request_guards::CartOwner;
+    use crate::schema;
+    use crate::schema::entities::*;
+    use crate::schema::joined::BookEdit;
+    use crate::schema::joined::BookPrice;
+    use crate::schema::joined::CartReservation;
+    use crate::schema::joined::CatalogImport;
+    use crate::schema::joined::CollectionWithBooks;
+    use crate::schema::joined::FulfilmentOrder;
+    use crate::schema::joined::Order;
+    use crate::schema::joined::OrderNoBooks;
+    use crate::schema::joined::OrderStatusChange;
+    use crate::schema::joined::RestockOrder;
+    use crate::schema::joined::StockLevel;
+    use crate::schema::joined::WishlistWithBooks;
+    use crate::schema::joined::{AuthorRoyalties, BookRoyalty, RoyaltyPayout, RoyaltyPayoutLine};
+    use crate::schema::joined::{PromotionQuote, PromotionSales, PromotionSummary};
+    use crate::schema::joined::{PublisherBookSales, PublisherRemittance, PublisherSettlement};
+    use crate::schema::no_id;
+    use crate::schema::no_id::Address;
+    use crate::schema::no_id::PaymentInfo;
+    use crate::PaymentProviderState;
+    use chrono::DateTime;
+    use chrono::Local;
+    use chrono::NaiveDate;
+    use postgres::error::SqlState;
+    use postgres::types::ToSql;
+    use postgres::GenericClient;
+    use rand::RngCore;
+    use serde::Serialize;
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+
+    pub async fn get_books(conn: &DbConn) -> Result<Vec<Book>, postgres::error::Error> {
+        let rows = conn
+            .run(|c| c.query("SELECT * FROM base.book", &[]))
+            .await?;
+        Ok(rows.iter().flat_map(|row| Book::from_row(row)).collect())
+    }
+
+    /// Every genre in the catalog, for the search form's dropdown
+    pub async fn get_genres(conn: &DbConn) -> Result<Vec<String>, postgres::error::Error> {
+        let rows = conn
+            .run(|c| c.query("SELECT DISTINCT genre FROM base.book ORDER BY genre", &[]))
+            .await?;
+        rows.iter().map(|row| row.try_get("genre")).collect()
+    }
+
+    /// Groups rows of collections left joined with their books, keeping the row order
+    fn collections_from_rows(
+        rows: &[postgres::Row],
+    ) -> Result<Vec<CollectionWithBooks>, postgres::error::Error> {
+        let mut collections: Vec<CollectionWithBooks> = vec![];
+
+        for row in rows {
+            let collection = BookCollection::from_row(row)?;
+
+            if collections.last().map(|last| last.collection.collection_id)
+                != Some(collection.collection_id)
+            {
+                collections.push(CollectionWithBooks {
+                    collection,
+                    books: vec![],
+                });
+            }
+
+            if row.try_get::<_, Option<Isbn>>("isbn")?.is_some() {
+                if let Some(last) = collections.last_mut() {
+                    last.books.push(Book::from_row(row)?);
+                }
+            }
+        }
+
+        Ok(collections)
+    }
+
+    /// Every collection in shelf order. Discontinued books are left out unless asked for.
+    pub async fn get_collections(
+        conn: &DbConn,
+        include_discontinued: bool,
+    ) -> Result<Vec<CollectionWithBooks>, postgres::error::Error> {
+        let rows = conn
+            .run(move |c| {
+                c.query(
+                    "
+                    SELECT collection.collection_id, collection.name, collection.curator_owner_id, collection.sort_order, book.*
+                    FROM base.book_collection AS collection
+                    LEFT JOIN base.in_collection AS in_collection ON collection.collection_id = in_collection.collection_id
+                    LEFT JOIN base.book AS book ON in_collection.isbn = book.isbn AND ($1 OR NOT book.discontinued)
+                    ORDER BY collection.sort_order, collection.collection_id, book.title;
+                    ",
+                    &[&include_discontinued],
+                )
+            })
+            .await?;
+
+        collections_from_rows(&rows)
+    }
+
+    pub async fn get_collection(
+        conn: &DbConn,
+        collection_id: PostgresInt,
+        include_discontinued: bool,
+    ) -> Result<CollectionWithBooks, CollectionError> {
+        let rows = conn
+            .run(move |c| {
+                c.query(
+                    "
+                    SELECT collection.collection_id, collection.name, collection.curator_owner_id, collection.sort_order, book.*
+                    FROM base.book_collection AS collection
+                    LEFT JOIN base.in_collection AS in_collection ON collection.collection_id = in_collection.collection_id
+                    LEFT JOIN base.book AS book ON in_collection.isbn = book.isbn AND ($2 OR NOT book.discontinued)
+                    WHERE collection.collection_id = $1
+                    ORDER BY book.title;
+                    ",
+                    &[&collection_id, &include_discontinued],
+                )
+            })
+            .await?;
+
+        collections_from_rows(&rows)?
+            .pop()
+            .ok_or(CollectionError::NoSuchCollection(collection_id))
+    }
+
+    fn validate_collection_name(name: String) -> Result<String, CollectionError> {
+        let name = name.trim().to_string();
+
+        if name.is_empty() || name.chars().count() > 50 {
+            Err(CollectionError::InvalidName)
+        } else {
+            Ok(name)
+        }
+    }
+
+    /// Creates an empty collection at the end of the shelves
+    pub async fn create_collection(
+        conn: &DbConn,
+        name: String,
+        curator_owner_id: Option<PostgresInt>,
+    ) -> Result<PostgresInt, CollectionError> {
+        let name = validate_collection_name(name)?;
+
+        Ok(conn
+            .run(move |c| {
+                c.query_one(
+                    "
+                    INSERT INTO base.book_collection (name, curator_owner_id, sort_order)
+                    SELECT $1, $2, coalesce(max(sort_order) + 1, 0) FROM base.book_collection
+                    RETURNING collection_id;
+                    ",
+                    &[&name, &curator_owner_id],
+                )
+            })
+            .await?
+            .try_get("collection_id")?)
+    }
+
+    pub async fn rename_collection(
+        conn: &DbConn,
+        collection_id: PostgresInt,
+        name: String,
+    ) -> Result<(), CollectionError> {
+        let name = validate_collection_name(name)?;
+
+        let updated = conn
+            .run(move |c| {
+                c.execute(
+                    "UPDATE base.book_collection SET name = $1 WHERE collection_id = $2;",
+                    &[&name, &collection_id],
+                )
+            })
+            .await?;
+
+        if updated == 0 {
+            Err(CollectionError::NoSuchCollection(collection_id))?;
+        }
+
+        Ok(())
+    }
+
+    /// Puts the shelves in the given order, which must name every collection once
+    pub async fn reorder_collections(
+        conn: &DbConn,
+        collection_ids: Vec<PostgresInt>,
+    ) -> Result<(), CollectionError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let mut existing: Vec<PostgresInt> = transaction
+                .query(
+                    "SELECT collection_id FROM base.book_collection FOR UPDATE;",
+                    &[],
+                )?
+                .iter()
+                .map(|row| row.try_get("collection_id"))
+                .collect::<Result<_, _>>()?;
+            existing.sort_unstable();
+
+            let mut requested = collection_ids.clone();
+            requested.sort_unstable();
+
+            if existing != requested {
+                Err(CollectionError::InvalidOrder)?;
+            }
+
+            for (sort_order, collection_id) in collection_ids.iter().enumerate() {
+                transaction.execute(
+                    "UPDATE base.book_collection SET sort_order = $1 WHERE collection_id = $2;",
+                    &[&(sort_order as PostgresInt), collection_id],
+                )?;
+            }
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn delete_collection(
+        conn: &DbConn,
+        collection_id: PostgresInt,
+    ) -> Result<(), CollectionError> {
+        let deleted = conn
+            .run(move |c| {
+                c.execute(
+                    "DELETE FROM base.book_collection WHERE collection_id = $1;",
+                    &[&collection_id],
+                )
+            })
+            .await?;
+
+        if deleted == 0 {
+            Err(CollectionError::NoSuchCollection(collection_id))?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn add_book_to_collection(
+        conn: &DbConn,
+        collection_id: PostgresInt,
+        isbn: Isbn,
+    ) -> Result<(), CollectionError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            transaction
+                .query_opt(
+                    "SELECT collection_id FROM base.book_collection WHERE collection_id = $1 FOR UPDATE;",
+                    &[&collection_id],
+                )?
+                .ok_or(CollectionError::NoSuchCollection(collection_id))?;
+
+            transaction
+                .query_opt("SELECT isbn FROM base.book WHERE isbn = $1;", &[&isbn])?
+                .ok_or(CollectionError::NoSuchBook(isbn))?;
+
+            transaction.execute(
+                "INSERT INTO base.in_collection (collection_id, isbn) VALUES ($1, $2) ON CONFLICT DO NOTHING;",
+                &[&collection_id, &isbn],
+            )?;
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn remove_book_from_collection(
+        conn: &DbConn,
+        collection_id: PostgresInt,
+        isbn: Isbn,
+    ) -> Result<(), CollectionError> {
+        conn.run(move |c| {
+            c.execute(
+                "DELETE FROM base.in_collection WHERE collection_id = $1 AND isbn = $2;",
+                &[&collection_id, &isbn],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Groups rows of wishlists left joined with their books, keeping the row order
+    fn wishlists_from_rows(
+        rows: &[postgres::Row],
+    ) -> Result<Vec<WishlistWithBooks>, postgres::error::Error> {
+        let mut wishlists: Vec<WishlistWithBooks> = vec![];
+
+        for row in rows {
+            let wishlist = Wishlist::from_row(row)?;
+
+            if wishlists.last().map(|last| last.wishlist.wishlist_id) != Some(wishlist.wishlist_id)
+            {
+                wishlists.push(WishlistWithBooks {
+                    wishlist,
+                    books: vec![],
+                });
+            }
+
+            if row.try_get::<_, Option<Isbn>>("isbn")?.is_some() {
+                if let Some(last) = wishlists.last_mut() {
+                    last.books.push(Book::from_row(row)?);
+                }
+            }
+        }
+
+        Ok(wishlists)
+    }
+
+    /// The customer's wishlists, oldest first
+    pub async fn get_wishlists(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+    ) -> Result<Vec<WishlistWithBooks>, postgres::error::Error> {
+        let rows = conn
+            .run(move |c| {
+                c.query(
+                    "
+                    SELECT wishlist.wishlist_id, wishlist.customer_id, wishlist.name, wishlist.share_token, book.*
+                    FROM base.wishlist AS wishlist
+                    LEFT JOIN base.in_wishlist AS in_wishlist ON wishlist.wishlist_id = in_wishlist.wishlist_id
+                    LEFT JOIN base.book AS book ON in_wishlist.isbn = book.isbn
+                    WHERE wishlist.customer_id = $1
+                    ORDER BY wishlist.created_at, wishlist.wishlist_id, book.title;
+                    ",
+                    &[&customer_id],
+                )
+            })
+            .await?;
+
+        wishlists_from_rows(&rows)
+    }
+
+    /// The wishlist with the share token, along with the name of the customer it belongs to
+    pub async fn get_shared_wishlist(
+        conn: &DbConn,
+        share_token: String,
+    ) -> Result<Option<(WishlistWithBooks, String)>, postgres::error::Error> {
+        let rows = conn
+            .run(move |c| {
+                c.query(
+                    "
+                    SELECT wishlist.wishlist_id, wishlist.customer_id, wishlist.name, wishlist.share_token, customer.name AS customer_name, book.*
+                    FROM base.wishlist AS wishlist
+                    INNER JOIN base.customer AS customer ON wishlist.customer_id = customer.customer_id
+                    LEFT JOIN base.in_wishlist AS in_wishlist ON wishlist.wishlist_id = in_wishlist.wishlist_id
+                    LEFT JOIN base.book AS book ON in_wishlist.isbn = book.isbn
+                    WHERE wishlist.share_token = $1
+                    ORDER BY book.title;
+                    ",
+                    &[&share_token],
+                )
+            })
+            .await?;
+
+        let customer_name: Option<String> = match rows.first() {
+            Some(row) => Some(row.try_get("customer_name")?),
+            None => None,
+        };
+
+        Ok(wishlists_from_rows(&rows)?.pop().zip(customer_name))
+    }
+
+    fn validate_wishlist_name(name: String) -> Result<String, WishlistError> {
+        let name = name.trim().to_string();
+
+        if name.is_empty() || name.chars().count() > 50 {
+            Err(WishlistError::InvalidName)
+        } else {
+            Ok(name)
+        }
+    }
+
+    /// Turns a unique violation on the customer's wishlist names into a `DuplicateName`
+    fn wishlist_name_error(e: postgres::error::Error, name: &str) -> WishlistError {
+        if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+            WishlistError::DuplicateName(name.to_string())
+        } else {
+            WishlistError::DBError(e)
+        }
+    }
+
+    fn create_share_token() -> String {
+        let mut token = [0u8; 24];
+        rand::thread_rng().fill_bytes(&mut token);
+
+        base64::encode_config(token, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub async fn create_wishlist(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+        name: String,
+    ) -> Result<PostgresInt, WishlistError> {
+        let name = validate_wishlist_name(name)?;
+        let share_token = create_share_token();
+
+        conn.run(move |c| {
+            c.query_one(
+                "
+                INSERT INTO base.wishlist (customer_id, name, share_token) VALUES ($1, $2, $3)
+                RETURNING wishlist_id;
+                ",
+                &[&customer_id, &name, &share_token],
+            )
+            .map_err(|e| wishlist_name_error(e, &name))?
+            .try_get("wishlist_id")
+            .map_err(WishlistError::from)
+        })
+        .await
+    }
+
+    pub async fn rename_wishlist(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+        wishlist_id: PostgresInt,
+        name: String,
+    ) -> Result<(), WishlistError> {
+        let name = validate_wishlist_name(name)?;
+
+        let updated = conn
+            .run(move |c| {
+                c.execute(
+                    "UPDATE base.wishlist SET name = $1 WHERE wishlist_id = $2 AND customer_id = $3;",
+                    &[&name, &wishlist_id, &customer_id],
+                )
+                .map_err(|e| wishlist_name_error(e, &name))
+            })
+            .await?;
+
+        if updated == 0 {
+            Err(WishlistError::NoSuchWishlist(wishlist_id))?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete_wishlist(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+        wishlist_id: PostgresInt,
+    ) -> Result<(), WishlistError> {
+        let deleted = conn
+            .run(move |c| {
+                c.execute(
+                    "DELETE FROM base.wishlist WHERE wishlist_id = $1 AND customer_id = $2;",
+                    &[&wishlist_id, &customer_id],
+                )
+            })
+            .await?;
+
+        if deleted == 0 {
+            Err(WishlistError::NoSuchWishlist(wishlist_id))?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn add_book_to_wishlist(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+        wishlist_id: PostgresInt,
+        isbn: Isbn,
+    ) -> Result<(), WishlistError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            transaction
+                .query_opt(
+                    "SELECT wishlist_id FROM base.wishlist WHERE wishlist_id = $1 AND customer_id = $2 FOR UPDATE;",
+                    &[&wishlist_id, &customer_id],
+                )?
+                .ok_or(WishlistError::NoSuchWishlist(wishlist_id))?;
+
+            transaction
+                .query_opt("SELECT isbn FROM base.book WHERE isbn = $1;", &[&isbn])?
+                .ok_or(WishlistError::NoSuchBook(isbn))?;
+
+            transaction.execute(
+                "INSERT INTO base.in_wishlist (wishlist_id, isbn) VALUES ($1, $2) ON CONFLICT DO NOTHING;",
+                &[&wishlist_id, &isbn],
+            )?;
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn remove_book_from_wishlist(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+        wishlist_id: PostgresInt,
+        isbn: Isbn,
+    ) -> Result<(), WishlistError> {
+        conn.run(move |c| {
+            c.execute(
+                "
+                DELETE FROM base.in_wishlist USING base.wishlist
+                WHERE in_wishlist.wishlist_id = wishlist.wishlist_id
+                AND wishlist.wishlist_id = $1 AND wishlist.customer_id = $2 AND in_wishlist.isbn = $3;
+                ",
+                &[&wishlist_id, &customer_id, &isbn],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// The books the customer has asked to hear about when they're back in stock
+    pub async fn get_stock_subscriptions(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+    ) -> Result<Vec<Isbn>, postgres::error::Error> {
+        conn.run(move |c| {
+            c.query(
+                "SELECT isbn FROM base.stock_subscription WHERE customer_id = $1;",
+                &[&customer_id],
+            )
+        })
+        .await?
+        .iter()
+        .map(|row| row.try_get("isbn"))
+        .collect()
+    }
+
+    /// Asks for a notification once the book is back in stock. Only books that are out of stock
+    /// can be waited on.
+    pub async fn subscribe_to_stock(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+        isbn: Isbn,
+    ) -> Result<(), WishlistError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let stock: PostgresInt = transaction
+                .query_opt(
+                    "SELECT stock FROM base.book WHERE isbn = $1 FOR SHARE;",
+                    &[&isbn],
+                )?
+                .ok_or(WishlistError::NoSuchBook(isbn))?
+                .try_get("stock")?;
+            if stock > 0 {
+                Err(WishlistError::InStock(isbn))?;
+            }
+
+            transaction.execute(
+                "INSERT INTO base.stock_subscription (customer_id, isbn) VALUES ($1, $2) ON CONFLICT DO NOTHING;",
+                &[&customer_id, &isbn],
+            )?;
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn unsubscribe_from_stock(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+        isbn: Isbn,
+    ) -> Result<(), postgres::error::Error> {
+        conn.run(move |c| {
+            c.execute(
+                "DELETE FROM base.stock_subscription WHERE customer_id = $1 AND isbn = $2;",
+                &[&customer_id, &isbn],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Queues a back in stock notification for everyone waiting on any of the books that now
+    /// have stock, using up their subscriptions. Called whenever a book's stock goes up.
+    fn queue_back_in_stock(
+        client: &mut impl GenericClient,
+        isbns: &[Isbn],
+    ) -> Result<u64, postgres::error::Error> {
+        client.execute(
+            "
+            WITH notified AS (
+                DELETE FROM base.stock_subscription AS subscription
+                USING base.book
+                WHERE subscription.isbn = book.isbn AND book.isbn = ANY($1) AND book.stock > 0
+                RETURNING subscription.customer_id, subscription.isbn
+            )
+            INSERT INTO base.notification (customer_id, isbn, kind)
+            SELECT customer_id, isbn, $2 FROM notified;
+            ",
+            &[&isbns, &NotificationKind::BackInStock.as_str()],
+        )
+    }
+
+    /// Notifications that haven't been sent yet and still have attempts left, oldest first
+    pub async fn get_pending_notifications(
+        conn: &DbConn,
+        max_attempts: PostgresInt,
+    ) -> Result<Vec<Notification>, NotificationError> {
+        conn.run(move |c| {
+            c.query(
+                "
+                SELECT notification.notification_id, notification.kind, customer.name, customer.email, book.isbn, book.title
+                FROM base.notification
+                INNER JOIN base.customer ON notification.customer_id = customer.customer_id
+                INNER JOIN base.book ON notification.isbn = book.isbn
+                WHERE notification.sent_at IS NULL AND notification.attempts < $1
+                ORDER BY notification.created_at, notification.notification_id;
+                ",
+                &[&max_attempts],
+            )
+        })
+        .await?
+        .iter()
+        .map(|row| {
+            let kind: &str = row.try_get("kind")?;
+
+            Ok(Notification {
+                notification_id: row.try_get("notification_id")?,
+                kind: kind.parse().map_err(StateError::new)?,
+                name: row.try_get("name")?,
+                email: row.try_get("email")?,
+                isbn: row.try_get("isbn")?,
+                title: row.try_get("title")?,
+            })
+        })
+        .collect()
+    }
+
+    /// Records an attempt to send the notification, which was sent unless there's an error
+    pub async fn record_notification_attempt(
+        conn: &DbConn,
+        notification_id: PostgresInt,
+        error: Option<String>,
+    ) -> Result<(), postgres::error::Error> {
+        conn.run(move |c| {
+            c.execute(
+                "
+                UPDATE base.notification
+                SET attempts = attempts + 1, last_error = $2, sent_at = CASE WHEN $2::text IS NULL THEN now() END
+                WHERE notification_id = $1;
+                ",
+                &[&notification_id, &error],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Queues an email in the outbox. Called inside the same transaction as whatever the email is
+    /// about, so it's only sent if that change is committed.
+    fn queue_mail<T: Serialize>(
+        client: &mut impl GenericClient,
+        template: MailTemplate,
+        recipient_name: Option<&str>,
+        recipient_email: &str,
+        context: &T,
+    ) -> Result<(), OutboxError> {
+        insert_mail(
+            client,
+            template,
+            recipient_name,
+            recipient_email,
+            None,
+            context,
+        )
+    }
+
+    /// Queues an email sending an account token. The link is only made when the email is sent,
+    /// so the outbox never holds a working token.
+    fn queue_token_mail<T: Serialize>(
+        client: &mut impl GenericClient,
+        template: MailTemplate,
+        recipient_name: Option<&str>,
+        recipient_email: &str,
+        account_token_id: PostgresInt,
+        context: &T,
+    ) -> Result<(), OutboxError> {
+        insert_mail(
+            client,
+            template,
+            recipient_name,
+            recipient_email,
+            Some(account_token_id),
+            context,
+        )
+    }
+
+    fn insert_mail<T: Serialize>(
+        client: &mut impl GenericClient,
+        template: MailTemplate,
+        recipient_name: Option<&str>,
+        recipient_email: &str,
+        account_token_id: Option<PostgresInt>,
+        context: &T,
+    ) -> Result<(), OutboxError> {
+        let context = serde_json::to_string(context)?;
+
+        client.execute(
+            "
+            INSERT INTO base.mail_outbox (template, recipient_name, recipient_email, account_token_id, context)
+            VALUES ($1, $2, $3, $4, $5::text::jsonb);
+            ",
+            &[
+                &template.as_str(),
+                &recipient_name,
+                &recipient_email,
+                &account_token_id,
+                &context,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    #[derive(Serialize)]
+    struct OrderMailBook {
+        isbn: Isbn,
+        title: String,
+        quantity: PostgresInt,
+        unit_price: PostgresNumeric,
+        line_total: PostgresNumeric,
+    }
+
+    #[derive(Serialize)]
+    struct OrderMail {
+        order_id: PostgresInt,
+        tracking_number: String,
+        order_status: String,
+        order_date: String,
+        cancellation_reason: Option<String>,
+        books: Vec<OrderMailBook>,
+        subtotal: PostgresNumeric,
+        discount: PostgresNumeric,
+        total: PostgresNumeric,
+    }
+
+    /// Queues an email about the order, as it stands in the transaction, to whoever placed it
+    fn queue_order_mail(
+        client: &mut impl GenericClient,
+        order_id: PostgresInt,
+        template: MailTemplate,
+    ) -> Result<(), OutboxError> {
+        let row = client.query_one(
+            "
+            SELECT orders.tracking_number, orders.order_status, orders.order_date, orders.discount,
+            COALESCE(customer.email, orders.guest_email) AS email, customer.name,
+            cancellation.reason AS cancellation_reason
+            FROM base.orders
+            LEFT JOIN base.customer ON orders.customer_id = customer.customer_id
+            LEFT JOIN base.order_cancellation AS cancellation ON orders.order_id = cancellation.order_id
+            WHERE orders.order_id = $1;
+            ",
+            &[&order_id],
+        )?;
+
+        let books = client
+            .query(
+                "
+                SELECT in_order.isbn, book.title, in_order.quantity, in_order.unit_price
+                FROM base.in_order
+                INNER JOIN base.book ON in_order.isbn = book.isbn
+                WHERE in_order.order_id = $1
+                ORDER BY book.title;
+                ",
+                &[&order_id],
+            )?
+            .iter()
+            .map(|row| {
+                let quantity: PostgresInt = row.try_get("quantity")?;
+                let unit_price: PostgresNumeric = row.try_get("unit_price")?;
+
+                Ok(OrderMailBook {
+                    isbn: row.try_get("isbn")?,
+                    title: row.try_get("title")?,
+                    quantity,
+                    unit_price,
+                    line_total: unit_price * PostgresNumeric::from(quantity),
+                })
+            })
+            .collect::<Result<Vec<_>, postgres::error::Error>>()?;
+
+        let order_status: OrderStatus = row
+            .try_get::<_, &str>("order_status")?
+            .parse()
+            .map_err(StateError::new)?;
+        let order_date: NaiveDate = row.try_get("order_date")?;
+        let subtotal: PostgresNumeric = books.iter().map(|book| book.line_total).sum();
+        let discount: PostgresNumeric = row.try_get("discount")?;
+
+        let context = OrderMail {
+            order_id,
+            tracking_number: row.try_get("tracking_number")?,
+            order_status: order_status.to_string(),
+            order_date: order_date.format("%Y-%m-%d").to_string(),
+            cancellation_reason: row.try_get("cancellation_reason")?,
+            books,
+            subtotal,
+            discount,
+            total: subtotal - discount,
+        };
+
+        queue_mail(
+            client,
+            template,
+            row.try_get("name")?,
+            row.try_get("email")?,
+            &context,
+        )
+    }
+
+    /// Claims up to `batch_size` emails that haven't been sent yet, are due to be tried, and
+    /// still have attempts left, oldest first. Claimed emails aren't due again for `lease_secs`,
+    /// so other dispatchers skip them while they're sent, and they're retried after that if the
+    /// attempt is never recorded.
+    pub async fn claim_due_mail(
+        conn: &DbConn,
+        max_attempts: PostgresInt,
+        batch_size: i64,
+        lease_secs: u64,
+    ) -> Result<Vec<OutgoingMail>, OutboxError> {
+        let lease_secs = lease_secs as f64;
+
+        conn.run(move |c| {
+            c.query(
+                "
+                WITH claimed AS (
+                    UPDATE base.mail_outbox
+                    SET next_attempt_at = now() + make_interval(secs => $3)
+                    WHERE mail_id IN (
+                        SELECT mail_id FROM base.mail_outbox
+                        WHERE sent_at IS NULL AND attempts < $1 AND next_attempt_at <= now()
+                        ORDER BY next_attempt_at, mail_id
+                        LIMIT $2
+                        FOR UPDATE SKIP LOCKED
+                    )
+                    RETURNING mail_id, template, recipient_name, recipient_email, account_token_id,
+                    context::text AS context
+                )
+                SELECT * FROM claimed ORDER BY mail_id;
+                ",
+                &[&max_attempts, &batch_size, &lease_secs],
+            )
+        })
+        .await?
+        .iter()
+        .map(|row| {
+            let template: &str = row.try_get("template")?;
+            let context: &str = row.try_get("context")?;
+
+            Ok(OutgoingMail {
+                mail_id: row.try_get("mail_id")?,
+                template: template.parse().map_err(StateError::new)?,
+                recipient_name: row.try_get("recipient_name")?,
+                recipient_email: row.try_get("recipient_email")?,
+                context: serde_json::from_str(context)?,
+                account_token_id: row.try_get("account_token_id")?,
+            })
+        })
+        .collect()
+    }
+
+    /// Gives the token a fresh secret for the email that's about to send it, returning what the
+    /// token is for and the secret. `None` if the token has been used or has expired, in which
+    /// case its email is dropped rather than sent with a link that won't work.
+    pub async fn renew_mail_token(
+        conn: &DbConn,
+        mail_id: PostgresInt,
+        account_token_id: PostgresInt,
+    ) -> Result<Option<(AccountTokenPurpose, String)>, OutboxError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let token = new_account_token();
+            let purpose = transaction.query_opt(
+                "
+                UPDATE base.account_token SET token_hash = $2
+                WHERE token_id = $1 AND used_at IS NULL AND expires_at > now()
+                RETURNING purpose;
+                ",
+                &[&account_token_id, &hash_token(&token)],
+            )?;
+
+            let renewed = match purpose {
+                Some(row) => {
+                    let purpose: AccountTokenPurpose = row
+                        .try_get::<_, &str>("purpose")?
+                        .parse()
+                        .map_err(StateError::new)?;
+                    Some((purpose, token))
+                }
+                None => {
+                    transaction.execute(
+                        "DELETE FROM base.mail_outbox WHERE mail_id = $1;",
+                        &[&mail_id],
+                    )?;
+                    None
+                }
+            };
+
+            transaction.commit()?;
+
+            Ok(renewed)
+        })
+        .await
+    }
+
+    /// Records an attempt to send the email, which was sent unless there's an error. A failed
+    /// email waits `retry_delay_secs`, doubled for every earlier attempt, before it's tried again.
+    pub async fn record_mail_attempt(
+        conn: &DbConn,
+        mail_id: PostgresInt,
+        error: Option<String>,
+        retry_delay_secs: u64,
+    ) -> Result<(), postgres::error::Error> {
+        let retry_delay_secs = retry_delay_secs as f64;
+
+        conn.run(move |c| {
+            c.execute(
+                "
+                UPDATE base.mail_outbox
+                SET attempts = attempts + 1, last_error = $2,
+                sent_at = CASE WHEN $2::text IS NULL THEN now() END,
+                next_attempt_at = now() + make_interval(secs => $3 * power(2, attempts))
+                WHERE mail_id = $1;
+                ",
+                &[&mail_id, &error, &retry_delay_secs],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn validate_customer_login<T: AsRef<str>>(
+        conn: &DbConn,
+        email: T,
+        password: T,
+    ) -> Result<PostgresInt, LoginError> {
+        let email = email.as_ref().to_owned();
+        let password = password.as_ref().to_owned();
+
+        let customer = conn
+            .run(move |c| {
+                c.query_opt(
+                    "SELECT customer_id, password_hash FROM base.customer WHERE email = $1",
+                    &[&email],
+                )
+            })
+            .await?;
+
+        match customer {
+            Some(customer) => {
+                let customer_id = customer.get("customer_id");
+                let password_hash = customer.get("password_hash");
+
+                if bcrypt::verify(password, password_hash)? {
+                    Ok(customer_id)
+                } else {
+                    Err(LoginError::CredentialError)?
+                }
+            }
+            None => Err(LoginError::CredentialError)?,
+        }
+    }
+
+    pub async fn try_add_address(
+        conn: &DbConn,
+        address: no_id::Address,
+    ) -> Result<PostgresInt, postgres::error::Error> {
+        let no_id::Address {
+            street_address,
+            postal_code,
+            province,
+        } = address;
+        Ok(conn.run(move |c| {
+            c.query_one(
+                "INSERT INTO base.address (street_address, postal_code, province) VALUES ($1, $2, $3) RETURNING address_id",
+                &[&street_address, &postal_code, &province],
+            )
+        }).await?.get("address_id"))
+    }
+
+    #[derive(Debug, Clone, Copy, Serialize)]
+    pub struct Expiry {
+        month: u32,
+        year: u32,
+    }
+
+    impl Expiry {
+        pub fn from_str<T: AsRef<str>>(s: T) -> Option<Expiry> {
+            let mut split = s.as_ref().split('/');
+            let month = split.next()?.parse().ok()?;
+            if month < 1 || month > 12 {
+                return None;
+            }
+
+            let year = split.next()?.parse().ok()?;
+
+            if split.next().is_some() {
+                return None;
+            }
+
+            Some(Expiry { month, year })
+        }
+    }
+
+    impl ToString for Expiry {
+        fn to_string(&self) -> String {
+            format!("{}/{}", self.month, self.year)
+        }
+    }
+
+    pub async fn try_add_payment_info(
+        conn: &DbConn,
+        payment_info: no_id::PaymentInfo,
+    ) -> Result<PostgresInt, postgres::error::Error> {
+        let no_id::PaymentInfo {
+            name_on_card,
+            expiry,
+            token,
+            last_four,
+            brand,
+            billing_address,
+        } = payment_info;
+
+        let billing_address_id = try_add_address(conn, billing_address).await?;
+        Ok(conn.run(move |c| {
+            c.query_one(
+                "INSERT INTO base.payment_info (name_on_card, expiry, token, last_four, brand, billing_address_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING payment_info_id",
+                &[&name_on_card, &expiry.to_string(), &token, &last_four, &brand, &billing_address_id],
+            )
+        }).await?.get("payment_info_id"))
+    }
+
+    pub async fn try_create_new_customer<'a, T: AsRef<str>>(
+        conn: &DbConn,
+        email: T,
+        password: T,
+        name: T,
+        address: no_id::Address,
+        payment_info: no_id::PaymentInfo,
+        accounts: AccountConfig,
+    ) -> Result<PostgresInt, CreateCustomerError> {
+        let name = name.as_ref().to_string();
+        let email = email.as_ref().to_string();
+        let password = password.as_ref().to_string();
+
+        let address_id = try_add_address(conn, address).await?;
+        let payment_info_id = try_add_payment_info(conn, payment_info).await?;
+
+        let password_hash = bcrypt::hash(password, 10)?;
+
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let customer_id: PostgresInt = transaction.query_one(
+                "INSERT INTO base.customer (name, email, password_hash, default_shipping_address, default_payment_info_id) VALUES ($1, $2, $3, $4, $5) RETURNING customer_id;",
+                &[&name, &email, &password_hash, &address_id, &payment_info_id],
+            )?.try_get("customer_id")?;
+
+            queue_mail(
+                &mut transaction,
+                MailTemplate::Welcome,
+                Some(&name),
+                &email,
+                &HashMap::from([("name", &name), ("email", &email)]),
+            )?;
+            queue_verification_mail(&mut transaction, customer_id, &name, &email, &accounts)?;
+
+            transaction.commit()?;
+
+            Ok(customer_id)
+        })
+        .await
+    }
+
+    /// Whose account an emailed token belongs to
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum AccountKind {
+        Customer,
+        Owner,
+    }
+
+    impl AccountKind {
+        /// The account table and its ID column
+        fn table(&self) -> (&'static str, &'static str) {
+            match self {
+                AccountKind::Customer => ("base.customer", "customer_id"),
+                AccountKind::Owner => ("base.owner", "owner_id"),
+            }
+        }
+    }
+
+    /// Account and session tokens are only stored hashed, so they can't be lifted from the database
+    fn hash_token(token: &str) -> String {
+        format!("{:x}", Sha256::digest(token.as_bytes()))
+    }
+
+    fn new_account_token() -> String {
+        let mut token = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut token);
+        base64::encode_config(token, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Records a request for a token sent to the email, refusing it once the email has asked for
+    /// too many within the window. Requests are counted whether or not the email has an account,
+    /// so the limit doesn't give away which emails do.
+    ///
+    /// The email is counted exactly as given, so it must be the same one the account is looked up by.
+    fn limit_token_requests(
+        client: &mut impl GenericClient,
+        email: &str,
+        purpose: AccountTokenPurpose,
+        accounts: &AccountConfig,
+    ) -> Result<(), AccountTokenError> {
+        let window = chrono::Duration::minutes(accounts.token_request_window_minutes);
+        let window_start = Local::now() - window;
+
+        // Requests for the same email wait on each other, so they can't all slip under the limit
+        client.execute(
+            "SELECT pg_advisory_xact_lock(hashtext('look_inna_book.account_token_request'), hashtext($1));",
+            &[&email],
+        )?;
+        client.execute(
+            "DELETE FROM base.account_token_request WHERE requested_at <= $1;",
+            &[&window_start],
+        )?;
+
+        let requests: i64 = client
+            .query_one(
+                "SELECT count(*) AS requests FROM base.account_token_request WHERE email = $1 AND purpose = $2;",
+                &[&email, &purpose.as_str()],
+            )?
+            .try_get("requests")?;
+        if requests >= accounts.max_token_requests {
+            Err(AccountTokenError::RateLimited)?;
+        }
+
+        client.execute(
+            "INSERT INTO base.account_token_request (email, purpose) VALUES ($1, $2);",
+            &[&email, &purpose.as_str()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Creates a token for the account, returning its ID. Any earlier tokens the account had for
+    /// the same purpose stop working, along with their unsent emails.
+    ///
+    /// Nobody knows the token's secret until its email is sent and gives it a new one.
+    fn issue_account_token(
+        client: &mut impl GenericClient,
+        kind: AccountKind,
+        account_id: PostgresInt,
+        purpose: AccountTokenPurpose,
+        valid_for: chrono::Duration,
+    ) -> Result<PostgresInt, postgres::error::Error> {
+        let (_, id_column) = kind.table();
+
+        client.execute(
+            format!("DELETE FROM base.account_token WHERE {id_column} = $1 AND purpose = $2;")
+                .as_str(),
+            &[&account_id, &purpose.as_str()],
+        )?;
+
+        let row = client.query_one(
+            format!(
+                "INSERT INTO base.account_token (purpose, {id_column}, token_hash, expires_at) VALUES ($1, $2, $3, $4) RETURNING token_id;"
+            )
+            .as_str(),
+            &[
+                &purpose.as_str(),
+                &account_id,
+                &hash_token(&new_account_token()),
+                &(Local::now() + valid_for),
+            ],
+        )?;
+
+        row.try_get("token_id")
+    }
+
+    /// Uses up the token if it's for the purpose and still valid, returning whose account it's for
+    fn redeem_account_token(
+        client: &mut impl GenericClient,
+        token: &str,
+        purpose: AccountTokenPurpose,
+    ) -> Result<SessionType, AccountTokenError> {
+        let row = client
+            .query_opt(
+                "
+                UPDATE base.account_token SET used_at = now()
+                WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > now()
+                RETURNING customer_id, owner_id;
+                ",
+                &[&hash_token(token), &purpose.as_str()],
+            )?
+            .ok_or(AccountTokenError::InvalidToken)?;
+
+        match (row.try_get("customer_id")?, row.try_get("owner_id")?) {
+            (Some(customer_id), _) => Ok(SessionType::Customer(customer_id)),
+            (None, Some(owner_id)) => Ok(SessionType::Owner(owner_id)),
+            (None, None) => Err(AccountTokenError::InvalidToken),
+        }
+    }
+
+    fn queue_verification_mail(
+        client: &mut impl GenericClient,
+        customer_id: PostgresInt,
+        name: &str,
+        email: &str,
+        accounts: &AccountConfig,
+    ) -> Result<(), AccountTokenError> {
+        let token_id = issue_account_token(
+            client,
+            AccountKind::Customer,
+            customer_id,
+            AccountTokenPurpose::VerifyEmail,
+            chrono::Duration::hours(accounts.verification_token_hours),
+        )?;
+
+        #[derive(Serialize)]
+        struct VerifyEmailMail {
+            expires_hours: i64,
+        }
+
+        queue_token_mail(
+            client,
+            MailTemplate::VerifyEmail,
+            Some(name),
+            email,
+            token_id,
+            &VerifyEmailMail {
+                expires_hours: accounts.verification_token_hours,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Emails a link for resetting the password of the account using the email, if there is one.
+    /// Nothing says whether there was, so the form can't be used to find out who has an account.
+    pub async fn request_password_reset(
+        conn: &DbConn,
+        kind: AccountKind,
+        email: String,
+        accounts: AccountConfig,
+    ) -> Result<(), AccountTokenError> {
+        // Accounts are found by exact email, like at login, so only surrounding space is dropped
+        let email = email.trim().to_string();
+
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            limit_token_requests(
+                &mut transaction,
+                &email,
+                AccountTokenPurpose::PasswordReset,
+                &accounts,
+            )?;
+
+            let (table, id_column) = kind.table();
+            let account = transaction.query_opt(
+                format!(
+                    "SELECT {id_column} AS account_id, name, email FROM {table} WHERE email = $1;"
+                )
+                .as_str(),
+                &[&email],
+            )?;
+
+            if let Some(account) = account {
+                let token_id = issue_account_token(
+                    &mut transaction,
+                    kind,
+                    account.try_get("account_id")?,
+                    AccountTokenPurpose::PasswordReset,
+                    chrono::Duration::minutes(accounts.reset_token_minutes),
+                )?;
+
+                #[derive(Serialize)]
+                struct PasswordResetMail {
+                    expires_minutes: i64,
+                }
+
+                queue_token_mail(
+                    &mut transaction,
+                    MailTemplate::PasswordReset,
+                    account.try_get("name")?,
+                    account.try_get("email")?,
+                    token_id,
+                    &PasswordResetMail {
+                        expires_minutes: accounts.reset_token_minutes,
+                    },
+                )?;
+            }
+
+            // The request is recorded even if there was no account, so it still counts towards the limit
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Sets a new password with a reset token, returning whose account it was so their sessions
+    /// can be ended. Following the link also proves a customer owns their email.
+    pub async fn reset_password(
+        conn: &DbConn,
+        token: String,
+        password: String,
+        confirm_password: String,
+    ) -> Result<SessionType, AccountTokenError> {
+        if password.is_empty() {
+            Err(AccountTokenError::EmptyPassword)?;
+        }
+        if password != confirm_password {
+            Err(AccountTokenError::PasswordMismatch)?;
+        }
+
+        let password_hash = bcrypt::hash(password, 10)?;
+
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let account =
+                redeem_account_token(&mut transaction, &token, AccountTokenPurpose::PasswordReset)?;
+
+            match account {
+                SessionType::Customer(customer_id) => transaction.execute(
+                    "
+                    UPDATE base.customer
+                    SET password_hash = $1, email_verified_at = COALESCE(email_verified_at, now())
+                    WHERE customer_id = $2;
+                    ",
+                    &[&password_hash, &customer_id],
+                )?,
+                SessionType::Owner(owner_id) => transaction.execute(
+                    "UPDATE base.owner SET password_hash = $1 WHERE owner_id = $2;",
+                    &[&password_hash, &owner_id],
+                )?,
+                SessionType::DefaultOwner => Err(AccountTokenError::InvalidToken)?,
+            };
+
+            transaction.commit()?;
+
+            Ok(account)
+        })
+        .await
+    }
+
+    /// Sends the customer a fresh verification link, replacing any earlier one
+    pub async fn resend_email_verification(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+        accounts: AccountConfig,
+    ) -> Result<(), AccountTokenError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let customer = transaction.query_one(
+                "SELECT name, email, email_verified_at IS NOT NULL AS verified FROM base.customer WHERE customer_id = $1 FOR UPDATE;",
+                &[&customer_id],
+            )?;
+            if customer.try_get("verified")? {
+                Err(AccountTokenError::AlreadyVerified)?;
+            }
+
+            let name: &str = customer.try_get("name")?;
+            let email: &str = customer.try_get("email")?;
+
+            limit_token_requests(
+                &mut transaction,
+                email,
+                AccountTokenPurpose::VerifyEmail,
+                &accounts,
+            )?;
+            queue_verification_mail(&mut transaction, customer_id, name, email, &accounts)?;
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Marks the customer the verification token was sent to as having a working email
+    pub async fn verify_email(conn: &DbConn, token: String) -> Result<(), AccountTokenError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let account =
+                redeem_account_token(&mut transaction, &token, AccountTokenPurpose::VerifyEmail)?;
+
+            if let SessionType::Customer(customer_id) = account {
+                transaction.execute(
+                    "UPDATE base.customer SET email_verified_at = COALESCE(email_verified_at, now()) WHERE customer_id = $1;",
+                    &[&customer_id],
+                )?;
+            }
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn try_create_new_owner<'a, T: AsRef<str>>(
+        conn: &DbConn,
+        email: T,
+        password: T,
+        name: T,
+    ) -> Result<PostgresInt, CreateOwnerError> {
+        let name = name.as_ref().to_string();
+        let email = email.as_ref().to_string();
+        let password = password.as_ref().to_string();
+
+        let password_hash = bcrypt::hash(password, 10)?;
+
+        Ok(conn
+            .run(move |c| {
+                c.query_one(
+                    "INSERT INTO base.owner (name, email, password_hash) VALUES ($1, $2, $3) RETURNING owner_id;",
+                    &[&name, &email, &password_hash],
+                )
+            })
+            .await?
+            .get("owner_id"))
+    }
+
+    pub async fn owner_exists(conn: &DbConn) -> Result<bool, postgres::error::Error> {
+        Ok(!conn
+            .run(|c| c.query("SELECT * FROM base.owner;", &[]))
+            .await?
+            .is_empty())
+    }
+
+    pub enum OwnerLoginType {
+        DefaultOwner,
+        OwnerAccount(PostgresInt),
+    }
+
+    pub async fn validate_owner_login<T: AsRef<str>>(
+        conn: &DbConn,
+        email: T,
+        password: T,
+    ) -> Result<OwnerLoginType, LoginError> {
+        let email = email.as_ref().to_owned();
+        let password = password.as_ref().to_owned();
+
+        if !owner_exists(conn).await? {
+            if email == "admin@local" && password == "default" {
+                Ok(OwnerLoginType::DefaultOwner)
+            } else {
+                Err(LoginError::CredentialError)
+            }
+        } else {
+            let row = conn
+                .run(move |c| {
+                    c.query_opt(
+                        "SELECT owner_id, password_hash FROM base.owner WHERE email = $1",
+                        &[&email],
+                    )
+                })
+                .await?;
+
+            if let Some(row) = row {
+                let owner_id = row.try_get("owner_id")?;
+                let password_hash = row.try_get("password_hash")?;
+
+                if bcrypt::verify(password, password_hash)? {
+                    Ok(OwnerLoginType::OwnerAccount(owner_id))
+                } else {
+                    Err(LoginError::CredentialError)
+                }
+            } else {
+                Err(LoginError::CredentialError)
+            }
+        }
+    }
+
+    pub async fn get_customer_info(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+    ) -> Result<Option<crate::schema::joined::Customer>, postgres::error::Error> {
+        Ok(conn
+            .run(move |c| {
+                c.query_opt(
+                    "
+                SELECT
+                customer_id,
+                name,
+                email,
+                expiry,
+                name_on_card,
+                def_shipping.street_address AS def_street_address,
+                def_shipping.postal_code AS def_postal,
+                def_shipping.province AS def_province,
+                billing_add.street_address AS bill_street_address,
+                billing_add.postal_code AS bill_postal,
+                billing_add.province AS bill_province,
+                customer.email_verified_at IS NOT NULL AS email_verified
+                FROM
+                base.customer AS customer
+                INNER JOIN base.address AS def_shipping ON customer.default_shipping_address = def_shipping.address_id
+                INNER JOIN base.payment_info AS payment ON customer.default_payment_info_id = payment.payment_info_id
+                INNER JOIN base.address AS billing_add ON payment.billing_address_id = billing_add.address_id
+                WHERE customer.customer_id = $1;
+            ",
+                    &[&customer_id],
+                )
+            })
+            .await?
+           .map(|row| {
+               use crate::schema::joined::Customer;
+            let result: Result<Customer, postgres::error::Error> = try { Customer {
+                            name: row.try_get("name")?,
+                            email: row.try_get("email")?,
+                            street_address: row.try_get("def_street_address")?,
+                            postal_code: row.try_get("def_postal")?,
+                            province: row.try_get("def_province")?,
+                            expiry: row.try_get("expiry")?,
+                            name_on_card: row.try_get("name_on_card")?,
+                            billing_street_address: row.try_get("bill_street_address")?,
+                            billing_postal_code: row.try_get("bill_postal")?,
+                            billing_province: row.try_get("bill_province")?,
+                            email_verified: row.try_get("email_verified")?,
+            }};
+               result.ok()
+           }).flatten())
+    }
+
+    /// The table a cart's books are kept in and the column picking out the cart
+    fn cart_table(cart: &CartOwner) -> (&'static str, &'static str) {
+        match cart {
+            CartOwner::Customer(_) => ("base.in_cart", "customer_id"),
+            CartOwner::Guest(_) => ("base.in_guest_cart", "cart_token"),
+        }
+    }
+
+    fn cart_key(cart: &CartOwner) -> &(dyn ToSql + Sync) {
+        match cart {
+            CartOwner::Customer(customer_id) => customer_id,
+            CartOwner::Guest(cart_token) => cart_token,
+        }
+    }
+
+    /// Starts the guest cart if it's new, and otherwise keeps it from being swept out for a while
+    fn touch_cart(
+        client: &mut impl GenericClient,
+        cart: &CartOwner,
+    ) -> Result<(), postgres::error::Error> {
+        if let CartOwner::Guest(cart_token) = cart {
+            client.execute(
+                "
+                INSERT INTO base.guest_cart (cart_token) VALUES ($1)
+                ON CONFLICT (cart_token) DO UPDATE SET last_active = now();
+                ",
+                &[cart_token],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_cart(
+        conn: &DbConn,
+        cart: &CartOwner,
+    ) -> Result<Vec<(Isbn, u32)>, postgres::error::Error> {
+        let cart = cart.clone();
+
+        Ok(conn
+            .run(move |c| {
+                let (table, key) = cart_table(&cart);
+
+                c.query(
+                    format!("SELECT isbn, quantity FROM {table} WHERE {key} = $1").as_str(),
+                    &[cart_key(&cart)],
+                )
+            })
+            .await?
+            .iter()
+            .filter_map(|row| {
+                let result: Result<(Isbn, i32), postgres::error::Error> =
+                    try { (row.try_get("isbn")?, row.try_get("quantity")?) };
+                result.ok()
+            })
+            .map(|b| (b.0, i32::max(b.1, 0) as u32))
+            .collect())
+    }
+
+    /// Locks the book and works out how many copies the cart could hold, which is the stock less
+    /// whatever other carts are holding. Books are always locked in ISBN order, so carts being
+    /// changed at the same time can't deadlock each other.
+    fn lock_available_stock(
+        client: &mut impl GenericClient,
+        cart: &CartOwner,
+        isbn: Isbn,
+    ) -> Result<Option<i64>, postgres::error::Error> {
+        let (_, key) = cart_table(cart);
+
+        let stock: PostgresInt = match client.query_opt(
+            "SELECT stock FROM base.book WHERE isbn = $1 FOR UPDATE;",
+            &[&isbn],
+        )? {
+            Some(row) => row.try_get("stock")?,
+            None => return Ok(None),
+        };
+
+        let reserved: i64 = client
+            .query_one(
+                format!(
+                    "
+                    SELECT coalesce(sum(quantity), 0)::bigint AS reserved
+                    FROM base.stock_reservation
+                    WHERE isbn = $1 AND {key} IS DISTINCT FROM $2;
+                    "
+                )
+                .as_str(),
+                &[&isbn, cart_key(cart)],
+            )?
+            .try_get("reserved")?;
+
+        Ok(Some(stock as i64 - reserved))
+    }
+
+    /// Sets how many copies of the book are in the cart and holds them until `reserved_until`,
+    /// as long as enough copies aren't already held elsewhere
+    fn hold_cart_book(
+        client: &mut impl GenericClient,
+        cart: &CartOwner,
+        isbn: Isbn,
+        quantity: PostgresInt,
+        reserved_until: ExpirationTime,
+    ) -> Result<(), CartError> {
+        let (table, key) = cart_table(cart);
+
+        if quantity <= 0 {
+            client.execute(
+                format!("DELETE FROM {table} WHERE isbn = $1 AND {key} = $2;").as_str(),
+                &[&isbn, cart_key(cart)],
+            )?;
+            return Ok(());
+        }
+
+        match lock_available_stock(client, cart, isbn)? {
+            Some(available) if quantity as i64 <= available => {}
+            _ => Err(CartError::NotEnoughStock)?,
+        }
+
+        client.execute(
+            format!(
+                "
+                INSERT INTO {table} (isbn, {key}, quantity, reserved_until) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (isbn, {key}) DO UPDATE
+                SET quantity = excluded.quantity, reserved_until = excluded.reserved_until;
+                "
+            )
+            .as_str(),
+            &[&isbn, cart_key(cart), &quantity, &reserved_until],
+        )?;
+
+        Ok(())
+    }
+
+    fn get_cart_quantity(
+        client: &mut impl GenericClient,
+        cart: &CartOwner,
+        isbn: Isbn,
+    ) -> Result<PostgresInt, postgres::error::Error> {
+        let (table, key) = cart_table(cart);
+
+        match client.query_opt(
+            format!("SELECT quantity FROM {table} WHERE {key} = $1 AND isbn = $2 FOR UPDATE")
+                .as_str(),
+            &[cart_key(cart), &isbn],
+        )? {
+            Some(row) => Ok(row
+                .try_get::<_, Option<PostgresInt>>("quantity")?
+                .unwrap_or(0)),
+            None => Ok(0),
+        }
+    }
+
+    /// Adds a copy of the book to the cart, holding every copy of it there until `reserved_until`
+    pub async fn add_to_cart(
+        conn: &DbConn,
+        cart: &CartOwner,
+        isbn: Isbn,
+        reserved_until: ExpirationTime,
+    ) -> Result<(), CartError> {
+        let cart = cart.clone();
+
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+            touch_cart(&mut transaction, &cart)?;
+
+            let quantity = get_cart_quantity(&mut transaction, &cart, isbn)?;
+            hold_cart_book(&mut transaction, &cart, isbn, quantity + 1, reserved_until)?;
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn cart_set_book_quantity(
+        conn: &DbConn,
+        cart: &CartOwner,
+        isbn: Isbn,
+        quantity: u32,
+        reserved_until: ExpirationTime,
+    ) -> Result<(), CartError> {
+        let quantity = PostgresInt::try_from(quantity).map_err(|_| CartError::NotEnoughStock)?;
+        let cart = cart.clone();
+
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+            touch_cart(&mut transaction, &cart)?;
+
+            hold_cart_book(&mut transaction, &cart, isbn, quantity, reserved_until)?;
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Holds the books in the cart for a while longer. Books that other carts have taken the
+    /// copies of in the meantime stay in the cart, but aren't held until enough copies free up.
+    pub async fn hold_cart(
+        conn: &DbConn,
+        cart: &CartOwner,
+        reserved_until: ExpirationTime,
+    ) -> Result<(), postgres::error::Error> {
+        let cart = cart.clone();
+
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+            let (table, key) = cart_table(&cart);
+
+            let books: Vec<(Isbn, Option<PostgresInt>)> = transaction
+                .query(
+                    format!(
+                        "SELECT isbn, quantity FROM {table} WHERE {key} = $1 ORDER BY isbn FOR UPDATE;"
+                    )
+                    .as_str(),
+                    &[cart_key(&cart)],
+                )?
+                .iter()
+                .map(|row| Ok((row.try_get("isbn")?, row.try_get("quantity")?)))
+                .collect::<Result<_, postgres::error::Error>>()?;
+
+            for (isbn, quantity) in books {
+                let available = lock_available_stock(&mut transaction, &cart, isbn)?;
+                let held = matches!(available, Some(available) if quantity.unwrap_or(0) as i64 <= available);
+
+                transaction.execute(
+                    format!(
+                        "UPDATE {table} SET reserved_until = $3 WHERE isbn = $1 AND {key} = $2;"
+                    )
+                    .as_str(),
+                    &[&isbn, cart_key(&cart), &Some(reserved_until).filter(|_| held)],
+                )?;
+            }
+
+            transaction.commit()
+        })
+        .await
+    }
+
+    /// Moves the books in a guest cart into the customer's cart once they log in. Books
+    /// already in both carts have their quantities added together, but never beyond the copies
+    /// the customer could hold, and books with no copies to spare are left behind.
+    pub async fn merge_guest_cart(
+        conn: &DbConn,
+        cart_token: String,
+        customer_id: PostgresInt,
+        reserved_until: ExpirationTime,
+    ) -> Result<(), CartError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let guest_books: Vec<(Isbn, PostgresInt)> = transaction
+                .query(
+                    "SELECT isbn, quantity FROM base.in_guest_cart WHERE cart_token = $1 ORDER BY isbn;",
+                    &[&cart_token],
+                )?
+                .iter()
+                .map(|row| Ok((row.try_get("isbn")?, row.try_get("quantity")?)))
+                .collect::<Result<_, postgres::error::Error>>()?;
+
+            // Dropping the guest cart first gives its copies over to the customer
+            transaction.execute(
+                "DELETE FROM base.guest_cart WHERE cart_token = $1;",
+                &[&cart_token],
+            )?;
+
+            let cart = CartOwner::Customer(customer_id);
+            for (isbn, guest_quantity) in guest_books {
+                let quantity = get_cart_quantity(&mut transaction, &cart, isbn)?;
+                let available = lock_available_stock(&mut transaction, &cart, isbn)?.unwrap_or(0);
+                let merged = i64::min((quantity + guest_quantity) as i64, available) as PostgresInt;
+
+                if merged > quantity {
+                    hold_cart_book(&mut transaction, &cart, isbn, merged, reserved_until)?;
+                }
+            }
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Forgets holds that have run out, returning how many were released. Expired holds are
+    /// already ignored when working out available stock, so this only tidies them away.
+    pub async fn release_expired_reservations(
+        conn: &DbConn,
+    ) -> Result<u64, postgres::error::Error> {
+        conn.run(|c| {
+            let mut transaction = c.transaction()?;
+
+            let released = transaction.execute(
+                "UPDATE base.in_cart SET reserved_until = NULL WHERE reserved_until <= now();",
+                &[],
+            )? + transaction.execute(
+                "UPDATE base.in_guest_cart SET reserved_until = NULL WHERE reserved_until <= now();",
+                &[],
+            )?;
+
+            transaction.commit()?;
+
+            Ok(released)
+        })
+        .await
+    }
+
+    /// The stock of each book, and how much of it is held in carts
+    pub async fn get_stock_levels(
+        conn: &DbConn,
+        isbns: Vec<Isbn>,
+    ) -> Result<Vec<StockLevel>, postgres::error::Error> {
+        conn.run(move |c| {
+            c.query(
+                "
+                SELECT book.isbn, book.stock, coalesce(held.quantity, 0)::bigint AS reserved
+                FROM base.book
+                LEFT JOIN (
+                    SELECT isbn, sum(quantity) AS quantity
+                    FROM base.stock_reservation
+                    GROUP BY isbn
+                ) AS held USING (isbn)
+                WHERE book.isbn = ANY($1);
+                ",
+                &[&isbns],
+            )
+        })
+        .await?
+        .iter()
+        .map(|row| {
+            let stock: PostgresInt = row.try_get("stock")?;
+            let reserved: i64 = row.try_get("reserved")?;
+
+            Ok(StockLevel {
+                isbn: row.try_get("isbn")?,
+                stock,
+                reserved,
+                available: i64::max(stock as i64 - reserved, 0),
+            })
+        })
+        .collect()
+    }
+
+    /// The books in the cart, with how long they're held for and how many copies the cart
+    /// could have
+    pub async fn get_cart_reservations(
+        conn: &DbConn,
+        cart: &CartOwner,
+    ) -> Result<Vec<CartReservation>, postgres::error::Error> {
+        let cart = cart.clone();
+
+        conn.run(move |c| {
+            let (table, key) = cart_table(&cart);
+
+            c.query(
+                format!(
+                    "
+                    SELECT cart.isbn, cart.reserved_until,
+                    book.stock - coalesce((
+                        SELECT sum(reservation.quantity) FROM base.stock_reservation AS reservation
+                        WHERE reservation.isbn = cart.isbn AND reservation.{key} IS DISTINCT FROM $1
+                    ), 0) AS available
+                    FROM {table} AS cart INNER JOIN base.book USING (isbn)
+                    WHERE cart.{key} = $1;
+                    "
+                )
+                .as_str(),
+                &[cart_key(&cart)],
+            )
+        })
+        .await?
+        .iter()
+        .map(|row| {
+            let reserved_until: Option<DateTime<Local>> = row.try_get("reserved_until")?;
+            let available: i64 = row.try_get("available")?;
+
+            Ok(CartReservation {
+                isbn: row.try_get("isbn")?,
+                held_until: reserved_until
+                    .filter(|reserved_until| *reserved_until > Local::now())
+                    .map(|reserved_until| reserved_until.format("%Y-%m-%d %H:%M").to_string()),
+                available: i64::max(available, 0),
+            })
+        })
+        .collect()
+    }
+
+    /// Drops guest carts nobody has changed in `days` days, returning how many were removed
+    pub async fn delete_stale_guest_carts(
+        conn: &DbConn,
+        days: u32,
+    ) -> Result<u64, postgres::error::Error> {
+        let days = days as i32;
+
+        conn.run(move |c| {
+            c.execute(
+                "DELETE FROM base.guest_cart WHERE last_active < now() - make_interval(days => $1);",
+                &[&days],
+            )
+        })
+        .await
+    }
+
+    fn find_address(
+        client: &mut impl GenericClient,
+        address: schema::no_id::Address,
+    ) -> Result<Option<PostgresInt>, postgres::error::Error> {
+        let schema::no_id::Address {
+            street_address,
+            postal_code,
+            province,
+        } = address;
+
+        let row = client.query_opt("SELECT address_id FROM base.address WHERE street_address = $1 AND postal_code = $2 AND province = $3", &[&street_address, &postal_code, &province])?;
+
+        match row {
+            Some(row) => Ok(row.try_get("address_id")?),
+            None => Ok(None),
+        }
+    }
+
+    fn find_payment_info(
+        client: &mut impl GenericClient,
+        payment_info: schema::no_id::PaymentInfo,
+    ) -> Result<Option<PostgresInt>, postgres::error::Error> {
+        let schema::no_id::PaymentInfo {
+            name_on_card,
+            expiry,
+            token,
+            billing_address,
+            ..
+        } = payment_info;
+
+        let schema::no_id::Address {
+            street_address,
+            postal_code,
+            province,
+        } = billing_address;
+
+        let row = client.query_opt(
+            "
+            SELECT payment_info_id FROM
+            base.payment_info INNER JOIN
+            base.address ON billing_address_id = address_id WHERE
+            name_on_card = $1 AND
+            expiry = $2 AND
+            token = $3 AND
+            street_address = $4 AND
+            postal_code = $5 AND
+            province = $6;
+            ",
+            &[
+                &name_on_card,
+                &expiry.to_string(),
+                &token,
+                &street_address,
+                &postal_code,
+                &province,
+            ],
+        )?;
+
+        Ok(match row {
+            Some(row) => Some(row.try_get("payment_info_id")?),
+            None => None,
+        })
+    }
+
+    fn get_or_insert_address(
+        client: &mut impl GenericClient,
+        address: schema::no_id::Address,
+    ) -> Result<PostgresInt, postgres::error::Error> {
+        match find_address(client, address.clone())? {
+            Some(address_id) => Ok(address_id),
+            None => {
+                let schema::no_id::Address {
+                    street_address,
+                    postal_code,
+                    province,
+                } = address;
+
+                client
+                    .query_one(
+                        "INSERT INTO base.address
+                (street_address, postal_code, province)
+                VALUES ($1, $2, $3)
+                RETURNING address_id;",
+                        &[&street_address, &postal_code, &province],
+                    )?
+                    .try_get("address_id")
+            }
+        }
+    }
+
+    fn get_or_insert_payment_info(
+        client: &mut impl GenericClient,
+        payment_info: schema::no_id::PaymentInfo,
+    ) -> Result<PostgresInt, postgres::error::Error> {
+        match find_payment_info(client, payment_info.clone())? {
+            Some(payment_info_id) => Ok(payment_info_id),
+            None => {
+                let schema::no_id::PaymentInfo {
+                    name_on_card,
+                    expiry,
+                    token,
+                    last_four,
+                    brand,
+                    billing_address,
+                } = payment_info;
+
+                let address_id = get_or_insert_address(client, billing_address)?;
+
+                client
+                    .query_one(
+                        "INSERT INTO base.payment_info
+                (name_on_card, expiry, token, last_four, brand, billing_address_id)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                RETURNING payment_info_id;",
+                        &[
+                            &name_on_card,
+                            &expiry.to_string(),
+                            &token,
+                            &last_four,
+                            &brand,
+                            &address_id,
+                        ],
+                    )?
+                    .try_get("payment_info_id")
+            }
+        }
+    }
+
+    fn get_tracking_number() -> String {
+        let mut rng = rand::thread_rng();
+        rng.next_u32().to_string()
+    }
+
+    fn clear_cart(
+        client: &mut impl GenericClient,
+        cart: &CartOwner,
+    ) -> Result<(), postgres::error::Error> {
+        let (table, key) = cart_table(cart);
+
+        client.execute(
+            format!("DELETE FROM {table} WHERE {key} = $1").as_str(),
+            &[cart_key(cart)],
+        )?;
+
+        Ok(())
+    }
+
+    fn add_books_to_order(
+        client: &mut impl GenericClient,
+        books: &[(Isbn, PostgresInt)],
+        order_id: PostgresInt,
+    ) -> Result<(), postgres::error::Error> {
+        for (isbn, quantity) in books {
+            // The latest price to have taken effect, even if the scheduler hasn't copied it
+            // into the book yet
+            client.execute(
+                "
+                INSERT INTO base.in_order (isbn, order_id, quantity, unit_price)
+                SELECT isbn, $2, $3, coalesce((
+                    SELECT book_price.price FROM base.book_price
+                    WHERE book_price.isbn = book.isbn AND book_price.effective_from <= now()
+                    ORDER BY book_price.effective_from DESC, book_price.book_price_id DESC
+                    LIMIT 1
+                ), book.price)
+                FROM base.book
+                WHERE isbn = $1;
+                ",
+                &[isbn, &order_id, quantity],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn remove_book_stock(
+        client: &mut impl GenericClient,
+        books: &[(Isbn, PostgresInt)],
+        restock_policy: RestockPolicy,
+    ) -> Result<(), postgres::error::Error> {
+        for (isbn, quantity) in books {
+            let row = client.query_one(
+                "UPDATE base.book SET stock = stock - $1 WHERE isbn = $2 RETURNING stock, reorder_threshold, discontinued;",
+                &[quantity, isbn],
+            )?;
+
+            let stock: PostgresInt = row.try_get("stock")?;
+            let reorder_threshold: PostgresInt = row.try_get("reorder_threshold")?;
+            let discontinued: bool = row.try_get("discontinued")?;
+
+            if stock < reorder_threshold && !discontinued {
+                let quantity = restock_policy.quantity_for(stock, reorder_threshold);
+                request_restock(client, *isbn, quantity)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Places a pending restock order for the book unless one is already open
+    fn request_restock(
+        client: &mut impl GenericClient,
+        isbn: Isbn,
+        quantity: PostgresInt,
+    ) -> Result<(), postgres::error::Error> {
+        client.execute(
+            "
+            INSERT INTO base.restock_order (isbn, quantity, order_date, order_status)
+            SELECT $1, $2, $3, $4
+            WHERE NOT EXISTS (
+                SELECT 1 FROM base.restock_order
+                WHERE isbn = $1 AND order_status IN ($4, $5)
+            );
+            ",
+            &[
+                &isbn,
+                &quantity,
+                &Local::today().naive_local(),
+                &RestockStatus::Pending.as_str(),
+                &RestockStatus::Approved.as_str(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub async fn get_restock_orders(conn: &DbConn) -> Result<Vec<RestockOrder>, RestockError> {
+        conn.run(|c| {
+            c.query(
+                "
+                    SELECT
+                    restock_order_id,
+                    isbn,
+                    title,
+                    quantity,
+                    price_per_unit::numeric AS price_per_unit,
+                    order_date,
+                    order_status,
+                    publisher_remittance_id
+                    FROM base.restock_order INNER JOIN base.book USING (isbn)
+                    ORDER BY order_date DESC, restock_order_id DESC;
+                    ",
+                &[],
+            )
+        })
+        .await?
+        .iter()
+        .map(restock_order_from_row)
+        .collect()
+    }
+
+    fn restock_order_from_row(row: &postgres::Row) -> Result<RestockOrder, RestockError> {
+        let order_date: NaiveDate = row.try_get("order_date")?;
+        let order_status: &str = row.try_get("order_status")?;
+
+        Ok(RestockOrder {
+            restock_order_id: row.try_get("restock_order_id")?,
+            isbn: row.try_get("isbn")?,
+            title: row.try_get("title")?,
+            quantity: row.try_get("quantity")?,
+            price_per_unit: row.try_get("price_per_unit")?,
+            order_date: order_date.to_string(),
+            order_status: order_status.parse().map_err(StateError::new)?,
+            publisher_remittance_id: row.try_get("publisher_remittance_id")?,
+        })
+    }
+
+    /// Moves a restock order to a new status, adding the ordered copies to the
+    /// book's stock when it is received.
+    pub async fn set_restock_order_status(
+        conn: &DbConn,
+        restock_order_id: PostgresInt,
+        next_status: RestockStatus,
+        price_per_unit: Option<PostgresNumeric>,
+    ) -> Result<(), RestockError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let row = transaction
+                .query_opt(
+                    "SELECT isbn, quantity, order_status FROM base.restock_order WHERE restock_order_id = $1 FOR UPDATE;",
+                    &[&restock_order_id],
+                )?
+                .ok_or(RestockError::NoSuchOrder(restock_order_id))?;
+
+            let isbn: Isbn = row.try_get("isbn")?;
+            let quantity: PostgresInt = row.try_get("quantity")?;
+            let status: &str = row.try_get("order_status")?;
+            let status: RestockStatus = status.parse().map_err(StateError::new)?;
+
+            if !status.can_transition_to(next_status) {
+                Err(RestockError::InvalidTransition(status, next_status))?;
+            }
+
+            transaction.execute(
+                "UPDATE base.restock_order SET order_status = $1 WHERE restock_order_id = $2;",
+                &[&next_status.as_str(), &restock_order_id],
+            )?;
+
+            if let Some(price_per_unit) = price_per_unit {
+                transaction.execute(
+                    "UPDATE base.restock_order SET price_per_unit = $1::numeric::money WHERE restock_order_id = $2;",
+                    &[&price_per_unit, &restock_order_id],
+                )?;
+            }
+
+            if next_status == RestockStatus::Received {
+                transaction.execute(
+                    "UPDATE base.book SET stock = stock + $1 WHERE isbn = $2;",
+                    &[&quantity, &isbn],
+                )?;
+                queue_back_in_stock(&mut transaction, &[isbn])?;
+            }
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Locks the cart and the books in it for the rest of the
+    /// transaction, returning the cart contents ordered by ISBN.
+    fn lock_cart_books(
+        client: &mut impl GenericClient,
+        owner: &CartOwner,
+    ) -> Result<Vec<(Isbn, PostgresInt)>, OrderError> {
+        let (table, key) = cart_table(owner);
+
+        let cart: Vec<(Isbn, PostgresInt)> = client
+            .query(
+                format!(
+                    "SELECT isbn, quantity FROM {table} WHERE {key} = $1 ORDER BY isbn FOR UPDATE;"
+                )
+                .as_str(),
+                &[cart_key(owner)],
+            )?
+            .iter()
+            .map(|row| Ok((row.try_get("isbn")?, row.try_get("quantity")?)))
+            .collect::<Result<_, postgres::error::Error>>()?;
+
+        if cart.is_empty() {
+            Err(OrderError::EmptyCart)?;
+        }
+
+        // Locking in ISBN order keeps concurrent checkouts from deadlocking each other. The
+        // cart's own holds become the order, so only copies held by other carts are off limits.
+        for (isbn, quantity) in cart.iter() {
+            match lock_available_stock(client, owner, *isbn)? {
+                Some(available) if *quantity as i64 <= available => {}
+                _ => Err(OrderError::NotEnoughStock)?,
+            }
+        }
+
+        Ok(cart)
+    }
+
+    fn get_customer_defaults(
+        client: &mut impl GenericClient,
+        customer_id: PostgresInt,
+    ) -> Result<(PostgresInt, PostgresInt), OrderError> {
+        let row = client
+            .query_opt(
+                "SELECT default_shipping_address, default_payment_info_id FROM base.customer WHERE customer_id = $1",
+                &[&customer_id],
+            )?
+            .ok_or(StateError::new(format!(
+                "No customer with the ID ({customer_id})"
+            )))?;
+
+        Ok((
+            row.try_get("default_shipping_address")?,
+            row.try_get("default_payment_info_id")?,
+        ))
+    }
+
+    fn authorise_order_payment(
+        client: &mut impl GenericClient,
+        payment_provider: &dyn PaymentProvider,
+        order_id: PostgresInt,
+        payment_info_id: PostgresInt,
+    ) -> Result<String, OrderError> {
+        let token: String = client
+            .query_one(
+                "SELECT token FROM base.payment_info WHERE payment_info_id = $1;",
+                &[&payment_info_id],
+            )?
+            .try_get("token")?;
+
+        let total: Option<PostgresNumeric> = client
+            .query_one(
+                "
+                SELECT (SELECT sum(unit_price * quantity) FROM base.in_order WHERE order_id = $1) - discount AS total
+                FROM base.orders
+                WHERE order_id = $1;
+                ",
+                &[&order_id],
+            )?
+            .try_get("total")?;
+
+        Ok(payment_provider.authorise(&token, total.unwrap_or_default())?)
+    }
+
+    fn is_transaction_conflict(e: &postgres::error::Error) -> bool {
+        matches!(
+            e.code(),
+            Some(&SqlState::T_R_SERIALIZATION_FAILURE) | Some(&SqlState::T_R_DEADLOCK_DETECTED)
+        )
+    }
+
+    fn promotion_from_row<E>(row: &postgres::Row) -> Result<Promotion, E>
+    where
+        E: From<postgres::error::Error> + From<StateError>,
+    {
+        let discount_type: &str = row.try_get("discount_type")?;
+        let genre: Option<String> = row.try_get("genre")?;
+        let publisher_id: Option<PublisherID> = row.try_get("publisher_id")?;
+        let isbn: Option<Isbn> = row.try_get("isbn")?;
+
+        let scope = match (genre, publisher_id, isbn) {
+            (Some(genre), _, _) => PromotionScope::Genre(genre),
+            (_, Some(publisher_id), _) => PromotionScope::Publisher(publisher_id),
+            (_, _, Some(isbn)) => PromotionScope::Isbn(isbn),
+            (None, None, None) => PromotionScope::All,
+        };
+
+        Ok(Promotion {
+            promotion_id: row.try_get("promotion_id")?,
+            terms: PromotionTerms {
+                code: row.try_get("code")?,
+                discount_type: discount_type.parse().map_err(StateError::new)?,
+                discount_value: row.try_get("discount_value")?,
+                min_spend: row.try_get("min_spend")?,
+                scope,
+                starts_at: row.try_get("starts_at")?,
+                ends_at: row.try_get("ends_at")?,
+                max_uses: row.try_get("max_uses")?,
+                max_uses_per_customer: row.try_get("max_uses_per_customer")?,
+            },
+            active: row.try_get("active")?,
+        })
+    }
+
+    fn promotion_lines_from_rows(
+        rows: &[postgres::Row],
+    ) -> Result<Vec<PromotionLine>, postgres::error::Error> {
+        rows.iter()
+            .map(|row| {
+                Ok(PromotionLine {
+                    isbn: row.try_get("isbn")?,
+                    genre: row.try_get("genre")?,
+                    publisher_id: row.try_get("publisher_id")?,
+                    unit_price: row.try_get("unit_price")?,
+                    quantity: row.try_get("quantity")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Checks the customer, or the guest with the email, can use the code on the lines,
+    /// returning the promotion and the discount it gives. Locking the promotion makes checkouts
+    /// using it take turns, so two of them can't both take its last use.
+    fn price_promotion(
+        client: &mut impl GenericClient,
+        customer_id: Option<PostgresInt>,
+        guest_email: Option<&str>,
+        code: &str,
+        lines: &[PromotionLine],
+        lock: bool,
+    ) -> Result<(Promotion, PostgresNumeric), OrderError> {
+        let code = normalise_code(code);
+        let query = if lock {
+            "SELECT * FROM base.promotion WHERE code = $1 FOR UPDATE;"
+        } else {
+            "SELECT * FROM base.promotion WHERE code = $1;"
+        };
+        let promotion: Promotion = match client.query_opt(query, &[&code])? {
+            Some(row) => promotion_from_row::<OrderError>(&row)?,
+            None => Err(DiscountError::UnknownCode(code))?,
+        };
+
+        let uses = client.query_one(
+            "
+            SELECT count(*) AS total_uses,
+            count(*) FILTER (WHERE customer_id = $2 OR lower(guest_email) = lower($4)) AS customer_uses
+            FROM base.orders
+            WHERE promotion_id = $1 AND order_status NOT IN ($3, $5);
+            ",
+            &[
+                &promotion.promotion_id,
+                &customer_id,
+                &OrderStatus::Cancelled.as_str(),
+                &guest_email,
+                &OrderStatus::Returned.as_str(),
+            ],
+        )?;
+        promotion.check_available(
+            Local::now(),
+            uses.try_get("total_uses")?,
+            uses.try_get("customer_uses")?,
+        )?;
+
+        let discount = promotion.terms.discount(lines)?;
+
+        Ok((promotion, discount))
+    }
+
+    /// Takes the promotion off the order, whose books have to have been added already
+    fn apply_promotion(
+        client: &mut impl GenericClient,
+        buyer: &Buyer,
+        order_id: PostgresInt,
+        code: &str,
+    ) -> Result<(), OrderError> {
+        let rows = client.query(
+            "
+            SELECT in_order.isbn, in_order.quantity, in_order.unit_price, book.genre, book.publisher_id
+            FROM base.in_order INNER JOIN base.book USING (isbn)
+            WHERE order_id = $1;
+            ",
+            &[&order_id],
+        )?;
+        let lines = promotion_lines_from_rows(&rows)?;
+
+        let (promotion, discount) = price_promotion(
+            client,
+            buyer.customer_id(),
+            buyer.guest_email(),
+            code,
+            &lines,
+            true,
+        )?;
+
+        client.execute(
+            "UPDATE base.orders SET promotion_id = $2, discount = $3 WHERE order_id = $1;",
+            &[&order_id, &promotion.promotion_id, &discount],
+        )?;
+
+        Ok(())
+    }
+
+    /// What the code would take off the cart at its current prices, without using it. A guest's
+    /// limit on uses of the code is only checked once they give their email at checkout.
+    pub async fn quote_promotion(
+        conn: &DbConn,
+        cart: CartOwner,
+        code: String,
+    ) -> Result<PromotionQuote, OrderError> {
+        conn.run(move |c| {
+            let (table, key) = cart_table(&cart);
+            let rows = c.query(
+                format!(
+                    "
+                    SELECT cart.isbn, cart.quantity, book.price AS unit_price, book.genre, book.publisher_id
+                    FROM {table} AS cart INNER JOIN base.book USING (isbn)
+                    WHERE {key} = $1;
+                    "
+                )
+                .as_str(),
+                &[cart_key(&cart)],
+            )?;
+            let lines = promotion_lines_from_rows(&rows)?;
+            if lines.is_empty() {
+                Err(OrderError::EmptyCart)?;
+            }
+
+            let (promotion, discount) =
+                price_promotion(c, cart.customer_id(), None, &code, &lines, false)?;
+            let subtotal: PostgresNumeric = lines
+                .iter()
+                .map(|line| line.unit_price * PostgresNumeric::from(line.quantity))
+                .sum();
+
+            Ok(PromotionQuote {
+                code: promotion.terms.code,
+                subtotal,
+                discount,
+                total: subtotal - discount,
+            })
+        })
+        .await
+    }
+
+    /// Creates a promotion from the owner's terms, returning its ID. New promotions are active.
+    pub async fn create_promotion(
+        conn: &DbConn,
+        mut terms: PromotionTerms,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<PostgresInt, PromotionError> {
+        terms.code = normalise_code(&terms.code);
+        let code = terms.code.clone();
+        let scope = terms.scope.clone();
+        let (genre, publisher_id, isbn) = match &terms.scope {
+            PromotionScope::All => (None, None, None),
+            PromotionScope::Genre(genre) => (Some(genre.clone()), None, None),
+            PromotionScope::Publisher(publisher_id) => (None, Some(*publisher_id), None),
+            PromotionScope::Isbn(isbn) => (None, None, Some(*isbn)),
+        };
+
+        let result = conn
+            .run(move |c| {
+                c.query_one(
+                    "
+                    INSERT INTO base.promotion
+                    (code, discount_type, discount_value, min_spend, genre, publisher_id, isbn, starts_at, ends_at, max_uses, max_uses_per_customer, owner_id)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+                    RETURNING promotion_id;
+                    ",
+                    &[
+                        &terms.code,
+                        &terms.discount_type.as_str(),
+                        &terms.discount_value,
+                        &terms.min_spend,
+                        &genre,
+                        &publisher_id,
+                        &isbn,
+                        &terms.starts_at,
+                        &terms.ends_at,
+                        &terms.max_uses,
+                        &terms.max_uses_per_customer,
+                        &owner_id,
+                    ],
+                )
+            })
+            .await;
+
+        match result {
+            Ok(row) => Ok(row.try_get("promotion_id")?),
+            Err(e) => {
+                let constraint = e.as_db_error().and_then(|e| e.constraint());
+                Err(match (e.code(), constraint, scope) {
+                    (Some(&SqlState::UNIQUE_VIOLATION), _, _) => {
+                        PromotionError::DuplicateCode(code)
+                    }
+                    (_, Some("promotion_isbn_fkey"), PromotionScope::Isbn(isbn)) => {
+                        PromotionError::NoSuchBook(isbn)
+                    }
+                    (_, Some("promotion_publisher_id_fkey"), PromotionScope::Publisher(id)) => {
+                        PromotionError::NoSuchPublisher(id)
+                    }
+                    _ => e.into(),
+                })
+            }
+        }
+    }
+
+    /// Every promotion, newest first, with how many orders have used it
+    pub async fn get_promotions(conn: &DbConn) -> Result<Vec<PromotionSummary>, PromotionError> {
+        let rows = conn
+            .run(|c| {
+                c.query(
+                    "
+                    SELECT promotion.*, publisher.company_name,
+                    (SELECT count(*) FROM base.orders WHERE orders.promotion_id = promotion.promotion_id AND order_status NOT IN ($1, $2)) AS uses
+                    FROM base.promotion
+                    LEFT JOIN base.publisher USING (publisher_id)
+                    ORDER BY promotion.created_at DESC, promotion.promotion_id DESC;
+                    ",
+                    &[
+                        &OrderStatus::Cancelled.as_str(),
+                        &OrderStatus::Returned.as_str(),
+                    ],
+                )
+            })
+            .await?;
+
+        rows.iter()
+            .map(|row| {
+                let promotion: Promotion = promotion_from_row::<PromotionError>(row)?;
+                let publisher_name: Option<&str> = row.try_get("company_name")?;
+                let format_time = |time: Option<DateTime<Local>>| {
+                    time.map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+                };
+
+                Ok(PromotionSummary {
+                    promotion_id: promotion.promotion_id,
+                    discount: promotion.terms.describe_discount(),
+                    scope: promotion.terms.scope.describe(publisher_name),
+                    starts_at: format_time(promotion.terms.starts_at),
+                    ends_at: format_time(promotion.terms.ends_at),
+                    code: promotion.terms.code,
+                    min_spend: promotion.terms.min_spend,
+                    max_uses: promotion.terms.max_uses,
+                    max_uses_per_customer: promotion.terms.max_uses_per_customer,
+                    uses: row.try_get("uses")?,
+                    active: promotion.active,
+                })
+            })
+            .collect()
+    }
+
+    /// Switches a promotion on or off. Orders that already used it keep their discount.
+    pub async fn set_promotion_active(
+        conn: &DbConn,
+        promotion_id: PostgresInt,
+        active: bool,
+    ) -> Result<(), PromotionError> {
+        let updated = conn
+            .run(move |c| {
+                c.execute(
+                    "UPDATE base.promotion SET active = $2 WHERE promotion_id = $1;",
+                    &[&promotion_id, &active],
+                )
+            })
+            .await?;
+
+        if updated == 0 {
+            Err(PromotionError::NoSuchPromotion(promotion_id))?;
+        }
+
+        Ok(())
+    }
+
+    /// Orders placed with each promotion within the period, with what they would have cost and
+    /// what the promotion took off. Cancelled and returned orders aren't counted.
+    pub async fn get_promotion_sales(
+        conn: &DbConn,
+        period_start: NaiveDate,
+        period_end: NaiveDate,
+    ) -> Result<Vec<PromotionSales>, RoyaltyError> {
+        if period_start > period_end {
+            Err(RoyaltyError::InvalidPeriod)?;
+        }
+
+        let rows = conn
+            .run(move |c| {
+                c.query(
+                    "
+                    SELECT promotion.promotion_id, promotion.code,
+                    count(*) AS orders,
+                    sum(lines.gross_sales) AS gross_sales,
+                    sum(orders.discount) AS discount
+                    FROM base.orders
+                    INNER JOIN base.promotion USING (promotion_id)
+                    INNER JOIN (
+                        SELECT order_id, sum(unit_price * quantity) AS gross_sales
+                        FROM base.in_order
+                        GROUP BY order_id
+                    ) AS lines USING (order_id)
+                    WHERE orders.order_status NOT IN ($3, $4) AND orders.order_date BETWEEN $1 AND $2
+                    GROUP BY promotion.promotion_id, promotion.code
+                    ORDER BY discount DESC, promotion.code;
+                    ",
+                    &[
+                        &period_start,
+                        &period_end,
+                        &OrderStatus::Cancelled.as_str(),
+                        &OrderStatus::Returned.as_str(),
+                    ],
+                )
+            })
+            .await?;
+
+        rows.iter()
+            .map(|row| {
+                let gross_sales: PostgresNumeric = row.try_get("gross_sales")?;
+                let discount: PostgresNumeric = row.try_get("discount")?;
+
+                Ok(PromotionSales {
+                    promotion_id: row.try_get("promotion_id")?,
+                    code: row.try_get("code")?,
+                    orders: row.try_get("orders")?,
+                    gross_sales,
+                    discount,
+                    net_sales: gross_sales - discount,
+                })
+            })
+            .collect()
+    }
+
+    /// Who an order is for: a customer, or a guest checking out their guest cart
+    #[derive(Clone, Debug)]
+    pub enum Buyer {
+        Customer(PostgresInt),
+        Guest { cart_token: String, email: String },
+    }
+
+    impl Buyer {
+        pub fn new(cart: CartOwner, guest_email: Option<String>) -> Buyer {
+            match cart {
+                CartOwner::Customer(customer_id) => Buyer::Customer(customer_id),
+                CartOwner::Guest(cart_token) => Buyer::Guest {
+                    cart_token,
+                    email: guest_email.unwrap_or_default().trim().to_string(),
+                },
+            }
+        }
+
+        fn cart(&self) -> CartOwner {
+            match self {
+                Buyer::Customer(customer_id) => CartOwner::Customer(*customer_id),
+                Buyer::Guest { cart_token, .. } => CartOwner::Guest(cart_token.clone()),
+            }
+        }
+
+        fn customer_id(&self) -> Option<PostgresInt> {
+            match self {
+                Buyer::Customer(customer_id) => Some(*customer_id),
+                Buyer::Guest { .. } => None,
+            }
+        }
+
+        fn guest_email(&self) -> Option<&str> {
+            match self {
+                Buyer::Customer(_) => None,
+                Buyer::Guest { email, .. } => Some(email),
+            }
+        }
+    }
+
+    /// Only catches obvious typos, since the address can't be checked without mailing it
+    fn is_email(email: &str) -> bool {
+        match email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+                    && !domain.contains('@')
+                    && !email.contains(char::is_whitespace)
+                    && email.len() <= 254
+            }
+            None => false,
+        }
+    }
+
+    /// Checks out the buyer's cart as a single transaction, so concurrent
+    /// checkouts cannot oversell a book and a failure part way through leaves
+    /// no trace of the order. Guests have no defaults to fall back on, so they
+    /// have to give an email, shipping address and card.
+    pub async fn create_order(
+        conn: &DbConn,
+        buyer: Buyer,
+        address: Option<schema::no_id::Address>,
+        payment_info: Option<schema::no_id::PaymentInfo>,
+        promotion_code: Option<String>,
+        restock_policy: RestockPolicy,
+        payment_provider: PaymentProviderState,
+    ) -> Result<PostgresInt, OrderError> {
+        if let Some(email) = buyer.guest_email() {
+            if email.is_empty() || address.is_none() || payment_info.is_none() {
+                Err(OrderError::GuestDetailsRequired)?;
+            }
+            if !is_email(email) {
+                Err(OrderError::InvalidEmail(email.to_string()))?;
+            }
+        }
+
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            if let Some(customer_id) = buyer.customer_id() {
+                let verified: bool = transaction
+                    .query_one(
+                        "SELECT email_verified_at IS NOT NULL AS verified FROM base.customer WHERE customer_id = $1;",
+                        &[&customer_id],
+                    )?
+                    .try_get("verified")?;
+                if !verified {
+                    Err(OrderError::EmailNotVerified)?;
+                }
+            }
+
+            let cart = buyer.cart();
+            let books = lock_cart_books(&mut transaction, &cart)?;
+
+            let (address_id, payment_info_id) = match (address, payment_info, buyer.customer_id()) {
+                (Some(address), Some(payment_info), _) => (
+                    get_or_insert_address(&mut transaction, address)?,
+                    get_or_insert_payment_info(&mut transaction, payment_info)?,
+                ),
+                (address, payment_info, Some(customer_id)) => {
+                    let (default_address_id, default_payment_info_id) =
+                        get_customer_defaults(&mut transaction, customer_id)?;
+
+                    let address_id = match address {
+                        Some(address) => get_or_insert_address(&mut transaction, address)?,
+                        None => default_address_id,
+                    };
+
+                    let payment_info_id = match payment_info {
+                        Some(payment_info) => get_or_insert_payment_info(&mut transaction, payment_info)?,
+                        None => default_payment_info_id,
+                    };
+
+                    (address_id, payment_info_id)
+                }
+                _ => Err(OrderError::GuestDetailsRequired)?,
+            };
+
+            let tracking_number = get_tracking_number();
+
+            let order_id: PostgresInt = transaction.query_one(
+                "
+                INSERT INTO base.orders
+                (customer_id, guest_email, shipping_address_id, tracking_number, order_status, order_date, payment_info_id)
+                VALUES
+                ($1, $2, $3, $4, $5, $6, $7)
+                RETURNING order_id;
+                ",
+                &[&buyer.customer_id(), &buyer.guest_email(), &address_id, &tracking_number, &OrderStatus::Processing.as_str(), &Local::today().naive_local(), &payment_info_id],
+            )?.try_get("order_id")?;
+
+            record_order_status(&mut transaction, order_id, OrderStatus::Processing)?;
+
+            add_books_to_order(&mut transaction, &books, order_id)?;
+            remove_book_stock(&mut transaction, &books, restock_policy)?;
+
+            if let Some(code) = promotion_code {
+                apply_promotion(&mut transaction, &buyer, order_id, &code)?;
+            }
+
+            clear_cart(&mut transaction, &cart)?;
+
+            queue_order_mail(&mut transaction, order_id, MailTemplate::OrderConfirmation)?;
+
+            // Charge last, so a declined payment simply rolls the order back
+            let authorisation_id = authorise_order_payment(&mut transaction, payment_provider.as_ref(), order_id, payment_info_id)?;
+
+            // The card is held from here on, so an order that isn't saved has to let it go
+            let saved = match transaction.execute(
+                "UPDATE base.orders SET payment_authorisation = $1 WHERE order_id = $2;",
+                &[&authorisation_id, &order_id],
+            ) {
+                Ok(_) => transaction.commit(),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = saved {
+                if let Err(void_error) = payment_provider.void(&authorisation_id) {
+                    error!(
+                        "Could not void authorisation {} for an order that wasn't saved: {}",
+                        authorisation_id, void_error
+                    );
+                }
+                Err(e)?;
+            }
+
+            Ok(order_id)
+        })
+        .await
+        .map_err(|e| match e {
+            OrderError::DBError(e) if is_transaction_conflict(&e) => OrderError::Conflict,
+            e => e,
+        })
+    }
+
+    fn record_order_status(
+        client: &mut impl GenericClient,
+        order_id: PostgresInt,
+        order_status: OrderStatus,
+    ) -> Result<(), postgres::error::Error> {
+        client.execute(
+            "INSERT INTO base.order_status_history (order_id, order_status) VALUES ($1, $2);",
+            &[&order_id, &order_status.as_str()],
+        )?;
+
+        Ok(())
+    }
+
+    /// Moves an order along its fulfilment lifecycle, recording when the change happened.
+    /// Cancelled and returned orders put their books back into stock, and cancelling needs a
+    /// `reason`, which is ignored for any other status.
+    pub async fn set_order_status(
+        conn: &DbConn,
+        order_id: PostgresInt,
+        next_status: OrderStatus,
+        reason: Option<String>,
+    ) -> Result<(), OrderStatusError> {
+        let reason = reason.unwrap_or_default().trim().to_string();
+
+        if next_status == OrderStatus::Cancelled {
+            if reason.is_empty() {
+                Err(OrderStatusError::MissingReason)?;
+            } else if reason.chars().count() > 200 {
+                Err(OrderStatusError::ReasonTooLong)?;
+            }
+        }
+
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let row = transaction
+                .query_opt(
+                    "SELECT order_status FROM base.orders WHERE order_id = $1 FOR UPDATE;",
+                    &[&order_id],
+                )?
+                .ok_or(OrderStatusError::NoSuchOrder(order_id))?;
+
+            let status: OrderStatus = row
+                .try_get::<_, &str>("order_status")?
+                .parse()
+                .map_err(StateError::new)?;
+
+            if !status.can_transition_to(next_status) {
+                Err(OrderStatusError::InvalidTransition(status, next_status))?;
+            }
+
+            if next_status == OrderStatus::Cancelled {
+                record_cancellation(&mut transaction, order_id, &reason)?;
+            } else {
+                transaction.execute(
+                    "UPDATE base.orders SET order_status = $1 WHERE order_id = $2;",
+                    &[&next_status.as_str(), &order_id],
+                )?;
+                record_order_status(&mut transaction, order_id, next_status)?;
+
+                if next_status == OrderStatus::Returned {
+                    restore_order_stock(&mut transaction, order_id)?;
+                }
+            }
+
+            queue_order_mail(&mut transaction, order_id, MailTemplate::OrderStatus)?;
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Puts the books from a cancelled or returned order back into stock
+    fn restore_order_stock(
+        client: &mut impl GenericClient,
+        order_id: PostgresInt,
+    ) -> Result<(), postgres::error::Error> {
+        let isbns: Vec<Isbn> = client
+            .query(
+                "
+                UPDATE base.book SET stock = book.stock + in_order.quantity
+                FROM base.in_order
+                WHERE book.isbn = in_order.isbn AND in_order.order_id = $1
+                RETURNING book.isbn;
+                ",
+                &[&order_id],
+            )?
+            .iter()
+            .map(|row| row.try_get("isbn"))
+            .collect::<Result<_, _>>()?;
+
+        queue_back_in_stock(client, &isbns)?;
+
+        Ok(())
+    }
+
+    /// Cancels an order for `reason`, returning its books to stock
+    fn record_cancellation(
+        client: &mut impl GenericClient,
+        order_id: PostgresInt,
+        reason: &str,
+    ) -> Result<(), postgres::error::Error> {
+        client.execute(
+            "UPDATE base.orders SET order_status = $1 WHERE order_id = $2;",
+            &[&OrderStatus::Cancelled.as_str(), &order_id],
+        )?;
+        record_order_status(client, order_id, OrderStatus::Cancelled)?;
+        restore_order_stock(client, order_id)?;
+
+        client.execute(
+            "INSERT INTO base.order_cancellation (order_id, reason) VALUES ($1, $2);",
+            &[&order_id, &reason],
+        )?;
+
+        Ok(())
+    }
+
+    /// Cancels one of the customer's orders that has not started fulfilment yet,
+    /// returning its books to stock and recording why it was cancelled
+    pub async fn cancel_order(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+        order_id: PostgresInt,
+        reason: String,
+    ) -> Result<(), CancelOrderError> {
+        let reason = reason.trim().to_string();
+
+        if reason.is_empty() {
+            Err(CancelOrderError::MissingReason)?;
+        } else if reason.chars().count() > 200 {
+            Err(CancelOrderError::ReasonTooLong)?;
+        }
+
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            // Another customer's order is reported as missing rather than revealing it exists
+            let row = transaction
+                .query_opt(
+                    "SELECT order_status FROM base.orders WHERE order_id = $1 AND customer_id = $2 FOR UPDATE;",
+                    &[&order_id, &customer_id],
+                )?
+                .ok_or(CancelOrderError::NoSuchOrder(order_id))?;
+
+            let status: OrderStatus = row
+                .try_get::<_, &str>("order_status")?
+                .parse()
+                .map_err(StateError::new)?;
+
+            if status != OrderStatus::Processing {
+                Err(CancelOrderError::NotCancellable(status))?;
+            }
+
+            record_cancellation(&mut transaction, order_id, &reason)?;
+
+            queue_order_mail(&mut transaction, order_id, MailTemplate::OrderStatus)?;
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn get_order_status_history(
+        conn: &DbConn,
+        order_id: PostgresInt,
+    ) -> Result<Vec<OrderStatusChange>, postgres::error::Error> {
+        Ok(conn
+            .run(move |c| {
+                c.query(
+                    "
+                    SELECT order_status, changed_at FROM base.order_status_history
+                    WHERE order_id = $1
+                    ORDER BY changed_at, order_status_history_id;
+                    ",
+                    &[&order_id],
                 )
             })
             .await?
-            .get("customer_id"))
+            .iter()
+            .filter_map(|row| {
+                let result: Result<OrderStatusChange, OrderStatusError> = try {
+                    let changed_at: DateTime<Local> = row.try_get("changed_at")?;
+
+                    OrderStatusChange {
+                        order_status: row
+                            .try_get::<_, &str>("order_status")?
+                            .parse()
+                            .map_err(StateError::new)?,
+                        changed_at: changed_at.format("%Y-%m-%d %H:%M").to_string(),
+                    }
+                };
+
+                result.ok()
+            })
+            .collect())
     }
 
-    pub async fn try_create_new_owner<'a, T: AsRef<str>>(
+    /// Lists orders for the fulfilment queue, oldest first, optionally only those in one status
+    pub async fn get_fulfilment_orders(
         conn: &DbConn,
-        email: T,
-        password: T,
-        name: T,
-    ) -> Result<PostgresInt, CreateOwnerError> {
-        let name = name.as_ref().to_string();
-        let email = email.as_ref().to_string();
-        let password = password.as_ref().to_string();
-
-        let password_hash = bcrypt::hash(password, 10)?;
+        order_status: Option<OrderStatus>,
+    ) -> Result<Vec<FulfilmentOrder>, postgres::error::Error> {
+        let order_status = order_status.map(|status| status.as_str());
 
         Ok(conn
             .run(move |c| {
-                c.query_one(
-                    "INSERT INTO base.owner (name, email, password_hash) VALUES ($1, $2, $3) RETURNING owner_id;",
-                    &[&name, &email, &password_hash],
+                c.query(
+                    "
+                    SELECT
+                    orders.order_id,
+                    coalesce(customer.name, 'Guest') AS name,
+                    coalesce(customer.email, orders.guest_email) AS email,
+                    add.street_address,
+                    add.postal_code,
+                    add.province,
+                    tracking_number,
+                    order_status,
+                    order_date,
+                    (SELECT sum(quantity) FROM base.in_order WHERE in_order.order_id = orders.order_id) AS num_books
+                    FROM
+                    base.orders AS orders
+                    LEFT JOIN base.customer AS customer ON orders.customer_id = customer.customer_id
+                    INNER JOIN base.address AS add ON orders.shipping_address_id = add.address_id
+                    WHERE $1::varchar IS NULL OR order_status = $1
+                    ORDER BY order_date, orders.order_id;
+                    ",
+                    &[&order_status],
                 )
             })
             .await?
-            .get("owner_id"))
+            .iter()
+            .filter_map(|row| {
+                let result: Result<FulfilmentOrder, OrderStatusError> = try {
+                    let order_status: OrderStatus = row
+                        .try_get::<_, &str>("order_status")?
+                        .parse()
+                        .map_err(StateError::new)?;
+                    let date: NaiveDate = row.try_get("order_date")?;
+                    let num_books: Option<i64> = row.try_get("num_books")?;
+
+                    FulfilmentOrder {
+                        order_id: row.try_get("order_id")?,
+                        customer_name: row.try_get("name")?,
+                        customer_email: row.try_get("email")?,
+                        shipping_address: Address::new::<&str>(
+                            row.try_get("street_address")?,
+                            row.try_get("postal_code")?,
+                            row.try_get("province")?,
+                        ),
+                        tracking_number: row.try_get("tracking_number")?,
+                        order_status,
+                        next_statuses: order_status.next_statuses(),
+                        order_date: date.to_string(),
+                        num_books: num_books.unwrap_or(0),
+                    }
+                };
+
+                result.ok()
+            })
+            .collect())
     }
 
-    pub async fn owner_exists(conn: &DbConn) -> Result<bool, postgres::error::Error> {
-        Ok(!conn
-            .run(|c| c.query("SELECT * FROM base.owner;", &[]))
-            .await?
-            .is_empty())
+    pub async fn get_books_for_order(
+        conn: &DbConn,
+        order: OrderNoBooks,
+    ) -> Result<Order, postgres::error::Error> {
+        let books = conn.run(move |c| {
+            // Books are listed at the price they were bought for
+            c.query("
+                SELECT book.isbn, title, author_name, genre, publisher_id, num_pages, in_order.unit_price AS price, author_royalties, reorder_threshold, stock, discontinued, quantity
+                FROM base.in_order INNER JOIN base.book ON base.in_order.isbn = base.book.isbn
+                WHERE order_id = $1;
+                ", &[&order.order_id])
+        }).await?.iter().flat_map(|row| {
+            let result: Result<(Book, u32), OrderError> = try {
+                let quantity: i32 = row.try_get("quantity")?;
+                (Book::from_row(row)?, i32::max(quantity, 0) as u32)
+            };
+
+            result.ok()
+        }).collect();
+
+        Ok(Order::from_order_with_id(order, books))
     }
 
-    pub enum OwnerLoginType {
-        DefaultOwner,
-        OwnerAccount(PostgresInt),
+    /// The customer's order, or `None` if they have no order with the ID
+    pub async fn get_order_info(
+        conn: &DbConn,
+        customer_id: PostgresInt,
+        order_id: PostgresInt,
+    ) -> Result<Option<OrderNoBooks>, OrderError> {
+        let row = conn.run(move |c| c.query_opt(
+            "
+            SELECT
+            orders.order_id,
+            add.street_address,
+            add.postal_code,
+            add.province,
+            bill.street_address AS bill_street_address,
+            bill.postal_code AS bill_postal_code,
+            bill.province AS bill_province,
+            order_status,
+            order_date,
+            tracking_number,
+            name_on_card,
+            token,
+            last_four,
+            brand,
+            expiry,
+            cancellation.reason AS cancellation_reason,
+            promotion.code AS promotion_code,
+            orders.discount
+            FROM
+            base.orders AS orders
+            INNER JOIN base.address AS add ON orders.shipping_address_id = add.address_id
+            INNER JOIN base.payment_info AS payment ON orders.payment_info_id = payment.payment_info_id
+            INNER JOIN base.address AS bill ON payment.billing_address_id = bill.address_id
+            LEFT JOIN base.order_cancellation AS cancellation ON orders.order_id = cancellation.order_id
+            LEFT JOIN base.promotion AS promotion ON orders.promotion_id = promotion.promotion_id
+            WHERE orders.order_id = $1 AND orders.customer_id = $2;
+            ",
+            &[&order_id, &customer_id])).await?;
+
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let address = Address::new::<&str>(
+            row.try_get("street_address")?,
+            row.try_get("postal_code")?,
+            row.try_get("province")?,
+        );
+        let billing_address = Address::new::<&str>(
+            row.try_get("bill_street_address")?,
+            row.try_get("bill_postal_code")?,
+            row.try_get("bill_province")?,
+        );
+        let expiry = Expiry::from_str::<&str>(row.try_get("expiry")?)
+            .ok_or(StateError::new("Invalid expiry"))?;
+        let payment_info = PaymentInfo::new::<&str>(
+            row.try_get("name_on_card")?,
+            expiry,
+            TokenisedCard {
+                token: row.try_get("token")?,
+                last_four: row.try_get("last_four")?,
+                brand: row.try_get("brand")?,
+            },
+            billing_address,
+        );
+
+        let date: NaiveDate = row.try_get("order_date")?;
+
+        let order = OrderNoBooks {
+            order_id: row.try_get("order_id")?,
+            shipping_address: address,
+            tracking_number: row.try_get("tracking_number")?,
+            order_status: row
+                .try_get::<_, &str>("order_status")?
+                .parse()
+                .map_err(StateError::new)?,
+            order_date: date.to_string(),
+            payment_info,
+            cancellation_reason: row.try_get("cancellation_reason")?,
+            promotion_code: row.try_get("promotion_code")?,
+            discount: row.try_get("discount")?,
+        };
+
+        Ok(Some(order))
     }
 
-    pub async fn validate_owner_login<T: AsRef<str>>(
+    pub async fn get_customer_orders_info(
         conn: &DbConn,
-        email: T,
-        password: T,
-    ) -> Result<OwnerLoginType, LoginError> {
-        let email = email.as_ref().to_owned();
-        let password = password.as_ref().to_owned();
+        customer_id: PostgresInt,
+    ) -> Result<Vec<schema::joined::Order>, postgres::error::Error> {
+        let orders_no_books: Vec<OrderNoBooks> = conn.run(move |c| c.query(
+            "
+            SELECT
+            orders.order_id,
+            add.street_address,
+            add.postal_code,
+            add.province,
+            bill.street_address AS bill_street_address,
+            bill.postal_code AS bill_postal_code,
+            bill.province AS bill_province,
+            order_status,
+            order_date,
+            tracking_number,
+            name_on_card,
+            token,
+            last_four,
+            brand,
+            expiry,
+            cancellation.reason AS cancellation_reason,
+            promotion.code AS promotion_code,
+            orders.discount
+            FROM
+            base.orders AS orders
+            INNER JOIN base.address AS add ON orders.shipping_address_id = add.address_id
+            INNER JOIN base.payment_info AS payment ON orders.payment_info_id = payment.payment_info_id
+            INNER JOIN base.address AS bill ON payment.billing_address_id = bill.address_id
+            LEFT JOIN base.order_cancellation AS cancellation ON orders.order_id = cancellation.order_id
+            LEFT JOIN base.promotion AS promotion ON orders.promotion_id = promotion.promotion_id
+            WHERE customer_id = $1;
+            ",
+            &[&customer_id])).await?.iter()
+           .filter_map(
+            |row| {
+                let result: Result<OrderNoBooks, OrderError> = try {
+                    let address = Address::new::<&str>(row.try_get("street_address")?, row.try_get("postal_code")?, row.try_get("province")?);
+                    let billing_address = Address::new::<&str>(row.try_get("bill_street_address")?, row.try_get("bill_postal_code")?, row.try_get("bill_province")?);
+                    let expiry = Expiry::from_str::<&str>(row.try_get("expiry")?).ok_or(StateError::new("Invalid expiry"))?;
+                    let card = TokenisedCard { token: row.try_get("token")?, last_four: row.try_get("last_four")?, brand: row.try_get("brand")? };
+                    let payment_info = PaymentInfo::new::<&str>(row.try_get("name_on_card")?, expiry, card, billing_address);
 
-        if !owner_exists(conn).await? {
-            if email == "admin@local" && password == "default" {
-                Ok(OwnerLoginType::DefaultOwner)
-            } else {
-                Err(LoginError::CredentialError)
-            }
-        } else {
-            let row = conn
-                .run(move |c| {
-                    c.query_opt(
-                        "SELECT owner_id, password_hash FROM base.owner WHERE email = $1",
-                        &[&email],
-                    )
-                })
-                .await?;
+                    let date: NaiveDate = row.try_get("order_date")?; 
 
-            if let Some(row) = row {
-                let owner_id = row.try_get("owner_id")?;
-                let password_hash = row.try_get("password_hash")?;
+                    OrderNoBooks {
+                        order_id: row.try_get("order_id")?,
+                        shipping_address: address,
+                        tracking_number: row.try_get("tracking_number")?,
+                        order_status: row.try_get::<_, &str>("order_status")?.parse().map_err(StateError::new)?,
+                        order_date: date.to_string(),
+                        payment_info,
+                        cancellation_reason: row.try_get("cancellation_reason")?,
+                        promotion_code: row.try_get("promotion_code")?,
+                        discount: row.try_get("discount")?,
+                    }
+                };
 
-                if bcrypt::verify(password, password_hash)? {
-                    Ok(OwnerLoginType::OwnerAccount(owner_id))
-                } else {
-                    Err(LoginError::CredentialError)
-                }
-            } else {
-                Err(LoginError::CredentialError)
+                result.ok()
+        }).collect();
+
+        let mut orders: Vec<Order> = vec![];
+
+        for order in orders_no_books {
+            orders.push(get_books_for_order(conn, order).await?);
+        }
+
+        Ok(orders)
+    }
+
+    /// Sets whether the books are discontinued, recording a change for the ones that weren't already
+    fn set_books_discontinued(
+        client: &mut impl GenericClient,
+        books: &[Isbn],
+        discontinued: bool,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<(), postgres::error::Error> {
+        for isbn in books {
+            let row = client.query_opt(
+                "UPDATE base.book SET discontinued = $2, version = version + 1 WHERE isbn = $1 AND discontinued <> $2 RETURNING version;",
+                &[isbn, &discontinued],
+            )?;
+
+            if let Some(row) = row {
+                let change = FieldChange {
+                    field: "Discontinued",
+                    old: (!discontinued).to_string(),
+                    new: discontinued.to_string(),
+                };
+                record_book_edit(client, *isbn, row.try_get("version")?, owner_id, &[change])?;
             }
         }
+
+        Ok(())
     }
 
-    pub async fn get_customer(
+    pub async fn discontinue_books(
         conn: &DbConn,
-        customer_id: PostgresInt,
-    ) -> Result<Option<crate::schema::entities::Customer>, postgres::error::Error> {
-        let row = conn
-            .run(move |c| {
-                c.query_opt(
-                    "SELECT name, email, default_shipping_address, default_payment_info_id
-            FROM base.customer WHERE customer_id = $1",
-                    &[&customer_id],
-                )
-            })
-            .await?;
+        books: Vec<Isbn>,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<(), postgres::error::Error> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+            set_books_discontinued(&mut transaction, &books, true, owner_id)?;
+            transaction.commit()
+        })
+        .await
+    }
 
-        Ok(match row {
-            Some(row) => Some(schema::entities::Customer {
-                name: row.try_get("name")?,
-                email: row.try_get("email")?,
-                default_shipping_address_id: row.try_get("default_shipping_address")?,
-                default_payment_info_id: row.try_get("default_payment_info_id")?,
-            }),
-            None => None,
+    pub async fn undiscontinue_books(
+        conn: &DbConn,
+        books: Vec<Isbn>,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<(), postgres::error::Error> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+            set_books_discontinued(&mut transaction, &books, false, owner_id)?;
+            transaction.commit()
         })
+        .await
+    }
+
+    pub async fn try_create_publisher<T: AsRef<str>>(
+        conn: &DbConn,
+        company_name: T,
+        email: T,
+        address: no_id::Address,
+        phone_number: T,
+        bank_number: T,
+    ) -> Result<PublisherID, CreatePublisherError> {
+        let company_name = company_name.as_ref().to_owned();
+        let email = email.as_ref().to_owned();
+        let phone_number = phone_number.as_ref().to_owned();
+        let bank_number = bank_number.as_ref().to_owned();
+
+        let address_id = conn.run(move |c| get_or_insert_address(c, address)).await?;
+
+        Ok(conn.run(move |c| c.query_one(
+            "INSERT INTO base.publisher
+            (company_name, phone_number, bank_number, address_id, email) VALUES ($1, $2, $3, $4, $5) RETURNING publisher_id;",
+            &[&company_name, &phone_number, &bank_number, &address_id, &email]) ).await?.try_get("publisher_id")?)
     }
 
-    pub async fn get_customer_info(
+    pub async fn get_sales_by_date(
         conn: &DbConn,
-        customer_id: PostgresInt,
-    ) -> Result<Option<crate::schema::joined::Customer>, postgres::error::Error> {
-        Ok(conn
-            .run(move |c| {
-                c.query_opt(
-                    "
-                SELECT
-                customer_id,
-                name,
-                email,
-                expiry,
-                name_on_card,
-                def_shipping.street_address AS def_street_address,
-                def_shipping.postal_code AS def_postal,
-                def_shipping.province AS def_province,
-                billing_add.street_address AS bill_street_address,
-                billing_add.postal_code AS bill_postal,
-                billing_add.province AS bill_province
-                FROM
-                base.customer AS customer
-                INNER JOIN base.address AS def_shipping ON customer.default_shipping_address = def_shipping.address_id
-                INNER JOIN base.payment_info AS payment ON customer.default_payment_info_id = payment.payment_info_id
-                INNER JOIN base.address AS billing_add ON payment.billing_address_id = billing_add.address_id
-                WHERE customer.customer_id = $1;
-            ",
-                    &[&customer_id],
-                )
+    ) -> Result<Vec<(NaiveDate, i64)>, postgres::error::Error> {
+        let rows = conn
+            .run(|c| c.query("SELECT * FROM base.sales;", &[]))
+            .await?;
+
+        Ok(rows
+            .iter()
+            .flat_map(|row| {
+                let result: Result<(NaiveDate, i64), postgres::error::Error> =
+                    try { (row.try_get("order_date")?, row.try_get("quantity")?) };
+
+                result.ok()
             })
-            .await?
-           .map(|row| {
-               use crate::schema::joined::Customer;
-            let result: Result<Customer, postgres::error::Error> = try { Customer {
-                            name: row.try_get("name")?,
-                            email: row.try_get("email")?,
-                            street_address: row.try_get("def_street_address")?,
-                            postal_code: row.try_get("def_postal")?,
-                            province: row.try_get("def_province")?,
-                            expiry: row.try_get("expiry")?,
-                            name_on_card: row.try_get("name_on_card")?,
-                            billing_street_address: row.try_get("bill_street_address")?,
-                            billing_postal_code: row.try_get("bill_postal")?,
-                            billing_province: row.try_get("bill_province")?,
-            }};
-               result.ok()
-           }).flatten())
+            .collect())
     }
 
-    pub async fn get_customer_cart(
+    pub async fn get_sales_by_publisher(
         conn: &DbConn,
-        customer_id: PostgresInt,
-    ) -> Result<Vec<(ISBN, u32)>, postgres::error::Error> {
-        Ok(conn
-            .run(move |c| {
-                c.query(
-                    "SELECT isbn, quantity FROM base.in_cart WHERE customer_id = $1",
-                    &[&customer_id],
-                )
-            })
-            .await?
+        publisher_id: PostgresInt,
+    ) -> Result<Vec<(NaiveDate, i64)>, postgres::error::Error> {
+        let rows = conn
+            .run(move |c| c.query("SELECT order_date, sum(quantity) as quantity FROM base.raw_sales_data WHERE publisher_id = $1 GROUP BY (order_date);", &[&publisher_id]))
+            .await?;
+
+        Ok(rows
             .iter()
-            .filter_map(|row| {
-                let result: Result<(ISBN, i32), postgres::error::Error> =
-                    try { (row.try_get("isbn")?, row.try_get("quantity")?) };
+            .flat_map(|row| {
+                let result: Result<(NaiveDate, i64), postgres::error::Error> =
+                    try { (row.try_get("order_date")?, row.try_get("quantity")?) };
+
                 result.ok()
             })
-            .map(|b| (b.0, i32::max(b.1, 0) as u32))
             .collect())
     }
 
-    pub async fn add_to_cart(
+    /// Order statuses whose sales earn royalties. Earlier orders may still be cancelled, and
+    /// returned orders drop out of the sales data altogether.
+    const ROYALTY_ORDER_STATUSES: [OrderStatus; 2] = [OrderStatus::Shipped, OrderStatus::Delivered];
+
+    fn royalty_order_statuses() -> Vec<&'static str> {
+        ROYALTY_ORDER_STATUSES
+            .iter()
+            .map(|status| status.as_str())
+            .collect()
+    }
+
+    /// Per author and per book royalties on sales ordered within the period, split into what
+    /// is still owed and what earlier payouts already covered
+    pub async fn get_royalties(
         conn: &DbConn,
-        customer_id: PostgresInt,
-        isbn: ISBN,
-    ) -> Result<(), postgres::error::Error> {
-        let cart_row = conn
+        period_start: NaiveDate,
+        period_end: NaiveDate,
+    ) -> Result<Vec<AuthorRoyalties>, RoyaltyError> {
+        if period_start > period_end {
+            Err(RoyaltyError::InvalidPeriod)?;
+        }
+
+        let statuses = royalty_order_statuses();
+
+        let rows = conn
             .run(move |c| {
-                c.query_opt(
-                    "SELECT quantity FROM base.in_cart WHERE customer_id = $1 AND isbn = $2",
-                    &[&customer_id, &isbn],
+                c.query(
+                    "
+                    SELECT
+                    sales.author_name,
+                    sales.isbn,
+                    sales.title,
+                    sales.author_royalties,
+                    sum(sales.quantity)::bigint AS quantity,
+                    sum(sales.quantity * coalesce(line.price, sales.price)) AS gross,
+                    coalesce(sum(round(sales.quantity * sales.price * sales.author_royalties, 2)) FILTER (WHERE line.order_id IS NULL), 0.00) AS royalty_owed,
+                    coalesce(sum(line.royalty), 0.00) AS royalty_paid
+                    FROM base.raw_sales_data AS sales
+                    LEFT JOIN base.royalty_payout_line AS line ON line.order_id = sales.order_id AND line.isbn = sales.isbn
+                    WHERE sales.order_date BETWEEN $1 AND $2
+                    AND (sales.order_status = ANY($3) OR line.order_id IS NOT NULL)
+                    GROUP BY sales.author_name, sales.isbn, sales.title, sales.author_royalties
+                    ORDER BY sales.author_name, sales.title;
+                    ",
+                    &[&period_start, &period_end, &statuses],
                 )
             })
             .await?;
 
-        match cart_row {
-            Some(_) => {
-                conn.run(move |c| c.execute("UPDATE base.in_cart SET quantity = quantity + 1 WHERE isbn = $1 AND customer_id = $2", &[&isbn, &customer_id]))
-                    .await?;
-                Ok(())
+        let mut authors: Vec<AuthorRoyalties> = vec![];
+
+        for row in rows {
+            let author_name: Option<String> = row.try_get("author_name")?;
+            let author_name = author_name.unwrap_or_default();
+            let title: Option<String> = row.try_get("title")?;
+            let book = BookRoyalty {
+                isbn: row.try_get("isbn")?,
+                title: title.unwrap_or_default(),
+                quantity: row.try_get("quantity")?,
+                gross: row.try_get("gross")?,
+                royalty_rate: row.try_get("author_royalties")?,
+                royalty_owed: row.try_get("royalty_owed")?,
+                royalty_paid: row.try_get("royalty_paid")?,
+            };
+
+            if authors.last().map(|last| &last.author_name) != Some(&author_name) {
+                authors.push(AuthorRoyalties {
+                    author_name,
+                    books: vec![],
+                    quantity: 0,
+                    gross: PostgresNumeric::ZERO,
+                    royalty_owed: PostgresNumeric::ZERO,
+                    royalty_paid: PostgresNumeric::ZERO,
+                });
             }
-            None => {
-                conn.run(move |c| {
-                    c.execute(
-                        "INSERT INTO base.in_cart (isbn, customer_id, quantity) VALUES ($1, $2, 1)",
-                        &[&isbn, &customer_id],
-                    )
-                })
-                .await?;
-                Ok(())
+
+            if let Some(author) = authors.last_mut() {
+                author.quantity += book.quantity;
+                author.gross += book.gross;
+                author.royalty_owed += book.royalty_owed;
+                author.royalty_paid += book.royalty_paid;
+                author.books.push(book);
             }
         }
+
+        Ok(authors)
     }
 
-    async fn check_enough_stock(
+    /// Records a payout of every unpaid royalty in the period, returning the payout ID.
+    ///
+    /// Each sale can only be in one payout, so overlapping periods never pay a sale twice.
+    pub async fn record_royalty_payout(
         conn: &DbConn,
-        isbn: ISBN,
-        quantity: u32,
-    ) -> Result<bool, postgres::error::Error> {
-        let stock: PostgresInt = conn
-            .run(move |c| c.query_one("SELECT stock FROM base.book WHERE isbn = $1", &[&isbn]))
-            .await?
-            .try_get("stock")?;
+        period_start: NaiveDate,
+        period_end: NaiveDate,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<PostgresInt, RoyaltyError> {
+        if period_start > period_end {
+            Err(RoyaltyError::InvalidPeriod)?;
+        }
+
+        let statuses = royalty_order_statuses();
+
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let royalty_payout_id: PostgresInt = transaction
+                .query_one(
+                    "
+                    INSERT INTO base.royalty_payout (period_start, period_end, owner_id)
+                    VALUES ($1, $2, $3)
+                    RETURNING royalty_payout_id;
+                    ",
+                    &[&period_start, &period_end, &owner_id],
+                )?
+                .try_get("royalty_payout_id")?;
+
+            let num_sales = transaction.execute(
+                "
+                INSERT INTO base.royalty_payout_line (royalty_payout_id, order_id, isbn, author_name, quantity, price, royalty_rate, royalty)
+                SELECT $1, order_id, isbn, author_name, quantity, price, author_royalties, round(quantity * price * author_royalties, 2)
+                FROM base.raw_sales_data
+                WHERE order_date BETWEEN $2 AND $3 AND order_status = ANY($4)
+                ON CONFLICT DO NOTHING;
+                ",
+                &[&royalty_payout_id, &period_start, &period_end, &statuses],
+            )?;
+
+            if num_sales == 0 {
+                Err(RoyaltyError::NothingToPay)?;
+            }
 
-        let quantity = u32::max(quantity, 0) as i32;
-        Ok(quantity < stock)
+            transaction.commit()?;
+
+            Ok(royalty_payout_id)
+        })
+        .await
     }
 
-    pub async fn cart_set_book_quantity(
-        conn: &DbConn,
-        customer_id: PostgresInt,
-        isbn: ISBN,
-        quantity: u32,
-    ) -> Result<(), CartError> {
-        if !check_enough_stock(conn, isbn, quantity).await? {
-            Err(CartError::NotEnoughStock)?;
-        }
+    const ROYALTY_PAYOUT_QUERY: &str = "
+        SELECT
+        payout.royalty_payout_id,
+        payout.period_start,
+        payout.period_end,
+        payout.paid_at,
+        count(line.order_id) AS num_sales,
+        coalesce(sum(line.royalty), 0.00) AS total
+        FROM base.royalty_payout AS payout
+        LEFT JOIN base.royalty_payout_line AS line ON payout.royalty_payout_id = line.royalty_payout_id
+        WHERE $1::integer IS NULL OR payout.royalty_payout_id = $1
+        GROUP BY payout.royalty_payout_id
+        ORDER BY payout.paid_at DESC, payout.royalty_payout_id DESC;
+    ";
 
-        let quantity = quantity as i32;
+    fn royalty_payout_from_row(
+        row: &postgres::Row,
+    ) -> Result<RoyaltyPayout, postgres::error::Error> {
+        let period_start: NaiveDate = row.try_get("period_start")?;
+        let period_end: NaiveDate = row.try_get("period_end")?;
+        let paid_at: DateTime<Local> = row.try_get("paid_at")?;
 
-        if quantity == 0 {
-            conn.run(move |c| {
-                c.execute(
-                    "DELETE FROM base.in_cart WHERE isbn = $1 AND customer_id = $2;",
-                    &[&isbn, &customer_id],
-                )
-            })
-            .await?;
-        } else {
-            conn.run(move |c| {
-                c.execute(
-                    "UPDATE base.in_cart SET quantity = $1 WHERE isbn = $2 AND customer_id = $3;",
-                    &[&quantity, &isbn, &customer_id],
-                )
-            })
-            .await?;
-        }
-        Ok(())
+        Ok(RoyaltyPayout {
+            royalty_payout_id: row.try_get("royalty_payout_id")?,
+            period_start: period_start.to_string(),
+            period_end: period_end.to_string(),
+            paid_at: paid_at.format("%Y-%m-%d %H:%M").to_string(),
+            num_sales: row.try_get("num_sales")?,
+            total: row.try_get("total")?,
+        })
     }
 
-    async fn find_address(
+    pub async fn get_royalty_payouts(
         conn: &DbConn,
-        address: schema::no_id::Address,
-    ) -> Result<Option<PostgresInt>, postgres::error::Error> {
-        let schema::no_id::Address {
-            street_address,
-            postal_code,
-            province,
-        } = address;
+    ) -> Result<Vec<RoyaltyPayout>, postgres::error::Error> {
+        conn.run(|c| c.query(ROYALTY_PAYOUT_QUERY, &[&None::<PostgresInt>]))
+            .await?
+            .iter()
+            .map(royalty_payout_from_row)
+            .collect()
+    }
+
+    /// A payout and every sale it paid
+    pub async fn get_royalty_payout(
+        conn: &DbConn,
+        royalty_payout_id: PostgresInt,
+    ) -> Result<(RoyaltyPayout, Vec<RoyaltyPayoutLine>), RoyaltyError> {
+        conn.run(move |c| {
+            let payout = c
+                .query_opt(ROYALTY_PAYOUT_QUERY, &[&Some(royalty_payout_id)])?
+                .ok_or(RoyaltyError::NoSuchPayout(royalty_payout_id))?;
+            let payout = royalty_payout_from_row(&payout)?;
 
-        let row = conn.run(move |c| {
-            c.query_opt("SELECT address_id FROM base.address WHERE street_address = $1 AND postal_code = $2 AND province = $3", &[&street_address, &postal_code, &province])
-        }).await?;
+            let lines = c
+                .query(
+                    "
+                    SELECT line.*, book.title
+                    FROM base.royalty_payout_line AS line
+                    INNER JOIN base.book AS book ON line.isbn = book.isbn
+                    WHERE line.royalty_payout_id = $1
+                    ORDER BY line.author_name, book.title, line.order_id;
+                    ",
+                    &[&royalty_payout_id],
+                )?
+                .iter()
+                .map(|row| {
+                    let author_name: Option<String> = row.try_get("author_name")?;
+                    let title: Option<String> = row.try_get("title")?;
 
-        match row {
-            Some(row) => Ok(row.try_get("address_id")?),
-            None => Ok(None),
+                    Ok(RoyaltyPayoutLine {
+                        order_id: row.try_get("order_id")?,
+                        isbn: row.try_get("isbn")?,
+                        title: title.unwrap_or_default(),
+                        author_name: author_name.unwrap_or_default(),
+                        quantity: row.try_get("quantity")?,
+                        price: row.try_get("price")?,
+                        royalty_rate: row.try_get("royalty_rate")?,
+                        royalty: row.try_get("royalty")?,
+                    })
+                })
+                .collect::<Result<_, postgres::error::Error>>()?;
+
+            Ok((payout, lines))
+        })
+        .await
+    }
+
+    /// Gross sales, royalties and restock costs per publisher over a period. Sales count once
+    /// they earn royalties, and restock orders once they are received. Restock orders received
+    /// without a price can't be costed, so they're counted separately instead.
+    const PUBLISHER_SETTLEMENT_QUERY: &str = "
+        WITH sales AS (
+            SELECT
+            publisher_id,
+            sum(quantity)::bigint AS quantity,
+            sum(quantity * price) AS gross_sales,
+            sum(round(quantity * price * author_royalties, 2)) AS royalties
+            FROM base.raw_sales_data
+            WHERE order_date BETWEEN $1 AND $2 AND order_status = ANY($3)
+            GROUP BY publisher_id
+        ), restock AS (
+            SELECT
+            book.publisher_id,
+            sum(restock.quantity * restock.price_per_unit::numeric) AS restock_costs,
+            sum(restock.quantity * restock.price_per_unit::numeric) FILTER (WHERE restock.publisher_remittance_id IS NULL) AS amount_due,
+            count(*) FILTER (WHERE restock.price_per_unit IS NULL) AS unpriced_restocks
+            FROM base.restock_order AS restock
+            INNER JOIN base.book AS book ON restock.isbn = book.isbn
+            WHERE restock.order_date BETWEEN $1 AND $2 AND restock.order_status = $4
+            GROUP BY book.publisher_id
+        )
+        SELECT
+        publisher.publisher_id,
+        publisher.company_name,
+        publisher.bank_number,
+        coalesce(sales.quantity, 0) AS quantity,
+        coalesce(sales.gross_sales, 0.00) AS gross_sales,
+        coalesce(sales.royalties, 0.00) AS royalties,
+        coalesce(restock.restock_costs, 0.00) AS restock_costs,
+        coalesce(restock.amount_due, 0.00) AS amount_due,
+        coalesce(restock.unpriced_restocks, 0) AS unpriced_restocks
+        FROM base.publisher AS publisher
+        LEFT JOIN sales ON publisher.publisher_id = sales.publisher_id
+        LEFT JOIN restock ON publisher.publisher_id = restock.publisher_id
+        WHERE $5::integer IS NULL OR publisher.publisher_id = $5
+        ORDER BY publisher.company_name;
+    ";
+
+    fn publisher_settlement_from_row(
+        row: &postgres::Row,
+    ) -> Result<PublisherSettlement, postgres::error::Error> {
+        let company_name: Option<String> = row.try_get("company_name")?;
+        let bank_number: Option<String> = row.try_get("bank_number")?;
+        let gross_sales: PostgresNumeric = row.try_get("gross_sales")?;
+        let royalties: PostgresNumeric = row.try_get("royalties")?;
+        let restock_costs: PostgresNumeric = row.try_get("restock_costs")?;
+
+        Ok(PublisherSettlement {
+            publisher_id: row.try_get("publisher_id")?,
+            company_name: company_name.unwrap_or_default(),
+            bank_number: bank_number.unwrap_or_default(),
+            quantity: row.try_get("quantity")?,
+            gross_sales,
+            royalties,
+            restock_costs,
+            store_margin: gross_sales - royalties - restock_costs,
+            amount_due: row.try_get("amount_due")?,
+            unpriced_restocks: row.try_get("unpriced_restocks")?,
+        })
+    }
+
+    fn query_publisher_settlements<C: GenericClient>(
+        client: &mut C,
+        period_start: NaiveDate,
+        period_end: NaiveDate,
+        publisher_id: Option<PublisherID>,
+    ) -> Result<Vec<PublisherSettlement>, postgres::error::Error> {
+        client
+            .query(
+                PUBLISHER_SETTLEMENT_QUERY,
+                &[
+                    &period_start,
+                    &period_end,
+                    &royalty_order_statuses(),
+                    &RestockStatus::Received.as_str(),
+                    &publisher_id,
+                ],
+            )?
+            .iter()
+            .map(publisher_settlement_from_row)
+            .collect()
+    }
+
+    pub async fn get_publisher_settlements(
+        conn: &DbConn,
+        period_start: NaiveDate,
+        period_end: NaiveDate,
+    ) -> Result<Vec<PublisherSettlement>, SettlementError> {
+        if period_start > period_end {
+            Err(SettlementError::InvalidPeriod)?;
         }
+
+        Ok(conn
+            .run(move |c| query_publisher_settlements(c, period_start, period_end, None))
+            .await?)
     }
 
-    async fn find_payment_info(
+    /// A publisher's settlement for the period, broken down by book and by restock order
+    pub async fn get_publisher_statement(
         conn: &DbConn,
-        payment_info: schema::no_id::PaymentInfo,
-    ) -> Result<Option<PostgresInt>, postgres::error::Error> {
-        let schema::no_id::PaymentInfo {
-            card_number,
-            name_on_card,
-            expiry,
-            cvv,
-            billing_address,
-        } = payment_info;
+        publisher_id: PublisherID,
+        period_start: NaiveDate,
+        period_end: NaiveDate,
+    ) -> Result<
+        (
+            PublisherSettlement,
+            Vec<PublisherBookSales>,
+            Vec<RestockOrder>,
+        ),
+        SettlementError,
+    > {
+        if period_start > period_end {
+            Err(SettlementError::InvalidPeriod)?;
+        }
 
-        let schema::no_id::Address {
-            street_address,
-            postal_code,
-            province,
-        } = billing_address;
+        conn.run(move |c| {
+            let settlement =
+                query_publisher_settlements(c, period_start, period_end, Some(publisher_id))?
+                    .pop()
+                    .ok_or(SettlementError::NoSuchPublisher(publisher_id))?;
 
-        let row = conn
-            .run(move |c| {
-                c.query_opt(
+            let books = c
+                .query(
                     "
-                SELECT payment_info_id FROM
-                base.payment_info INNER JOIN
-                base.address ON billing_address_id = address_id WHERE
-                name_on_card = $1 AND
-                expiry = $2 AND
-                card_number = $3 AND 
-                cvv = $4 AND
-                street_address = $5 AND
-                postal_code = $6 AND
-                province = $7;
-                ",
+                    SELECT
+                    isbn,
+                    title,
+                    sum(quantity)::bigint AS quantity,
+                    sum(quantity * price) AS gross_sales,
+                    sum(round(quantity * price * author_royalties, 2)) AS royalties
+                    FROM base.raw_sales_data
+                    WHERE publisher_id = $1 AND order_date BETWEEN $2 AND $3 AND order_status = ANY($4)
+                    GROUP BY isbn, title
+                    ORDER BY title;
+                    ",
+                    &[&publisher_id, &period_start, &period_end, &royalty_order_statuses()],
+                )?
+                .iter()
+                .map(|row| {
+                    let title: Option<String> = row.try_get("title")?;
+
+                    Ok(PublisherBookSales {
+                        isbn: row.try_get("isbn")?,
+                        title: title.unwrap_or_default(),
+                        quantity: row.try_get("quantity")?,
+                        gross_sales: row.try_get("gross_sales")?,
+                        royalties: row.try_get("royalties")?,
+                    })
+                })
+                .collect::<Result<_, postgres::error::Error>>()?;
+
+            let restock_orders = c
+                .query(
+                    "
+                    SELECT
+                    restock_order_id,
+                    isbn,
+                    title,
+                    quantity,
+                    price_per_unit::numeric AS price_per_unit,
+                    order_date,
+                    order_status,
+                    publisher_remittance_id
+                    FROM base.restock_order INNER JOIN base.book USING (isbn)
+                    WHERE publisher_id = $1 AND order_date BETWEEN $2 AND $3 AND order_status = $4
+                    ORDER BY order_date, restock_order_id;
+                    ",
                     &[
-                        &name_on_card,
-                        &expiry.to_string(),
-                        &card_number,
-                        &cvv,
-                        &street_address,
-                        &postal_code,
-                        &province,
+                        &publisher_id,
+                        &period_start,
+                        &period_end,
+                        &RestockStatus::Received.as_str(),
                     ],
-                )
-            })
-            .await?;
+                )?
+                .iter()
+                .map(restock_order_from_row)
+                .collect::<Result<_, RestockError>>()
+                .map_err(|e| StateError::new(e.to_string()))?;
 
-        Ok(match row {
-            Some(row) => Some(row.try_get("payment_info_id")?),
-            None => None,
+            Ok((settlement, books, restock_orders))
+        })
+        .await
+    }
+
+    fn publisher_remittance_from_row(
+        row: &postgres::Row,
+    ) -> Result<PublisherRemittance, SettlementError> {
+        let period_start: NaiveDate = row.try_get("period_start")?;
+        let period_end: NaiveDate = row.try_get("period_end")?;
+        let bank_number: Option<String> = row.try_get("bank_number")?;
+        let remittance_status: &str = row.try_get("remittance_status")?;
+        let created_at: DateTime<Local> = row.try_get("created_at")?;
+        let remitted_at: Option<DateTime<Local>> = row.try_get("remitted_at")?;
+
+        Ok(PublisherRemittance {
+            publisher_remittance_id: row.try_get("publisher_remittance_id")?,
+            publisher_id: row.try_get("publisher_id")?,
+            period_start: period_start.to_string(),
+            period_end: period_end.to_string(),
+            amount: row.try_get("amount")?,
+            bank_number: bank_number.unwrap_or_default(),
+            remittance_status: remittance_status.parse().map_err(StateError::new)?,
+            created_at: created_at.format("%Y-%m-%d %H:%M").to_string(),
+            remitted_at: remitted_at
+                .map(|remitted_at| remitted_at.format("%Y-%m-%d %H:%M").to_string()),
         })
     }
 
-    async fn get_or_insert_address(
+    pub async fn get_publisher_remittances(
         conn: &DbConn,
-        address: schema::no_id::Address,
-    ) -> Result<PostgresInt, postgres::error::Error> {
-        match find_address(conn, address.clone()).await? {
-            Some(address_id) => Ok(address_id),
-            None => {
-                let schema::no_id::Address {
-                    street_address,
-                    postal_code,
-                    province,
-                } = address;
+        publisher_id: PublisherID,
+    ) -> Result<Vec<PublisherRemittance>, SettlementError> {
+        conn.run(move |c| {
+            c.query(
+                "
+                SELECT * FROM base.publisher_remittance
+                WHERE publisher_id = $1
+                ORDER BY created_at DESC, publisher_remittance_id DESC;
+                ",
+                &[&publisher_id],
+            )
+        })
+        .await?
+        .iter()
+        .map(publisher_remittance_from_row)
+        .collect()
+    }
+
+    /// Records a pending remittance to the publisher for every received restock order in the
+    /// period that no other remittance covers, returning the remittance ID. Orders without a
+    /// price are left out, so they aren't marked paid without anything being paid for them.
+    pub async fn create_publisher_remittance(
+        conn: &DbConn,
+        publisher_id: PublisherID,
+        period_start: NaiveDate,
+        period_end: NaiveDate,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<PostgresInt, SettlementError> {
+        if period_start > period_end {
+            Err(SettlementError::InvalidPeriod)?;
+        }
+
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
+
+            let bank_number: Option<String> = transaction
+                .query_opt(
+                    "SELECT bank_number FROM base.publisher WHERE publisher_id = $1;",
+                    &[&publisher_id],
+                )?
+                .ok_or(SettlementError::NoSuchPublisher(publisher_id))?
+                .try_get("bank_number")?;
+
+            let publisher_remittance_id: PostgresInt = transaction
+                .query_one(
+                    "
+                    INSERT INTO base.publisher_remittance (publisher_id, period_start, period_end, amount, bank_number, remittance_status, owner_id)
+                    VALUES ($1, $2, $3, 0, $4, $5, $6)
+                    RETURNING publisher_remittance_id;
+                    ",
+                    &[
+                        &publisher_id,
+                        &period_start,
+                        &period_end,
+                        &bank_number,
+                        &RemittanceStatus::Pending.as_str(),
+                        &owner_id,
+                    ],
+                )?
+                .try_get("publisher_remittance_id")?;
+
+            // Row locks stop a concurrent remittance from claiming the same restock orders
+            let amount: Option<PostgresNumeric> = transaction
+                .query_one(
+                    "
+                    WITH claimed AS (
+                        UPDATE base.restock_order AS restock
+                        SET publisher_remittance_id = $1
+                        FROM base.book AS book
+                        WHERE restock.isbn = book.isbn
+                        AND book.publisher_id = $2
+                        AND restock.order_date BETWEEN $3 AND $4
+                        AND restock.order_status = $5
+                        AND restock.publisher_remittance_id IS NULL
+                        AND restock.price_per_unit IS NOT NULL
+                        RETURNING restock.quantity * restock.price_per_unit::numeric AS cost
+                    )
+                    SELECT sum(cost) AS amount FROM claimed;
+                    ",
+                    &[
+                        &publisher_remittance_id,
+                        &publisher_id,
+                        &period_start,
+                        &period_end,
+                        &RestockStatus::Received.as_str(),
+                    ],
+                )?
+                .try_get("amount")?;
+
+            let amount = amount.ok_or(SettlementError::NothingToRemit)?;
+
+            transaction.execute(
+                "UPDATE base.publisher_remittance SET amount = $1 WHERE publisher_remittance_id = $2;",
+                &[&amount, &publisher_remittance_id],
+            )?;
+
+            transaction.commit()?;
 
-                Ok(conn
-                    .run(move |c| {
-                        c.query_one(
-                            "INSERT INTO base.address
-                    (street_address, postal_code, province)
-                    VALUES ($1, $2, $3)
-                    RETURNING address_id;",
-                            &[&street_address, &postal_code, &province],
-                        )
-                    })
-                    .await?
-                    .try_get("address_id")?)
-            }
-        }
+            Ok(publisher_remittance_id)
+        })
+        .await
     }
 
-    async fn get_or_insert_payment_info(
+    /// Marks a pending remittance as sent, or cancels it and frees its restock orders to be
+    /// remitted again
+    pub async fn set_remittance_status(
         conn: &DbConn,
-        payment_info: schema::no_id::PaymentInfo,
-    ) -> Result<PostgresInt, postgres::error::Error> {
-        match find_payment_info(conn, payment_info.clone()).await? {
-            Some(payment_info_id) => Ok(payment_info_id),
-            None => {
-                let schema::no_id::PaymentInfo {
-                    card_number,
-                    name_on_card,
-                    expiry,
-                    cvv,
-                    billing_address,
-                } = payment_info;
+        publisher_remittance_id: PostgresInt,
+        remittance_status: RemittanceStatus,
+    ) -> Result<(), SettlementError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
 
-                let address_id = get_or_insert_address(conn, billing_address).await?;
-
-                Ok(conn
-                    .run(move |c| {
-                        c.query_one(
-                            "INSERT INTO base.payment_info
-                    (name_on_card, expiry, card_number, cvv, billing_address_id)
-                    VALUES ($1, $2, $3, $4)
-                    RETURNING payment_info_id;",
-                            &[
-                                &name_on_card,
-                                &expiry.to_string(),
-                                &card_number,
-                                &cvv,
-                                &address_id,
-                            ],
-                        )
-                    })
-                    .await?
-                    .try_get("payment_info_id")?)
+            let current_status: RemittanceStatus = transaction
+                .query_opt(
+                    "SELECT remittance_status FROM base.publisher_remittance WHERE publisher_remittance_id = $1 FOR UPDATE;",
+                    &[&publisher_remittance_id],
+                )?
+                .ok_or(SettlementError::NoSuchRemittance(publisher_remittance_id))?
+                .try_get::<_, &str>("remittance_status")?
+                .parse()
+                .map_err(StateError::new)?;
+
+            if !current_status.can_transition_to(remittance_status) {
+                Err(SettlementError::InvalidTransition(
+                    current_status,
+                    remittance_status,
+                ))?;
             }
-        }
+
+            transaction.execute(
+                "
+                UPDATE base.publisher_remittance
+                SET remittance_status = $1, remitted_at = CASE WHEN $2 THEN now() END
+                WHERE publisher_remittance_id = $3;
+                ",
+                &[
+                    &remittance_status.as_str(),
+                    &(remittance_status == RemittanceStatus::Remitted),
+                    &publisher_remittance_id,
+                ],
+            )?;
+
+            if remittance_status == RemittanceStatus::Cancelled {
+                transaction.execute(
+                    "UPDATE base.restock_order SET publisher_remittance_id = NULL WHERE publisher_remittance_id = $1;",
+                    &[&publisher_remittance_id],
+                )?;
+            }
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
     }
 
-    fn get_tracking_number() -> String {
-        let mut rng = rand::thread_rng();
-        rng.next_u32().to_string()
+    fn catalog_import_from_row(row: &postgres::Row) -> Result<CatalogImport, ImportError> {
+        let import_format: &str = row.try_get("import_format")?;
+        let uploaded_at: DateTime<Local> = row.try_get("uploaded_at")?;
+        let applied_at: Option<DateTime<Local>> = row.try_get("applied_at")?;
+
+        Ok(CatalogImport {
+            catalog_import_id: row.try_get("catalog_import_id")?,
+            file_name: row.try_get("file_name")?,
+            import_format: import_format.parse().map_err(StateError::new)?,
+            owner_name: row.try_get("owner_name")?,
+            uploaded_at: uploaded_at.format("%Y-%m-%d %H:%M").to_string(),
+            applied_at: applied_at
+                .map(|applied_at| applied_at.format("%Y-%m-%d %H:%M").to_string()),
+            books_added: row.try_get("books_added")?,
+            books_updated: row.try_get("books_updated")?,
+            records_rejected: row.try_get("records_rejected")?,
+        })
     }
 
-    pub async fn clear_cart(
+    /// Stores an uploaded catalog file to be previewed, returning the import ID.
+    ///
+    /// Files that can't be read at all are refused here rather than at preview time.
+    pub async fn create_catalog_import(
         conn: &DbConn,
-        customer_id: PostgresInt,
-    ) -> Result<(), postgres::error::Error> {
+        file_name: String,
+        import_format: ImportFormat,
+        contents: String,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<PostgresInt, ImportError> {
+        read_catalog(import_format, &contents)?;
+
         conn.run(move |c| {
-            c.execute(
-                "DELETE FROM base.in_cart WHERE customer_id = $1",
-                &[&customer_id],
-            )
+            Ok(c.query_one(
+                "
+                INSERT INTO base.catalog_import (file_name, import_format, contents, owner_id)
+                VALUES ($1, $2, $3, $4)
+                RETURNING catalog_import_id;
+                ",
+                &[&file_name, &import_format.as_str(), &contents, &owner_id],
+            )?
+            .try_get("catalog_import_id")?)
         })
-        .await?;
+        .await
+    }
 
-        Ok(())
+    pub async fn get_catalog_imports(conn: &DbConn) -> Result<Vec<CatalogImport>, ImportError> {
+        conn.run(|c| {
+            c.query(
+                "
+                SELECT catalog_import.*, owner.name AS owner_name
+                FROM base.catalog_import
+                LEFT JOIN base.owner USING (owner_id)
+                ORDER BY uploaded_at DESC, catalog_import_id DESC;
+                ",
+                &[],
+            )
+        })
+        .await?
+        .iter()
+        .map(catalog_import_from_row)
+        .collect()
     }
 
-    async fn add_books_to_order(
-        conn: &DbConn,
-        books: Vec<(ISBN, PostgresInt)>,
-        order_id: PostgresInt,
-    ) -> Result<(), postgres::error::Error> {
-        for (isbn, quantity) in books {
-            conn.run(move |c| {
-                c.execute(
-                    "
-                    INSERT INTO base.in_order
-                    VALUES ($1, $2, $3);
-                    ",
-                    &[&isbn, &order_id, &quantity],
+    /// Plans the import's file against the catalog as `client` currently sees it
+    /// Works out what applying the import would do. When `lock_books` is set, the books it
+    /// touches are locked, in the same ISBN order checkouts lock them in, so the plan can't go
+    /// stale before it's applied.
+    fn plan_catalog_import<C: GenericClient>(
+        client: &mut C,
+        row: &postgres::Row,
+        lock_books: bool,
+    ) -> Result<ImportPlan, ImportError> {
+        let import_format: ImportFormat = row
+            .try_get::<_, &str>("import_format")?
+            .parse()
+            .map_err(StateError::new)?;
+        let records = read_catalog(import_format, row.try_get("contents")?)?;
+
+        let isbns: Vec<Isbn> = records
+            .iter()
+            .filter_map(|record| Isbn::parse(record.book.isbn.as_deref()?).ok())
+            .collect();
+        let books: Vec<Book> = client
+            .query(
+                format!(
+                    "SELECT * FROM base.book WHERE isbn = ANY($1) ORDER BY isbn{};",
+                    if lock_books { " FOR UPDATE" } else { "" }
                 )
-            })
-            .await?;
+                .as_str(),
+                &[&isbns],
+            )?
+            .iter()
+            .map(Book::from_row)
+            .collect::<Result<_, _>>()?;
+        let publishers: Vec<Publisher> = client
+            .query("SELECT * FROM base.publisher;", &[])?
+            .iter()
+            .map(Publisher::from_row)
+            .collect::<Result<_, _>>()?;
+
+        let mut plan = plan_import(records, &books, &publishers);
+
+        // Lowering the stock below the copies held in carts would sell copies that aren't there
+        let current_stock: HashMap<Isbn, PostgresInt> =
+            books.iter().map(|book| (book.isbn, book.stock)).collect();
+        let mut updated_books = Vec::with_capacity(plan.updated_books.len());
+        for planned in plan.updated_books {
+            let stock = planned.book.stock;
+            if current_stock
+                .get(&planned.book.isbn)
+                .is_some_and(|&current| stock < current)
+            {
+                let reserved = reserved_copies(client, planned.book.isbn)?;
+                if (stock as i64) < reserved {
+                    plan.rejected.push(RejectedRecord {
+                        location: planned.location,
+                        isbn: Some(planned.book.isbn.to_string()),
+                        title: Some(planned.book.title),
+                        reasons: vec![format!(
+                            "Stock: can't go below the {} copies held in carts",
+                            reserved
+                        )],
+                    });
+                    continue;
+                }
+            }
+            updated_books.push(planned);
         }
-        Ok(())
+        plan.updated_books = updated_books;
+
+        Ok(plan)
     }
 
-    async fn remove_book_stock(
+    /// The import along with what applying it would do to the catalog right now
+    pub async fn get_catalog_import(
         conn: &DbConn,
-        books: Vec<(ISBN, PostgresInt)>,
-    ) -> Result<(), postgres::error::Error> {
-        for (isbn, quantity) in books {
-            conn.run(move |c| {
-                c.execute(
-                    "UPDATE base.book SET stock = stock - $1 WHERE isbn = $2;",
-                    &[&quantity, &isbn],
-                )
-            })
-            .await?;
-        }
+        catalog_import_id: PostgresInt,
+    ) -> Result<(CatalogImport, ImportPlan), ImportError> {
+        conn.run(move |c| {
+            let row = c
+                .query_opt(
+                    "
+                    SELECT catalog_import.*, owner.name AS owner_name
+                    FROM base.catalog_import
+                    LEFT JOIN base.owner USING (owner_id)
+                    WHERE catalog_import_id = $1;
+                    ",
+                    &[&catalog_import_id],
+                )?
+                .ok_or(ImportError::NoSuchImport(catalog_import_id))?;
 
-        Ok(())
+            Ok((
+                catalog_import_from_row(&row)?,
+                plan_catalog_import(c, &row, false)?,
+            ))
+        })
+        .await
     }
 
-    pub async fn create_order(
+    /// Adds and updates every valid book in the import in one transaction, skipping the
+    /// rejected ones, and returns what was done
+    pub async fn apply_catalog_import(
         conn: &DbConn,
-        customer_id: PostgresInt,
-        books: Vec<(ISBN, u32)>,
-        address: Option<schema::no_id::Address>,
-        payment_info: Option<schema::no_id::PaymentInfo>,
-    ) -> Result<PostgresInt, OrderError> {
-        let books: Vec<(ISBN, PostgresInt)> = books
-            .into_iter()
-            .map(|(isbn, quantity)| (isbn, quantity as i32))
-            .collect();
-        for (isbn, quantity) in books.iter() {
-            let quantity = i32::max(*quantity, 0) as u32;
-            if !check_enough_stock(conn, *isbn, quantity).await? {
-                Err(OrderError::NotEnoughStock)?;
-            }
-        }
+        catalog_import_id: PostgresInt,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<ImportPlan, ImportError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
 
-        let address_id = match address {
-            Some(address) => get_or_insert_address(conn, address).await?,
-            None => {
-                let customer = get_customer(conn, customer_id).await?;
-                customer
-                    .ok_or(StateError::new(format!(
-                        "No customer with the ID ({})",
-                        customer_id
-                    )))?
-                    .default_shipping_address_id
+            let row = transaction
+                .query_opt(
+                    "SELECT * FROM base.catalog_import WHERE catalog_import_id = $1 FOR UPDATE;",
+                    &[&catalog_import_id],
+                )?
+                .ok_or(ImportError::NoSuchImport(catalog_import_id))?;
+            let applied_at: Option<DateTime<Local>> = row.try_get("applied_at")?;
+            if applied_at.is_some() {
+                Err(ImportError::AlreadyApplied)?;
             }
-        };
 
-        let payment_info_id = match payment_info {
-            Some(payment_info) => get_or_insert_payment_info(conn, payment_info).await?,
-            None => {
-                let customer = get_customer(conn, customer_id).await?;
-                customer
-                    .ok_or(StateError::new(format!(
-                        "No customer with the ID ({})",
-                        customer_id
-                    )))?
-                    .default_payment_info_id
+            let plan = plan_catalog_import(&mut transaction, &row, true)?;
+
+            for PlannedBook { book, .. } in &plan.new_books {
+                transaction.execute(
+                    "
+                    INSERT INTO base.book (isbn, author_name, genre, publisher_id, num_pages, price, author_royalties, reorder_threshold, title, stock, discontinued)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11);
+                    ",
+                    &[&book.isbn, &book.author_name, &book.genre, &book.publisher, &book.num_pages, &book.price, &book.author_royalties, &book.reorder_threshold, &book.title, &book.stock, &book.discontinued],
+                )?;
+                record_book_price(&mut transaction, book.isbn, book.price, owner_id)?;
             }
-        };
 
-        let tracking_number = get_tracking_number();
+            for PlannedBook { book, changes, .. } in &plan.updated_books {
+                let version = write_book(&mut transaction, book)?;
+                record_book_edit(&mut transaction, book.isbn, version, owner_id, changes)?;
+                if changes.iter().any(|change| change.field == PRICE_FIELD) {
+                    record_book_price(&mut transaction, book.isbn, book.price, owner_id)?;
+                }
+            }
 
-        let order_id: PostgresInt = conn.run(move |c| {
-            c.query_one(
+            transaction.execute(
                 "
-                INSERT INTO base.orders
-                (customer_id, shipping_address_id, tracking_number, order_status, order_date, payment_info_id)
-                VALUES
-                ($1, $2, $3, $4, $5, $6)
-                RETURNING order_id;
+                UPDATE base.catalog_import
+                SET applied_at = now(), books_added = $2, books_updated = $3, records_rejected = $4
+                WHERE catalog_import_id = $1;
                 ",
-                &[&customer_id, &address_id, &tracking_number, &"PR", &Local::today().naive_local(), &payment_info_id],
-            )
+                &[
+                    &catalog_import_id,
+                    &(plan.new_books.len() as PostgresInt),
+                    &(plan.updated_books.len() as PostgresInt),
+                    &(plan.rejected.len() as PostgresInt),
+                ],
+            )?;
+
+            transaction.commit()?;
+
+            Ok(plan)
         })
-        .await?.try_get("order_id")?;
+        .await
+    }
 
-        add_books_to_order(conn, books.clone(), order_id).await?;
-        remove_book_stock(conn, books).await?;
+    /// Overwrites every field of the book, returning its new version
+    fn write_book(
+        client: &mut impl GenericClient,
+        book: &Book,
+    ) -> Result<PostgresInt, postgres::error::Error> {
+        let version = client
+            .query_one(
+                "
+                UPDATE base.book
+                SET author_name = $2, genre = $3, publisher_id = $4, num_pages = $5, price = $6, author_royalties = $7, reorder_threshold = $8, title = $9, stock = $10, discontinued = $11, version = version + 1
+                WHERE isbn = $1
+                RETURNING version;
+                ",
+                &[&book.isbn, &book.author_name, &book.genre, &book.publisher, &book.num_pages, &book.price, &book.author_royalties, &book.reorder_threshold, &book.title, &book.stock, &book.discontinued],
+            )?
+            .try_get("version")?;
 
-        clear_cart(conn, customer_id).await?;
+        queue_back_in_stock(client, &[book.isbn])?;
 
-        Ok(order_id)
+        Ok(version)
     }
 
-    pub async fn get_books_for_order(
+    /// Records the changes that took the book to `version`
+    fn record_book_edit(
+        client: &mut impl GenericClient,
+        isbn: Isbn,
+        version: PostgresInt,
+        owner_id: Option<PostgresInt>,
+        changes: &[FieldChange],
+    ) -> Result<(), postgres::error::Error> {
+        for change in changes {
+            client.execute(
+                "
+                INSERT INTO base.book_edit (isbn, version, owner_id, field_name, old_value, new_value)
+                VALUES ($1, $2, $3, $4, $5, $6);
+                ",
+                &[&isbn, &version, &owner_id, &change.field, &change.old, &change.new],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// The book along with its version, which has to be given back to `update_book`
+    pub async fn get_book_for_edit(
         conn: &DbConn,
-        order: OrderNoBooks,
-    ) -> Result<Order, postgres::error::Error> {
-        let books = conn.run(move |c| {
-            c.query("SELECT * FROM base.in_order INNER JOIN base.book ON base.in_order.isbn = base.book.isbn WHERE order_id = $1;", &[&order.order_id])
-        }).await?.iter().flat_map(|row| {
-            let result: Result<(Book, u32), OrderError> = try {
-                let quantity: i32 = row.try_get("quantity")?;
-                (Book::from_row(row)?, i32::max(quantity, 0) as u32)
-            };
+        isbn: Isbn,
+    ) -> Result<(Book, PostgresInt), BookEditError> {
+        let row = conn
+            .run(move |c| c.query_opt("SELECT * FROM base.book WHERE isbn = $1;", &[&isbn]))
+            .await?
+            .ok_or(BookEditError::NoSuchBook(isbn))?;
 
-            result.ok()
-        }).collect();
+        Ok((Book::from_row(&row)?, row.try_get("version")?))
+    }
 
-        Ok(Order::from_order_with_id(order, books))
+    /// How many copies of the book are held in carts
+    fn reserved_copies(
+        client: &mut impl GenericClient,
+        isbn: Isbn,
+    ) -> Result<i64, postgres::error::Error> {
+        client
+            .query_one(
+                "SELECT coalesce(sum(quantity), 0)::bigint AS reserved FROM base.stock_reservation WHERE isbn = $1;",
+                &[&isbn],
+            )?
+            .try_get("reserved")
     }
 
-    pub async fn get_order_info(
+    /// Saves an owner's changes to a book, as long as nobody else has edited it since they
+    /// read `version` of it.
+    ///
+    /// Sales and restocks don't change the version, so the stock is moved by however much the
+    /// owner changed it from `read_stock` rather than overwritten.
+    pub async fn update_book(
         conn: &DbConn,
-        order_id: PostgresInt,
-    ) -> Result<OrderNoBooks, OrderError> {
-        let row = conn.run(move |c| c.query_one(
-            "
-            SELECT
-            order_id,
-            add.street_address,
-            add.postal_code,
-            add.province,
-            bill.street_address AS bill_street_address,
-            bill.postal_code AS bill_postal_code,
-            bill.province AS bill_province,
-            order_status,
-            order_date,
-            tracking_number,
-            name_on_card,
-            card_number,
-            expiry,
-            cvv
-            FROM
-            base.orders AS orders
-            INNER JOIN base.address AS add ON orders.shipping_address_id = add.address_id
-            INNER JOIN base.payment_info AS payment ON orders.payment_info_id = payment.payment_info_id
-            INNER JOIN base.address AS bill ON payment.billing_address_id = bill.address_id
-            WHERE order_id = $1;
-            ",
-            &[&order_id])).await?;
+        mut book: Book,
+        version: PostgresInt,
+        read_stock: PostgresInt,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<(), BookEditError> {
+        conn.run(move |c| {
+            let mut transaction = c.transaction()?;
 
-        let address = Address::new::<&str>(
-            row.try_get("street_address")?,
-            row.try_get("postal_code")?,
-            row.try_get("province")?,
-        );
-        let billing_address = Address::new::<&str>(
-            row.try_get("bill_street_address")?,
-            row.try_get("bill_postal_code")?,
-            row.try_get("bill_province")?,
-        );
-        let expiry = Expiry::from_str::<&str>(row.try_get("expiry")?)
-            .ok_or(StateError::new("Invalid expiry"))?;
-        let payment_info = PaymentInfo::new::<&str>(
-            row.try_get("name_on_card")?,
-            expiry,
-            row.try_get("card_number")?,
-            row.try_get("cvv")?,
-            billing_address,
-        );
+            let row = transaction
+                .query_opt(
+                    "SELECT * FROM base.book WHERE isbn = $1 FOR UPDATE;",
+                    &[&book.isbn],
+                )?
+                .ok_or(BookEditError::NoSuchBook(book.isbn))?;
+            let current_version: PostgresInt = row.try_get("version")?;
+            if current_version != version {
+                Err(BookEditError::Conflict)?;
+            }
 
-        let date: NaiveDate = row.try_get("order_date")?;
+            let current_stock: PostgresInt = row.try_get("stock")?;
+            book.stock = current_stock + (book.stock - read_stock);
+            if book.stock < current_stock {
+                let reserved = reserved_copies(&mut transaction, book.isbn)?;
+                if (book.stock as i64) < reserved {
+                    Err(BookEditError::StockBelowReserved {
+                        stock: book.stock,
+                        reserved,
+                    })?;
+                }
+            }
 
-        let order = OrderNoBooks {
-            order_id: row.try_get("order_id")?,
-            shipping_address: address,
-            tracking_number: row.try_get("tracking_number")?,
-            order_status: row.try_get("order_status")?,
-            order_date: date.to_string(),
-            payment_info,
-        };
+            let publishers: HashMap<PublisherID, String> = transaction
+                .query(
+                    "SELECT publisher_id, company_name FROM base.publisher;",
+                    &[],
+                )?
+                .iter()
+                .map(|row| Ok((row.try_get("publisher_id")?, row.try_get("company_name")?)))
+                .collect::<Result<_, postgres::error::Error>>()?;
+            if !publishers.contains_key(&book.publisher) {
+                Err(BookEditError::NoSuchPublisher(book.publisher))?;
+            }
 
-        Ok(order)
+            let changes = Book::from_row(&row)?.changes(&book, |id| {
+                publishers
+                    .get(&id)
+                    .cloned()
+                    .unwrap_or_else(|| id.to_string())
+            });
+            if changes.is_empty() {
+                return Ok(());
+            }
+
+            let version = write_book(&mut transaction, &book)?;
+            record_book_edit(&mut transaction, book.isbn, version, owner_id, &changes)?;
+            if changes.iter().any(|change| change.field == PRICE_FIELD) {
+                record_book_price(&mut transaction, book.isbn, book.price, owner_id)?;
+            }
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+        .await
     }
 
-    pub async fn get_customer_orders_info(
+    /// Every change made to the book, newest first
+    pub async fn get_book_edits(
         conn: &DbConn,
-        customer_id: PostgresInt,
-    ) -> Result<Vec<schema::joined::Order>, postgres::error::Error> {
-        let orders_no_books: Vec<OrderNoBooks> = conn.run(move |c| c.query(
+        isbn: Isbn,
+    ) -> Result<Vec<BookEdit>, postgres::error::Error> {
+        conn.run(move |c| {
+            c.query(
+                "
+                SELECT book_edit.*, owner.name AS owner_name
+                FROM base.book_edit
+                LEFT JOIN base.owner USING (owner_id)
+                WHERE isbn = $1
+                ORDER BY version DESC, book_edit_id;
+                ",
+                &[&isbn],
+            )
+        })
+        .await?
+        .iter()
+        .map(|row| {
+            let edited_at: DateTime<Local> = row.try_get("edited_at")?;
+
+            Ok(BookEdit {
+                version: row.try_get("version")?,
+                owner_name: row.try_get("owner_name")?,
+                edited_at: edited_at.format("%Y-%m-%d %H:%M").to_string(),
+                field_name: row.try_get("field_name")?,
+                old_value: row.try_get("old_value")?,
+                new_value: row.try_get("new_value")?,
+            })
+        })
+        .collect()
+    }
+
+    /// The name `Book::changes` gives a change of price
+    const PRICE_FIELD: &str = "Price";
+
+    /// Adds a price that takes effect straight away to the book's price history
+    fn record_book_price(
+        client: &mut impl GenericClient,
+        isbn: Isbn,
+        price: PostgresNumeric,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<(), postgres::error::Error> {
+        client.execute(
             "
-            SELECT
-            order_id,
-            add.street_address,
-            add.postal_code,
-            add.province,
-            bill.street_address AS bill_street_address,
-            bill.postal_code AS bill_postal_code,
-            bill.province AS bill_province,
-            order_status,
-            order_date,
-            tracking_number,
-            name_on_card,
-            card_number,
-            expiry,
-            cvv
-            FROM
-            base.orders AS orders
-            INNER JOIN base.address AS add ON orders.shipping_address_id = add.address_id
-            INNER JOIN base.payment_info AS payment ON orders.payment_info_id = payment.payment_info_id
-            INNER JOIN base.address AS bill ON payment.billing_address_id = bill.address_id
-            WHERE customer_id = $1;
+            INSERT INTO base.book_price (isbn, price, effective_from, owner_id, applied_at)
+            VALUES ($1, $2, now(), $3, now());
             ",
-            &[&customer_id])).await?.iter()
-           .filter_map(
-            |row| {
-                let result: Result<OrderNoBooks, OrderError> = try {
-                    let address = Address::new::<&str>(row.try_get("street_address")?, row.try_get("postal_code")?, row.try_get("province")?);
-                    let billing_address = Address::new::<&str>(row.try_get("bill_street_address")?, row.try_get("bill_postal_code")?, row.try_get("bill_province")?);
-                    let expiry = Expiry::from_str::<&str>(row.try_get("expiry")?).ok_or(StateError::new("Invalid expiry"))?;
-                    let payment_info = PaymentInfo::new::<&str>(row.try_get("name_on_card")?, expiry, row.try_get("card_number")?, row.try_get("cvv")?, billing_address);
+            &[&isbn, &price, &owner_id],
+        )?;
 
-                    let date: NaiveDate = row.try_get("order_date")?; 
-
-                    OrderNoBooks {
-                        order_id: row.try_get("order_id")?,
-                        shipping_address: address,
-                        tracking_number: row.try_get("tracking_number")?,
-                        order_status: row.try_get("order_status")?,
-                        order_date: date.to_string(),
-                        payment_info,
-                    }
-                };
+        Ok(())
+    }
 
-                result.ok()
-        }).collect();
+    /// Every price the book has had or is scheduled to have, latest first
+    pub async fn get_book_prices(
+        conn: &DbConn,
+        isbn: Isbn,
+    ) -> Result<Vec<BookPrice>, postgres::error::Error> {
+        let rows = conn
+            .run(move |c| {
+                c.query(
+                    "
+                    SELECT book_price.*, owner.name AS owner_name, book_price.effective_from > now() AS scheduled
+                    FROM base.book_price
+                    LEFT JOIN base.owner USING (owner_id)
+                    WHERE isbn = $1
+                    ORDER BY effective_from DESC, book_price_id DESC;
+                    ",
+                    &[&isbn],
+                )
+            })
+            .await?;
 
-        let mut orders: Vec<Order> = vec![];
+        let mut found_current = false;
 
-        for order in orders_no_books {
-            orders.push(get_books_for_order(conn, order).await?);
-        }
+        rows.iter()
+            .map(|row| {
+                let effective_from: DateTime<Local> = row.try_get("effective_from")?;
+                let scheduled: bool = row.try_get("scheduled")?;
+                let current = !scheduled && !found_current;
+                found_current |= current;
 
-        Ok(orders)
+                Ok(BookPrice {
+                    book_price_id: row.try_get("book_price_id")?,
+                    price: row.try_get("price")?,
+                    effective_from: effective_from.format("%Y-%m-%d %H:%M").to_string(),
+                    owner_name: row.try_get("owner_name")?,
+                    scheduled,
+                    current,
+                })
+            })
+            .collect()
     }
 
-    pub async fn discontinue_books(
+    /// Schedules the book's price to change at `effective_from`, which has to be in the future
+    pub async fn schedule_price_change(
         conn: &DbConn,
-        books: Vec<ISBN>,
-    ) -> Result<(), postgres::error::Error> {
-        for isbn in books {
-            conn.run(move |c| {
-                c.execute(
-                    "UPDATE base.book SET discontinued = true WHERE isbn = $1",
-                    &[&isbn],
-                )
-            })
-            .await?;
+        isbn: Isbn,
+        price: PostgresNumeric,
+        effective_from: DateTime<Local>,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<PostgresInt, PriceError> {
+        if effective_from <= Local::now() {
+            Err(PriceError::NotInFuture)?;
         }
 
-        Ok(())
+        conn.run(move |c| {
+            let row = c
+                .query_opt(
+                    "
+                    INSERT INTO base.book_price (isbn, price, effective_from, owner_id)
+                    SELECT isbn, $2, $3, $4 FROM base.book WHERE isbn = $1
+                    RETURNING book_price_id;
+                    ",
+                    &[&isbn, &price, &effective_from, &owner_id],
+                )?
+                .ok_or(PriceError::NoSuchBook(isbn))?;
+
+            Ok(row.try_get("book_price_id")?)
+        })
+        .await
     }
 
-    pub async fn undiscontinue_books(
+    /// Cancels a price change that hasn't taken effect yet
+    pub async fn cancel_price_change(
         conn: &DbConn,
-        books: Vec<ISBN>,
-    ) -> Result<(), postgres::error::Error> {
-        for isbn in books {
-            conn.run(move |c| {
+        isbn: Isbn,
+        book_price_id: PostgresInt,
+    ) -> Result<(), PriceError> {
+        let deleted = conn
+            .run(move |c| {
                 c.execute(
-                    "UPDATE base.book SET discontinued = false WHERE isbn = $1",
-                    &[&isbn],
+                    "
+                    DELETE FROM base.book_price
+                    WHERE book_price_id = $1 AND isbn = $2 AND applied_at IS NULL AND effective_from > now();
+                    ",
+                    &[&book_price_id, &isbn],
                 )
             })
             .await?;
+
+        if deleted == 0 {
+            Err(PriceError::NoSuchPriceChange(book_price_id))?;
         }
 
         Ok(())
     }
 
-    pub async fn try_create_publisher<T: AsRef<str>>(
-        conn: &DbConn,
-        company_name: T,
-        email: T,
-        address: no_id::Address,
-        phone_number: T,
-        bank_number: T,
-    ) -> Result<PublisherID, CreatePublisherError> {
-        let company_name = company_name.as_ref().to_owned();
-        let email = email.as_ref().to_owned();
-        let phone_number = phone_number.as_ref().to_owned();
-        let bank_number = bank_number.as_ref().to_owned();
+    /// Copies every scheduled price whose time has come into its book, returning how many books
+    /// changed price.
+    ///
+    /// A book that was given a price directly since the scheduled one took effect keeps it.
+    pub async fn apply_scheduled_prices(conn: &DbConn) -> Result<u64, postgres::error::Error> {
+        conn.run(|c| {
+            let mut transaction = c.transaction()?;
 
-        let address_id = get_or_insert_address(&conn, address).await?;
+            let isbns: Vec<Isbn> = transaction
+                .query(
+                    "SELECT DISTINCT isbn FROM base.book_price WHERE applied_at IS NULL AND effective_from <= now() ORDER BY isbn;",
+                    &[],
+                )?
+                .iter()
+                .map(|row| row.try_get("isbn"))
+                .collect::<Result<_, _>>()?;
 
-        Ok(conn.run(move |c| c.query_one(
-            "INSERT INTO base.publisher
-            (company_name, phone_number, bank_number, address_id, email) VALUES ($1, $2, $3, $4, $5) RETURNING publisher_id;",
-            &[&company_name, &phone_number, &bank_number, &address_id, &email]) ).await?.try_get("publisher_id")?)
-    }
+            let mut changed = 0;
 
-    pub async fn get_sales_by_date(
-        conn: &DbConn,
-    ) -> Result<Vec<(NaiveDate, i64)>, postgres::error::Error> {
-        let rows = conn
-            .run(|c| c.query("SELECT * FROM base.sales;", &[]))
-            .await?;
+            for isbn in isbns {
+                let old_price: PostgresNumeric = transaction
+                    .query_one(
+                        "SELECT price FROM base.book WHERE isbn = $1 FOR UPDATE;",
+                        &[&isbn],
+                    )?
+                    .try_get("price")?;
 
-        Ok(rows
-            .iter()
-            .flat_map(|row| {
-                let result: Result<(NaiveDate, i64), postgres::error::Error> =
-                    try { (row.try_get("order_date")?, row.try_get("quantity")?) };
+                let latest = transaction.query_one(
+                    "
+                    SELECT price, owner_id FROM base.book_price
+                    WHERE isbn = $1 AND effective_from <= now()
+                    ORDER BY effective_from DESC, book_price_id DESC
+                    LIMIT 1;
+                    ",
+                    &[&isbn],
+                )?;
+                let new_price: PostgresNumeric = latest.try_get("price")?;
+                let owner_id: Option<PostgresInt> = latest.try_get("owner_id")?;
 
-                result.ok()
-            })
-            .collect())
-    }
+                if new_price != old_price {
+                    let version: PostgresInt = transaction
+                        .query_one(
+                            "UPDATE base.book SET price = $2, version = version + 1 WHERE isbn = $1 RETURNING version;",
+                            &[&isbn, &new_price],
+                        )?
+                        .try_get("version")?;
+                    let change = FieldChange {
+                        field: PRICE_FIELD,
+                        old: old_price.to_string(),
+                        new: new_price.to_string(),
+                    };
+                    record_book_edit(&mut transaction, isbn, version, owner_id, &[change])?;
+                    changed += 1;
+                }
 
-    pub async fn get_sales_by_publisher(
-        conn: &DbConn,
-        publisher_id: PostgresInt,
-    ) -> Result<Vec<(NaiveDate, i64)>, postgres::error::Error> {
-        let rows = conn
-            .run(move |c| c.query("SELECT order_date, sum(quantity) as quantity FROM base.raw_sales_data WHERE publisher_id = $1 GROUP BY (order_date);", &[&publisher_id]))
-            .await?;
+                transaction.execute(
+                    "UPDATE base.book_price SET applied_at = now() WHERE isbn = $1 AND applied_at IS NULL AND effective_from <= now();",
+                    &[&isbn],
+                )?;
+            }
 
-        Ok(rows
-            .iter()
-            .flat_map(|row| {
-                let result: Result<(NaiveDate, i64), postgres::error::Error> =
-                    try { (row.try_get("order_date")?, row.try_get("quantity")?) };
+            transaction.commit()?;
 
-                result.ok()
-            })
-            .collect())
+            Ok(changed)
+        })
+        .await
     }
 
-    pub async fn create_book(conn: &DbConn, book: Book) -> Result<(), postgres::error::Error> {
+    pub async fn create_book(
+        conn: &DbConn,
+        book: Book,
+        owner_id: Option<PostgresInt>,
+    ) -> Result<(), postgres::error::Error> {
         let Book {
             isbn,
             title,
@@ -1026,16 +5830,19 @@ pub mod query {
             discontinued,
         } = book;
         conn.run(move |c| {
-            c.execute(
+            let mut transaction = c.transaction()?;
+
+            transaction.execute(
                 "
                 INSERT INTO base.book (isbn, author_name, genre, publisher_id, num_pages, price, author_royalties, reorder_threshold, title, stock, discontinued)
                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11);
                 ",
                 &[&isbn, &author_name, &genre, &publisher, &num_pages, &price, &author_royalties, &reorder_threshold, &title, &stock, &discontinued],
-            )
-        }).await?;
+            )?;
+            record_book_price(&mut transaction, isbn, price, owner_id)?;
 
-        Ok(())
+            transaction.commit()
+        }).await
     }
 
     pub async fn get_publishers(conn: &DbConn) -> Result<Vec<Publisher>, postgres::error::Error> {
@@ -1114,6 +5921,118 @@ pub mod query {
         Ok(())
     }
 
+    fn session_type_columns(session_type: SessionType) -> (&'static str, Option<PostgresInt>) {
+        match session_type {
+            SessionType::Customer(customer_id) => ("CUSTOMER", Some(customer_id)),
+            SessionType::Owner(owner_id) => ("OWNER", Some(owner_id)),
+            SessionType::DefaultOwner => ("DEFAULT", None),
+        }
+    }
+
+    pub async fn insert_session(
+        conn: &DbConn,
+        token: String,
+        session_type: SessionType,
+        expiry: ExpirationTime,
+    ) -> Result<(), postgres::error::Error> {
+        let (session_type, account_id) = session_type_columns(session_type);
+        let token_hash = hash_token(&token);
+
+        conn.run(move |c| {
+            c.execute(
+                "INSERT INTO base.session (token_hash, session_type, account_id, expires_at) VALUES ($1, $2, $3, $4);",
+                &[&token_hash, &session_type, &account_id, &expiry],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Looks up an unexpired session, deleting it if it has expired
+    pub async fn get_session(
+        conn: &DbConn,
+        token: String,
+    ) -> Result<Option<SessionType>, postgres::error::Error> {
+        let token_hash = hash_token(&token);
+
+        conn.run(move |c| {
+            let row = match c.query_opt(
+                "SELECT session_type, account_id, expires_at > now() AS valid FROM base.session WHERE token_hash = $1;",
+                &[&token_hash],
+            )? {
+                Some(row) => row,
+                None => return Ok(None),
+            };
+
+            if !row.try_get::<_, bool>("valid")? {
+                c.execute(
+                    "DELETE FROM base.session WHERE token_hash = $1;",
+                    &[&token_hash],
+                )?;
+                return Ok(None);
+            }
+
+            let account_id: Option<PostgresInt> = row.try_get("account_id")?;
+
+            Ok(match (row.try_get::<_, &str>("session_type")?, account_id) {
+                ("CUSTOMER", Some(customer_id)) => Some(SessionType::Customer(customer_id)),
+                ("OWNER", Some(owner_id)) => Some(SessionType::Owner(owner_id)),
+                ("DEFAULT", None) => Some(SessionType::DefaultOwner),
+                _ => None,
+            })
+        })
+        .await
+    }
+
+    pub async fn delete_session(
+        conn: &DbConn,
+        token: String,
+    ) -> Result<(), postgres::error::Error> {
+        let token_hash = hash_token(&token);
+
+        conn.run(move |c| {
+            c.execute(
+                "DELETE FROM base.session WHERE token_hash = $1;",
+                &[&token_hash],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_account_sessions(
+        conn: &DbConn,
+        session_type: SessionType,
+    ) -> Result<u64, postgres::error::Error> {
+        let (session_type, account_id) = session_type_columns(session_type);
+
+        conn.run(move |c| {
+            c.execute(
+                "DELETE FROM base.session WHERE session_type = $1 AND account_id IS NOT DISTINCT FROM $2;",
+                &[&session_type, &account_id],
+            )
+        })
+        .await
+    }
+
+    pub async fn delete_expired_sessions(conn: &DbConn) -> Result<u64, postgres::error::Error> {
+        conn.run(|c| c.execute("DELETE FROM base.session WHERE expires_at <= now();", &[]))
+            .await
+    }
+
+    pub async fn does_owner_session_exist(conn: &DbConn) -> Result<bool, postgres::error::Error> {
+        conn.run(|c| {
+            c.query_one(
+                "SELECT EXISTS (SELECT 1 FROM base.session WHERE session_type = 'OWNER' AND expires_at > now()) AS owner_session;",
+                &[],
+            )
+        })
+        .await?
+        .try_get("owner_session")
+    }
+
     pub async fn does_owner_exist(conn: &DbConn) -> Result<bool, postgres::error::Error> {
         Ok(conn
             .run(|c| c.query("SELECT owner_id FROM base.owner;", &[]))
@@ -1123,3 +6042,461 @@ pub mod query {
             > 0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::conn::DbConn;
+    use super::error::{OrderError, OrderStatusError};
+    use super::migrations;
+    use super::query::{claim_due_mail, create_order, set_order_status, Buyer, Expiry};
+    use crate::config::RestockPolicy;
+    use crate::payment::{
+        CardDetails, MockPaymentProvider, PaymentError, PaymentProvider, TokenisedCard,
+    };
+    use crate::schema::entities::{OrderStatus, PostgresInt};
+    use crate::schema::no_id::{Address, PaymentInfo};
+    use crate::PaymentProviderState;
+    use rand::Rng;
+    use rocket::tokio;
+    use rocket::{Ignite, Rocket};
+    use rocket_sync_db_pools::postgres::{Client, NoTls};
+    use rust_decimal::Decimal;
+    use std::sync::{Arc, Mutex};
+
+    const BUYERS: usize = 12;
+    const ISBN: i64 = 9780306406157;
+
+    /// A throwaway database on the configured server, dropped when the test finishes.
+    ///
+    /// The synchronous client can't run inside the test's runtime, so it's driven from its own thread.
+    struct ScratchDatabase {
+        server_url: String,
+        name: String,
+    }
+
+    impl ScratchDatabase {
+        fn create() -> ScratchDatabase {
+            let server_url: String = rocket::Config::figment()
+                .extract_inner("databases.postgres.url")
+                .expect("a database URL to test against");
+            let name = format!(
+                "look_inna_book_test_{:08x}",
+                rand::thread_rng().gen::<u32>()
+            );
+
+            let scratch = ScratchDatabase { server_url, name };
+            scratch.admin(format!("CREATE DATABASE {};", scratch.name));
+            scratch
+        }
+
+        fn url(&self) -> String {
+            let (server, _) = self.server_url.rsplit_once('/').expect("a database URL");
+            format!("{}/{}", server, self.name)
+        }
+
+        fn admin(&self, statement: String) {
+            let url = self.server_url.clone();
+            std::thread::spawn(move || {
+                let mut client = Client::connect(&url, NoTls).expect("a connection to the server");
+                client
+                    .batch_execute(&statement)
+                    .expect("the statement to succeed");
+            })
+            .join()
+            .expect("the admin thread not to panic");
+        }
+    }
+
+    impl Drop for ScratchDatabase {
+        fn drop(&mut self) {
+            self.admin(format!(
+                "DROP DATABASE IF EXISTS {} WITH (FORCE);",
+                self.name
+            ));
+        }
+    }
+
+    /// Rocket with a pool of `pool_size` connections to the migrated scratch database
+    async fn scratch_rocket(scratch: &ScratchDatabase, pool_size: u32) -> Rocket<Ignite> {
+        let figment = rocket::Config::figment()
+            .merge(("databases.postgres.url", scratch.url()))
+            .merge(("databases.postgres.pool_size", pool_size));
+
+        rocket::custom(figment)
+            .attach(DbConn::fairing())
+            .attach(migrations::fairing())
+            .ignite()
+            .await
+            .expect("the scratch database to migrate")
+    }
+
+    /// Seeds one copy of a book and puts it in every buyer's cart, returning the buyers
+    fn seed_last_copy(client: &mut Client) -> Vec<PostgresInt> {
+        let address_id: PostgresInt = client
+            .query_one(
+                "INSERT INTO base.address (street_address, postal_code, province) VALUES ('1 Main St', 'K1A0B1', 'ON') RETURNING address_id;",
+                &[],
+            )
+            .unwrap()
+            .get("address_id");
+        let publisher_id: PostgresInt = client
+            .query_one(
+                "INSERT INTO base.publisher (company_name, phone_number, bank_number, address_id, email) VALUES ('Pub', '555', '1', $1, 'pub@local') RETURNING publisher_id;",
+                &[&address_id],
+            )
+            .unwrap()
+            .get("publisher_id");
+        client
+            .execute(
+                "
+                INSERT INTO base.book
+                (isbn, author_name, genre, publisher_id, num_pages, price, author_royalties, reorder_threshold, title, stock, discontinued)
+                VALUES ($1, 'Author', 'Fiction', $2, 100, 10.00, 0.10, 0, 'The Last Copy', 1, true);
+                ",
+                &[&ISBN, &publisher_id],
+            )
+            .unwrap();
+
+        (0..BUYERS)
+            .map(|i| {
+                let customer_id: PostgresInt = client
+                    .query_one(
+                        "INSERT INTO base.customer (name, email, email_verified_at) VALUES ($1, $2, now()) RETURNING customer_id;",
+                        &[&format!("Buyer {i}"), &format!("buyer{i}@local")],
+                    )
+                    .unwrap()
+                    .get("customer_id");
+                // Not held, so every cart races for the copy at checkout
+                client
+                    .execute(
+                        "INSERT INTO base.in_cart (isbn, customer_id, quantity, reserved_until) VALUES ($1, $2, 1, NULL);",
+                        &[&ISBN, &customer_id],
+                    )
+                    .unwrap();
+                customer_id
+            })
+            .collect()
+    }
+
+    #[rocket::async_test]
+    async fn concurrent_checkouts_sell_the_last_copy_once() {
+        let scratch = ScratchDatabase::create();
+        let rocket = scratch_rocket(&scratch, BUYERS as u32 + 1).await;
+
+        let seed_conn = DbConn::get_one(&rocket).await.unwrap();
+        let customers = seed_conn.run(seed_last_copy).await;
+
+        let payment_provider: PaymentProviderState =
+            Arc::new(MockPaymentProvider::new("concurrent-checkout-test"));
+        let expiry = Expiry::from_str("12/30").unwrap();
+        let card = CardDetails::new("Buyer", expiry, "4111111111111111", "123");
+        let tokenised = payment_provider.tokenise(&card).unwrap();
+
+        let mut checkouts = Vec::new();
+        for customer_id in customers {
+            let conn = DbConn::get_one(&rocket).await.unwrap();
+            let address = Address::new("1 Main St", "K1A0B1", "ON");
+            let payment_info =
+                PaymentInfo::new("Buyer", expiry, tokenised.clone(), address.clone());
+            let payment_provider = payment_provider.clone();
+
+            checkouts.push(tokio::spawn(async move {
+                create_order(
+                    &conn,
+                    Buyer::Customer(customer_id),
+                    Some(address),
+                    Some(payment_info),
+                    None,
+                    RestockPolicy::default(),
+                    payment_provider,
+                )
+                .await
+            }));
+        }
+
+        let mut sold = 0;
+        for checkout in checkouts {
+            match checkout.await.unwrap() {
+                Ok(_) => sold += 1,
+                Err(OrderError::NotEnoughStock) | Err(OrderError::Conflict) => {}
+                Err(e) => panic!("unexpected checkout error: {e}"),
+            }
+        }
+        assert_eq!(sold, 1);
+
+        let stock: PostgresInt = seed_conn
+            .run(|c| {
+                c.query_one("SELECT stock FROM base.book WHERE isbn = $1;", &[&ISBN])
+                    .unwrap()
+                    .get("stock")
+            })
+            .await;
+        assert_eq!(stock, 0);
+    }
+
+    #[rocket::async_test]
+    async fn a_returned_order_is_restocked_and_leaves_the_sales() {
+        let scratch = ScratchDatabase::create();
+        let rocket = scratch_rocket(&scratch, 2).await;
+
+        let conn = DbConn::get_one(&rocket).await.unwrap();
+        let customers = conn.run(seed_last_copy).await;
+
+        let payment_provider: PaymentProviderState =
+            Arc::new(MockPaymentProvider::new("return-test"));
+        let expiry = Expiry::from_str("12/30").unwrap();
+        let card = CardDetails::new("Buyer", expiry, "4111111111111111", "123");
+        let address = Address::new("1 Main St", "K1A0B1", "ON");
+        let payment_info = PaymentInfo::new(
+            "Buyer",
+            expiry,
+            payment_provider.tokenise(&card).unwrap(),
+            address.clone(),
+        );
+
+        let order_id = create_order(
+            &conn,
+            Buyer::Customer(customers[0]),
+            Some(address),
+            Some(payment_info),
+            None,
+            RestockPolicy::default(),
+            payment_provider,
+        )
+        .await
+        .unwrap();
+
+        let stock_and_sales = |c: &mut Client| -> (PostgresInt, i64) {
+            let row = c
+                .query_one(
+                    "
+                    SELECT book.stock,
+                    (SELECT count(*) FROM base.raw_sales_data WHERE isbn = $1) AS sales
+                    FROM base.book WHERE isbn = $1;
+                    ",
+                    &[&ISBN],
+                )
+                .unwrap();
+            (row.get("stock"), row.get("sales"))
+        };
+
+        for status in [
+            OrderStatus::Packed,
+            OrderStatus::Shipped,
+            OrderStatus::Delivered,
+        ] {
+            set_order_status(&conn, order_id, status, None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(conn.run(stock_and_sales).await, (0, 1));
+
+        set_order_status(&conn, order_id, OrderStatus::Returned, None)
+            .await
+            .unwrap();
+        assert_eq!(conn.run(stock_and_sales).await, (1, 0));
+    }
+
+    #[rocket::async_test]
+    async fn an_owner_cancelling_an_order_records_why() {
+        let scratch = ScratchDatabase::create();
+        let rocket = scratch_rocket(&scratch, 2).await;
+
+        let conn = DbConn::get_one(&rocket).await.unwrap();
+        let customers = conn.run(seed_last_copy).await;
+
+        let payment_provider: PaymentProviderState =
+            Arc::new(MockPaymentProvider::new("owner-cancel-test"));
+        let expiry = Expiry::from_str("12/30").unwrap();
+        let card = CardDetails::new("Buyer", expiry, "4111111111111111", "123");
+        let address = Address::new("1 Main St", "K1A0B1", "ON");
+        let payment_info = PaymentInfo::new(
+            "Buyer",
+            expiry,
+            payment_provider.tokenise(&card).unwrap(),
+            address.clone(),
+        );
+
+        let order_id = create_order(
+            &conn,
+            Buyer::Customer(customers[0]),
+            Some(address),
+            Some(payment_info),
+            None,
+            RestockPolicy::default(),
+            payment_provider,
+        )
+        .await
+        .unwrap();
+
+        let result =
+            set_order_status(&conn, order_id, OrderStatus::Cancelled, Some(" ".into())).await;
+        assert!(matches!(result, Err(OrderStatusError::MissingReason)));
+
+        set_order_status(
+            &conn,
+            order_id,
+            OrderStatus::Cancelled,
+            Some("Damaged in the warehouse".into()),
+        )
+        .await
+        .unwrap();
+
+        let (status, reason): (String, String) = conn
+            .run(move |c| {
+                let row = c
+                    .query_one(
+                        "
+                        SELECT orders.order_status, order_cancellation.reason
+                        FROM base.orders
+                        INNER JOIN base.order_cancellation USING (order_id)
+                        WHERE order_id = $1;
+                        ",
+                        &[&order_id],
+                    )
+                    .unwrap();
+                (row.get("order_status"), row.get("reason"))
+            })
+            .await;
+        assert_eq!(status, OrderStatus::Cancelled.as_str());
+        assert_eq!(reason, "Damaged in the warehouse");
+    }
+
+    #[rocket::async_test]
+    async fn concurrent_dispatchers_claim_each_email_once() {
+        const MAILS: i64 = 40;
+        const DISPATCHERS: usize = 8;
+
+        let scratch = ScratchDatabase::create();
+        let rocket = scratch_rocket(&scratch, DISPATCHERS as u32 + 1).await;
+
+        let seed_conn = DbConn::get_one(&rocket).await.unwrap();
+        seed_conn
+            .run(|c| {
+                c.execute(
+                    "
+                    INSERT INTO base.mail_outbox (template, recipient_email, context)
+                    SELECT 'WELCOME', 'reader' || n || '@local', '{}'::jsonb
+                    FROM generate_series(1, $1::bigint) AS n;
+                    ",
+                    &[&MAILS],
+                )
+            })
+            .await
+            .unwrap();
+
+        let mut dispatchers = Vec::new();
+        for _ in 0..DISPATCHERS {
+            let conn = DbConn::get_one(&rocket).await.unwrap();
+            dispatchers.push(tokio::spawn(async move {
+                let mut claimed = Vec::new();
+                loop {
+                    let batch = claim_due_mail(&conn, 5, 3, 300).await.unwrap();
+                    if batch.is_empty() {
+                        break claimed;
+                    }
+                    claimed.extend(batch.into_iter().map(|mail| mail.mail_id));
+                }
+            }));
+        }
+
+        let mut claimed = Vec::new();
+        for dispatcher in dispatchers {
+            claimed.extend(dispatcher.await.unwrap());
+        }
+        claimed.sort_unstable();
+        assert_eq!(claimed, (1..=MAILS as PostgresInt).collect::<Vec<_>>());
+
+        let again = claim_due_mail(&seed_conn, 5, MAILS, 300).await.unwrap();
+        assert!(again.is_empty());
+    }
+
+    /// The mock provider, remembering which authorisations it gave out and which were voided
+    struct RecordingProvider {
+        mock: MockPaymentProvider,
+        authorised: Mutex<Vec<String>>,
+        voided: Mutex<Vec<String>>,
+    }
+
+    impl PaymentProvider for RecordingProvider {
+        fn tokenise(&self, card: &CardDetails) -> Result<TokenisedCard, PaymentError> {
+            self.mock.tokenise(card)
+        }
+
+        fn authorise(&self, token: &str, amount: Decimal) -> Result<String, PaymentError> {
+            let authorisation_id = self.mock.authorise(token, amount)?;
+            self.authorised
+                .lock()
+                .unwrap()
+                .push(authorisation_id.clone());
+            Ok(authorisation_id)
+        }
+
+        fn void(&self, authorisation_id: &str) -> Result<(), PaymentError> {
+            self.mock.void(authorisation_id)?;
+            self.voided
+                .lock()
+                .unwrap()
+                .push(authorisation_id.to_string());
+            Ok(())
+        }
+    }
+
+    #[rocket::async_test]
+    async fn an_order_that_fails_to_commit_voids_its_payment() {
+        let scratch = ScratchDatabase::create();
+        let rocket = scratch_rocket(&scratch, 2).await;
+
+        let conn = DbConn::get_one(&rocket).await.unwrap();
+        let customers = conn.run(seed_last_copy).await;
+        // Checked when the transaction commits, after the card has been authorised
+        conn.run(|c| {
+            c.batch_execute(
+                "
+                CREATE FUNCTION base.refuse_order() RETURNS trigger LANGUAGE plpgsql AS $$
+                BEGIN
+                    RAISE EXCEPTION 'refused at commit';
+                END
+                $$;
+
+                CREATE CONSTRAINT TRIGGER refuse_order AFTER INSERT ON base.orders
+                DEFERRABLE INITIALLY DEFERRED
+                FOR EACH ROW EXECUTE FUNCTION base.refuse_order();
+                ",
+            )
+        })
+        .await
+        .unwrap();
+
+        let provider = Arc::new(RecordingProvider {
+            mock: MockPaymentProvider::new("void-test"),
+            authorised: Mutex::new(vec![]),
+            voided: Mutex::new(vec![]),
+        });
+        let expiry = Expiry::from_str("12/30").unwrap();
+        let card = CardDetails::new("Buyer", expiry, "4111111111111111", "123");
+        let address = Address::new("1 Main St", "K1A0B1", "ON");
+        let payment_info = PaymentInfo::new(
+            "Buyer",
+            expiry,
+            provider.tokenise(&card).unwrap(),
+            address.clone(),
+        );
+
+        let result = create_order(
+            &conn,
+            Buyer::Customer(customers[0]),
+            Some(address),
+            Some(payment_info),
+            None,
+            RestockPolicy::default(),
+            provider.clone(),
+        )
+        .await;
+        assert!(matches!(result, Err(OrderError::DBError(_))));
+
+        let authorised = provider.authorised.lock().unwrap().clone();
+        assert_eq!(authorised.len(), 1);
+        assert_eq!(*provider.voided.lock().unwrap(), authorised);
+    }
+}
@@ -1,38 +1,69 @@
-use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 
+use crate::config::{AccountConfig, ReservationConfig, RestockPolicy};
 use crate::db::conn::DbConn;
-use crate::db::error::{CartError, OrderError, StateError};
+use crate::db::error::{
+    AccountTokenError, CancelOrderError, CartError, CollectionError, ImportError, OrderError,
+    OrderStatusError, RestockError, RoyaltyError, SettlementError, StateError, WishlistError,
+};
 use crate::db::query::{
-    add_to_cart, cart_set_book_quantity, create_book, delete_customer_account,
-    delete_owner_account, discontinue_books, get_books, get_books_for_order,
-    get_books_with_publisher_name, get_customer_accounts, get_customer_cart, get_customer_info,
-    get_customer_orders_info, get_order_info, get_owner_accounts, get_publishers,
-    get_sales_by_date, get_sales_by_publisher, try_create_new_customer, try_create_new_owner,
-    try_create_publisher, undiscontinue_books, validate_customer_login, validate_owner_login,
-    Expiry, OwnerLoginType,
+    add_book_to_collection, add_book_to_wishlist, add_to_cart, apply_catalog_import, cancel_order,
+    cancel_price_change, cart_set_book_quantity, create_book, create_catalog_import,
+    create_collection, create_promotion, create_publisher_remittance, create_wishlist,
+    delete_collection, delete_customer_account, delete_owner_account, delete_wishlist,
+    discontinue_books, get_book_edits, get_book_for_edit, get_book_prices, get_books,
+    get_books_for_order, get_cart, get_cart_reservations, get_catalog_import, get_catalog_imports,
+    get_collection, get_collections, get_customer_accounts, get_customer_info,
+    get_customer_orders_info, get_fulfilment_orders, get_genres, get_order_info,
+    get_order_status_history, get_owner_accounts, get_promotion_sales, get_promotions,
+    get_publisher_remittances, get_publisher_settlements, get_publisher_statement, get_publishers,
+    get_restock_orders, get_royalties, get_royalty_payout, get_royalty_payouts, get_sales_by_date,
+    get_sales_by_publisher, get_shared_wishlist, get_stock_levels, get_stock_subscriptions,
+    get_wishlists, hold_cart, merge_guest_cart, quote_promotion, record_royalty_payout,
+    remove_book_from_collection, remove_book_from_wishlist, rename_collection, rename_wishlist,
+    reorder_collections, request_password_reset, resend_email_verification, reset_password,
+    schedule_price_change, set_order_status, set_promotion_active, set_remittance_status,
+    set_restock_order_status, subscribe_to_stock, try_create_new_customer, try_create_new_owner,
+    try_create_publisher, undiscontinue_books, unsubscribe_from_stock, update_book,
+    validate_customer_login, validate_owner_login, verify_email, AccountKind, Buyer, Expiry,
+    OwnerLoginType,
+};
+use crate::db::search::{
+    export_books, search_books, suggest_books, BookQuery, BookSort, Suggestion,
 };
 use crate::request_guards::state::SessionType;
-use crate::schema::entities::{Book, BookWithPublisherName, PostgresInt, ISBN};
-use crate::schema::joined::Order;
+use crate::schema::entities::{
+    Book, Isbn, OrderStatus, PostgresInt, PostgresNumeric, PublisherID, RemittanceStatus,
+    RestockStatus,
+};
+use crate::schema::joined::{CartReservation, Order, PromotionQuote};
 use crate::schema::no_id::{Address, PaymentInfo};
 use crate::schema::{self, no_id};
-use chrono::{Duration, Local, NaiveDate};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
 use rand::{RngCore, SeedableRng};
 use rocket::form::validate::Contains;
 use rocket::form::Form;
+use rocket::fs::TempFile;
 use rocket::http::{ContentType, Cookie, CookieJar, Status};
 use rocket::response::Redirect;
 use rocket::serde::json::Json;
+use rocket::tokio::sync::mpsc;
 use rocket::State;
 use rocket_dyn_templates::tera::Context;
 use rocket_dyn_templates::Template;
 use rust_decimal::Decimal;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
-use strsim::sorensen_dice;
 
-use crate::{request_guards::*, SessionTokenState};
+use crate::csv::{CsvFile, CsvWriter};
+use crate::export::{CatalogExport, ExportFormat};
+use crate::import::ImportFormat;
+use crate::isbn::IsbnError;
+use crate::payment::CardDetails;
+use crate::promotion::{
+    normalise_code, DiscountError, DiscountType, PromotionScope, PromotionTerms,
+};
+use crate::{request_guards::*, PaymentProviderState, SessionStoreState};
 
 async fn render_error_template<T: AsRef<str>>(
     error: T,
@@ -56,7 +87,7 @@ async fn add_customer_info(conn: &DbConn, customer: &Option<Customer>, context:
             context.insert("customer", &crate::schema::joined::Customer::default());
         }
 
-        if let Ok(cart) = get_customer_cart(&conn, customer.customer_id).await {
+        if let Ok(cart) = get_cart(conn, &CartOwner::Customer(customer.customer_id)).await {
             context.insert("cart_size", &cart.len());
         } else {
             context.insert("cart_size", &0);
@@ -64,18 +95,27 @@ async fn add_customer_info(conn: &DbConn, customer: &Option<Customer>, context:
     }
 }
 
+/// Visitors who haven't logged in still see how many books are in their guest cart
+async fn add_guest_cart_info(conn: &DbConn, cart: &Option<CartOwner>, context: &mut Context) {
+    match cart {
+        Some(cart @ CartOwner::Guest(_)) => {
+            let cart_size = get_cart(conn, cart).await.map_or(0, |cart| cart.len());
+            context.insert("cart_size", &cart_size);
+        }
+        Some(CartOwner::Customer(_)) => {}
+        None => context.insert("cart_size", &0),
+    }
+}
+
 fn add_owner_tag(owner: &Option<Owner>, context: &mut Context) {
     if let Some(_) = owner {
         context.insert("owner_logged_in", &true);
     }
 }
 
-fn extract_genre_list(books: &Vec<BookWithPublisherName>) -> HashSet<String> {
-    books.iter().map(|book| book.genre.clone()).collect()
-}
-
 #[derive(FromForm, Debug)]
 pub struct Search<'r> {
+    q: Option<&'r str>,
     title: Option<&'r str>,
     isbn: Option<&'r str>,
     genre: Option<&'r str>,
@@ -87,118 +127,137 @@ pub struct Search<'r> {
     max_price: Option<&'r str>,
     show_discontinued: Option<bool>,
     show_no_stock: Option<bool>,
+    sort: Option<BookSort>,
+    pub(crate) page: Option<u32>,
 }
 
-fn filter_books(
-    books: Vec<BookWithPublisherName>,
-    search: Search<'_>,
-) -> Vec<BookWithPublisherName> {
-    let Search {
-        title,
-        isbn,
-        genre,
-        author,
-        publisher,
-        min_pages,
-        max_pages,
-        min_price,
-        max_price,
-        show_discontinued,
-        show_no_stock,
-    } = search;
-    let mut books = books;
-
-    if !show_discontinued.unwrap_or(false) {
-        books.retain(|book| !book.discontinued);
-    }
-
-    if !show_no_stock.unwrap_or(false) {
-        books.retain(|book| book.stock != 0);
-    }
-
-    let isbn = isbn.unwrap_or("");
-    if isbn != "" {
-        if let Ok(isbn) = isbn.parse::<i32>() {
-            books.retain(|book| book.isbn == isbn);
-        }
+pub(crate) const BOOKS_PER_PAGE: u32 = 24;
+
+impl Search<'_> {
+    fn text(&self) -> Option<&str> {
+        self.q.map(str::trim).filter(|q| !q.is_empty())
     }
 
-    let genre = genre.unwrap_or("");
-    if genre != "" {
-        if genre != "N/A" {
-            books.retain(|book| book.genre == genre);
+    /// Searching for text sorts by relevance unless another order was picked
+    fn sort(&self) -> BookSort {
+        match (self.sort, self.text()) {
+            (Some(sort), _) => sort,
+            (None, Some(_)) => BookSort::Relevance,
+            (None, None) => BookSort::default(),
         }
     }
+}
+
+/// Turns the search form into a query for one page of `per_page` books
+pub(crate) fn book_query(search: &Search<'_>, per_page: u32) -> BookQuery {
+    search_query(search).page(search.page.unwrap_or(1), per_page)
+}
+
+/// Turns the search form into a query for every matching book
+fn search_query(search: &Search<'_>) -> BookQuery {
+    let mut query = BookQuery::new()
+        .include_discontinued(search.show_discontinued.unwrap_or(false))
+        .include_no_stock(search.show_no_stock.unwrap_or(false))
+        .sort(search.sort());
+
+    if let Some(text) = search.text() {
+        query = query.text(text);
+    }
 
-    let author = author.unwrap_or("");
-    if author != "" {
-        books.retain(|book| book.author_name == author);
+    if let Some(Ok(isbn)) = search.isbn.filter(|isbn| !isbn.is_empty()).map(str::parse) {
+        query = query.isbn(isbn);
     }
 
-    let publisher = publisher.unwrap_or("");
-    if publisher != "" {
-        books.retain(|book| book.publisher_name == publisher);
+    if let Some(title) = search.title.filter(|title| !title.is_empty()) {
+        query = query.title(title);
     }
 
-    if let Some(min_pages) = min_pages {
-        books.retain(|book| book.num_pages >= min_pages);
+    if let Some(genre) = search
+        .genre
+        .filter(|genre| !genre.is_empty() && *genre != "N/A")
+    {
+        query = query.genre(genre);
     }
 
-    if let Some(max_pages) = max_pages {
-        books.retain(|book| book.num_pages <= max_pages);
+    if let Some(author) = search.author.filter(|author| !author.is_empty()) {
+        query = query.author(author);
     }
 
-    if let Some(min_price) = min_price {
-        if let Ok(min_price) = Decimal::from_str(min_price) {
-            books.retain(|book| book.price >= min_price);
-        }
+    if let Some(publisher) = search.publisher.filter(|publisher| !publisher.is_empty()) {
+        query = query.publisher(publisher);
     }
 
-    if let Some(max_price) = max_price {
-        if let Ok(max_price) = Decimal::from_str(max_price) {
-            books.retain(|book| book.price <= max_price);
-        }
+    if let Some(min_pages) = search.min_pages {
+        query = query.min_pages(min_pages);
+    }
+
+    if let Some(max_pages) = search.max_pages {
+        query = query.max_pages(max_pages);
     }
 
-    let title = title.unwrap_or("");
-    if title != "" {
-        books.sort_by(|a, b| {
-            let a_score = sorensen_dice(&a.title, title);
-            let b_score = sorensen_dice(&b.title, title);
+    if let Some(Ok(min_price)) = search.min_price.map(Decimal::from_str) {
+        query = query.min_price(min_price);
+    }
 
-            // Descending sort
-            match b_score.partial_cmp(&a_score) {
-                Some(ord) => ord,
-                None => Ordering::Equal,
-            }
-        });
+    if let Some(Ok(max_price)) = search.max_price.map(Decimal::from_str) {
+        query = query.max_price(max_price);
     }
 
-    books
+    query
+}
+
+/// Runs the search and fills in the books, genres and page navigation for the search pages
+async fn add_search_results(
+    conn: &DbConn,
+    search: &Search<'_>,
+    context: &mut Context,
+) -> Result<(), postgres::error::Error> {
+    let results = search_books(conn, book_query(search, BOOKS_PER_PAGE)).await?;
+    let total_pages = (results.total_books as u32).div_ceil(BOOKS_PER_PAGE).max(1);
+
+    context.insert("books", &results.books);
+    context.insert("total_books", &results.total_books);
+    context.insert("page", &search.page.unwrap_or(1).max(1));
+    context.insert("total_pages", &total_pages);
+    context.insert("q", search.text().unwrap_or(""));
+    context.insert("suggestion", &results.suggestion);
+    context.insert("sort", search.sort().as_str());
+    context.insert(
+        "sorts",
+        &BookSort::ALL
+            .iter()
+            .map(|sort| (sort.as_str(), sort.label()))
+            .collect::<Vec<_>>(),
+    );
+    context.insert("genres", &get_genres(conn).await?);
+
+    Ok(())
 }
 
 #[get("/?<search>")]
 pub async fn index(
     conn: DbConn,
     customer: Option<Customer>,
+    cart: Option<CartOwner>,
     owner: Option<Owner>,
     search: Search<'_>,
 ) -> Template {
     let mut context = Context::new();
     add_customer_info(&conn, &customer, &mut context).await;
+    add_guest_cart_info(&conn, &cart, &mut context).await;
     add_owner_tag(&owner, &mut context);
 
-    let books = get_books_with_publisher_name(&conn).await;
-    if let Ok(books) = books {
-        let books = filter_books(books, search);
-
-        context.insert("books", &books);
-        context.insert("genres", &extract_genre_list(&books));
+    let results = add_search_results(&conn, &search, &mut context).await;
+    if results.is_ok() {
+        // Shelves are a nice to have, so a failure here shouldn't take the storefront down
+        if let Ok(collections) = get_collections(&conn, false).await {
+            context.insert("collections", &collections);
+        }
 
         Template::render("index", context.into_json())
     } else {
         render_error_template(
-            format!("Could not query books: {:?}", books),
+            format!("Could not query books: {results:?}"),
             &conn,
             &customer,
         )
@@ -206,6 +265,24 @@ pub async fn index(
     }
 }
 
+pub(crate) const SUGGESTION_LIMIT: i64 = 8;
+
+#[get("/search/suggest?<q>")]
+pub async fn search_suggestions(
+    conn: DbConn,
+    q: Option<&str>,
+) -> Result<Json<Vec<Suggestion>>, (Status, String)> {
+    let q = q.unwrap_or("").trim();
+    if q.is_empty() {
+        return Ok(Json(vec![]));
+    }
+
+    match suggest_books(&conn, q.to_string(), SUGGESTION_LIMIT).await {
+        Ok(suggestions) => Ok(Json(suggestions)),
+        Err(e) => Err((Status::InternalServerError, e.to_string())),
+    }
+}
+
 #[get("/login")]
 pub async fn login_page() -> Template {
     let context = HashMap::<&str, &str>::new();
@@ -260,7 +337,7 @@ pub struct Login<'r> {
     password: &'r str,
 }
 
-fn create_session_token() -> String {
+pub(crate) fn create_session_token() -> String {
     let mut rng = rand_chacha::ChaCha12Rng::from_entropy();
     let mut token: [u8; 32] = [0; 32];
     rng.fill_bytes(&mut token);
@@ -274,21 +351,42 @@ fn create_session_token() -> String {
 pub async fn login(
     conn: DbConn,
     login_data: Form<Login<'_>>,
-    session_tokens: &State<SessionTokenState>,
+    session_store: &State<SessionStoreState>,
+    reservations: &State<ReservationConfig>,
     cookies: &CookieJar<'_>,
 ) -> Redirect {
     match validate_customer_login(&conn, login_data.email, login_data.password).await {
         Ok(customer_id) => {
             let token = create_session_token();
 
-            cookies.add_private(Cookie::new(CUST_SESSION_COOKIE_NAME, token.clone()));
+            let expiry = Local::now() + Duration::days(30);
 
-            let mut session_tokens = session_tokens.lock().await;
+            match session_store
+                .insert(token.clone(), SessionType::Customer(customer_id), expiry)
+                .await
+            {
+                Ok(_) => {
+                    cookies.add_private(Cookie::new(CUST_SESSION_COOKIE_NAME, token));
 
-            let expiry = Local::now() + Duration::days(30);
+                    if let Some(cookie) = cookies.get_private(GUEST_CART_COOKIE_NAME) {
+                        // A cart that can't be merged is left for the sweeper rather than failing the login
+                        if let Err(e) = merge_guest_cart(
+                            &conn,
+                            cookie.value().to_string(),
+                            customer_id,
+                            reservations.reserved_until(),
+                        )
+                        .await
+                        {
+                            warn!("Could not merge guest cart: {}", e);
+                        }
+                        cookies.remove_private(Cookie::named(GUEST_CART_COOKIE_NAME));
+                    }
 
-            session_tokens.insert(token, (SessionType::Customer(customer_id), expiry));
-            Redirect::to(uri!(customer_page()))
+                    Redirect::to(uri!(customer_page()))
+                }
+                Err(e) => Redirect::to(uri!(login_failed(e.to_string()))),
+            }
         }
         Err(e) => match e {
             _ => Redirect::to(uri!(login_failed("Invalid email/password"))),
@@ -314,7 +412,12 @@ pub struct Register<'r> {
 }
 
 #[post("/register", data = "<register_data>")]
-pub async fn register(conn: DbConn, register_data: Form<Register<'_>>) -> Redirect {
+pub async fn register(
+    conn: DbConn,
+    register_data: Form<Register<'_>>,
+    payment_provider: &State<PaymentProviderState>,
+    accounts: &State<AccountConfig>,
+) -> Redirect {
     let Register {
         email,
         name,
@@ -347,21 +450,46 @@ pub async fn register(conn: DbConn, register_data: Form<Register<'_>>) -> Redire
         ))));
     };
 
-    let payment_info =
-        no_id::PaymentInfo::new(name_on_card, expiry, card_number, cvv, billing_address);
+    let card = match payment_provider.tokenise(&CardDetails::new(
+        name_on_card,
+        expiry,
+        card_number,
+        cvv,
+    )) {
+        Ok(card) => card,
+        Err(e) => return Redirect::to(uri!(register_failed(e.to_string()))),
+    };
+
+    let payment_info = no_id::PaymentInfo::new(name_on_card, expiry, card, billing_address);
 
-    match try_create_new_customer(&conn, email, password, name, address, payment_info).await {
+    match try_create_new_customer(
+        &conn,
+        email,
+        password,
+        name,
+        address,
+        payment_info,
+        accounts.inner().clone(),
+    )
+    .await
+    {
         Ok(_) => Redirect::to("/"),
         Err(e) => Redirect::to(uri!(register_failed(format!("{:?}", e)))),
     }
 }
 
 #[get("/book/<isbn>")]
-pub async fn book(conn: DbConn, isbn: &str, customer: Option<Customer>) -> Template {
+pub async fn book(
+    conn: DbConn,
+    isbn: Result<Isbn, IsbnError>,
+    customer: Option<Customer>,
+    cart: Option<CartOwner>,
+) -> Template {
     let mut context = Context::new();
     add_customer_info(&conn, &customer, &mut context).await;
+    add_guest_cart_info(&conn, &cart, &mut context).await;
 
-    match isbn.parse::<i32>() {
+    match isbn {
         Ok(isbn) => {
             let books = get_books(&conn).await;
 
@@ -369,6 +497,21 @@ pub async fn book(conn: DbConn, isbn: &str, customer: Option<Customer>) -> Templ
                 Ok(books) => match books.iter().find(|book| book.isbn == isbn) {
                     Some(book) => {
                         context.insert("book", &book);
+                        context.insert("isbn10", &book.isbn.isbn10());
+                        if let Ok(mut stock_levels) = get_stock_levels(&conn, vec![isbn]).await {
+                            context.insert("stock_level", &stock_levels.pop());
+                        }
+                        if let Some(customer) = customer {
+                            if let Ok(wishlists) = get_wishlists(&conn, customer.customer_id).await
+                            {
+                                context.insert("wishlists", &wishlists);
+                            }
+                            if let Ok(subscriptions) =
+                                get_stock_subscriptions(&conn, customer.customer_id).await
+                            {
+                                context.insert("subscribed", &subscriptions.contains(&isbn));
+                            }
+                        }
                         Template::render("book", context.into_json())
                     }
                     None => {
@@ -385,121 +528,177 @@ pub async fn book(conn: DbConn, isbn: &str, customer: Option<Customer>) -> Templ
                 }
             }
         }
-        Err(_) => {
-            render_error_template(format!("{} is not a valid ISBN", isbn), &conn, &customer).await
-        }
+        Err(e) => render_error_template(e.to_string(), &conn, &customer).await,
     }
 }
 
 #[get("/customer/cart")]
-pub async fn customer_cart_page(conn: DbConn, customer: Option<Customer>) -> Template {
+pub async fn customer_cart_page(
+    conn: DbConn,
+    customer: Option<Customer>,
+    cart: Option<CartOwner>,
+    reservations: &State<ReservationConfig>,
+) -> Template {
     let mut context = Context::new();
     add_customer_info(&conn, &customer, &mut context).await;
+    add_guest_cart_info(&conn, &cart, &mut context).await;
 
     use schema::entities::Book;
 
-    match customer {
-        Some(customer) => match get_customer_cart(&conn, customer.customer_id).await {
-            Ok(cart) => match get_books(&conn).await {
-                Ok(books) => {
-                    context.insert("cart", &cart);
+    // Visitors who haven't added anything yet don't have a cart
+    let cart = match cart {
+        Some(cart) => cart,
+        None => return Template::render("customer_cart", context.into_json()),
+    };
+
+    // Looking at the cart keeps its books held
+    if let Err(e) = hold_cart(&conn, &cart, reservations.reserved_until()).await {
+        warn!("Could not hold the books in a cart: {}", e);
+    }
+    let reservations: HashMap<Isbn, CartReservation> = get_cart_reservations(&conn, &cart)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|reservation| (reservation.isbn, reservation))
+        .collect();
 
-                    let isbns: Vec<ISBN> = cart.iter().map(|c| c.0).collect();
-                    let quantities: HashMap<ISBN, u32> = cart.iter().map(|p| p.clone()).collect();
+    match get_cart(&conn, &cart).await {
+        Ok(cart) => match get_books(&conn).await {
+            Ok(books) => {
+                context.insert("cart", &cart);
 
-                    #[derive(serde::Serialize)]
-                    struct BookWithQuantity {
-                        book: Book,
-                        quantity: u32,
-                    }
+                let isbns: Vec<Isbn> = cart.iter().map(|c| c.0).collect();
+                let quantities: HashMap<Isbn, u32> = cart.iter().copied().collect();
 
-                    let books: Vec<BookWithQuantity> = books
-                        .into_iter()
-                        .filter(|b| isbns.contains(&b.isbn))
-                        .map(|b| BookWithQuantity {
-                            book: b.clone(),
-                            quantity: *quantities.get(&b.isbn).unwrap(),
-                        })
-                        .collect();
-
-                    context.insert("books", &books);
-                    Template::render("customer_cart", context.into_json())
-                }
-                Err(e) => {
-                    render_error_template(
-                        format!("Error fetching books: {}", e),
-                        &conn,
-                        &Some(customer),
-                    )
-                    .await
+                #[derive(serde::Serialize)]
+                struct BookWithQuantity {
+                    book: Book,
+                    quantity: u32,
+                    reservation: Option<CartReservation>,
                 }
-            },
-            Err(_) => {
-                render_error_template(
-                    format!("Could not fetch cart for {}", customer.customer_id),
-                    &conn,
-                    &Some(customer),
-                )
-                .await
+
+                let books: Vec<BookWithQuantity> = books
+                    .into_iter()
+                    .filter(|b| isbns.contains(&b.isbn))
+                    .map(|b| BookWithQuantity {
+                        book: b.clone(),
+                        quantity: *quantities.get(&b.isbn).unwrap(),
+                        reservation: reservations.get(&b.isbn).cloned(),
+                    })
+                    .collect();
+
+                context.insert("books", &books);
+                Template::render("customer_cart", context.into_json())
+            }
+            Err(e) => {
+                render_error_template(format!("Error fetching books: {e}"), &conn, &customer).await
             }
         },
-        None => render_error_template("Please login to see your cart.", &conn, &customer).await,
+        Err(e) => {
+            render_error_template(format!("Could not fetch cart: {e}"), &conn, &customer).await
+        }
     }
 }
 
-#[put("/customer/cart/add/<isbn>")]
-pub async fn customer_cart_add(conn: DbConn, customer: Customer, isbn: ISBN) -> Status {
-    match add_to_cart(&conn, customer.customer_id, isbn).await {
-        Ok(_) => Status::Ok,
-        Err(_) => Status::InternalServerError,
+/// Visitors who haven't logged in get a guest cart when they first add a book
+fn cart_for_change(cart: Option<CartOwner>, cookies: &CookieJar<'_>) -> CartOwner {
+    match cart {
+        Some(cart @ CartOwner::Customer(_)) => cart,
+        _ => CartOwner::guest(cookies),
     }
 }
 
+#[put("/customer/cart/add/<isbn>")]
+pub async fn customer_cart_add(
+    conn: DbConn,
+    cart: Option<CartOwner>,
+    cookies: &CookieJar<'_>,
+    reservations: &State<ReservationConfig>,
+    isbn: Isbn,
+) -> Result<(), (Status, String)> {
+    add_to_cart(
+        &conn,
+        &cart_for_change(cart, cookies),
+        isbn,
+        reservations.reserved_until(),
+    )
+    .await
+    .map_err(cart_error_status)
+}
+
 #[put("/customer/cart/quantity/<isbn>/<quantity>")]
 pub async fn customer_cart_set_quantity(
     conn: DbConn,
-    customer: Customer,
-    isbn: ISBN,
+    cart: Option<CartOwner>,
+    cookies: &CookieJar<'_>,
+    reservations: &State<ReservationConfig>,
+    isbn: Isbn,
     quantity: u32,
 ) -> Result<(), (Status, String)> {
-    cart_set_book_quantity(&conn, customer.customer_id, isbn, quantity)
-        .await
-        .map_err(|e| match e {
-            CartError::NotEnoughStock => (Status::Conflict, "Insufficient book stock".to_owned()),
-            CartError::DBError(e) => (Status::InternalServerError, e.to_string()),
-        })
+    cart_set_book_quantity(
+        &conn,
+        &cart_for_change(cart, cookies),
+        isbn,
+        quantity,
+        reservations.reserved_until(),
+    )
+    .await
+    .map_err(cart_error_status)
+}
+
+pub(crate) fn cart_error_status(e: CartError) -> (Status, String) {
+    match e {
+        CartError::NotEnoughStock => (Status::Conflict, e.to_string()),
+        CartError::DBError(e) => (Status::InternalServerError, e.to_string()),
+    }
 }
 
 #[post("/account/logout")]
 pub async fn account_logout(
     cookies: &CookieJar<'_>,
-    session_tokens: &State<SessionTokenState>,
+    session_store: &State<SessionStoreState>,
 ) -> () {
-    let mut session_tokens = session_tokens.lock().await;
     if let Some(cookie) = cookies.get_private(CUST_SESSION_COOKIE_NAME) {
-        session_tokens.remove(cookie.value());
+        let _ = session_store.remove(cookie.value()).await;
     }
 
     if let Some(cookie) = cookies.get_private(OWNER_SESSION_COOKIE_NAME) {
-        session_tokens.remove(cookie.value());
+        let _ = session_store.remove(cookie.value()).await;
     }
 }
 
 #[get("/checkout")]
-pub async fn checkout_page(conn: DbConn, customer: Customer) -> Template {
+pub async fn checkout_page(
+    conn: DbConn,
+    customer: Option<Customer>,
+    cart: Option<CartOwner>,
+    reservations: &State<ReservationConfig>,
+) -> Template {
     let mut context = Context::new();
-    let customer_id = customer.customer_id;
 
     use schema::entities::Book;
 
-    add_customer_info(&conn, &Some(customer), &mut context).await;
-    match get_customer_cart(&conn, customer_id).await {
+    add_customer_info(&conn, &customer, &mut context).await;
+    add_guest_cart_info(&conn, &cart, &mut context).await;
+
+    let cart = match cart {
+        Some(cart) => cart,
+        None => return Template::render("checkout_page", context.into_json()),
+    };
+    context.insert("guest", &cart.customer_id().is_none());
+
+    // The books stay held while the order is filled in
+    if let Err(e) = hold_cart(&conn, &cart, reservations.reserved_until()).await {
+        warn!("Could not hold the books in a cart: {}", e);
+    }
+
+    match get_cart(&conn, &cart).await {
         Ok(cart) => match get_books(&conn).await {
             Ok(books) => {
                 context.insert("cart", &cart);
 
-                let isbns: Vec<ISBN> = cart.iter().map(|c| c.0).collect();
-                let quantities: HashMap<ISBN, u32> = cart.iter().map(|p| p.clone()).collect();
+                let isbns: Vec<Isbn> = cart.iter().map(|c| c.0).collect();
+                let quantities: HashMap<Isbn, u32> = cart.iter().copied().collect();
 
                 #[derive(serde::Serialize)]
                 struct BookWithQuantity {
@@ -516,22 +715,22 @@ pub async fn checkout_page(conn: DbConn, customer: Customer) -> Template {
                     })
                     .collect();
 
+                context.insert(
+                    "subtotal",
+                    &books
+                        .iter()
+                        .map(|b| b.book.price * Decimal::from(b.quantity))
+                        .sum::<Decimal>(),
+                );
                 context.insert("books", &books);
 
                 Template::render("checkout_page", context.into_json())
             }
             Err(e) => {
-                render_error_template(
-                    format!("Error fetching books: {}", e),
-                    &conn,
-                    &Some(customer),
-                )
-                .await
+                render_error_template(format!("Error fetching books: {e}"), &conn, &customer).await
             }
         },
-        Err(e) => {
-            render_error_template(format!("Server error: {}", e), &conn, &Some(customer)).await
-        }
+        Err(e) => render_error_template(format!("Server error: {e}"), &conn, &customer).await,
     }
 }
 
@@ -549,17 +748,21 @@ pub struct CreateOrder<'r> {
     billing_street_address: &'r str,
     billing_postal_code: &'r str,
     billing_province: &'r str,
+    promotion_code: &'r str,
+    /// Only guests are asked for their email
+    email: Option<&'r str>,
 }
 
 #[post("/order/create", data = "<create_order>")]
 pub async fn create_order_req(
     conn: DbConn,
     create_order: Form<CreateOrder<'_>>,
-    customer: Customer,
+    customer: Option<Customer>,
+    cart: CartOwner,
+    restock_policy: &State<RestockPolicy>,
+    payment_provider: &State<PaymentProviderState>,
 ) -> Template {
     let result: Result<PostgresInt, OrderError> = try {
-        let cart = get_customer_cart(&conn, customer.customer_id).await?;
-
         let address = if create_order.default_shipping {
             None
         } else {
@@ -578,57 +781,105 @@ pub async fn create_order_req(
                 create_order.billing_postal_code,
                 create_order.billing_province,
             );
-            Some(PaymentInfo::new(
+            let expiry =
+                Expiry::from_str(create_order.expiry).ok_or(StateError::new("Invalid expiry"))?;
+            let card = payment_provider.tokenise(&CardDetails::new(
                 create_order.name_on_card,
-                Expiry::from_str(create_order.expiry).ok_or(StateError::new("Invalid expiry"))?,
+                expiry,
                 create_order.card_number,
                 create_order.cvv,
+            ))?;
+
+            Some(PaymentInfo::new(
+                create_order.name_on_card,
+                expiry,
+                card,
                 address,
             ))
         };
 
-        crate::db::query::create_order(&conn, customer.customer_id, cart, address, payment_info)
-            .await?
+        crate::db::query::create_order(
+            &conn,
+            Buyer::new(cart.clone(), create_order.email.map(str::to_string)),
+            address,
+            payment_info,
+            Some(create_order.promotion_code.to_string()).filter(|code| !code.trim().is_empty()),
+            *restock_policy.inner(),
+            payment_provider.inner().clone(),
+        )
+        .await?
     };
 
     match result {
         Ok(order_id) => {
             let mut context = Context::new();
+            add_customer_info(&conn, &customer, &mut context).await;
             context.insert("order_id", &order_id);
+            context.insert("guest", &cart.customer_id().is_none());
             Template::render("order_success", context.into_json())
         }
-        Err(e) => {
-            render_error_template(format!("Order error: {}", e), &conn, &Some(customer)).await
+        Err(e) => render_error_template(format!("Order error: {e}"), &conn, &customer).await,
+    }
+}
+
+pub(crate) fn order_error_status(e: OrderError) -> (Status, String) {
+    match e {
+        OrderError::NotEnoughStock | OrderError::Conflict => (Status::Conflict, e.to_string()),
+        OrderError::EmptyCart | OrderError::GuestDetailsRequired | OrderError::InvalidEmail(_) => {
+            (Status::BadRequest, e.to_string())
+        }
+        OrderError::Discount(DiscountError::UnknownCode(_)) => (Status::NotFound, e.to_string()),
+        OrderError::Discount(_) => (Status::UnprocessableEntity, e.to_string()),
+        OrderError::PaymentError(_) => (Status::PaymentRequired, e.to_string()),
+        OrderError::EmailNotVerified => (Status::Forbidden, e.to_string()),
+        OrderError::DBError(_) | OrderError::StateError(_) | OrderError::Outbox(_) => {
+            (Status::InternalServerError, e.to_string())
         }
     }
 }
 
+#[get("/customer/checkout/promotion?<code>")]
+pub async fn quote_promotion_endpoint(
+    conn: DbConn,
+    cart: CartOwner,
+    code: &str,
+) -> Result<Json<PromotionQuote>, (Status, String)> {
+    quote_promotion(&conn, cart, code.to_string())
+        .await
+        .map(Json)
+        .map_err(order_error_status)
+}
+
 #[derive(Serialize, Debug)]
-struct CensoredPaymentInfo {
+pub(crate) struct CensoredPaymentInfo {
     pub name_on_card: String,
     pub expiry: Expiry,
+    pub brand: String,
     pub censored_card_number: String,
     pub billing_address: Address,
 }
 
 #[derive(Serialize, Debug)]
-struct BookWithQuantity {
-    book: Book,
-    quantity: u32,
+pub(crate) struct BookWithQuantity {
+    pub(crate) book: Book,
+    pub(crate) quantity: u32,
 }
 
 #[derive(Serialize, Debug)]
-struct CensoredOrder {
+pub(crate) struct CensoredOrder {
     pub order_id: PostgresInt,
     pub shipping_address: Address,
     pub tracking_number: String,
-    pub order_status: String,
+    pub order_status: OrderStatus,
     pub order_date: String,
     pub payment_info: CensoredPaymentInfo,
     pub books: Vec<BookWithQuantity>,
+    pub cancellation_reason: Option<String>,
+    pub promotion_code: Option<String>,
+    pub discount: PostgresNumeric,
 }
 
-fn censor_order(order: Order) -> CensoredOrder {
+pub(crate) fn censor_order(order: Order) -> CensoredOrder {
     let Order {
         order_id,
         shipping_address,
@@ -637,20 +888,24 @@ fn censor_order(order: Order) -> CensoredOrder {
         order_date,
         payment_info,
         books,
+        cancellation_reason,
+        promotion_code,
+        discount,
     } = order;
     let PaymentInfo {
         name_on_card,
         expiry,
-        card_number,
-        cvv: _,
+        token: _,
+        last_four,
+        brand,
         billing_address,
     } = payment_info;
 
-    let num_last_digits = usize::min(card_number.len(), 4);
-    let censored_card_number = "*".repeat(12) + &card_number[card_number.len() - num_last_digits..];
+    let censored_card_number = "*".repeat(12) + &last_four;
     let censored_payment_info = CensoredPaymentInfo {
         name_on_card,
         expiry,
+        brand,
         censored_card_number,
         billing_address,
     };
@@ -668,6 +923,9 @@ fn censor_order(order: Order) -> CensoredOrder {
         order_date,
         payment_info: censored_payment_info,
         books,
+        cancellation_reason,
+        promotion_code,
+        discount,
     }
 }
 
@@ -698,25 +956,81 @@ pub async fn orders_page(conn: DbConn, customer: Customer) -> Template {
 }
 
 #[get("/order/view/<order_id>")]
-pub async fn view_order(conn: DbConn, customer: Customer, order_id: i32) -> Template {
+pub async fn view_order(
+    conn: DbConn,
+    customer: Customer,
+    order_id: i32,
+) -> Result<Template, (Status, Template)> {
     let mut context = Context::new();
 
     add_customer_info(&conn, &Some(customer), &mut context).await;
 
-    match get_order_info(&conn, order_id).await {
-        Ok(order_info) => match get_books_for_order(&conn, order_info).await {
+    match get_order_info(&conn, customer.customer_id, order_id).await {
+        Ok(Some(order_info)) => match get_books_for_order(&conn, order_info).await {
             Ok(order) => {
+                let status_history = get_order_status_history(&conn, order.order_id)
+                    .await
+                    .unwrap_or_default();
                 let censored_order = censor_order(order);
                 context.insert("order", &censored_order);
-                Template::render("order", context.into_json())
+                context.insert("status_history", &status_history);
+                Ok(Template::render("order", context.into_json()))
             }
             Err(e) => {
-                render_error_template(format!("Server error: {}", e), &conn, &Some(customer)).await
+                Ok(
+                    render_error_template(format!("Server error: {e}"), &conn, &Some(customer))
+                        .await,
+                )
             }
         },
+        // Other customers' orders are treated as missing, so they can't be found by guessing IDs
+        Ok(None) => Err((
+            Status::NotFound,
+            render_error_template(
+                format!("No order with the ID ({order_id})"),
+                &conn,
+                &Some(customer),
+            )
+            .await,
+        )),
         Err(e) => {
-            render_error_template(format!("Server error: {}", e), &conn, &Some(customer)).await
+            Ok(render_error_template(format!("Server error: {e}"), &conn, &Some(customer)).await)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CancelOrder {
+    pub(crate) reason: String,
+}
+
+#[put("/order/<order_id>/cancel", data = "<cancellation>")]
+pub async fn cancel_order_req(
+    conn: DbConn,
+    customer: Customer,
+    order_id: PostgresInt,
+    cancellation: Json<CancelOrder>,
+) -> Result<(), (Status, String)> {
+    cancel_order(
+        &conn,
+        customer.customer_id,
+        order_id,
+        cancellation.into_inner().reason,
+    )
+    .await
+    .map_err(cancel_order_error_status)
+}
+
+pub(crate) fn cancel_order_error_status(e: CancelOrderError) -> (Status, String) {
+    match e {
+        CancelOrderError::NoSuchOrder(_) => (Status::NotFound, e.to_string()),
+        CancelOrderError::NotCancellable(_) => (Status::Conflict, e.to_string()),
+        CancelOrderError::MissingReason | CancelOrderError::ReasonTooLong => {
+            (Status::BadRequest, e.to_string())
         }
+        CancelOrderError::DBError(_)
+        | CancelOrderError::StateError(_)
+        | CancelOrderError::Outbox(_) => (Status::InternalServerError, e.to_string()),
     }
 }
 
@@ -730,7 +1044,7 @@ pub async fn owner_login_page() -> Template {
 pub async fn owner_login(
     conn: DbConn,
     login_data: Form<Login<'_>>,
-    session_tokens: &State<SessionTokenState>,
+    session_store: &State<SessionStoreState>,
     cookies: &CookieJar<'_>,
 ) -> Redirect {
     match validate_owner_login(&conn, login_data.email, login_data.password).await {
@@ -738,100 +1052,650 @@ pub async fn owner_login(
             OwnerLoginType::DefaultOwner => {
                 let token = create_session_token();
 
-                cookies.add_private(Cookie::new(OWNER_SESSION_COOKIE_NAME, token.clone()));
-
-                let mut session_tokens = session_tokens.lock().await;
-
                 let expiry = Local::now() + Duration::days(30);
 
-                session_tokens.insert(token, (SessionType::DefaultOwner, expiry));
-                Redirect::to(uri!("/"))
+                match session_store
+                    .insert(token.clone(), SessionType::DefaultOwner, expiry)
+                    .await
+                {
+                    Ok(_) => {
+                        cookies.add_private(Cookie::new(OWNER_SESSION_COOKIE_NAME, token));
+                        Redirect::to(uri!("/"))
+                    }
+                    Err(e) => Redirect::to(uri!(login_failed(e.to_string()))),
+                }
             }
             OwnerLoginType::OwnerAccount(owner_id) => {
                 let token = create_session_token();
 
-                cookies.add_private(Cookie::new(OWNER_SESSION_COOKIE_NAME, token.clone()));
-
-                let mut session_tokens = session_tokens.lock().await;
-
                 let expiry = Local::now() + Duration::days(30);
 
-                session_tokens.insert(token, (SessionType::Owner(owner_id), expiry));
-                Redirect::to(uri!("/"))
+                match session_store
+                    .insert(token.clone(), SessionType::Owner(owner_id), expiry)
+                    .await
+                {
+                    Ok(_) => {
+                        cookies.add_private(Cookie::new(OWNER_SESSION_COOKIE_NAME, token));
+                        Redirect::to(uri!("/"))
+                    }
+                    Err(e) => Redirect::to(uri!(login_failed(e.to_string()))),
+                }
             }
         },
         Err(e) => Redirect::to(uri!(login_failed(e.to_string()))),
     }
 }
 
-#[get("/owner/manage/view?<search>")]
-pub async fn book_management(conn: DbConn, owner: Owner, search: Search<'_>) -> Template {
-    let mut context = Context::new();
-    add_owner_tag(&Some(owner), &mut context);
+#[derive(FromForm)]
+pub struct ForgotPassword<'r> {
+    email: &'r str,
+}
 
-    let books = get_books_with_publisher_name(&conn).await;
-    if let Ok(books) = books {
-        let books = filter_books(books, search);
+fn render_forgot_password(kind: AccountKind, sent: bool, error: Option<String>) -> Template {
+    let mut context = Context::new();
+    context.insert("owner", &(kind == AccountKind::Owner));
+    context.insert("sent", &sent);
+    context.insert("error", &error);
 
-        context.insert("books", &books);
-        context.insert("genres", &extract_genre_list(&books));
+    Template::render("forgot_password", context.into_json())
+}
 
-        Template::render("book_management", context.into_json())
-    } else {
-        render_error_template(format!("Could not query books: {:?}", books), &conn, &None).await
+async fn forgot_password(
+    conn: &DbConn,
+    kind: AccountKind,
+    email: &str,
+    accounts: &AccountConfig,
+) -> Template {
+    match request_password_reset(conn, kind, email.to_string(), accounts.clone()).await {
+        Ok(()) => render_forgot_password(kind, true, None),
+        Err(e @ AccountTokenError::RateLimited) => {
+            render_forgot_password(kind, false, Some(e.to_string()))
+        }
+        Err(e) => render_error_template(format!("Password reset failed: {e}"), conn, &None).await,
     }
 }
 
-#[put("/owner/manage/books/discontinue", data = "<books>")]
-pub async fn discontinue_books_endpoint(
-    conn: DbConn,
-    _owner: Owner,
-    books: Json<Vec<ISBN>>,
-) -> Result<(), (Status, String)> {
-    match discontinue_books(&conn, books.into_inner()).await {
-        Ok(_) => Ok(()),
-        Err(e) => Err((Status::InternalServerError, e.to_string())),
-    }
+#[get("/login/forgot")]
+pub async fn forgot_password_page() -> Template {
+    render_forgot_password(AccountKind::Customer, false, None)
 }
 
-#[put("/owner/manage/books/undiscontinue", data = "<books>")]
-pub async fn undiscontinue_books_endpoint(
+#[post("/login/forgot", data = "<forgot>")]
+pub async fn forgot_password_req(
     conn: DbConn,
-    _owner: Owner,
-    books: Json<Vec<ISBN>>,
-) -> Result<(), (Status, String)> {
-    match undiscontinue_books(&conn, books.into_inner()).await {
-        Ok(_) => Ok(()),
-        Err(e) => Err((Status::InternalServerError, e.to_string())),
-    }
+    forgot: Form<ForgotPassword<'_>>,
+    accounts: &State<AccountConfig>,
+) -> Template {
+    forgot_password(&conn, AccountKind::Customer, forgot.email, accounts).await
 }
 
-#[derive(FromForm)]
-pub struct CreatePublisher<'r> {
-    company_name: &'r str,
-    email: &'r str,
-    street_address: &'r str,
-    postal_code: &'r str,
-    province: &'r str,
-    phone_number: &'r str,
-    bank_number: &'r str,
+#[get("/login/owner/forgot")]
+pub async fn owner_forgot_password_page() -> Template {
+    render_forgot_password(AccountKind::Owner, false, None)
 }
 
-#[get("/owner/create/publisher")]
-pub async fn create_publisher_page(owner: Owner) -> Template {
+#[post("/login/owner/forgot", data = "<forgot>")]
+pub async fn owner_forgot_password_req(
+    conn: DbConn,
+    forgot: Form<ForgotPassword<'_>>,
+    accounts: &State<AccountConfig>,
+) -> Template {
+    forgot_password(&conn, AccountKind::Owner, forgot.email, accounts).await
+}
+
+#[get("/password/reset/<token>")]
+pub async fn reset_password_page(token: &str) -> Template {
     let mut context = Context::new();
-    add_owner_tag(&Some(owner), &mut context);
+    context.insert("token", token);
 
-    Template::render("create_publisher", context.into_json())
+    Template::render("reset_password", context.into_json())
 }
 
-#[post("/owner/create/publisher", data = "<publisher>")]
-pub async fn create_publisher(
+#[derive(FromForm)]
+pub struct ResetPassword<'r> {
+    token: &'r str,
+    password: &'r str,
+    confirm_password: &'r str,
+}
+
+#[post("/password/reset", data = "<reset>")]
+pub async fn reset_password_req(
     conn: DbConn,
-    _owner: Owner,
-    publisher: Form<CreatePublisher<'_>>,
+    reset: Form<ResetPassword<'_>>,
+    session_store: &State<SessionStoreState>,
 ) -> Template {
-    let CreatePublisher {
+    let mut context = Context::new();
+    context.insert("token", reset.token);
+
+    match reset_password(
+        &conn,
+        reset.token.to_string(),
+        reset.password.to_string(),
+        reset.confirm_password.to_string(),
+    )
+    .await
+    {
+        Ok(account) => {
+            // Anyone who got in with the old password is logged out
+            if let Err(e) = session_store.remove_account(account).await {
+                warn!(
+                    "Could not end the sessions of an account after a password reset: {}",
+                    e
+                );
+            }
+
+            context.insert("done", &true);
+            context.insert("owner", &matches!(account, SessionType::Owner(_)));
+        }
+        Err(e) => context.insert("error", &e.to_string()),
+    }
+
+    Template::render("reset_password", context.into_json())
+}
+
+#[get("/verify/<token>")]
+pub async fn verify_email_page(conn: DbConn, customer: Option<Customer>, token: &str) -> Template {
+    let mut context = Context::new();
+
+    let result = verify_email(&conn, token.to_string()).await;
+    // Shown after verifying, so the customer's details are up to date
+    add_customer_info(&conn, &customer, &mut context).await;
+
+    match result {
+        Ok(()) => context.insert("verified", &true),
+        Err(e) => context.insert("error", &e.to_string()),
+    }
+
+    Template::render("verify_email", context.into_json())
+}
+
+pub(crate) fn account_token_error_status(e: AccountTokenError) -> (Status, String) {
+    match e {
+        AccountTokenError::RateLimited => (Status::TooManyRequests, e.to_string()),
+        AccountTokenError::InvalidToken => (Status::NotFound, e.to_string()),
+        AccountTokenError::AlreadyVerified => (Status::Conflict, e.to_string()),
+        AccountTokenError::EmptyPassword | AccountTokenError::PasswordMismatch => {
+            (Status::BadRequest, e.to_string())
+        }
+        AccountTokenError::Outbox(_)
+        | AccountTokenError::DBError(_)
+        | AccountTokenError::BCryptError(_) => (Status::InternalServerError, e.to_string()),
+    }
+}
+
+#[post("/customer/verify")]
+pub async fn resend_verification(
+    conn: DbConn,
+    customer: Customer,
+    accounts: &State<AccountConfig>,
+) -> Result<(), (Status, String)> {
+    resend_email_verification(&conn, customer.customer_id, accounts.inner().clone())
+        .await
+        .map_err(account_token_error_status)
+}
+
+#[get("/owner/manage/view?<search>")]
+pub async fn book_management(conn: DbConn, owner: Owner, search: Search<'_>) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    let results = add_search_results(&conn, &search, &mut context).await;
+    if results.is_ok() {
+        Template::render("book_management", context.into_json())
+    } else {
+        render_error_template(format!("Could not query books: {results:?}"), &conn, &None).await
+    }
+}
+
+#[put("/owner/manage/books/discontinue", data = "<books>")]
+pub async fn discontinue_books_endpoint(
+    conn: DbConn,
+    owner: Owner,
+    books: Json<Vec<Isbn>>,
+) -> Result<(), (Status, String)> {
+    match discontinue_books(&conn, books.into_inner(), owner.owner_id()).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err((Status::InternalServerError, e.to_string())),
+    }
+}
+
+#[put("/owner/manage/books/undiscontinue", data = "<books>")]
+pub async fn undiscontinue_books_endpoint(
+    conn: DbConn,
+    owner: Owner,
+    books: Json<Vec<Isbn>>,
+) -> Result<(), (Status, String)> {
+    match undiscontinue_books(&conn, books.into_inner(), owner.owner_id()).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err((Status::InternalServerError, e.to_string())),
+    }
+}
+
+#[get("/owner/manage/restock")]
+pub async fn restock_orders_page(conn: DbConn, owner: Owner) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    match get_restock_orders(&conn).await {
+        Ok(restock_orders) => {
+            context.insert("restock_orders", &restock_orders);
+            Template::render("restock_orders", context.into_json())
+        }
+        Err(e) => render_error_template(format!("Server error: {e}"), &conn, &None).await,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApproveRestock {
+    pub(crate) price_per_unit: Option<PostgresNumeric>,
+}
+
+pub(crate) fn restock_error_status(e: RestockError) -> (Status, String) {
+    match e {
+        RestockError::NoSuchOrder(_) => (Status::NotFound, e.to_string()),
+        RestockError::InvalidTransition(_, _) => (Status::Conflict, e.to_string()),
+        RestockError::DBError(_) | RestockError::StateError(_) => {
+            (Status::InternalServerError, e.to_string())
+        }
+    }
+}
+
+#[put(
+    "/owner/manage/restock/<restock_order_id>/approve",
+    data = "<approval>"
+)]
+pub async fn approve_restock_order(
+    conn: DbConn,
+    _owner: Owner,
+    restock_order_id: PostgresInt,
+    approval: Json<ApproveRestock>,
+) -> Result<(), (Status, String)> {
+    set_restock_order_status(
+        &conn,
+        restock_order_id,
+        RestockStatus::Approved,
+        approval.price_per_unit,
+    )
+    .await
+    .map_err(restock_error_status)
+}
+
+#[put("/owner/manage/restock/<restock_order_id>/receive")]
+pub async fn receive_restock_order(
+    conn: DbConn,
+    _owner: Owner,
+    restock_order_id: PostgresInt,
+) -> Result<(), (Status, String)> {
+    set_restock_order_status(&conn, restock_order_id, RestockStatus::Received, None)
+        .await
+        .map_err(restock_error_status)
+}
+
+#[put("/owner/manage/restock/<restock_order_id>/cancel")]
+pub async fn cancel_restock_order(
+    conn: DbConn,
+    _owner: Owner,
+    restock_order_id: PostgresInt,
+) -> Result<(), (Status, String)> {
+    set_restock_order_status(&conn, restock_order_id, RestockStatus::Cancelled, None)
+        .await
+        .map_err(restock_error_status)
+}
+
+#[get("/owner/manage/orders?<status>")]
+pub async fn fulfilment_page(conn: DbConn, owner: Owner, status: Option<OrderStatus>) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    match get_fulfilment_orders(&conn, status).await {
+        Ok(orders) => {
+            context.insert("orders", &orders);
+            context.insert("selected_status", &status);
+            context.insert("statuses", &OrderStatus::ALL);
+            Template::render("fulfilment", context.into_json())
+        }
+        Err(e) => render_error_template(format!("Server error: {e}"), &conn, &None).await,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateOrderStatus {
+    pub(crate) order_status: OrderStatus,
+    /// Why the order is being cancelled, needed only when cancelling it
+    #[serde(default)]
+    pub(crate) reason: Option<String>,
+}
+
+#[put("/owner/manage/orders/<order_id>/status", data = "<update>")]
+pub async fn update_order_status(
+    conn: DbConn,
+    _owner: Owner,
+    order_id: PostgresInt,
+    update: Json<UpdateOrderStatus>,
+) -> Result<(), (Status, String)> {
+    let update = update.into_inner();
+
+    set_order_status(&conn, order_id, update.order_status, update.reason)
+        .await
+        .map_err(order_status_error_status)
+}
+
+pub(crate) fn order_status_error_status(e: OrderStatusError) -> (Status, String) {
+    match e {
+        OrderStatusError::NoSuchOrder(_) => (Status::NotFound, e.to_string()),
+        OrderStatusError::InvalidTransition(_, _) => (Status::Conflict, e.to_string()),
+        OrderStatusError::MissingReason | OrderStatusError::ReasonTooLong => {
+            (Status::BadRequest, e.to_string())
+        }
+        OrderStatusError::DBError(_)
+        | OrderStatusError::StateError(_)
+        | OrderStatusError::Outbox(_) => (Status::InternalServerError, e.to_string()),
+    }
+}
+
+#[get("/collection/<collection_id>")]
+pub async fn collection_page(
+    conn: DbConn,
+    customer: Option<Customer>,
+    cart: Option<CartOwner>,
+    owner: Option<Owner>,
+    collection_id: PostgresInt,
+) -> Template {
+    let mut context = Context::new();
+    add_customer_info(&conn, &customer, &mut context).await;
+    add_guest_cart_info(&conn, &cart, &mut context).await;
+    add_owner_tag(&owner, &mut context);
+
+    match get_collection(&conn, collection_id, false).await {
+        Ok(collection) => {
+            context.insert("collection", &collection);
+            Template::render("collection", context.into_json())
+        }
+        Err(e) => render_error_template(e.to_string(), &conn, &customer).await,
+    }
+}
+
+pub(crate) fn collection_error_status(e: CollectionError) -> (Status, String) {
+    match e {
+        CollectionError::NoSuchCollection(_) | CollectionError::NoSuchBook(_) => {
+            (Status::NotFound, e.to_string())
+        }
+        CollectionError::InvalidName | CollectionError::InvalidOrder => {
+            (Status::BadRequest, e.to_string())
+        }
+        CollectionError::DBError(_) => (Status::InternalServerError, e.to_string()),
+    }
+}
+
+#[get("/owner/manage/collections")]
+pub async fn collections_page(conn: DbConn, owner: Owner) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    match get_collections(&conn, true).await {
+        Ok(collections) => {
+            context.insert("collections", &collections);
+            Template::render("collections", context.into_json())
+        }
+        Err(e) => render_error_template(format!("Server error: {e}"), &conn, &None).await,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CollectionName {
+    name: String,
+}
+
+#[post("/owner/manage/collections", data = "<collection>")]
+pub async fn create_collection_endpoint(
+    conn: DbConn,
+    owner: Owner,
+    collection: Json<CollectionName>,
+) -> Result<Json<PostgresInt>, (Status, String)> {
+    create_collection(&conn, collection.into_inner().name, owner.owner_id())
+        .await
+        .map(Json)
+        .map_err(collection_error_status)
+}
+
+#[put(
+    "/owner/manage/collections/<collection_id>/name",
+    data = "<collection>"
+)]
+pub async fn rename_collection_endpoint(
+    conn: DbConn,
+    _owner: Owner,
+    collection_id: PostgresInt,
+    collection: Json<CollectionName>,
+) -> Result<(), (Status, String)> {
+    rename_collection(&conn, collection_id, collection.into_inner().name)
+        .await
+        .map_err(collection_error_status)
+}
+
+#[put("/owner/manage/collections/order", data = "<collection_ids>")]
+pub async fn reorder_collections_endpoint(
+    conn: DbConn,
+    _owner: Owner,
+    collection_ids: Json<Vec<PostgresInt>>,
+) -> Result<(), (Status, String)> {
+    reorder_collections(&conn, collection_ids.into_inner())
+        .await
+        .map_err(collection_error_status)
+}
+
+#[delete("/owner/manage/collections/<collection_id>")]
+pub async fn delete_collection_endpoint(
+    conn: DbConn,
+    _owner: Owner,
+    collection_id: PostgresInt,
+) -> Result<(), (Status, String)> {
+    delete_collection(&conn, collection_id)
+        .await
+        .map_err(collection_error_status)
+}
+
+#[put("/owner/manage/collections/<collection_id>/books/<isbn>")]
+pub async fn add_collection_book_endpoint(
+    conn: DbConn,
+    _owner: Owner,
+    collection_id: PostgresInt,
+    isbn: Isbn,
+) -> Result<(), (Status, String)> {
+    add_book_to_collection(&conn, collection_id, isbn)
+        .await
+        .map_err(collection_error_status)
+}
+
+#[delete("/owner/manage/collections/<collection_id>/books/<isbn>")]
+pub async fn remove_collection_book_endpoint(
+    conn: DbConn,
+    _owner: Owner,
+    collection_id: PostgresInt,
+    isbn: Isbn,
+) -> Result<(), (Status, String)> {
+    remove_book_from_collection(&conn, collection_id, isbn)
+        .await
+        .map_err(collection_error_status)
+}
+
+pub(crate) fn wishlist_error_status(e: WishlistError) -> (Status, String) {
+    match e {
+        WishlistError::NoSuchWishlist(_) | WishlistError::NoSuchBook(_) => {
+            (Status::NotFound, e.to_string())
+        }
+        WishlistError::InvalidName => (Status::BadRequest, e.to_string()),
+        WishlistError::DuplicateName(_) | WishlistError::InStock(_) => {
+            (Status::Conflict, e.to_string())
+        }
+        WishlistError::DBError(_) => (Status::InternalServerError, e.to_string()),
+    }
+}
+
+#[get("/customer/wishlists")]
+pub async fn wishlists_page(conn: DbConn, customer: Customer) -> Template {
+    let mut context = Context::new();
+    add_customer_info(&conn, &Some(customer), &mut context).await;
+
+    match get_wishlists(&conn, customer.customer_id).await {
+        Ok(wishlists) => {
+            let subscriptions = get_stock_subscriptions(&conn, customer.customer_id)
+                .await
+                .unwrap_or_default();
+
+            context.insert("wishlists", &wishlists);
+            context.insert("subscriptions", &subscriptions);
+            Template::render("wishlists", context.into_json())
+        }
+        Err(e) => render_error_template(format!("Server error: {e}"), &conn, &Some(customer)).await,
+    }
+}
+
+/// A wishlist someone has shared the link to, which anyone can look at
+#[get("/wishlist/<share_token>")]
+pub async fn shared_wishlist_page(
+    conn: DbConn,
+    customer: Option<Customer>,
+    cart: Option<CartOwner>,
+    owner: Option<Owner>,
+    share_token: String,
+) -> Template {
+    let mut context = Context::new();
+    add_customer_info(&conn, &customer, &mut context).await;
+    add_guest_cart_info(&conn, &cart, &mut context).await;
+    add_owner_tag(&owner, &mut context);
+
+    match get_shared_wishlist(&conn, share_token).await {
+        Ok(Some((wishlist, customer_name))) => {
+            context.insert("wishlist", &wishlist);
+            context.insert("customer_name", &customer_name);
+            Template::render("shared_wishlist", context.into_json())
+        }
+        Ok(None) => {
+            render_error_template("No wishlist at this link".to_string(), &conn, &customer).await
+        }
+        Err(e) => render_error_template(format!("Server error: {e}"), &conn, &customer).await,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WishlistName {
+    name: String,
+}
+
+#[post("/customer/wishlists", data = "<wishlist>")]
+pub async fn create_wishlist_endpoint(
+    conn: DbConn,
+    customer: Customer,
+    wishlist: Json<WishlistName>,
+) -> Result<Json<PostgresInt>, (Status, String)> {
+    create_wishlist(&conn, customer.customer_id, wishlist.into_inner().name)
+        .await
+        .map(Json)
+        .map_err(wishlist_error_status)
+}
+
+#[put("/customer/wishlists/<wishlist_id>/name", data = "<wishlist>")]
+pub async fn rename_wishlist_endpoint(
+    conn: DbConn,
+    customer: Customer,
+    wishlist_id: PostgresInt,
+    wishlist: Json<WishlistName>,
+) -> Result<(), (Status, String)> {
+    rename_wishlist(
+        &conn,
+        customer.customer_id,
+        wishlist_id,
+        wishlist.into_inner().name,
+    )
+    .await
+    .map_err(wishlist_error_status)
+}
+
+#[delete("/customer/wishlists/<wishlist_id>")]
+pub async fn delete_wishlist_endpoint(
+    conn: DbConn,
+    customer: Customer,
+    wishlist_id: PostgresInt,
+) -> Result<(), (Status, String)> {
+    delete_wishlist(&conn, customer.customer_id, wishlist_id)
+        .await
+        .map_err(wishlist_error_status)
+}
+
+#[put("/customer/wishlists/<wishlist_id>/books/<isbn>")]
+pub async fn add_wishlist_book_endpoint(
+    conn: DbConn,
+    customer: Customer,
+    wishlist_id: PostgresInt,
+    isbn: Isbn,
+) -> Result<(), (Status, String)> {
+    add_book_to_wishlist(&conn, customer.customer_id, wishlist_id, isbn)
+        .await
+        .map_err(wishlist_error_status)
+}
+
+#[delete("/customer/wishlists/<wishlist_id>/books/<isbn>")]
+pub async fn remove_wishlist_book_endpoint(
+    conn: DbConn,
+    customer: Customer,
+    wishlist_id: PostgresInt,
+    isbn: Isbn,
+) -> Result<(), (Status, String)> {
+    remove_book_from_wishlist(&conn, customer.customer_id, wishlist_id, isbn)
+        .await
+        .map_err(wishlist_error_status)
+}
+
+/// Asks to be told when an out of stock book is back
+#[put("/customer/notify/<isbn>")]
+pub async fn subscribe_to_stock_endpoint(
+    conn: DbConn,
+    customer: Customer,
+    isbn: Isbn,
+) -> Result<(), (Status, String)> {
+    subscribe_to_stock(&conn, customer.customer_id, isbn)
+        .await
+        .map_err(wishlist_error_status)
+}
+
+#[delete("/customer/notify/<isbn>")]
+pub async fn unsubscribe_from_stock_endpoint(
+    conn: DbConn,
+    customer: Customer,
+    isbn: Isbn,
+) -> Result<(), (Status, String)> {
+    unsubscribe_from_stock(&conn, customer.customer_id, isbn)
+        .await
+        .map_err(|e| (Status::InternalServerError, e.to_string()))
+}
+
+#[derive(FromForm)]
+pub struct CreatePublisher<'r> {
+    company_name: &'r str,
+    email: &'r str,
+    street_address: &'r str,
+    postal_code: &'r str,
+    province: &'r str,
+    phone_number: &'r str,
+    bank_number: &'r str,
+}
+
+#[get("/owner/create/publisher")]
+pub async fn create_publisher_page(owner: Owner) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    Template::render("create_publisher", context.into_json())
+}
+
+#[post("/owner/create/publisher", data = "<publisher>")]
+pub async fn create_publisher(
+    conn: DbConn,
+    _owner: Owner,
+    publisher: Form<CreatePublisher<'_>>,
+) -> Template {
+    let CreatePublisher {
         company_name,
         email,
         street_address,
@@ -904,68 +1768,515 @@ pub async fn sales_report_image(conn: DbConn, _owner: Owner) -> (ContentType, St
         }
     }
 
-    let days_included = sales_data
-        .iter()
-        .map(|(_, data)| data.iter().map(|(day, _)| *day))
-        .flatten()
-        .collect::<HashSet<i128>>();
+    let days_included = sales_data
+        .iter()
+        .map(|(_, data)| data.iter().map(|(day, _)| *day))
+        .flatten()
+        .collect::<HashSet<i128>>();
+
+    // Add missing dates as 0 data points
+    for (_, data) in &mut sales_data {
+        let days: Vec<i128> = data.iter().map(|(day, _)| *day).collect();
+        for day in &days_included {
+            if !days.contains(*day) {
+                data.push((*day, 0));
+            }
+        }
+    }
+
+    let earliest_day = *sales_data
+        .iter()
+        .map(|(_, data)| data)
+        .flatten()
+        .map(|(days_since_today, _)| days_since_today)
+        .min()
+        .unwrap_or(&0);
+
+    let mut sales_data_as_days_since_earliest: Vec<(String, Vec<(i128, i128)>)> = sales_data
+        .into_iter()
+        .map(|(name, data)| {
+            (
+                name,
+                data.into_iter()
+                    .map(|(days_since_today, quantity)| (days_since_today - earliest_day, quantity))
+                    .collect(),
+            )
+        })
+        .collect();
+
+    for (_, data) in &mut sales_data_as_days_since_earliest {
+        data.sort_by(|(x0, _), (x1, _)| x0.cmp(x1));
+    }
+
+    let mut s = poloto::plot("Book Sales", "Date", "Sales (1 Book)");
+
+    s.ymarker(-1);
+
+    for (name, data) in sales_data_as_days_since_earliest {
+        s.line(name, data);
+    }
+
+    s.xinterval_fmt(|fmt, val, _| {
+        write!(
+            fmt,
+            "{}",
+            today
+                .checked_add_signed(Duration::days((val + earliest_day) as i64))
+                .unwrap_or(today)
+                .to_string()
+        )
+    });
+
+    let svg = poloto::disp(|a| poloto::simple_theme(a, s)).to_string();
+
+    (ContentType::SVG, svg)
+}
+
+/// Parses a report's `YYYY-MM-DD` date range, defaulting to the month so far
+pub(crate) fn parse_report_period(
+    period_start: Option<&str>,
+    period_end: Option<&str>,
+) -> Result<(NaiveDate, NaiveDate), String> {
+    let today = Local::today().naive_local();
+    let parse = |date: Option<&str>, default: NaiveDate| match date {
+        Some(date) if !date.is_empty() => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| format!("{date} is not a valid date")),
+        _ => Ok(default),
+    };
+
+    Ok((
+        parse(period_start, today.with_day(1).unwrap_or(today))?,
+        parse(period_end, today)?,
+    ))
+}
+
+pub(crate) fn royalty_error_status(e: RoyaltyError) -> (Status, String) {
+    match e {
+        RoyaltyError::InvalidPeriod => (Status::BadRequest, e.to_string()),
+        RoyaltyError::NothingToPay => (Status::Conflict, e.to_string()),
+        RoyaltyError::NoSuchPayout(_) => (Status::NotFound, e.to_string()),
+        RoyaltyError::DBError(_) => (Status::InternalServerError, e.to_string()),
+    }
+}
+
+#[get("/owner/reports/royalties?<from>&<to>")]
+pub async fn royalties_page(
+    conn: DbConn,
+    owner: Owner,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    let result: Result<(), String> = try {
+        let (period_start, period_end) = parse_report_period(from, to)?;
+        let royalties = get_royalties(&conn, period_start, period_end)
+            .await
+            .map_err(|e| e.to_string())?;
+        let payouts = get_royalty_payouts(&conn)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        context.insert("period_start", &period_start.to_string());
+        context.insert("period_end", &period_end.to_string());
+        context.insert(
+            "royalty_owed",
+            &royalties
+                .iter()
+                .map(|author| author.royalty_owed)
+                .sum::<Decimal>(),
+        );
+        context.insert("authors", &royalties);
+        context.insert("payouts", &payouts);
+    };
+
+    match result {
+        Ok(()) => Template::render("royalties", context.into_json()),
+        Err(e) => render_error_template(e, &conn, &None).await,
+    }
+}
+
+#[get("/owner/reports/royalties/statement.csv?<from>&<to>")]
+pub async fn royalty_statement_csv(
+    conn: DbConn,
+    _owner: Owner,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<CsvFile, (Status, String)> {
+    let (period_start, period_end) =
+        parse_report_period(from, to).map_err(|e| (Status::BadRequest, e))?;
+    let royalties = get_royalties(&conn, period_start, period_end)
+        .await
+        .map_err(royalty_error_status)?;
+
+    let mut csv = CsvWriter::new();
+    csv.row(&[
+        &"author_name",
+        &"isbn",
+        &"title",
+        &"quantity",
+        &"gross",
+        &"royalty_rate",
+        &"royalty_owed",
+        &"royalty_paid",
+    ]);
+
+    for author in &royalties {
+        for book in &author.books {
+            csv.row(&[
+                &author.author_name,
+                &book.isbn,
+                &book.title,
+                &book.quantity,
+                &book.gross,
+                &book.royalty_rate,
+                &book.royalty_owed,
+                &book.royalty_paid,
+            ]);
+        }
+    }
+
+    Ok(CsvFile::new(
+        format!("royalties_{period_start}_{period_end}.csv"),
+        csv.finish(),
+    ))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReportPeriod {
+    period_start: String,
+    period_end: String,
+}
+
+#[post("/owner/reports/royalties/payouts", data = "<payout>")]
+pub async fn create_royalty_payout(
+    conn: DbConn,
+    owner: Owner,
+    payout: Json<ReportPeriod>,
+) -> Result<Json<PostgresInt>, (Status, String)> {
+    let (period_start, period_end) =
+        parse_report_period(Some(&payout.period_start), Some(&payout.period_end))
+            .map_err(|e| (Status::BadRequest, e))?;
+    let owner_id = owner.owner_id();
+
+    record_royalty_payout(&conn, period_start, period_end, owner_id)
+        .await
+        .map(Json)
+        .map_err(royalty_error_status)
+}
+
+#[get("/owner/reports/royalties/payouts/<royalty_payout_id>/statement.csv")]
+pub async fn royalty_payout_csv(
+    conn: DbConn,
+    _owner: Owner,
+    royalty_payout_id: PostgresInt,
+) -> Result<CsvFile, (Status, String)> {
+    let (payout, lines) = get_royalty_payout(&conn, royalty_payout_id)
+        .await
+        .map_err(royalty_error_status)?;
+
+    let mut csv = CsvWriter::new();
+    csv.row(&[
+        &"royalty_payout_id",
+        &"period_start",
+        &"period_end",
+        &"order_id",
+        &"author_name",
+        &"isbn",
+        &"title",
+        &"quantity",
+        &"price",
+        &"royalty_rate",
+        &"royalty",
+    ]);
+
+    for line in &lines {
+        csv.row(&[
+            &payout.royalty_payout_id,
+            &payout.period_start,
+            &payout.period_end,
+            &line.order_id,
+            &line.author_name,
+            &line.isbn,
+            &line.title,
+            &line.quantity,
+            &line.price,
+            &line.royalty_rate,
+            &line.royalty,
+        ]);
+    }
+
+    Ok(CsvFile::new(
+        format!("royalty_payout_{}.csv", payout.royalty_payout_id),
+        csv.finish(),
+    ))
+}
+
+pub(crate) fn settlement_error_status(e: SettlementError) -> (Status, String) {
+    match e {
+        SettlementError::InvalidPeriod => (Status::BadRequest, e.to_string()),
+        SettlementError::NoSuchPublisher(_) | SettlementError::NoSuchRemittance(_) => {
+            (Status::NotFound, e.to_string())
+        }
+        SettlementError::NothingToRemit | SettlementError::InvalidTransition(_, _) => {
+            (Status::Conflict, e.to_string())
+        }
+        SettlementError::DBError(_) | SettlementError::StateError(_) => {
+            (Status::InternalServerError, e.to_string())
+        }
+    }
+}
+
+#[get("/owner/reports/promotions?<from>&<to>")]
+pub async fn promotion_sales_page(
+    conn: DbConn,
+    owner: Owner,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    let result: Result<(), String> = try {
+        let (period_start, period_end) = parse_report_period(from, to)?;
+        let promotions = get_promotion_sales(&conn, period_start, period_end)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        context.insert("period_start", &period_start.to_string());
+        context.insert("period_end", &period_end.to_string());
+        context.insert(
+            "discount",
+            &promotions
+                .iter()
+                .map(|promotion| promotion.discount)
+                .sum::<Decimal>(),
+        );
+        context.insert("promotions", &promotions);
+    };
+
+    match result {
+        Ok(()) => Template::render("promotion_sales", context.into_json()),
+        Err(e) => render_error_template(e, &conn, &None).await,
+    }
+}
+
+#[get("/owner/reports/publishers?<from>&<to>")]
+pub async fn publisher_settlements_page(
+    conn: DbConn,
+    owner: Owner,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    let result: Result<(), String> = try {
+        let (period_start, period_end) = parse_report_period(from, to)?;
+        let settlements = get_publisher_settlements(&conn, period_start, period_end)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        context.insert("period_start", &period_start.to_string());
+        context.insert("period_end", &period_end.to_string());
+        context.insert("settlements", &settlements);
+    };
+
+    match result {
+        Ok(()) => Template::render("publisher_settlements", context.into_json()),
+        Err(e) => render_error_template(e, &conn, &None).await,
+    }
+}
+
+#[get("/owner/reports/publishers/<publisher_id>?<from>&<to>")]
+pub async fn publisher_statement_page(
+    conn: DbConn,
+    owner: Owner,
+    publisher_id: PublisherID,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
 
-    // Add missing dates as 0 data points
-    for (_, data) in &mut sales_data {
-        let days: Vec<i128> = data.iter().map(|(day, _)| *day).collect();
-        for day in &days_included {
-            if !days.contains(*day) {
-                data.push((*day, 0));
-            }
-        }
-    }
+    let result: Result<(), String> = try {
+        let (period_start, period_end) = parse_report_period(from, to)?;
+        let (settlement, books, restock_orders) =
+            get_publisher_statement(&conn, publisher_id, period_start, period_end)
+                .await
+                .map_err(|e| e.to_string())?;
+        let remittances = get_publisher_remittances(&conn, publisher_id)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        context.insert("period_start", &period_start.to_string());
+        context.insert("period_end", &period_end.to_string());
+        context.insert("settlement", &settlement);
+        context.insert("books", &books);
+        context.insert("restock_orders", &restock_orders);
+        context.insert("remittances", &remittances);
+    };
 
-    let earliest_day = *sales_data
-        .iter()
-        .map(|(_, data)| data)
-        .flatten()
-        .map(|(days_since_today, _)| days_since_today)
-        .min()
-        .unwrap_or(&0);
+    match result {
+        Ok(()) => Template::render("publisher_statement", context.into_json()),
+        Err(e) => render_error_template(e, &conn, &None).await,
+    }
+}
 
-    let mut sales_data_as_days_since_earliest: Vec<(String, Vec<(i128, i128)>)> = sales_data
-        .into_iter()
-        .map(|(name, data)| {
-            (
-                name,
-                data.into_iter()
-                    .map(|(days_since_today, quantity)| (days_since_today - earliest_day, quantity))
-                    .collect(),
-            )
-        })
-        .collect();
+#[get("/owner/reports/publishers/<publisher_id>/statement.csv?<from>&<to>")]
+pub async fn publisher_statement_csv(
+    conn: DbConn,
+    _owner: Owner,
+    publisher_id: PublisherID,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<CsvFile, (Status, String)> {
+    let (period_start, period_end) =
+        parse_report_period(from, to).map_err(|e| (Status::BadRequest, e))?;
+    let (settlement, books, restock_orders) =
+        get_publisher_statement(&conn, publisher_id, period_start, period_end)
+            .await
+            .map_err(settlement_error_status)?;
+
+    let mut csv = CsvWriter::new();
+    csv.row(&[&"publisher", &settlement.company_name])
+        .row(&[&"bank_number", &settlement.bank_number])
+        .row(&[&"period_start", &period_start])
+        .row(&[&"period_end", &period_end])
+        .row(&[&"gross_sales", &settlement.gross_sales])
+        .row(&[&"royalties_withheld", &settlement.royalties])
+        .row(&[&"restock_costs", &settlement.restock_costs])
+        .row(&[&"store_margin", &settlement.store_margin])
+        .row(&[&"amount_due", &settlement.amount_due])
+        .row(&[&"unpriced_restock_orders", &settlement.unpriced_restocks])
+        .row(&[])
+        .row(&[
+            &"isbn",
+            &"title",
+            &"quantity",
+            &"gross_sales",
+            &"royalties_withheld",
+        ]);
+
+    for book in &books {
+        csv.row(&[
+            &book.isbn,
+            &book.title,
+            &book.quantity,
+            &book.gross_sales,
+            &book.royalties,
+        ]);
+    }
 
-    for (_, data) in &mut sales_data_as_days_since_earliest {
-        data.sort_by(|(x0, _), (x1, _)| x0.cmp(x1));
+    csv.row(&[]).row(&[
+        &"restock_order_id",
+        &"order_date",
+        &"isbn",
+        &"title",
+        &"quantity",
+        &"price_per_unit",
+        &"publisher_remittance_id",
+    ]);
+
+    for restock_order in &restock_orders {
+        csv.row(&[
+            &restock_order.restock_order_id,
+            &restock_order.order_date,
+            &restock_order.isbn,
+            &restock_order.title,
+            &restock_order.quantity,
+            &restock_order
+                .price_per_unit
+                .map(|price| price.to_string())
+                .unwrap_or_default(),
+            &restock_order
+                .publisher_remittance_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+        ]);
     }
 
-    let mut s = poloto::plot("Book Sales", "Date", "Sales (1 Book)");
+    Ok(CsvFile::new(
+        format!("publisher_{publisher_id}_{period_start}_{period_end}.csv"),
+        csv.finish(),
+    ))
+}
 
-    s.ymarker(-1);
+#[post(
+    "/owner/reports/publishers/<publisher_id>/remittances",
+    data = "<period>"
+)]
+pub async fn create_publisher_remittance_endpoint(
+    conn: DbConn,
+    owner: Owner,
+    publisher_id: PublisherID,
+    period: Json<ReportPeriod>,
+) -> Result<Json<PostgresInt>, (Status, String)> {
+    let (period_start, period_end) =
+        parse_report_period(Some(&period.period_start), Some(&period.period_end))
+            .map_err(|e| (Status::BadRequest, e))?;
+    let owner_id = owner.owner_id();
+
+    create_publisher_remittance(&conn, publisher_id, period_start, period_end, owner_id)
+        .await
+        .map(Json)
+        .map_err(settlement_error_status)
+}
 
-    for (name, data) in sales_data_as_days_since_earliest {
-        s.line(name, data);
-    }
+#[derive(Deserialize, Debug)]
+pub struct UpdateRemittanceStatus {
+    remittance_status: RemittanceStatus,
+}
 
-    s.xinterval_fmt(|fmt, val, _| {
-        write!(
-            fmt,
-            "{}",
-            today
-                .checked_add_signed(Duration::days((val + earliest_day) as i64))
-                .unwrap_or(today)
-                .to_string()
-        )
+#[put(
+    "/owner/reports/remittances/<publisher_remittance_id>/status",
+    data = "<update>"
+)]
+pub async fn update_remittance_status(
+    conn: DbConn,
+    _owner: Owner,
+    publisher_remittance_id: PostgresInt,
+    update: Json<UpdateRemittanceStatus>,
+) -> Result<(), (Status, String)> {
+    set_remittance_status(&conn, publisher_remittance_id, update.remittance_status)
+        .await
+        .map_err(settlement_error_status)
+}
+
+#[get("/owner/manage/export?<format>&<search>")]
+pub async fn export_books_endpoint(
+    conn: DbConn,
+    _owner: Owner,
+    format: ExportFormat,
+    search: Search<'_>,
+) -> Result<CatalogExport, (Status, String)> {
+    let query = search_query(&search);
+    let (batches, mut receiver) = mpsc::channel(2);
+
+    rocket::tokio::spawn(async move {
+        let errors = batches.clone();
+        if let Err(e) = export_books(&conn, query, batches).await {
+            let _ = errors.send(Err(e.to_string())).await;
+        }
     });
 
-    let svg = poloto::disp(|a| poloto::simple_theme(a, s)).to_string();
+    // Waiting for the first batch means an export that can't start is an error, not an empty file
+    let first_batch = match receiver.recv().await {
+        Some(Ok(books)) => Some(books),
+        Some(Err(e)) => {
+            error!("Could not export the catalog: {}", e);
+            return Err((Status::InternalServerError, e));
+        }
+        None => None,
+    };
 
-    (ContentType::SVG, svg)
+    Ok(CatalogExport::new(
+        format,
+        format!("catalog_{}", Local::today().format("%Y-%m-%d")),
+        first_batch,
+        receiver,
+    ))
 }
 
 #[get("/owner/create/book")]
@@ -999,9 +2310,24 @@ pub async fn create_book_page(owner: Owner, conn: DbConn) -> Template {
     Template::render("create_book", context.into_json())
 }
 
+/// Parses a price, written out to the cent as the database would give it back
+fn parse_price(price: &str) -> Result<Decimal, String> {
+    let mut price =
+        Decimal::from_str(price).map_err(|_| format!("`{price}` isn't a valid price"))?;
+
+    if price.is_sign_negative() || price > Decimal::new(999999, 2) || price.normalize().scale() > 2
+    {
+        Err("Prices must be between $0 and $9999.99, in whole cents")?;
+    }
+
+    price.rescale(2);
+
+    Ok(price)
+}
+
 #[derive(FromForm)]
 pub struct CreateBook<'r> {
-    isbn: i32,
+    isbn: &'r str,
     title: &'r str,
     author_name: &'r str,
     genre: &'r str,
@@ -1014,48 +2340,72 @@ pub struct CreateBook<'r> {
     discontinued: bool,
 }
 
+impl CreateBook<'_> {
+    /// Checks the form against the `Book` model, so mistakes are caught before the database's
+    /// own constraints reject them with a less helpful message
+    fn to_book(&self) -> Result<Book, String> {
+        let isbn = Isbn::parse(self.isbn).map_err(|e| e.to_string())?;
+        let title = self.title.trim();
+        let author_name = self.author_name.trim();
+        let genre = self.genre.trim();
+        let price = parse_price(self.price)?;
+        let mut author_royalties = Decimal::from_str(self.author_royalties)
+            .map_err(|_| format!("`{}` isn't a valid author royalty", self.author_royalties))?;
+
+        if title.is_empty() || title.chars().count() > 50 {
+            Err("Titles must be between 1 and 50 characters")?;
+        }
+        if author_name.is_empty() || author_name.chars().count() > 20 {
+            Err("Author names must be between 1 and 20 characters")?;
+        }
+        if genre.is_empty() || genre.chars().count() > 20 {
+            Err("Genres must be between 1 and 20 characters")?;
+        }
+        if self.num_pages < 1 {
+            Err("A book needs at least 1 page")?;
+        }
+        if author_royalties.is_sign_negative()
+            || author_royalties > Decimal::ONE
+            || author_royalties.normalize().scale() > 2
+        {
+            Err("Author royalties must be between 0 and 1, to 2 decimal places")?;
+        }
+        if self.reorder_threshold < 0 || self.stock < 0 {
+            Err("The reorder threshold and stock can't be negative")?;
+        }
+
+        // Written out to the cent, as the database would give them back
+        author_royalties.rescale(2);
+
+        Ok(Book::new(
+            isbn,
+            title.to_string(),
+            author_name.to_string(),
+            genre.to_string(),
+            self.publisher_id,
+            self.num_pages,
+            price,
+            author_royalties,
+            self.reorder_threshold,
+            self.stock,
+            self.discontinued,
+        ))
+    }
+}
+
 #[post("/owner/create/book", data = "<book>")]
 pub async fn create_book_endpoint(
     conn: DbConn,
     owner: Owner,
     book: Form<CreateBook<'_>>,
 ) -> Template {
-    let CreateBook {
-        isbn,
-        title,
-        author_name,
-        genre,
-        publisher_id,
-        num_pages,
-        price,
-        author_royalties,
-        reorder_threshold,
-        stock,
-        discontinued,
-    } = *book;
-
-    let book: Result<Book, <Decimal as FromStr>::Err> = try {
-        Book::new(
-            isbn,
-            title.to_string(),
-            author_name.to_string(),
-            genre.to_string(),
-            publisher_id,
-            num_pages,
-            Decimal::from_str(price)?,
-            Decimal::from_str(author_royalties)?,
-            reorder_threshold,
-            stock,
-            discontinued,
-        )
-    };
-
-    let result = match book {
-        Ok(book) => create_book(&conn, book).await,
-        Err(e) => return render_error_template(e.to_string(), &conn, &None).await,
+    let book = match book.to_book() {
+        Ok(book) => book,
+        Err(e) => return render_error_template(e, &conn, &None).await,
     };
+    let isbn = book.isbn;
 
-    match result {
+    match create_book(&conn, book, owner.owner_id()).await {
         Ok(_) => {
             let mut context = Context::new();
             add_owner_tag(&Some(owner), &mut context);
@@ -1068,6 +2418,383 @@ pub async fn create_book_endpoint(
     }
 }
 
+#[get("/owner/manage/book/<isbn>/edit")]
+pub async fn edit_book_page(conn: DbConn, owner: Owner, isbn: Result<Isbn, IsbnError>) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    let result: Result<(), String> = try {
+        let isbn = isbn.map_err(|e| e.to_string())?;
+        let (book, version) = get_book_for_edit(&conn, isbn)
+            .await
+            .map_err(|e| e.to_string())?;
+        let publishers = get_publishers(&conn).await.map_err(|e| e.to_string())?;
+        let edits = get_book_edits(&conn, isbn)
+            .await
+            .map_err(|e| e.to_string())?;
+        let prices = get_book_prices(&conn, isbn)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        context.insert("book", &book);
+        context.insert("version", &version);
+        context.insert("publishers", &publishers);
+        context.insert("edits", &edits);
+        context.insert("prices", &prices);
+    };
+
+    match result {
+        Ok(()) => Template::render("edit_book", context.into_json()),
+        Err(e) => render_error_template(e, &conn, &None).await,
+    }
+}
+
+#[derive(FromForm)]
+pub struct EditBook<'r> {
+    /// The version of the book the form was filled in from
+    version: PostgresInt,
+    /// The stock the form was filled in with, which copies sold since are taken off
+    read_stock: PostgresInt,
+    book: CreateBook<'r>,
+}
+
+#[post("/owner/manage/book/<isbn>/edit", data = "<edit>")]
+pub async fn edit_book_endpoint(
+    conn: DbConn,
+    owner: Owner,
+    isbn: Isbn,
+    edit: Form<EditBook<'_>>,
+) -> Result<Redirect, Template> {
+    let result: Result<(), String> = try {
+        let book = edit.book.to_book()?;
+        if book.isbn != isbn {
+            Err("A book's ISBN can't be changed")?;
+        }
+
+        update_book(&conn, book, edit.version, edit.read_stock, owner.owner_id())
+            .await
+            .map_err(|e| e.to_string())?;
+    };
+
+    match result {
+        Ok(()) => Ok(Redirect::to(uri!(edit_book_page(&isbn)))),
+        Err(e) => Err(render_error_template(e, &conn, &None).await),
+    }
+}
+
+/// Parses a local time as given by a `datetime-local` input
+fn parse_local_time(time: &str) -> Result<DateTime<Local>, String> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M")
+        .ok()
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+        .ok_or_else(|| format!("`{time}` isn't a valid time"))
+}
+
+#[derive(FromForm)]
+pub struct SchedulePrice<'r> {
+    price: &'r str,
+    /// Local time, as given by a `datetime-local` input
+    effective_from: &'r str,
+}
+
+#[post("/owner/manage/book/<isbn>/prices", data = "<schedule>")]
+pub async fn schedule_price_endpoint(
+    conn: DbConn,
+    owner: Owner,
+    isbn: Isbn,
+    schedule: Form<SchedulePrice<'_>>,
+) -> Result<Redirect, Template> {
+    let result: Result<(), String> = try {
+        let price = parse_price(schedule.price)?;
+        let effective_from = parse_local_time(schedule.effective_from)?;
+
+        schedule_price_change(&conn, isbn, price, effective_from, owner.owner_id())
+            .await
+            .map_err(|e| e.to_string())?;
+    };
+
+    match result {
+        Ok(()) => Ok(Redirect::to(uri!(edit_book_page(&isbn)))),
+        Err(e) => Err(render_error_template(e, &conn, &None).await),
+    }
+}
+
+#[post("/owner/manage/book/<isbn>/prices/<book_price_id>/cancel")]
+pub async fn cancel_price_endpoint(
+    conn: DbConn,
+    _owner: Owner,
+    isbn: Isbn,
+    book_price_id: PostgresInt,
+) -> Result<Redirect, Template> {
+    match cancel_price_change(&conn, isbn, book_price_id).await {
+        Ok(()) => Ok(Redirect::to(uri!(edit_book_page(&isbn)))),
+        Err(e) => Err(render_error_template(e.to_string(), &conn, &None).await),
+    }
+}
+
+#[get("/owner/manage/promotions")]
+pub async fn promotions_page(conn: DbConn, owner: Owner) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    let result: Result<(), String> = try {
+        let promotions = get_promotions(&conn).await.map_err(|e| e.to_string())?;
+        let publishers = get_publishers(&conn).await.map_err(|e| e.to_string())?;
+
+        context.insert("promotions", &promotions);
+        context.insert("publishers", &publishers);
+    };
+
+    match result {
+        Ok(()) => Template::render("promotions", context.into_json()),
+        Err(e) => render_error_template(e, &conn, &None).await,
+    }
+}
+
+#[derive(FromFormField, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromotionScopeKind {
+    #[field(value = "all")]
+    All,
+    #[field(value = "genre")]
+    Genre,
+    #[field(value = "publisher")]
+    Publisher,
+    #[field(value = "isbn")]
+    Isbn,
+}
+
+#[derive(FromForm)]
+pub struct CreatePromotion<'r> {
+    code: &'r str,
+    discount_type: DiscountType,
+    discount_value: &'r str,
+    min_spend: &'r str,
+    scope: PromotionScopeKind,
+    genre: &'r str,
+    publisher_id: Option<PublisherID>,
+    isbn: &'r str,
+    /// Local times, as given by `datetime-local` inputs, or empty for no limit
+    starts_at: &'r str,
+    ends_at: &'r str,
+    max_uses: Option<PostgresInt>,
+    max_uses_per_customer: Option<PostgresInt>,
+}
+
+impl CreatePromotion<'_> {
+    /// Checks the form against the database's constraints, so mistakes get a helpful message
+    fn to_terms(&self) -> Result<PromotionTerms, String> {
+        let code = normalise_code(self.code);
+        if code.is_empty()
+            || code.chars().count() > 30
+            || !code
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            Err("Codes must be 1 to 30 letters, digits, dashes or underscores")?;
+        }
+
+        let mut discount_value = Decimal::from_str(self.discount_value)
+            .map_err(|_| format!("`{}` isn't a valid discount", self.discount_value))?;
+        if discount_value <= Decimal::ZERO
+            || discount_value > Decimal::new(99999999, 2)
+            || discount_value.normalize().scale() > 2
+        {
+            Err("Discounts must be more than 0, in whole cents or hundredths of a percent")?;
+        }
+        if self.discount_type == DiscountType::Percent && discount_value > Decimal::ONE_HUNDRED {
+            Err("A percentage discount can't be more than 100%")?;
+        }
+        discount_value.rescale(2);
+
+        let min_spend = match self.min_spend.trim() {
+            "" => Decimal::new(0, 2),
+            min_spend => parse_price(min_spend)?,
+        };
+
+        let scope = match self.scope {
+            PromotionScopeKind::All => PromotionScope::All,
+            PromotionScopeKind::Genre => {
+                let genre = self.genre.trim();
+                if genre.is_empty() || genre.chars().count() > 20 {
+                    Err("Genres must be between 1 and 20 characters")?;
+                }
+                PromotionScope::Genre(genre.to_string())
+            }
+            PromotionScopeKind::Publisher => PromotionScope::Publisher(
+                self.publisher_id
+                    .ok_or("Choose the publisher the promotion is for")?,
+            ),
+            PromotionScopeKind::Isbn => {
+                PromotionScope::Isbn(Isbn::parse(self.isbn).map_err(|e| e.to_string())?)
+            }
+        };
+
+        let parse_time = |time: &str| match time.trim() {
+            "" => Ok(None),
+            time => parse_local_time(time).map(Some),
+        };
+        let starts_at = parse_time(self.starts_at)?;
+        let ends_at = parse_time(self.ends_at)?;
+        if let (Some(starts_at), Some(ends_at)) = (starts_at, ends_at) {
+            if starts_at >= ends_at {
+                Err("A promotion has to end after it starts")?;
+            }
+        }
+
+        if self.max_uses.is_some_and(|uses| uses < 1)
+            || self.max_uses_per_customer.is_some_and(|uses| uses < 1)
+        {
+            Err("Usage limits have to be at least 1, or left empty for no limit")?;
+        }
+
+        Ok(PromotionTerms {
+            code,
+            discount_type: self.discount_type,
+            discount_value,
+            min_spend,
+            scope,
+            starts_at,
+            ends_at,
+            max_uses: self.max_uses,
+            max_uses_per_customer: self.max_uses_per_customer,
+        })
+    }
+}
+
+#[post("/owner/manage/promotions", data = "<promotion>")]
+pub async fn create_promotion_endpoint(
+    conn: DbConn,
+    owner: Owner,
+    promotion: Form<CreatePromotion<'_>>,
+) -> Result<Redirect, Template> {
+    let result: Result<PostgresInt, String> = try {
+        let terms = promotion.to_terms()?;
+
+        create_promotion(&conn, terms, owner.owner_id())
+            .await
+            .map_err(|e| e.to_string())?
+    };
+
+    match result {
+        Ok(_) => Ok(Redirect::to(uri!(promotions_page))),
+        Err(e) => Err(render_error_template(e, &conn, &None).await),
+    }
+}
+
+#[derive(FromForm)]
+pub struct PromotionActive {
+    active: bool,
+}
+
+#[post("/owner/manage/promotions/<promotion_id>/active", data = "<update>")]
+pub async fn set_promotion_active_endpoint(
+    conn: DbConn,
+    _owner: Owner,
+    promotion_id: PostgresInt,
+    update: Form<PromotionActive>,
+) -> Result<Redirect, Template> {
+    match set_promotion_active(&conn, promotion_id, update.active).await {
+        Ok(()) => Ok(Redirect::to(uri!(promotions_page))),
+        Err(e) => Err(render_error_template(e.to_string(), &conn, &None).await),
+    }
+}
+
+#[get("/owner/import")]
+pub async fn catalog_imports_page(conn: DbConn, owner: Owner) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    match get_catalog_imports(&conn).await {
+        Ok(imports) => {
+            context.insert("imports", &imports);
+
+            Template::render("catalog_imports", context.into_json())
+        }
+        Err(e) => render_error_template(e.to_string(), &conn, &None).await,
+    }
+}
+
+#[derive(FromForm)]
+pub struct CatalogUpload<'r> {
+    format: ImportFormat,
+    file: TempFile<'r>,
+}
+
+#[post("/owner/import", data = "<upload>")]
+pub async fn upload_catalog_import(
+    conn: DbConn,
+    owner: Owner,
+    upload: Form<CatalogUpload<'_>>,
+) -> Result<Redirect, Template> {
+    let owner_id = owner.owner_id();
+    let file_name = upload
+        .file
+        .raw_name()
+        .map(|name| name.dangerous_unsafe_unsanitized_raw().to_string())
+        .unwrap_or_else(|| "catalog".to_string());
+
+    let contents = match upload.file.path() {
+        Some(path) => rocket::tokio::fs::read_to_string(path).await.ok(),
+        None => None,
+    };
+    let contents = match contents {
+        Some(contents) => contents,
+        None => {
+            return Err(
+                render_error_template("The catalog file must be UTF-8 text", &conn, &None).await,
+            )
+        }
+    };
+
+    match create_catalog_import(&conn, file_name, upload.format, contents, owner_id).await {
+        Ok(import_id) => Ok(Redirect::to(uri!(catalog_import_page(import_id)))),
+        Err(e) => Err(render_error_template(e.to_string(), &conn, &None).await),
+    }
+}
+
+#[get("/owner/import/<catalog_import_id>")]
+pub async fn catalog_import_page(
+    conn: DbConn,
+    owner: Owner,
+    catalog_import_id: PostgresInt,
+) -> Template {
+    let mut context = Context::new();
+    add_owner_tag(&Some(owner), &mut context);
+
+    match get_catalog_import(&conn, catalog_import_id).await {
+        Ok((import, plan)) => {
+            context.insert("import", &import);
+            context.insert("plan", &plan);
+
+            Template::render("catalog_import", context.into_json())
+        }
+        Err(e) => render_error_template(e.to_string(), &conn, &None).await,
+    }
+}
+
+pub(crate) fn import_error_status(e: ImportError) -> (Status, String) {
+    match e {
+        ImportError::NoSuchImport(_) => (Status::NotFound, e.to_string()),
+        ImportError::AlreadyApplied => (Status::Conflict, e.to_string()),
+        ImportError::InvalidFile(_) => (Status::BadRequest, e.to_string()),
+        ImportError::DBError(_) | ImportError::StateError(_) => {
+            (Status::InternalServerError, e.to_string())
+        }
+    }
+}
+
+#[post("/owner/import/<catalog_import_id>/apply")]
+pub async fn apply_catalog_import_endpoint(
+    conn: DbConn,
+    owner: Owner,
+    catalog_import_id: PostgresInt,
+) -> Result<(), (Status, String)> {
+    apply_catalog_import(&conn, catalog_import_id, owner.owner_id())
+        .await
+        .map(|_| ())
+        .map_err(import_error_status)
+}
+
 #[get("/owner/manage/accounts")]
 pub async fn manage_accounts(conn: DbConn, owner: Owner) -> Template {
     let mut context = Context::new();
@@ -0,0 +1,311 @@
+//! Catalog exports, streamed to the owner as CSV or JSON while the books are read.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use rocket::futures::Stream;
+use rocket::http::{ContentType, Header};
+use rocket::response::stream::TextStream;
+use rocket::response::{self, Responder, Response};
+use rocket::tokio::sync::mpsc;
+use rocket::Request;
+
+use crate::csv::CsvWriter;
+use crate::schema::entities::BookWithPublisherName;
+
+#[derive(FromFormField, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[field(value = "csv")]
+    Csv,
+    #[field(value = "json")]
+    Json,
+}
+
+impl ExportFormat {
+    fn content_type(&self) -> ContentType {
+        match self {
+            ExportFormat::Csv => ContentType::CSV,
+            ExportFormat::Json => ContentType::JSON,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// A batch of books to export, or why the export couldn't carry on
+pub type ExportBatch = Result<Vec<BookWithPublisherName>, String>;
+
+/// The export's text, a batch of books at a time, with the CSV header or JSON brackets around them.
+///
+/// An export that fails part way through ends with a line saying so instead of the closing
+/// bracket, so it can't be mistaken for the whole catalog.
+pub struct ExportChunks {
+    format: ExportFormat,
+    first_batch: Option<Vec<BookWithPublisherName>>,
+    batches: mpsc::Receiver<ExportBatch>,
+    started: bool,
+    wrote_books: bool,
+    finished: bool,
+}
+
+impl ExportChunks {
+    fn encode(&mut self, books: Vec<BookWithPublisherName>) -> Result<String, serde_json::Error> {
+        Ok(match self.format {
+            ExportFormat::Csv => {
+                let mut csv = CsvWriter::new();
+                for book in books {
+                    csv.row(&[
+                        &book.isbn,
+                        &book.title,
+                        &book.author_name,
+                        &book.genre,
+                        &book.publisher_id,
+                        &book.publisher_name,
+                        &book.num_pages,
+                        &book.price,
+                        &book.author_royalties,
+                        &book.reorder_threshold,
+                        &book.stock,
+                        &book.discontinued,
+                    ]);
+                }
+                csv.finish()
+            }
+            ExportFormat::Json => {
+                let mut text = String::new();
+                for book in books {
+                    if self.wrote_books {
+                        text.push(',');
+                    }
+                    self.wrote_books = true;
+                    text.push_str("\n  ");
+                    text.push_str(&serde_json::to_string(&book)?);
+                }
+                text
+            }
+        })
+    }
+
+    /// Ends the export early with why it failed
+    fn fail(&mut self, error: String) -> String {
+        self.finished = true;
+        error!("Could not export the catalog: {}", error);
+
+        let message = format!("The export failed part way through: {error}");
+        match self.format {
+            ExportFormat::Csv => {
+                let mut csv = CsvWriter::new();
+                csv.row(&[&message]);
+                csv.finish()
+            }
+            ExportFormat::Json => format!("\n{message}\n"),
+        }
+    }
+
+    fn send(&mut self, books: Vec<BookWithPublisherName>) -> String {
+        match self.encode(books) {
+            Ok(text) => text,
+            Err(e) => self.fail(e.to_string()),
+        }
+    }
+}
+
+impl Stream for ExportChunks {
+    type Item = String;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<String>> {
+        if !self.started {
+            self.started = true;
+
+            // Column names match the import's, so an export can be edited and imported back
+            return Poll::Ready(Some(match self.format {
+                ExportFormat::Csv => {
+                    let mut csv = CsvWriter::new();
+                    csv.row(&[
+                        &"isbn",
+                        &"title",
+                        &"author_name",
+                        &"genre",
+                        &"publisher_id",
+                        &"publisher",
+                        &"num_pages",
+                        &"price",
+                        &"author_royalties",
+                        &"reorder_threshold",
+                        &"stock",
+                        &"discontinued",
+                    ]);
+                    csv.finish()
+                }
+                ExportFormat::Json => "[".to_string(),
+            }));
+        }
+
+        if let Some(books) = self.first_batch.take() {
+            return Poll::Ready(Some(self.send(books)));
+        }
+
+        if self.finished {
+            return Poll::Ready(None);
+        }
+
+        match self.batches.poll_recv(cx) {
+            Poll::Ready(Some(Ok(books))) => Poll::Ready(Some(self.send(books))),
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(self.fail(e))),
+            Poll::Ready(None) => {
+                self.finished = true;
+
+                Poll::Ready(Some(match self.format {
+                    ExportFormat::Csv => String::new(),
+                    ExportFormat::Json if self.wrote_books => "\n]\n".to_string(),
+                    ExportFormat::Json => "]\n".to_string(),
+                }))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// A catalog export served as a download, sent as the books are read
+pub struct CatalogExport {
+    body: TextStream<ExportChunks>,
+    content_type: ContentType,
+    disposition: Header<'static>,
+}
+
+impl CatalogExport {
+    /// Starts a download named `name` (plus the format's extension) of `first_batch`, if there is
+    /// one, and then the books sent to `batches`
+    pub fn new<T: AsRef<str>>(
+        format: ExportFormat,
+        name: T,
+        first_batch: Option<Vec<BookWithPublisherName>>,
+        batches: mpsc::Receiver<ExportBatch>,
+    ) -> CatalogExport {
+        CatalogExport {
+            body: TextStream(ExportChunks {
+                format,
+                first_batch,
+                batches,
+                started: false,
+                wrote_books: false,
+                finished: false,
+            }),
+            content_type: format.content_type(),
+            disposition: Header::new(
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"{}.{}\"",
+                    name.as_ref(),
+                    format.extension()
+                ),
+            ),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'r> for CatalogExport {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        Response::build_from(self.body.respond_to(request)?)
+            .header(self.content_type)
+            .header(self.disposition)
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::entities::Isbn;
+    use rocket::futures::StreamExt;
+    use rust_decimal::Decimal;
+
+    fn book(title: &str) -> BookWithPublisherName {
+        BookWithPublisherName::new(
+            Isbn::parse("9780306406157").unwrap(),
+            title.to_string(),
+            "Jane Smith".to_string(),
+            "Physics".to_string(),
+            1,
+            "Light & Co".to_string(),
+            320,
+            Decimal::new(2499, 2),
+            Decimal::new(10, 2),
+            2,
+            5,
+            false,
+        )
+    }
+
+    async fn export(format: ExportFormat, batches: Vec<ExportBatch>) -> String {
+        let (sender, receiver) = mpsc::channel(batches.len().max(1));
+        for batch in batches {
+            sender.send(batch).await.unwrap();
+        }
+        drop(sender);
+
+        let chunks = ExportChunks {
+            format,
+            first_batch: None,
+            batches: receiver,
+            started: false,
+            wrote_books: false,
+            finished: false,
+        };
+        chunks.collect::<Vec<String>>().await.concat()
+    }
+
+    #[rocket::async_test]
+    async fn exports_json() {
+        let text = export(
+            ExportFormat::Json,
+            vec![Ok(vec![book("Optics")]), Ok(vec![book("Light")])],
+        )
+        .await;
+
+        let books: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(books.as_array().unwrap().len(), 2);
+        assert_eq!(books[1]["title"], "Light");
+    }
+
+    #[rocket::async_test]
+    async fn exports_an_empty_catalog() {
+        assert_eq!(export(ExportFormat::Json, vec![]).await, "[]\n");
+        assert_eq!(export(ExportFormat::Csv, vec![]).await.lines().count(), 1);
+    }
+
+    #[rocket::async_test]
+    async fn a_failed_json_export_is_not_valid_json() {
+        let text = export(
+            ExportFormat::Json,
+            vec![Ok(vec![book("Optics")]), Err("connection lost".to_string())],
+        )
+        .await;
+
+        assert!(serde_json::from_str::<serde_json::Value>(&text).is_err());
+        assert!(!text.trim_end().ends_with(']'));
+        assert!(text.contains("connection lost"));
+    }
+
+    #[rocket::async_test]
+    async fn a_failed_csv_export_ends_with_the_error() {
+        let text = export(
+            ExportFormat::Csv,
+            vec![
+                Ok(vec![book("Optics")]),
+                Err("connection lost".to_string()),
+                Ok(vec![book("Never sent")]),
+            ],
+        )
+        .await;
+
+        let last = text.lines().last().unwrap();
+        assert!(last.contains("connection lost"));
+        assert!(!text.contains("Never sent"));
+    }
+}
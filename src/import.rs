@@ -0,0 +1,514 @@
+//! Bulk catalog imports: reading books out of a CSV file or ONIX feed, checking each against
+//! the catalog, and planning which books would be added, updated or rejected.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::csv::{read_csv, strip_formula_prefix, CsvError};
+use crate::isbn::Isbn;
+use crate::onix::{read_onix, OnixError};
+use crate::schema::entities::{Book, FieldChange, PostgresInt, Publisher, PublisherID};
+
+#[derive(FromFormField, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportFormat {
+    #[field(value = "csv")]
+    #[serde(rename = "CSV")]
+    Csv,
+    #[field(value = "onix")]
+    #[serde(rename = "ONIX")]
+    Onix,
+}
+
+impl ImportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportFormat::Csv => "CSV",
+            ImportFormat::Onix => "ONIX",
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ImportFormat, String> {
+        match s {
+            "CSV" => Ok(ImportFormat::Csv),
+            "ONIX" => Ok(ImportFormat::Onix),
+            _ => Err(format!("Unknown import format: {s}")),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ImportFileError {
+    #[error("{0}")]
+    Csv(#[from] CsvError),
+    #[error("{0}")]
+    Onix(#[from] OnixError),
+    #[error("The CSV header is missing the column(s): {}", .0.join(", "))]
+    MissingColumns(Vec<&'static str>),
+    #[error("The CSV header has unknown column(s): {}", .0.join(", "))]
+    UnknownColumns(Vec<String>),
+    #[error("The file doesn't have any books in it")]
+    NoBooks,
+}
+
+/// A book as written in the import file, before any of it has been checked
+#[derive(Serialize, Default, Debug)]
+pub struct RawBook {
+    pub isbn: Option<String>,
+    pub title: Option<String>,
+    pub author_name: Option<String>,
+    pub genre: Option<String>,
+    /// The publisher's company name, or their ID in `publisher_id`
+    pub publisher: Option<String>,
+    pub publisher_id: Option<String>,
+    pub num_pages: Option<String>,
+    pub price: Option<String>,
+    pub author_royalties: Option<String>,
+    pub reorder_threshold: Option<String>,
+    pub stock: Option<String>,
+    pub discontinued: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportRecord {
+    /// Where the book is in the file, like "Line 3" or "Product 2"
+    pub location: String,
+    pub book: RawBook,
+}
+
+const CSV_COLUMNS: [&str; 12] = [
+    "isbn",
+    "title",
+    "author_name",
+    "genre",
+    "publisher",
+    "publisher_id",
+    "num_pages",
+    "price",
+    "author_royalties",
+    "reorder_threshold",
+    "stock",
+    "discontinued",
+];
+
+const REQUIRED_CSV_COLUMNS: [&str; 6] = [
+    "isbn",
+    "title",
+    "author_name",
+    "genre",
+    "num_pages",
+    "price",
+];
+
+fn read_csv_catalog(text: &str) -> Result<Vec<ImportRecord>, ImportFileError> {
+    let mut rows = read_csv(text)?.into_iter();
+    let header: Vec<String> = match rows.next() {
+        Some(header) => header
+            .fields
+            .iter()
+            .map(|column| column.trim().to_lowercase())
+            .collect(),
+        None => return Err(ImportFileError::NoBooks),
+    };
+
+    let unknown: Vec<String> = header
+        .iter()
+        .filter(|column| !CSV_COLUMNS.contains(&column.as_str()))
+        .cloned()
+        .collect();
+    if !unknown.is_empty() {
+        return Err(ImportFileError::UnknownColumns(unknown));
+    }
+
+    let mut missing: Vec<&'static str> = REQUIRED_CSV_COLUMNS
+        .iter()
+        .filter(|column| !header.iter().any(|h| h == *column))
+        .copied()
+        .collect();
+    if !header
+        .iter()
+        .any(|h| h == "publisher" || h == "publisher_id")
+    {
+        missing.push("publisher (or publisher_id)");
+    }
+    if !missing.is_empty() {
+        return Err(ImportFileError::MissingColumns(missing));
+    }
+
+    Ok(rows
+        .map(|row| {
+            let field = |name: &str| {
+                header
+                    .iter()
+                    .position(|column| column == name)
+                    .and_then(|i| row.fields.get(i))
+                    .map(|value| strip_formula_prefix(value.trim()).to_string())
+                    .filter(|value| !value.is_empty())
+            };
+
+            ImportRecord {
+                location: format!("Line {}", row.line),
+                book: RawBook {
+                    isbn: field("isbn"),
+                    title: field("title"),
+                    author_name: field("author_name"),
+                    genre: field("genre"),
+                    publisher: field("publisher"),
+                    publisher_id: field("publisher_id"),
+                    num_pages: field("num_pages"),
+                    price: field("price"),
+                    author_royalties: field("author_royalties"),
+                    reorder_threshold: field("reorder_threshold"),
+                    stock: field("stock"),
+                    discontinued: field("discontinued"),
+                },
+            }
+        })
+        .collect())
+}
+
+/// Reads every book out of an uploaded file, failing only if the file itself can't be read
+pub fn read_catalog(
+    format: ImportFormat,
+    text: &str,
+) -> Result<Vec<ImportRecord>, ImportFileError> {
+    let records = match format {
+        ImportFormat::Csv => read_csv_catalog(text)?,
+        ImportFormat::Onix => read_onix(text)?
+            .into_iter()
+            .map(|(product, book)| ImportRecord {
+                location: format!("Product {product}"),
+                book,
+            })
+            .collect(),
+    };
+
+    if records.is_empty() {
+        Err(ImportFileError::NoBooks)
+    } else {
+        Ok(records)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlannedBook {
+    pub location: String,
+    pub book: Book,
+    pub publisher_name: String,
+    /// What changes for a book already in the catalog, which is empty for a new one
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RejectedRecord {
+    pub location: String,
+    pub isbn: Option<String>,
+    pub title: Option<String>,
+    pub reasons: Vec<String>,
+}
+
+/// What applying an import would do to the catalog as it is now
+#[derive(Serialize, Default, Debug)]
+pub struct ImportPlan {
+    pub new_books: Vec<PlannedBook>,
+    pub updated_books: Vec<PlannedBook>,
+    pub unchanged: usize,
+    pub rejected: Vec<RejectedRecord>,
+}
+
+/// Collects every problem with a record rather than stopping at the first
+struct Checker {
+    reasons: Vec<String>,
+}
+
+impl Checker {
+    /// Uses the new value if there is one, then the existing book's, then `default`
+    fn field<T, F>(
+        &mut self,
+        name: &str,
+        raw: &Option<String>,
+        existing: Option<T>,
+        default: Option<T>,
+        parse: F,
+    ) -> Option<T>
+    where
+        F: FnOnce(&str) -> Result<T, String>,
+    {
+        match raw {
+            Some(raw) => match parse(raw) {
+                Ok(value) => Some(value),
+                Err(reason) => {
+                    self.reasons.push(format!("{name}: {reason}"));
+                    None
+                }
+            },
+            None => {
+                let value = existing.or(default);
+                if value.is_none() {
+                    self.reasons.push(format!("{name} is missing"));
+                }
+                value
+            }
+        }
+    }
+}
+
+fn parse_text(max_len: usize) -> impl Fn(&str) -> Result<String, String> {
+    move |s| {
+        if s.chars().count() > max_len {
+            Err(format!("longer than {max_len} characters"))
+        } else {
+            Ok(s.to_string())
+        }
+    }
+}
+
+fn parse_count(min: PostgresInt) -> impl Fn(&str) -> Result<PostgresInt, String> {
+    move |s| match s.parse::<PostgresInt>() {
+        Ok(n) if n >= min => Ok(n),
+        Ok(_) => Err(format!("must be at least {min}")),
+        Err(_) => Err(format!("`{s}` isn't a whole number")),
+    }
+}
+
+/// Parses an amount that fits a `numeric` column with 2 decimal places, up to `max`
+fn parse_amount(max: Decimal) -> impl Fn(&str) -> Result<Decimal, String> {
+    move |s| match Decimal::from_str(s.trim_start_matches('$')) {
+        Ok(n) if n.is_sign_negative() => Err("can't be negative".to_string()),
+        Ok(n) if n > max => Err(format!("can't be more than {max}")),
+        Ok(n) if n.scale() > 2 && n.normalize().scale() > 2 => {
+            Err("has more than 2 decimal places".to_string())
+        }
+        Ok(mut n) => {
+            n.rescale(2);
+            Ok(n)
+        }
+        Err(_) => Err(format!("`{s}` isn't a number")),
+    }
+}
+
+fn parse_bool(s: &str) -> Result<bool, String> {
+    match s.to_lowercase().as_str() {
+        "true" | "yes" | "y" | "1" => Ok(true),
+        "false" | "no" | "n" | "0" => Ok(false),
+        _ => Err(format!("`{s}` isn't true or false")),
+    }
+}
+
+/// Checks a record against the catalog, giving the book as it would be after the import
+fn check_record(
+    raw: &RawBook,
+    isbn: Isbn,
+    existing: Option<&Book>,
+    publishers: &[Publisher],
+) -> Result<Book, Vec<String>> {
+    let mut checker = Checker { reasons: vec![] };
+
+    let title = checker.field(
+        "Title",
+        &raw.title,
+        existing.map(|b| b.title.clone()),
+        None,
+        parse_text(50),
+    );
+    let author_name = checker.field(
+        "Author",
+        &raw.author_name,
+        existing.map(|b| b.author_name.clone()),
+        None,
+        parse_text(20),
+    );
+    let genre = checker.field(
+        "Genre",
+        &raw.genre,
+        existing.map(|b| b.genre.clone()),
+        None,
+        parse_text(20),
+    );
+
+    let publisher_by_id = |s: &str| match s.parse::<PublisherID>() {
+        Ok(id) if publishers.iter().any(|p| p.publisher_id == id) => Ok(id),
+        _ => Err(format!("no publisher with the ID `{s}`")),
+    };
+    let publisher_by_name = |s: &str| {
+        publishers
+            .iter()
+            .find(|p| p.company_name.eq_ignore_ascii_case(s))
+            .map(|p| p.publisher_id)
+            .ok_or_else(|| format!("no publisher named `{s}`"))
+    };
+    let publisher = match (&raw.publisher_id, &raw.publisher) {
+        (Some(_), _) => checker.field("Publisher", &raw.publisher_id, None, None, publisher_by_id),
+        (None, _) => checker.field(
+            "Publisher",
+            &raw.publisher,
+            existing.map(|b| b.publisher),
+            None,
+            publisher_by_name,
+        ),
+    };
+
+    let num_pages = checker.field(
+        "Pages",
+        &raw.num_pages,
+        existing.map(|b| b.num_pages),
+        None,
+        parse_count(1),
+    );
+    let price = checker.field(
+        "Price",
+        &raw.price,
+        existing.map(|b| b.price),
+        None,
+        parse_amount(Decimal::new(999999, 2)),
+    );
+    let author_royalties = checker.field(
+        "Author royalties",
+        &raw.author_royalties,
+        existing.map(|b| b.author_royalties),
+        Some(Decimal::ZERO),
+        parse_amount(Decimal::ONE),
+    );
+    let reorder_threshold = checker.field(
+        "Reorder threshold",
+        &raw.reorder_threshold,
+        existing.map(|b| b.reorder_threshold),
+        Some(0),
+        parse_count(0),
+    );
+    let stock = checker.field(
+        "Stock",
+        &raw.stock,
+        existing.map(|b| b.stock),
+        Some(0),
+        parse_count(0),
+    );
+    let discontinued = checker.field(
+        "Discontinued",
+        &raw.discontinued,
+        existing.map(|b| b.discontinued),
+        Some(false),
+        parse_bool,
+    );
+
+    match (
+        title,
+        author_name,
+        genre,
+        publisher,
+        num_pages,
+        price,
+        author_royalties,
+        reorder_threshold,
+        stock,
+        discontinued,
+    ) {
+        (
+            Some(title),
+            Some(author_name),
+            Some(genre),
+            Some(publisher),
+            Some(num_pages),
+            Some(price),
+            Some(author_royalties),
+            Some(reorder_threshold),
+            Some(stock),
+            Some(discontinued),
+        ) if checker.reasons.is_empty() => Ok(Book::new(
+            isbn,
+            title,
+            author_name,
+            genre,
+            publisher,
+            num_pages,
+            price,
+            author_royalties,
+            reorder_threshold,
+            stock,
+            discontinued,
+        )),
+        _ => Err(checker.reasons),
+    }
+}
+
+/// Works out what importing `records` would do, given the current books and publishers
+pub fn plan_import(
+    records: Vec<ImportRecord>,
+    books: &[Book],
+    publishers: &[Publisher],
+) -> ImportPlan {
+    let books: HashMap<Isbn, &Book> = books.iter().map(|book| (book.isbn, book)).collect();
+    let publisher_name = |id: PublisherID| {
+        publishers
+            .iter()
+            .find(|p| p.publisher_id == id)
+            .map(|p| p.company_name.clone())
+            .unwrap_or_else(|| id.to_string())
+    };
+    let mut seen: HashMap<Isbn, String> = HashMap::new();
+    let mut plan = ImportPlan::default();
+
+    for ImportRecord {
+        location,
+        book: raw,
+    } in records
+    {
+        let reject = |reasons: Vec<String>| RejectedRecord {
+            location: location.clone(),
+            isbn: raw.isbn.clone(),
+            title: raw.title.clone(),
+            reasons,
+        };
+
+        let isbn = match raw.isbn.as_deref().map(Isbn::parse) {
+            Some(Ok(isbn)) => isbn,
+            Some(Err(e)) => {
+                plan.rejected.push(reject(vec![format!("ISBN: {}", e)]));
+                continue;
+            }
+            None => {
+                plan.rejected
+                    .push(reject(vec!["ISBN is missing".to_string()]));
+                continue;
+            }
+        };
+
+        if let Some(first) = seen.get(&isbn) {
+            plan.rejected.push(reject(vec![format!(
+                "ISBN {} was already imported from {}",
+                isbn, first
+            )]));
+            continue;
+        }
+        seen.insert(isbn, location.clone());
+
+        let existing = books.get(&isbn).copied();
+        match check_record(&raw, isbn, existing, publishers) {
+            Ok(book) => {
+                let changes = existing.map(|existing| existing.changes(&book, publisher_name));
+                let planned = PlannedBook {
+                    location: location.clone(),
+                    publisher_name: publisher_name(book.publisher),
+                    book,
+                    changes: changes.clone().unwrap_or_default(),
+                };
+
+                match changes {
+                    Some(changes) if changes.is_empty() => plan.unchanged += 1,
+                    Some(_) => plan.updated_books.push(planned),
+                    None => plan.new_books.push(planned),
+                }
+            }
+            Err(reasons) => plan.rejected.push(reject(reasons)),
+        }
+    }
+
+    plan
+}
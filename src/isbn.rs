@@ -0,0 +1,378 @@
+//! ISBNs, which can be written as ISBN-10 or ISBN-13 (with or without hyphens) but are always
+//! stored and compared as ISBN-13.
+
+use std::error::Error;
+use std::fmt;
+
+use postgres::types::private::BytesMut;
+use postgres::types::{accepts, to_sql_checked, FromSql, IsNull, ToSql, Type};
+use rocket::http::impl_from_uri_param_identity;
+use rocket::http::uri::fmt::{Formatter, Path, UriDisplay};
+use rocket::request::FromParam;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum IsbnError {
+    #[error("An ISBN has 10 or 13 digits, but `{0}` has {1}")]
+    WrongLength(String, usize),
+    #[error("`{0}` has characters that can't be in an ISBN")]
+    InvalidCharacter(String),
+    #[error("`{0}` has the wrong check digit")]
+    WrongCheckDigit(String),
+    #[error("An ISBN-13 starts with 978 or 979, but `{0}` doesn't")]
+    WrongPrefix(String),
+}
+
+/// A valid ISBN, held as the 13 digit number of its ISBN-13
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Isbn(i64);
+
+/// The ISBN-13 check digit for its first 12 digits, which are weighted 1, 3, 1, 3, ...
+fn isbn13_check_digit(digits: &[u32]) -> u32 {
+    let sum: u32 = digits
+        .iter()
+        .enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { *digit } else { digit * 3 })
+        .sum();
+
+    (10 - sum % 10) % 10
+}
+
+/// The ISBN-10 check digit for its first 9 digits, where 10 is written as X
+fn isbn10_check_digit(digits: &[u32]) -> char {
+    let sum: u32 = digits
+        .iter()
+        .enumerate()
+        .map(|(i, digit)| (10 - i as u32) * digit)
+        .sum();
+
+    match (11 - sum % 11) % 11 {
+        10 => 'X',
+        check => char::from_digit(check, 10).unwrap(),
+    }
+}
+
+impl Isbn {
+    /// Parses an ISBN-10 or ISBN-13, ignoring hyphens and spaces
+    pub fn parse(s: &str) -> Result<Isbn, IsbnError> {
+        let chars: Vec<char> = s.chars().filter(|c| *c != '-' && *c != ' ').collect();
+
+        match chars.len() {
+            10 => {
+                let digits = chars[..9]
+                    .iter()
+                    .map(|c| c.to_digit(10))
+                    .collect::<Option<Vec<u32>>>()
+                    .ok_or_else(|| IsbnError::InvalidCharacter(s.to_string()))?;
+                let check = chars[9].to_ascii_uppercase();
+                if !check.is_ascii_digit() && check != 'X' {
+                    return Err(IsbnError::InvalidCharacter(s.to_string()));
+                }
+                if isbn10_check_digit(&digits) != check {
+                    return Err(IsbnError::WrongCheckDigit(s.to_string()));
+                }
+
+                let mut isbn13 = vec![9, 7, 8];
+                isbn13.extend(digits);
+                isbn13.push(isbn13_check_digit(&isbn13));
+
+                Ok(Isbn(digits_to_number(&isbn13)))
+            }
+            13 => {
+                let digits = chars
+                    .iter()
+                    .map(|c| c.to_digit(10))
+                    .collect::<Option<Vec<u32>>>()
+                    .ok_or_else(|| IsbnError::InvalidCharacter(s.to_string()))?;
+                if digits[..3] != [9, 7, 8] && digits[..3] != [9, 7, 9] {
+                    return Err(IsbnError::WrongPrefix(s.to_string()));
+                }
+                if isbn13_check_digit(&digits[..12]) != digits[12] {
+                    return Err(IsbnError::WrongCheckDigit(s.to_string()));
+                }
+
+                Ok(Isbn(digits_to_number(&digits)))
+            }
+            len => Err(IsbnError::WrongLength(s.to_string(), len)),
+        }
+    }
+
+    /// The 13 digits of the ISBN-13, without hyphens
+    pub fn isbn13(&self) -> String {
+        format!("{:013}", self.0)
+    }
+
+    /// The ISBN-10, which only ISBNs starting with 978 have
+    pub fn isbn10(&self) -> Option<String> {
+        let isbn13 = self.isbn13();
+        let body = isbn13.strip_prefix("978")?;
+        let digits: Vec<u32> = body[..9].chars().flat_map(|c| c.to_digit(10)).collect();
+
+        Some(format!("{}{}", &body[..9], isbn10_check_digit(&digits)))
+    }
+}
+
+fn digits_to_number(digits: &[u32]) -> i64 {
+    digits
+        .iter()
+        .fold(0, |number, digit| number * 10 + *digit as i64)
+}
+
+impl TryFrom<i64> for Isbn {
+    type Error = IsbnError;
+
+    /// Checks a number read back as an ISBN-13 is a valid one
+    fn try_from(number: i64) -> Result<Isbn, IsbnError> {
+        let digits = format!("{number:013}");
+
+        // Parsing skips hyphens, so a minus sign has to be caught here
+        if number < 0 {
+            return Err(IsbnError::InvalidCharacter(digits));
+        }
+
+        Isbn::parse(&digits)
+    }
+}
+
+impl fmt::Display for Isbn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.isbn13())
+    }
+}
+
+impl std::str::FromStr for Isbn {
+    type Err = IsbnError;
+
+    fn from_str(s: &str) -> Result<Isbn, IsbnError> {
+        Isbn::parse(s)
+    }
+}
+
+impl ToSql for Isbn {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        self.0.to_sql(ty, out)
+    }
+
+    accepts!(INT8);
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Isbn {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Isbn, Box<dyn Error + Sync + Send>> {
+        Ok(Isbn::try_from(i64::from_sql(ty, raw)?)?)
+    }
+
+    accepts!(INT8);
+}
+
+impl<'a> FromParam<'a> for Isbn {
+    type Error = IsbnError;
+
+    fn from_param(param: &'a str) -> Result<Isbn, IsbnError> {
+        Isbn::parse(param)
+    }
+}
+
+impl UriDisplay<Path> for Isbn {
+    fn fmt(&self, f: &mut Formatter<'_, Path>) -> fmt::Result {
+        f.write_value(self.isbn13())
+    }
+}
+
+impl_from_uri_param_identity!([Path] Isbn);
+
+/// ISBNs are written out as strings, since JavaScript can't be trusted with 13 digit numbers
+impl Serialize for Isbn {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.isbn13())
+    }
+}
+
+struct IsbnVisitor;
+
+impl<'de> Visitor<'de> for IsbnVisitor {
+    type Value = Isbn;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an ISBN-10 or ISBN-13")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Isbn, E> {
+        Isbn::parse(s).map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, number: i64) -> Result<Isbn, E> {
+        Isbn::try_from(number).map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, number: u64) -> Result<Isbn, E> {
+        let number = i64::try_from(number).map_err(E::custom)?;
+        Isbn::try_from(number).map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Isbn {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Isbn, D::Error> {
+        deserializer.deserialize_any(IsbnVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_an_isbn13() {
+        let isbn = Isbn::parse("9780306406157").unwrap();
+
+        assert_eq!(isbn.isbn13(), "9780306406157");
+        assert_eq!(isbn.to_string(), "9780306406157");
+    }
+
+    #[test]
+    fn converts_an_isbn10_to_isbn13() {
+        assert_eq!(Isbn::parse("0306406152").unwrap().isbn13(), "9780306406157");
+        assert_eq!(Isbn::parse("0131103628").unwrap().isbn13(), "9780131103627");
+    }
+
+    #[test]
+    fn accepts_an_x_check_digit_in_either_case() {
+        assert_eq!(Isbn::parse("080442957X").unwrap().isbn13(), "9780804429573");
+        assert_eq!(Isbn::parse("080442957x").unwrap().isbn13(), "9780804429573");
+    }
+
+    #[test]
+    fn ignores_hyphens_and_spaces() {
+        let isbn = Isbn::parse("9780306406157").unwrap();
+
+        assert_eq!(Isbn::parse("978-0-306-40615-7"), Ok(isbn));
+        assert_eq!(Isbn::parse("978 0 306 40615 7"), Ok(isbn));
+        assert_eq!(Isbn::parse("0-306-40615-2"), Ok(isbn));
+        assert_eq!(Isbn::parse(" 0 306-40615 2 "), Ok(isbn));
+    }
+
+    #[test]
+    fn gives_back_the_isbn10() {
+        assert_eq!(
+            Isbn::parse("9780306406157").unwrap().isbn10().as_deref(),
+            Some("0306406152")
+        );
+        assert_eq!(
+            Isbn::parse("9780804429573").unwrap().isbn10().as_deref(),
+            Some("080442957X")
+        );
+    }
+
+    #[test]
+    fn a_979_isbn_has_no_isbn10() {
+        let isbn = Isbn::parse("979-10-90636-07-1").unwrap();
+
+        assert_eq!(isbn.isbn13(), "9791090636071");
+        assert_eq!(isbn.isbn10(), None);
+    }
+
+    #[test]
+    fn rejects_wrong_check_digits() {
+        assert_eq!(
+            Isbn::parse("9780306406158"),
+            Err(IsbnError::WrongCheckDigit("9780306406158".to_string()))
+        );
+        assert_eq!(
+            Isbn::parse("0306406153"),
+            Err(IsbnError::WrongCheckDigit("0306406153".to_string()))
+        );
+        assert_eq!(
+            Isbn::parse("0306406159X"),
+            Err(IsbnError::WrongLength("0306406159X".to_string(), 11))
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_lengths() {
+        assert_eq!(
+            Isbn::parse("978030640615"),
+            Err(IsbnError::WrongLength("978030640615".to_string(), 12))
+        );
+        assert_eq!(
+            Isbn::parse("97803064061570"),
+            Err(IsbnError::WrongLength("97803064061570".to_string(), 14))
+        );
+        assert_eq!(
+            Isbn::parse(""),
+            Err(IsbnError::WrongLength(String::new(), 0))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_characters() {
+        assert_eq!(
+            Isbn::parse("03064061X2"),
+            Err(IsbnError::InvalidCharacter("03064061X2".to_string()))
+        );
+        assert_eq!(
+            Isbn::parse("978030640615X"),
+            Err(IsbnError::InvalidCharacter("978030640615X".to_string()))
+        );
+        assert_eq!(
+            Isbn::parse("030640615Y"),
+            Err(IsbnError::InvalidCharacter("030640615Y".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_other_ean_prefixes() {
+        // A valid EAN-13, but not a book
+        assert_eq!(
+            Isbn::parse("4006381333931"),
+            Err(IsbnError::WrongPrefix("4006381333931".to_string()))
+        );
+    }
+
+    #[test]
+    fn round_trips_through_i64() {
+        for s in ["9780306406157", "9780804429573", "9791090636071"] {
+            let isbn = Isbn::parse(s).unwrap();
+            let number: i64 = s.parse().unwrap();
+
+            assert_eq!(isbn.0, number);
+            assert_eq!(Isbn::try_from(number), Ok(isbn));
+        }
+    }
+
+    #[test]
+    fn rejects_numbers_that_are_not_isbn13s() {
+        assert!(matches!(
+            Isbn::try_from(306406152),
+            Err(IsbnError::WrongPrefix(_))
+        ));
+        assert!(matches!(
+            Isbn::try_from(9780306406158),
+            Err(IsbnError::WrongCheckDigit(_))
+        ));
+        assert!(matches!(
+            Isbn::try_from(-9780306406157),
+            Err(IsbnError::InvalidCharacter(_))
+        ));
+        assert!(Isbn::try_from(19780306406157).is_err());
+    }
+
+    #[test]
+    fn serialises_as_a_string_and_reads_strings_or_numbers() {
+        let isbn = Isbn::parse("9780306406157").unwrap();
+
+        assert_eq!(serde_json::to_string(&isbn).unwrap(), "\"9780306406157\"");
+        assert_eq!(
+            serde_json::from_str::<Isbn>("\"0-306-40615-2\"").unwrap(),
+            isbn
+        );
+        assert_eq!(serde_json::from_str::<Isbn>("9780306406157").unwrap(), isbn);
+        assert!(serde_json::from_str::<Isbn>("9780306406158").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! Transactional email: the templates emails are rendered from, and the transports that deliver
+//! them. Emails are queued in the outbox alongside whatever caused them, then rendered and sent
+//! in the background.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::{Message, SmtpTransport, Transport};
+use rand::RngCore;
+use rocket_dyn_templates::tera::{Context, Tera};
+use thiserror::Error;
+
+use crate::schema::entities::PostgresInt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MailTemplate {
+    Welcome,
+    OrderConfirmation,
+    OrderStatus,
+    PasswordReset,
+    VerifyEmail,
+    /// Sent by the mail notifier rather than through the outbox
+    BackInStock,
+}
+
+impl MailTemplate {
+    /// The code stored in `base.mail_outbox.template`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailTemplate::Welcome => "WELCOME",
+            MailTemplate::OrderConfirmation => "ORDER_CONFIRMATION",
+            MailTemplate::OrderStatus => "ORDER_STATUS",
+            MailTemplate::PasswordReset => "PASSWORD_RESET",
+            MailTemplate::VerifyEmail => "VERIFY_EMAIL",
+            MailTemplate::BackInStock => "BACK_IN_STOCK",
+        }
+    }
+
+    /// The name of the Tera template the email is rendered from
+    pub fn template_name(&self) -> &'static str {
+        match self {
+            MailTemplate::Welcome => "welcome.txt.tera",
+            MailTemplate::OrderConfirmation => "order_confirmation.txt.tera",
+            MailTemplate::OrderStatus => "order_status.txt.tera",
+            MailTemplate::PasswordReset => "password_reset.txt.tera",
+            MailTemplate::VerifyEmail => "verify_email.txt.tera",
+            MailTemplate::BackInStock => "back_in_stock.txt.tera",
+        }
+    }
+}
+
+impl FromStr for MailTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MailTemplate, String> {
+        match s {
+            "WELCOME" => Ok(MailTemplate::Welcome),
+            "ORDER_CONFIRMATION" => Ok(MailTemplate::OrderConfirmation),
+            "ORDER_STATUS" => Ok(MailTemplate::OrderStatus),
+            "PASSWORD_RESET" => Ok(MailTemplate::PasswordReset),
+            "VERIFY_EMAIL" => Ok(MailTemplate::VerifyEmail),
+            "BACK_IN_STOCK" => Ok(MailTemplate::BackInStock),
+            _ => Err(format!("Unknown mail template: {s}")),
+        }
+    }
+}
+
+/// An email waiting in the outbox
+#[derive(Clone, Debug)]
+pub struct OutgoingMail {
+    pub mail_id: PostgresInt,
+    pub template: MailTemplate,
+    pub recipient_name: Option<String>,
+    pub recipient_email: String,
+    pub context: serde_json::Value,
+    /// The account token the email sends, whose link is added to the context when it's sent
+    pub account_token_id: Option<PostgresInt>,
+}
+
+#[derive(Debug, Error)]
+pub enum MailError {
+    #[error("Could not render the email: {0}")]
+    Template(#[from] rocket_dyn_templates::tera::Error),
+    #[error("The template `{0}` must start with a `Subject:` line")]
+    MissingSubject(&'static str),
+    #[error("Invalid email address: {0}")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("Could not build the email: {0}")]
+    Message(#[from] lettre::error::Error),
+    #[error("SMTP error: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("Could not write the email: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Renders outbox entries into emails.
+///
+/// Each template renders to a `Subject:` line, a blank line, and then the plain text body.
+pub struct Mailer {
+    templates: Tera,
+    from: Mailbox,
+}
+
+impl Mailer {
+    /// Loads every template in `template_dir`, failing if any of them are missing or don't parse
+    pub fn new<P: AsRef<Path>>(template_dir: P, from: &str) -> Result<Mailer, MailError> {
+        let mut templates = Tera::default();
+        for template in [
+            MailTemplate::Welcome,
+            MailTemplate::OrderConfirmation,
+            MailTemplate::OrderStatus,
+            MailTemplate::PasswordReset,
+            MailTemplate::VerifyEmail,
+            MailTemplate::BackInStock,
+        ] {
+            let name = template.template_name();
+            templates.add_template_file(template_dir.as_ref().join(name), Some(name))?;
+        }
+
+        Ok(Mailer {
+            templates,
+            from: from.parse()?,
+        })
+    }
+
+    pub fn render(&self, mail: &OutgoingMail) -> Result<Message, MailError> {
+        self.render_template(
+            mail.template,
+            mail.recipient_name.as_deref(),
+            &mail.recipient_email,
+            mail.context.clone(),
+        )
+    }
+
+    /// Renders an email that isn't in the outbox
+    pub fn render_template(
+        &self,
+        template: MailTemplate,
+        recipient_name: Option<&str>,
+        recipient_email: &str,
+        context: serde_json::Value,
+    ) -> Result<Message, MailError> {
+        let name = template.template_name();
+
+        let mut context = Context::from_value(context)?;
+        if let Some(recipient_name) = recipient_name {
+            context.insert("recipient_name", recipient_name);
+        }
+
+        let rendered = self.templates.render(name, &context)?;
+        let (subject, body) = rendered
+            .split_once('\n')
+            .and_then(|(first, rest)| Some((first.strip_prefix("Subject:")?.trim(), rest)))
+            .ok_or(MailError::MissingSubject(name))?;
+
+        let to = Mailbox::new(recipient_name.map(str::to_string), recipient_email.parse()?);
+
+        Ok(Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body.trim_start().to_string())?)
+    }
+}
+
+/// Delivers rendered emails.
+///
+/// Calls are synchronous and may block, and an email that fails is tried again later.
+pub trait MailTransport: Send + Sync {
+    fn send(&self, message: &Message) -> Result<(), MailError>;
+}
+
+/// Sends emails through an SMTP server
+pub struct SmtpMailTransport {
+    transport: SmtpTransport,
+}
+
+impl SmtpMailTransport {
+    pub fn new(transport: SmtpTransport) -> SmtpMailTransport {
+        SmtpMailTransport { transport }
+    }
+}
+
+impl MailTransport for SmtpMailTransport {
+    fn send(&self, message: &Message) -> Result<(), MailError> {
+        self.transport.send(message)?;
+
+        Ok(())
+    }
+}
+
+/// Delivers emails into a local maildir, for trying things out without sending anything.
+///
+/// Each email is written to `tmp/` and then moved into `new/`, so mail readers never see one
+/// half written.
+pub struct MaildirTransport {
+    path: PathBuf,
+}
+
+impl MaildirTransport {
+    pub fn new<T: Into<PathBuf>>(path: T) -> Result<MaildirTransport, MailError> {
+        let path = path.into();
+        for dir in ["tmp", "new", "cur"] {
+            fs::create_dir_all(path.join(dir))?;
+        }
+
+        Ok(MaildirTransport { path })
+    }
+}
+
+impl MailTransport for MaildirTransport {
+    fn send(&self, message: &Message) -> Result<(), MailError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let file_name = format!(
+            "{}.M{}P{}R{:016x}.lookinnabook",
+            timestamp.as_secs(),
+            timestamp.subsec_micros(),
+            std::process::id(),
+            rand::thread_rng().next_u64()
+        );
+
+        let tmp = self.path.join("tmp").join(&file_name);
+        fs::write(&tmp, message.formatted())?;
+        fs::rename(&tmp, self.path.join("new").join(&file_name))?;
+
+        Ok(())
+    }
+}
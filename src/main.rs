@@ -2,30 +2,67 @@
 #[macro_use]
 extern crate rocket;
 
+mod api;
+mod config;
+mod csv;
 mod db;
 mod endpoints;
+mod export;
+mod import;
+mod isbn;
+mod mail;
+mod notify;
+mod onix;
+mod payment;
+mod promotion;
 mod request_guards;
 mod schema;
 
 use std::sync::Arc;
 
+use config::{
+    AccountConfig, MailConfig, NotificationConfig, PaymentConfig, PriceConfig, ReservationConfig,
+    RestockPolicy, SessionConfig,
+};
 use db::conn::DbConn;
 use endpoints::*;
-use rocket::{fs::FileServer, futures::lock::Mutex};
+use rocket::{fs::FileServer, Build, Rocket};
 use rocket_dyn_templates::Template;
 
-use request_guards::state::SessionTokens;
+use mail::MailTransport;
+use notify::Notifier;
+use payment::PaymentProvider;
+use request_guards::state::SessionStore;
 
-pub type SessionTokenState = Arc<Mutex<SessionTokens>>;
+pub type SessionStoreState = Arc<dyn SessionStore>;
+pub type PaymentProviderState = Arc<dyn PaymentProvider>;
+pub type NotifierState = Arc<dyn Notifier>;
+pub type MailTransportState = Arc<dyn MailTransport>;
 
-#[launch]
-fn rocket() -> _ {
+#[rocket::main]
+async fn main() {
+    let command = std::env::args().nth(1);
+
+    // As with `#[launch]`, Rocket reports any error when it is dropped
+    let _ = match command.as_deref() {
+        // Igniting runs the migrations fairing without serving any requests
+        Some("migrate") => rocket().ignite().await.map(|_| ()),
+        Some(command) => {
+            eprintln!("Unknown command `{command}`, expected `migrate` or nothing");
+            std::process::exit(2);
+        }
+        None => rocket().launch().await,
+    };
+}
+
+fn rocket() -> Rocket<Build> {
     rocket::build()
         .mount(
             "/",
             routes![
                 book,
                 index,
+                search_suggestions,
                 login,
                 login_page,
                 login_failed,
@@ -34,25 +71,80 @@ fn rocket() -> _ {
                 register,
                 register_page,
                 register_failed,
+                forgot_password_page,
+                forgot_password_req,
+                owner_forgot_password_page,
+                owner_forgot_password_req,
+                reset_password_page,
+                reset_password_req,
+                verify_email_page,
+                resend_verification,
                 customer_cart_page,
                 customer_cart_add,
                 customer_cart_set_quantity,
                 account_logout,
                 checkout_page,
                 create_order_req,
+                quote_promotion_endpoint,
                 orders_page,
                 view_order,
+                cancel_order_req,
                 owner_login_page,
                 owner_login,
                 book_management,
+                export_books_endpoint,
                 discontinue_books_endpoint,
                 undiscontinue_books_endpoint,
+                restock_orders_page,
+                approve_restock_order,
+                receive_restock_order,
+                cancel_restock_order,
+                fulfilment_page,
+                update_order_status,
+                collection_page,
+                collections_page,
+                create_collection_endpoint,
+                rename_collection_endpoint,
+                reorder_collections_endpoint,
+                delete_collection_endpoint,
+                add_collection_book_endpoint,
+                remove_collection_book_endpoint,
+                wishlists_page,
+                shared_wishlist_page,
+                create_wishlist_endpoint,
+                rename_wishlist_endpoint,
+                delete_wishlist_endpoint,
+                add_wishlist_book_endpoint,
+                remove_wishlist_book_endpoint,
+                subscribe_to_stock_endpoint,
+                unsubscribe_from_stock_endpoint,
                 create_publisher_page,
                 create_publisher,
                 sales_report_image,
+                royalties_page,
+                royalty_statement_csv,
+                create_royalty_payout,
+                royalty_payout_csv,
+                publisher_settlements_page,
+                publisher_statement_page,
+                publisher_statement_csv,
+                create_publisher_remittance_endpoint,
+                update_remittance_status,
                 reports_page,
+                promotion_sales_page,
                 create_book_page,
                 create_book_endpoint,
+                edit_book_page,
+                edit_book_endpoint,
+                schedule_price_endpoint,
+                cancel_price_endpoint,
+                promotions_page,
+                create_promotion_endpoint,
+                set_promotion_active_endpoint,
+                catalog_imports_page,
+                upload_catalog_import,
+                catalog_import_page,
+                apply_catalog_import_endpoint,
                 manage_accounts,
                 delete_owner_page,
                 delete_customer_page,
@@ -63,8 +155,18 @@ fn rocket() -> _ {
                 create_owner,
             ],
         )
+        .mount(api::BASE, api::routes())
+        .register(api::BASE, api::catchers())
         .mount("/style", FileServer::from("style/"))
-        .manage(SessionTokenState::new(Mutex::new(SessionTokens::new())))
         .attach(DbConn::fairing())
+        .attach(db::migrations::fairing())
+        .attach(SessionConfig::fairing())
+        .attach(RestockPolicy::fairing())
+        .attach(PaymentConfig::fairing())
+        .attach(PriceConfig::fairing())
+        .attach(ReservationConfig::fairing())
+        .attach(MailConfig::fairing())
+        .attach(NotificationConfig::fairing())
+        .attach(AccountConfig::fairing())
         .attach(Template::fairing())
 }
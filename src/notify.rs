@@ -0,0 +1,171 @@
+//! Notifications sent to customers, such as a book they were waiting on being back in stock,
+//! and the notifiers that deliver them.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use thiserror::Error;
+
+use crate::mail::{MailError, MailTemplate, Mailer};
+use crate::schema::entities::{Isbn, PostgresInt};
+use crate::MailTransportState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotificationKind {
+    BackInStock,
+}
+
+impl NotificationKind {
+    /// The code stored in `base.notification.kind`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::BackInStock => "BACK_IN_STOCK",
+        }
+    }
+}
+
+impl FromStr for NotificationKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NotificationKind, String> {
+        match s {
+            "BACK_IN_STOCK" => Ok(NotificationKind::BackInStock),
+            _ => Err(format!("Unknown notification kind: {s}")),
+        }
+    }
+}
+
+/// A queued notification, along with who it's for and the book it's about
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub notification_id: PostgresInt,
+    pub kind: NotificationKind,
+    pub name: String,
+    pub email: String,
+    pub isbn: Isbn,
+    pub title: String,
+}
+
+impl Notification {
+    pub fn subject(&self) -> String {
+        match self.kind {
+            NotificationKind::BackInStock => format!("{} is back in stock", self.title),
+        }
+    }
+
+    pub fn body(&self) -> String {
+        match self.kind {
+            NotificationKind::BackInStock => format!(
+                "Hi {},\n\n{} (ISBN {}) is back in stock at Look Inna Book. You asked us to let you know, so you can order it before it sells out again.",
+                self.name, self.title, self.isbn
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum NotifyError {
+    #[error("Could not write the notification: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not email the notification: {0}")]
+    Mail(#[from] MailError),
+}
+
+/// Delivers notifications to customers.
+///
+/// Calls are synchronous, and a notification that fails is tried again later.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, notification: &Notification) -> Result<(), NotifyError>;
+}
+
+/// Writes notifications to the application log instead of sending them anywhere
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        info!(
+            "Notification for {} <{}>: {}",
+            notification.name,
+            notification.email,
+            notification.subject()
+        );
+
+        Ok(())
+    }
+}
+
+/// Appends notifications to a local file, one block of text each, for trying things out
+/// without sending anything
+pub struct FileNotifier {
+    path: PathBuf,
+    /// Keeps notifications written at the same time from interleaving
+    lock: Mutex<()>,
+}
+
+impl FileNotifier {
+    pub fn new<T: Into<PathBuf>>(path: T) -> FileNotifier {
+        FileNotifier {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl Notifier for FileNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        writeln!(
+            file,
+            "To: {} <{}>\nSubject: {}\n\n{}\n",
+            notification.name,
+            notification.email,
+            notification.subject(),
+            notification.body()
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Emails notifications with the same templates and transport as the mail outbox. Notifications
+/// are already queued and retried, so they're sent straight away rather than through the outbox.
+pub struct MailNotifier {
+    mailer: Arc<Mailer>,
+    transport: MailTransportState,
+}
+
+impl MailNotifier {
+    pub fn new(mailer: Arc<Mailer>, transport: MailTransportState) -> MailNotifier {
+        MailNotifier { mailer, transport }
+    }
+}
+
+impl Notifier for MailNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let template = match notification.kind {
+            NotificationKind::BackInStock => MailTemplate::BackInStock,
+        };
+
+        let message = self.mailer.render_template(
+            template,
+            Some(&notification.name),
+            &notification.email,
+            serde_json::json!({
+                "isbn": notification.isbn,
+                "title": notification.title,
+            }),
+        )?;
+        self.transport.send(&message)?;
+
+        Ok(())
+    }
+}
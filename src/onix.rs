@@ -0,0 +1,509 @@
+//! Reads the books out of an ONIX 3.0 feed, the XML format publishers send catalog data in.
+//!
+//! Only the reference tag names (`<Product>`, not `<product>`) are understood, and only the
+//! handful of composites the catalog has columns for are looked at.
+
+use thiserror::Error;
+
+use crate::import::RawBook;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum OnixError {
+    #[error("Invalid XML on line {0}: {1}")]
+    Xml(usize, String),
+    #[error("Expected an ONIX 3.0 <ONIXMessage>, but the document is a <{0}>")]
+    NotOnix(String),
+}
+
+/// An XML element, keeping only what ONIX needs: child elements and the text directly inside
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// The trimmed text of the first `name` child, if it has any
+    fn child_text(&self, name: &str) -> Option<String> {
+        self.child(name)
+            .map(|child| child.text.trim().to_string())
+            .filter(|text| !text.is_empty())
+    }
+}
+
+/// Drops any namespace prefix, since feeds differ on whether they use one
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn decode_entities(text: &str, line: usize) -> Result<String, OnixError> {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(';')
+            .ok_or_else(|| OnixError::Xml(line, "unterminated entity".to_string()))?
+            + start;
+        let entity = &rest[start + 1..end];
+
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+            {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => entity
+                    .strip_prefix('#')
+                    .and_then(|dec| dec.parse().ok())
+                    .and_then(char::from_u32),
+            },
+        };
+        decoded
+            .push(c.ok_or_else(|| OnixError::Xml(line, format!("unknown entity `&{entity};`")))?);
+        rest = &rest[end + 1..];
+    }
+    decoded.push_str(rest);
+
+    Ok(decoded)
+}
+
+/// Parses an XML document into its root element
+fn parse_xml(xml: &str) -> Result<Element, OnixError> {
+    let mut stack: Vec<Element> = vec![];
+    let mut root = None;
+    let mut rest = xml.trim_start_matches('\u{feff}');
+    let line_of = |rest: &str| xml[..xml.len() - rest.len()].matches('\n').count() + 1;
+
+    let skip_past = |rest: &str, end: &str| -> Result<usize, OnixError> {
+        rest.find(end)
+            .map(|i| i + end.len())
+            .ok_or_else(|| OnixError::Xml(line_of(rest), format!("missing `{end}`")))
+    };
+
+    while !rest.is_empty() {
+        let line = line_of(rest);
+
+        if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = skip_past(after, "]]>")?;
+            match stack.last_mut() {
+                Some(parent) => parent.text.push_str(&after[..end - 3]),
+                None => return Err(OnixError::Xml(line, "text outside the root".to_string())),
+            }
+            rest = &after[end..];
+        } else if rest.starts_with("<!--") {
+            rest = &rest[skip_past(rest, "-->")?..];
+        } else if rest.starts_with("<?") {
+            rest = &rest[skip_past(rest, "?>")?..];
+        } else if rest.starts_with("<!") {
+            // A DOCTYPE, possibly with an internal subset in brackets
+            let end = match (rest.find('['), rest.find('>')) {
+                (Some(open), Some(close)) if open < close => skip_past(rest, "]>")?,
+                _ => skip_past(rest, ">")?,
+            };
+            rest = &rest[end..];
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = skip_past(after, ">")?;
+            let name = local_name(after[..end - 1].trim());
+            let element = stack
+                .pop()
+                .filter(|element| element.name == name)
+                .ok_or_else(|| OnixError::Xml(line, format!("unexpected `</{name}>`")))?;
+
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(element),
+                None => root = Some(element),
+            }
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('<') {
+            // Attributes are skipped, but a quoted `>` mustn't end the tag early
+            let mut quote = None;
+            let end = after
+                .char_indices()
+                .find(|(_, c)| match quote {
+                    Some(q) if *c == q => {
+                        quote = None;
+                        false
+                    }
+                    Some(_) => false,
+                    None if *c == '"' || *c == '\'' => {
+                        quote = Some(*c);
+                        false
+                    }
+                    None => *c == '>',
+                })
+                .map(|(i, _)| i)
+                .ok_or_else(|| OnixError::Xml(line, "unterminated tag".to_string()))?;
+            let tag = &after[..end];
+            let self_closing = tag.ends_with('/');
+            let name = local_name(
+                tag.trim_end_matches('/')
+                    .split(|c: char| c.is_whitespace())
+                    .next()
+                    .unwrap_or(""),
+            );
+            if name.is_empty() {
+                return Err(OnixError::Xml(line, "tag without a name".to_string()));
+            }
+            if root.is_some() {
+                return Err(OnixError::Xml(
+                    line,
+                    "more than one root element".to_string(),
+                ));
+            }
+
+            let element = Element {
+                name: name.to_string(),
+                ..Element::default()
+            };
+            match (self_closing, stack.last_mut()) {
+                (true, Some(parent)) => parent.children.push(element),
+                (true, None) => root = Some(element),
+                (false, _) => stack.push(element),
+            }
+            rest = &after[end + 1..];
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = decode_entities(&rest[..end], line)?;
+            match stack.last_mut() {
+                Some(parent) => parent.text.push_str(&text),
+                None if text.trim().is_empty() => {}
+                None => return Err(OnixError::Xml(line, "text outside the root".to_string())),
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    match (stack.pop(), root) {
+        (Some(unclosed), _) => Err(OnixError::Xml(
+            line_of(""),
+            format!("`<{}>` is never closed", unclosed.name),
+        )),
+        (None, Some(root)) => Ok(root),
+        (None, None) => Err(OnixError::Xml(1, "no root element".to_string())),
+    }
+}
+
+/// The ISBN from the product's identifiers, preferring ISBN-13 (15) over GTIN-13 (03) and ISBN-10 (02)
+fn product_isbn(product: &Element) -> Option<String> {
+    ["15", "03", "02"].iter().find_map(|id_type| {
+        product
+            .children("ProductIdentifier")
+            .find(|identifier| identifier.child_text("ProductIDType").as_deref() == Some(id_type))
+            .and_then(|identifier| identifier.child_text("IDValue"))
+    })
+}
+
+/// The distinctive title (title type 01) at the product level (element level 01)
+fn product_title(descriptive: &Element) -> Option<String> {
+    let title = descriptive
+        .children("TitleDetail")
+        .find(|detail| detail.child_text("TitleType").as_deref() == Some("01"))?
+        .children("TitleElement")
+        .find(|element| element.child_text("TitleElementLevel").as_deref() == Some("01"))?;
+
+    title.child_text("TitleText").or_else(|| {
+        let without_prefix = title.child_text("TitleWithoutPrefix")?;
+        Some(match title.child_text("TitlePrefix") {
+            Some(prefix) => format!("{prefix} {without_prefix}"),
+            None => without_prefix,
+        })
+    })
+}
+
+/// The first contributor credited as author (role A01)
+fn product_author(descriptive: &Element) -> Option<String> {
+    let author = descriptive
+        .children("Contributor")
+        .find(|contributor| contributor.child_text("ContributorRole").as_deref() == Some("A01"))?;
+
+    author
+        .child_text("PersonName")
+        .or_else(|| {
+            let key_names = author.child_text("KeyNames")?;
+            Some(match author.child_text("NamesBeforeKey") {
+                Some(names) => format!("{names} {key_names}"),
+                None => key_names,
+            })
+        })
+        .or_else(|| author.child_text("CorporateName"))
+}
+
+/// The main subject's heading if one is marked, otherwise the first heading given
+fn product_genre(descriptive: &Element) -> Option<String> {
+    descriptive
+        .children("Subject")
+        .find(|subject| subject.child("MainSubject").is_some())
+        .and_then(|subject| subject.child_text("SubjectHeadingText"))
+        .or_else(|| {
+            descriptive
+                .children("Subject")
+                .find_map(|subject| subject.child_text("SubjectHeadingText"))
+        })
+}
+
+/// The page count, from the main content extent (type 00) if there is one
+fn product_pages(descriptive: &Element) -> Option<String> {
+    let page_extents: Vec<&Element> = descriptive
+        .children("Extent")
+        .filter(|extent| extent.child_text("ExtentUnit").as_deref() == Some("03"))
+        .collect();
+
+    page_extents
+        .iter()
+        .find(|extent| extent.child_text("ExtentType").as_deref() == Some("00"))
+        .or_else(|| page_extents.first())
+        .and_then(|extent| extent.child_text("ExtentValue"))
+}
+
+fn product_publisher(product: &Element) -> Option<String> {
+    product
+        .child("PublishingDetail")?
+        .children("Publisher")
+        .find(|publisher| {
+            publisher
+                .child_text("PublishingRole")
+                .is_none_or(|role| role == "01")
+        })?
+        .child_text("PublisherName")
+}
+
+/// Whether the product is withdrawn: deleted by the notification, out of print, or unavailable
+fn product_discontinued(product: &Element) -> Option<String> {
+    if product.child_text("NotificationType").as_deref() == Some("05") {
+        return Some("true".to_string());
+    }
+
+    let publishing_status = product
+        .child("PublishingDetail")
+        .and_then(|detail| detail.child_text("PublishingStatus"));
+    let availability = product
+        .child("ProductSupply")
+        .and_then(|supply| supply.child("SupplyDetail"))
+        .and_then(|detail| detail.child_text("ProductAvailability"));
+
+    match (publishing_status.as_deref(), availability.as_deref()) {
+        (Some("06" | "07" | "08"), _) => Some("true".to_string()),
+        (_, Some(code)) if code.starts_with('4') => Some("true".to_string()),
+        (None, None) => None,
+        _ => Some("false".to_string()),
+    }
+}
+
+/// Reads each `<Product>` in the feed, returning its position (counting from 1) with the book
+pub fn read_onix(xml: &str) -> Result<Vec<(usize, RawBook)>, OnixError> {
+    let root = parse_xml(xml)?;
+    if root.name != "ONIXMessage" {
+        return Err(OnixError::NotOnix(root.name));
+    }
+
+    Ok(root
+        .children("Product")
+        .enumerate()
+        .map(|(i, product)| {
+            let descriptive = product.child("DescriptiveDetail");
+            let supply = product
+                .child("ProductSupply")
+                .and_then(|supply| supply.child("SupplyDetail"));
+
+            let book = RawBook {
+                isbn: product_isbn(product),
+                title: descriptive.and_then(product_title),
+                author_name: descriptive.and_then(product_author),
+                genre: descriptive.and_then(product_genre),
+                publisher: product_publisher(product),
+                num_pages: descriptive.and_then(product_pages),
+                price: supply
+                    .and_then(|supply| supply.child("Price"))
+                    .and_then(|price| price.child_text("PriceAmount")),
+                // The feed's `<Stock>` is what the publisher has in their warehouse, not ours,
+                // so the store's own stock is left alone
+                discontinued: product_discontinued(product),
+                ..RawBook::default()
+            };
+
+            (i + 1, book)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nested_elements_and_text() {
+        let root = parse_xml("<a><b>one</b><b>two</b><c/></a>").unwrap();
+
+        assert_eq!(root.name, "a");
+        let texts: Vec<String> = root.children("b").map(|b| b.text.clone()).collect();
+        assert_eq!(texts, ["one", "two"]);
+        assert!(root.child("c").is_some());
+    }
+
+    #[test]
+    fn decodes_entities() {
+        let root = parse_xml("<a>Tom &amp; Jerry &lt;&gt; &quot;&apos; &#233;&#xE9;</a>").unwrap();
+
+        assert_eq!(root.text, "Tom & Jerry <> \"' éé");
+    }
+
+    #[test]
+    fn rejects_unknown_and_unterminated_entities() {
+        assert!(matches!(
+            parse_xml("<a>&nbsp;</a>"),
+            Err(OnixError::Xml(1, _))
+        ));
+        assert!(matches!(
+            parse_xml("<a>AT&T</a>"),
+            Err(OnixError::Xml(1, _))
+        ));
+    }
+
+    #[test]
+    fn keeps_cdata_as_written() {
+        let root = parse_xml("<a>x <![CDATA[<b>&amp;]]]]> y</a>").unwrap();
+
+        assert_eq!(root.text, "x <b>&amp;]] y");
+        assert!(root.children.is_empty());
+    }
+
+    #[test]
+    fn skips_prolog_doctype_comments_and_instructions() {
+        let xml = "\u{feff}<?xml version=\"1.0\"?>\n\
+            <!DOCTYPE a [ <!ENTITY x \"y\"> ]>\n\
+            <!-- <b>not an element</b> -->\n\
+            <a><?pi data?>text</a>";
+
+        let root = parse_xml(xml).unwrap();
+        assert_eq!(root.name, "a");
+        assert_eq!(root.text, "text");
+        assert!(root.children.is_empty());
+    }
+
+    #[test]
+    fn drops_namespace_prefixes() {
+        let root =
+            parse_xml("<onix:ONIXMessage xmlns:onix=\"http://ns.editeur.org/onix/3.0/reference\"><onix:Product/></onix:ONIXMessage>")
+                .unwrap();
+
+        assert_eq!(root.name, "ONIXMessage");
+        assert!(root.child("Product").is_some());
+    }
+
+    #[test]
+    fn ignores_a_quoted_gt_in_attributes() {
+        let root = parse_xml("<a title=\"x > y\" other='>'><b/></a>").unwrap();
+
+        assert!(root.child("b").is_some());
+        assert_eq!(root.text, "");
+    }
+
+    #[test]
+    fn rejects_mismatched_tags() {
+        assert_eq!(
+            parse_xml("<a>\n<b>\n</a>").unwrap_err(),
+            OnixError::Xml(3, "unexpected `</a>`".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_unclosed_elements() {
+        assert!(matches!(parse_xml("<a><b></b>"), Err(OnixError::Xml(_, _))));
+    }
+
+    #[test]
+    fn rejects_text_outside_the_root() {
+        assert!(parse_xml("stray<a/>").is_err());
+        assert!(parse_xml("<a/>stray").is_err());
+        assert!(parse_xml("<a/>\n").is_ok());
+    }
+
+    #[test]
+    fn rejects_more_than_one_root() {
+        assert!(parse_xml("<a/><b/>").is_err());
+    }
+
+    #[test]
+    fn reads_a_product() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ONIXMessage release="3.0">
+  <Header/>
+  <Product>
+    <NotificationType>03</NotificationType>
+    <ProductIdentifier><ProductIDType>02</ProductIDType><IDValue>0306406152</IDValue></ProductIdentifier>
+    <ProductIdentifier><ProductIDType>15</ProductIDType><IDValue>9780306406157</IDValue></ProductIdentifier>
+    <DescriptiveDetail>
+      <TitleDetail>
+        <TitleType>01</TitleType>
+        <TitleElement>
+          <TitleElementLevel>01</TitleElementLevel>
+          <TitlePrefix>The</TitlePrefix>
+          <TitleWithoutPrefix>Optics &amp; Light</TitleWithoutPrefix>
+        </TitleElement>
+      </TitleDetail>
+      <Contributor>
+        <ContributorRole>B01</ContributorRole>
+        <PersonName>An Editor</PersonName>
+      </Contributor>
+      <Contributor>
+        <ContributorRole>A01</ContributorRole>
+        <NamesBeforeKey>Jane</NamesBeforeKey>
+        <KeyNames>Smith</KeyNames>
+      </Contributor>
+      <Extent><ExtentType>00</ExtentType><ExtentValue>320</ExtentValue><ExtentUnit>03</ExtentUnit></Extent>
+      <Subject><SubjectHeadingText>Physics</SubjectHeadingText></Subject>
+    </DescriptiveDetail>
+    <PublishingDetail>
+      <Publisher><PublishingRole>01</PublishingRole><PublisherName><![CDATA[Light & Co]]></PublisherName></Publisher>
+      <PublishingStatus>04</PublishingStatus>
+    </PublishingDetail>
+    <ProductSupply>
+      <SupplyDetail>
+        <ProductAvailability>21</ProductAvailability>
+        <Stock><OnHand>500</OnHand></Stock>
+        <Price><PriceAmount>24.99</PriceAmount></Price>
+      </SupplyDetail>
+    </ProductSupply>
+  </Product>
+</ONIXMessage>"#;
+
+        let books = read_onix(xml).unwrap();
+        assert_eq!(books.len(), 1);
+
+        let (position, book) = &books[0];
+        assert_eq!(*position, 1);
+        assert_eq!(book.isbn.as_deref(), Some("9780306406157"));
+        assert_eq!(book.title.as_deref(), Some("The Optics & Light"));
+        assert_eq!(book.author_name.as_deref(), Some("Jane Smith"));
+        assert_eq!(book.genre.as_deref(), Some("Physics"));
+        assert_eq!(book.publisher.as_deref(), Some("Light & Co"));
+        assert_eq!(book.num_pages.as_deref(), Some("320"));
+        assert_eq!(book.price.as_deref(), Some("24.99"));
+        assert_eq!(book.stock, None);
+        assert_eq!(book.discontinued.as_deref(), Some("false"));
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        assert_eq!(
+            read_onix("<html/>").unwrap_err(),
+            OnixError::NotOnix("html".to_string())
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use hmac::{Hmac, Mac, NewMac};
+use rocket::serde::Serialize;
+use rust_decimal::Decimal;
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::db::query::Expiry;
+
+/// Card details as entered by the customer. These are only ever handed to the
+/// payment provider and are never written to the database.
+#[derive(Clone, Debug)]
+pub struct CardDetails {
+    pub name_on_card: String,
+    pub expiry: Expiry,
+    pub card_number: String,
+    pub cvv: String,
+}
+
+impl CardDetails {
+    pub fn new<T: AsRef<str>>(
+        name_on_card: T,
+        expiry: Expiry,
+        card_number: T,
+        cvv: T,
+    ) -> CardDetails {
+        CardDetails {
+            name_on_card: name_on_card.as_ref().to_string(),
+            expiry,
+            // Customers often type card numbers with spaces or dashes between the groups
+            card_number: card_number
+                .as_ref()
+                .chars()
+                .filter(|c| !c.is_whitespace() && *c != '-')
+                .collect(),
+            cvv: cvv.as_ref().trim().to_string(),
+        }
+    }
+}
+
+/// What the provider hands back in place of a card number
+#[derive(Clone, Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TokenisedCard {
+    pub token: String,
+    pub last_four: String,
+    pub brand: String,
+}
+
+#[derive(Debug, Error)]
+pub enum PaymentError {
+    #[error("Invalid card number")]
+    InvalidCardNumber,
+    #[error("Invalid card CVV")]
+    InvalidCvv,
+    #[error("This card is not recognised, please enter it again")]
+    UnknownToken,
+    #[error("Payment declined: {0}")]
+    Declined(String),
+    #[error("No payment authorisation with this ID")]
+    UnknownAuthorisation,
+}
+
+/// Turns card details into opaque tokens and charges against those tokens.
+///
+/// Calls are synchronous so they can be made from inside a DB transaction.
+pub trait PaymentProvider: Send + Sync {
+    fn tokenise(&self, card: &CardDetails) -> Result<TokenisedCard, PaymentError>;
+
+    /// Authorises a charge of `amount` against the card, returning the authorisation ID
+    fn authorise(&self, token: &str, amount: Decimal) -> Result<String, PaymentError>;
+
+    /// Releases an authorisation that won't be charged, e.g. because its order wasn't saved
+    fn void(&self, authorisation_id: &str) -> Result<(), PaymentError>;
+}
+
+const MOCK_TOKEN_PREFIX: &str = "tok_mock_";
+const MOCK_AUTHORISATION_PREFIX: &str = "auth_mock_";
+
+/// A local stand-in for a real payment processor.
+///
+/// Tokens are a keyed hash of the card number, so the same card always gets the
+/// same token, and every well formed charge is approved.
+pub struct MockPaymentProvider {
+    secret: String,
+}
+
+impl MockPaymentProvider {
+    pub fn new<T: AsRef<str>>(secret: T) -> MockPaymentProvider {
+        MockPaymentProvider {
+            secret: secret.as_ref().to_string(),
+        }
+    }
+
+    fn card_brand(card_number: &str) -> &'static str {
+        let prefix = |len: usize| card_number[..len].parse::<u32>().unwrap_or(0);
+
+        match card_number {
+            _ if card_number.starts_with('4') => "Visa",
+            _ if (51..=55).contains(&prefix(2)) || (2221..=2720).contains(&prefix(4)) => {
+                "Mastercard"
+            }
+            _ if card_number.starts_with("34") || card_number.starts_with("37") => "Amex",
+            _ if card_number.starts_with("6011") || card_number.starts_with("65") => "Discover",
+            _ => "Card",
+        }
+    }
+}
+
+impl PaymentProvider for MockPaymentProvider {
+    fn tokenise(&self, card: &CardDetails) -> Result<TokenisedCard, PaymentError> {
+        let card_number = &card.card_number;
+
+        if !(12..=19).contains(&card_number.len())
+            || !card_number.chars().all(|c| c.is_ascii_digit())
+        {
+            Err(PaymentError::InvalidCardNumber)?;
+        }
+
+        if !(3..=4).contains(&card.cvv.len()) || !card.cvv.chars().all(|c| c.is_ascii_digit()) {
+            Err(PaymentError::InvalidCvv)?;
+        }
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(card_number.as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        Ok(TokenisedCard {
+            token: MOCK_TOKEN_PREFIX.to_string()
+                + &base64::encode_config(digest, base64::URL_SAFE_NO_PAD),
+            last_four: card_number[card_number.len() - 4..].to_string(),
+            brand: MockPaymentProvider::card_brand(card_number).to_string(),
+        })
+    }
+
+    fn authorise(&self, token: &str, amount: Decimal) -> Result<String, PaymentError> {
+        if !token.starts_with(MOCK_TOKEN_PREFIX) {
+            Err(PaymentError::UnknownToken)?;
+        }
+
+        if amount.is_sign_negative() {
+            Err(PaymentError::Declined("negative amount".to_string()))?;
+        }
+
+        let mut authorisation_id = [0u8; 12];
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut authorisation_id);
+
+        Ok(MOCK_AUTHORISATION_PREFIX.to_string()
+            + &base64::encode_config(authorisation_id, base64::URL_SAFE_NO_PAD))
+    }
+
+    fn void(&self, authorisation_id: &str) -> Result<(), PaymentError> {
+        if !authorisation_id.starts_with(MOCK_AUTHORISATION_PREFIX) {
+            Err(PaymentError::UnknownAuthorisation)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+//! Promotions: coupon codes owners hand out for a percentage or fixed amount off an order,
+//! and the rules deciding whether a code can be used on a given cart.
+
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::isbn::Isbn;
+use crate::schema::entities::{PostgresInt, PostgresNumeric, PublisherID};
+
+#[derive(FromFormField, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiscountType {
+    #[field(value = "percent")]
+    #[serde(rename = "PERCENT")]
+    Percent,
+    #[field(value = "fixed")]
+    #[serde(rename = "FIXED")]
+    Fixed,
+}
+
+impl DiscountType {
+    /// The code stored in `base.promotion.discount_type`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiscountType::Percent => "PERCENT",
+            DiscountType::Fixed => "FIXED",
+        }
+    }
+}
+
+impl FromStr for DiscountType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DiscountType, String> {
+        match s {
+            "PERCENT" => Ok(DiscountType::Percent),
+            "FIXED" => Ok(DiscountType::Fixed),
+            _ => Err(format!("Unknown discount type: {s}")),
+        }
+    }
+}
+
+/// Which books a promotion takes money off
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", content = "value")]
+pub enum PromotionScope {
+    All,
+    Genre(String),
+    Publisher(PublisherID),
+    Isbn(Isbn),
+}
+
+impl PromotionScope {
+    /// How the scope is written for people, given the publisher's name for `Publisher` scopes
+    pub fn describe(&self, publisher_name: Option<&str>) -> String {
+        match self {
+            PromotionScope::All => "All books".to_string(),
+            PromotionScope::Genre(genre) => format!("Genre: {genre}"),
+            PromotionScope::Publisher(publisher_id) => match publisher_name {
+                Some(name) => format!("Publisher: {name}"),
+                None => format!("Publisher #{publisher_id}"),
+            },
+            PromotionScope::Isbn(isbn) => format!("ISBN: {isbn}"),
+        }
+    }
+
+    pub fn covers(&self, line: &PromotionLine) -> bool {
+        match self {
+            PromotionScope::All => true,
+            PromotionScope::Genre(genre) => line.genre.eq_ignore_ascii_case(genre),
+            PromotionScope::Publisher(publisher_id) => line.publisher_id == *publisher_id,
+            PromotionScope::Isbn(isbn) => line.isbn == *isbn,
+        }
+    }
+}
+
+/// Why a code can't be used on an order
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DiscountError {
+    #[error("There's no promotion with the code `{0}`")]
+    UnknownCode(String),
+    #[error("The code `{0}` is no longer available")]
+    Inactive(String),
+    #[error("The code `{0}` can't be used until {1}")]
+    NotStarted(String, String),
+    #[error("The code `{0}` expired on {1}")]
+    Expired(String, String),
+    #[error("The code `{0}` has been used up")]
+    UsedUp(String),
+    #[error("You've already used the code `{0}` as many times as you can")]
+    AlreadyUsed(String),
+    #[error("The code `{0}` needs ${1} of eligible books in the cart")]
+    BelowMinimumSpend(String, PostgresNumeric),
+    #[error("The code `{0}` doesn't apply to any of the books in the cart")]
+    NotApplicable(String),
+}
+
+/// What a promotion offers and who can use it, as an owner sets it up
+#[derive(Clone, Debug)]
+pub struct PromotionTerms {
+    pub code: String,
+    pub discount_type: DiscountType,
+    /// A percentage for `Percent` promotions, or an amount in dollars for `Fixed` ones
+    pub discount_value: PostgresNumeric,
+    /// The least that has to be spent on books the promotion covers
+    pub min_spend: PostgresNumeric,
+    pub scope: PromotionScope,
+    pub starts_at: Option<DateTime<Local>>,
+    pub ends_at: Option<DateTime<Local>>,
+    pub max_uses: Option<PostgresInt>,
+    pub max_uses_per_customer: Option<PostgresInt>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Promotion {
+    pub promotion_id: PostgresInt,
+    pub terms: PromotionTerms,
+    /// Owners can switch a promotion off before it ends
+    pub active: bool,
+}
+
+/// A book on an order (or in a cart) that a promotion might take money off
+#[derive(Clone, Debug)]
+pub struct PromotionLine {
+    pub isbn: Isbn,
+    pub genre: String,
+    pub publisher_id: PublisherID,
+    pub unit_price: PostgresNumeric,
+    pub quantity: PostgresInt,
+}
+
+/// Normalises a code as typed by a customer or owner, since codes are matched case-insensitively
+pub fn normalise_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+fn format_time(time: &DateTime<Local>) -> String {
+    time.format("%Y-%m-%d %H:%M").to_string()
+}
+
+impl PromotionTerms {
+    /// The amount taken off the lines, to the cent. Only the lines the promotion covers count
+    /// towards the minimum spend, and a fixed discount is never more than they cost.
+    pub fn discount(&self, lines: &[PromotionLine]) -> Result<PostgresNumeric, DiscountError> {
+        let covered: Vec<&PromotionLine> = lines
+            .iter()
+            .filter(|line| self.scope.covers(line))
+            .collect();
+        if covered.is_empty() {
+            Err(DiscountError::NotApplicable(self.code.clone()))?;
+        }
+
+        let eligible: Decimal = covered
+            .iter()
+            .map(|line| line.unit_price * Decimal::from(line.quantity))
+            .sum();
+        if eligible < self.min_spend {
+            Err(DiscountError::BelowMinimumSpend(
+                self.code.clone(),
+                self.min_spend,
+            ))?;
+        }
+
+        let discount = match self.discount_type {
+            DiscountType::Percent => (eligible * self.discount_value / Decimal::ONE_HUNDRED)
+                .round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero),
+            DiscountType::Fixed => Decimal::min(self.discount_value, eligible),
+        };
+
+        Ok(discount)
+    }
+
+    /// How the discount is written for people, e.g. `15%` or `$5.00`
+    pub fn describe_discount(&self) -> String {
+        match self.discount_type {
+            DiscountType::Percent => format!("{}%", self.discount_value.normalize()),
+            DiscountType::Fixed => format!("${}", self.discount_value),
+        }
+    }
+}
+
+impl Promotion {
+    /// Checks the promotion can be used at `now` by a customer who has used it `customer_uses`
+    /// times, out of `total_uses` uses by everyone. Cancelled and returned orders don't count as
+    /// uses.
+    pub fn check_available(
+        &self,
+        now: DateTime<Local>,
+        total_uses: i64,
+        customer_uses: i64,
+    ) -> Result<(), DiscountError> {
+        let terms = &self.terms;
+        let code = || terms.code.clone();
+
+        if !self.active {
+            Err(DiscountError::Inactive(code()))?;
+        }
+        if let Some(starts_at) = terms.starts_at.filter(|starts_at| now < *starts_at) {
+            Err(DiscountError::NotStarted(code(), format_time(&starts_at)))?;
+        }
+        if let Some(ends_at) = terms.ends_at.filter(|ends_at| now >= *ends_at) {
+            Err(DiscountError::Expired(code(), format_time(&ends_at)))?;
+        }
+        if terms
+            .max_uses
+            .is_some_and(|max_uses| total_uses >= max_uses as i64)
+        {
+            Err(DiscountError::UsedUp(code()))?;
+        }
+        if terms
+            .max_uses_per_customer
+            .is_some_and(|max_uses| customer_uses >= max_uses as i64)
+        {
+            Err(DiscountError::AlreadyUsed(code()))?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,17 +1,28 @@
-use chrono::Local;
-use rocket::{http, outcome::Outcome, request::FromRequest};
+use rocket::{
+    http::{self, Cookie, CookieJar},
+    outcome::Outcome,
+    request::FromRequest,
+};
 
-use crate::{db::query::does_owner_exist, schema::entities::PostgresInt};
+use crate::{db::conn::DbConn, db::query::does_owner_exist, schema::entities::PostgresInt};
 
-use self::state::{SessionTokens, SessionType};
+use self::state::SessionType;
 
 pub mod state {
     use chrono::Local;
+    use rocket::futures::lock::Mutex;
 
+    use crate::db::conn::{DbConn, DbPool};
+    use crate::db::error::SessionError;
+    use crate::db::query::{
+        delete_account_sessions, delete_expired_sessions, delete_session, does_owner_session_exist,
+        get_session, insert_session,
+    };
     use crate::schema::entities::PostgresInt;
     use chrono::DateTime;
     use std::collections::HashMap;
 
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub enum SessionType {
         Customer(PostgresInt),
         Owner(PostgresInt),
@@ -20,23 +31,194 @@ pub mod state {
 
     pub type ExpirationTime = DateTime<Local>;
     pub type SessionTokens = HashMap<String, (SessionType, ExpirationTime)>;
+
+    /// Somewhere to keep the sessions behind the login cookies
+    #[rocket::async_trait]
+    pub trait SessionStore: Send + Sync {
+        async fn insert(
+            &self,
+            token: String,
+            session_type: SessionType,
+            expiry: ExpirationTime,
+        ) -> Result<(), SessionError>;
+
+        /// Looks up an unexpired session, forgetting the token if it has expired
+        async fn get(&self, token: &str) -> Result<Option<SessionType>, SessionError>;
+
+        async fn remove(&self, token: &str) -> Result<(), SessionError>;
+
+        /// Drops every expired session, returning how many were removed
+        async fn remove_expired(&self) -> Result<u64, SessionError>;
+
+        /// Logs the account out everywhere, e.g. once its password has been reset
+        async fn remove_account(&self, session_type: SessionType) -> Result<u64, SessionError>;
+
+        /// Whether anyone is logged in with an owner account
+        async fn has_owner_session(&self) -> Result<bool, SessionError>;
+    }
+
+    /// Keeps sessions in memory, so they are lost on restart and not shared between instances
+    #[derive(Default)]
+    pub struct MemorySessionStore {
+        session_tokens: Mutex<SessionTokens>,
+    }
+
+    #[rocket::async_trait]
+    impl SessionStore for MemorySessionStore {
+        async fn insert(
+            &self,
+            token: String,
+            session_type: SessionType,
+            expiry: ExpirationTime,
+        ) -> Result<(), SessionError> {
+            self.session_tokens
+                .lock()
+                .await
+                .insert(token, (session_type, expiry));
+            Ok(())
+        }
+
+        async fn get(&self, token: &str) -> Result<Option<SessionType>, SessionError> {
+            let mut session_tokens = self.session_tokens.lock().await;
+
+            match session_tokens.get(token) {
+                Some(&(session_type, expiry)) if Local::now() <= expiry => Ok(Some(session_type)),
+                Some(_) => {
+                    session_tokens.remove(token);
+                    Ok(None)
+                }
+                None => Ok(None),
+            }
+        }
+
+        async fn remove(&self, token: &str) -> Result<(), SessionError> {
+            self.session_tokens.lock().await.remove(token);
+            Ok(())
+        }
+
+        async fn remove_expired(&self) -> Result<u64, SessionError> {
+            let mut session_tokens = self.session_tokens.lock().await;
+            let now = Local::now();
+            let num_sessions = session_tokens.len();
+
+            session_tokens.retain(|_, (_, expiry)| now <= *expiry);
+
+            Ok((num_sessions - session_tokens.len()) as u64)
+        }
+
+        async fn remove_account(&self, session_type: SessionType) -> Result<u64, SessionError> {
+            let mut session_tokens = self.session_tokens.lock().await;
+            let num_sessions = session_tokens.len();
+
+            session_tokens.retain(|_, (session, _)| *session != session_type);
+
+            Ok((num_sessions - session_tokens.len()) as u64)
+        }
+
+        async fn has_owner_session(&self) -> Result<bool, SessionError> {
+            let now = Local::now();
+
+            Ok(self
+                .session_tokens
+                .lock()
+                .await
+                .values()
+                .any(|(session_type, expiry)| {
+                    matches!(session_type, SessionType::Owner(_)) && now <= *expiry
+                }))
+        }
+    }
+
+    /// Keeps sessions in `base.session`, under the hash of their token, so they survive restarts
+    /// and are shared between instances.
+    /// Each call takes a connection from the pool and gives it straight back.
+    pub struct PostgresSessionStore {
+        pool: DbPool,
+    }
+
+    impl PostgresSessionStore {
+        pub fn new(pool: DbPool) -> PostgresSessionStore {
+            PostgresSessionStore { pool }
+        }
+
+        async fn conn(&self) -> Result<DbConn, SessionError> {
+            self.pool.get().await.ok_or(SessionError::NoConnection)
+        }
+    }
+
+    #[rocket::async_trait]
+    impl SessionStore for PostgresSessionStore {
+        async fn insert(
+            &self,
+            token: String,
+            session_type: SessionType,
+            expiry: ExpirationTime,
+        ) -> Result<(), SessionError> {
+            Ok(insert_session(&self.conn().await?, token, session_type, expiry).await?)
+        }
+
+        async fn get(&self, token: &str) -> Result<Option<SessionType>, SessionError> {
+            Ok(get_session(&self.conn().await?, token.to_string()).await?)
+        }
+
+        async fn remove(&self, token: &str) -> Result<(), SessionError> {
+            Ok(delete_session(&self.conn().await?, token.to_string()).await?)
+        }
+
+        async fn remove_expired(&self) -> Result<u64, SessionError> {
+            Ok(delete_expired_sessions(&self.conn().await?).await?)
+        }
+
+        async fn remove_account(&self, session_type: SessionType) -> Result<u64, SessionError> {
+            Ok(delete_account_sessions(&self.conn().await?, session_type).await?)
+        }
+
+        async fn has_owner_session(&self) -> Result<bool, SessionError> {
+            Ok(does_owner_session_exist(&self.conn().await?).await?)
+        }
+    }
 }
 
 pub const CUST_SESSION_COOKIE_NAME: &str = "lookinnabook_custsession";
 pub const OWNER_SESSION_COOKIE_NAME: &str = "lookinnabook_ownersession";
+pub const GUEST_CART_COOKIE_NAME: &str = "lookinnabook_guestcart";
 
-fn does_owner_session_token_exist(session_tokens: &SessionTokens) -> bool {
-    session_tokens
-        .values()
-        .filter(|(session_type, _)| {
-            if let SessionType::Owner(_) = &session_type {
-                true
-            } else {
-                false
-            }
-        })
-        .count()
-        > 0
+/// The session token sent as `Authorization: Bearer <token>` by API clients
+pub struct BearerToken(pub String);
+
+impl BearerToken {
+    fn from_request(request: &rocket::Request<'_>) -> Option<BearerToken> {
+        request
+            .headers()
+            .get_one("Authorization")?
+            .strip_prefix("Bearer ")
+            .map(|token| BearerToken(token.trim().to_string()))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+    type Error = ();
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        match BearerToken::from_request(request) {
+            Some(token) => Outcome::Success(token),
+            None => Outcome::Failure((http::Status::Unauthorized, ())),
+        }
+    }
+}
+
+/// API clients send a bearer token, and browsers send the login cookie
+fn session_token(request: &rocket::Request<'_>, cookie_name: &str) -> Option<String> {
+    match BearerToken::from_request(request) {
+        Some(BearerToken(token)) => Some(token),
+        None => request
+            .cookies()
+            .get_private(cookie_name)
+            .map(|cookie| cookie.value().to_string()),
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -50,6 +232,16 @@ pub struct Owner {
     pub owner: OwnerType,
 }
 
+impl Owner {
+    /// The owner's account ID, which the default owner doesn't have
+    pub fn owner_id(&self) -> Option<PostgresInt> {
+        match self.owner {
+            OwnerType::OwnerAccount(owner_id) => Some(owner_id),
+            OwnerType::DefaultOwner => None,
+        }
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for Owner {
     type Error = ();
@@ -58,47 +250,36 @@ impl<'r> FromRequest<'r> for Owner {
         request: &'r rocket::Request<'_>,
     ) -> rocket::request::Outcome<Self, Self::Error> {
         let result: Result<Owner, ()> = try {
-            let session_token_lock = request
+            let session_store = request
                 .rocket()
-                .state::<crate::SessionTokenState>()
+                .state::<crate::SessionStoreState>()
                 .ok_or(())?;
+            let token = session_token(request, OWNER_SESSION_COOKIE_NAME).ok_or(())?;
 
-            let mut session_tokens = session_token_lock.lock().await;
-            let cookies = request.cookies();
-
-            let owner_session_cookie = cookies.get_private(OWNER_SESSION_COOKIE_NAME).ok_or(())?;
-
-            let (session_type, expiration_time) =
-                session_tokens.get(owner_session_cookie.value()).ok_or(())?;
-            if Local::now() > expiration_time.clone() {
-                session_tokens.remove(owner_session_cookie.value());
-                Err(())?
-            } else {
-                use state::SessionType;
-                match session_type {
-                    &SessionType::Owner(owner_id) => Owner {
-                        owner: OwnerType::OwnerAccount(owner_id),
-                    },
-                    &SessionType::DefaultOwner => {
-                        let conn = request.rocket().state::<crate::DbConn>().ok_or(())?;
-
-                        // Short circuit so we avoid DB access if possible
-                        if does_owner_session_token_exist(&session_tokens) {
-                            Err(())?
-                        } else {
-                            let does_owner_exist = does_owner_exist(conn).await.unwrap_or(true);
+            let session_type = session_store.get(&token).await.map_err(|_| ())?.ok_or(())?;
+
+            match session_type {
+                SessionType::Owner(owner_id) => Owner {
+                    owner: OwnerType::OwnerAccount(owner_id),
+                },
+                SessionType::DefaultOwner => {
+                    // Someone logged in with an owner account means an owner exists, so skip the lookup
+                    if session_store.has_owner_session().await.unwrap_or(true) {
+                        Err(())?
+                    } else {
+                        let conn = request.guard::<DbConn>().await.succeeded().ok_or(())?;
+                        let does_owner_exist = does_owner_exist(&conn).await.unwrap_or(true);
 
-                            if does_owner_exist {
-                                Err(())?
-                            }
+                        if does_owner_exist {
+                            Err(())?
+                        }
 
-                            Owner {
-                                owner: OwnerType::DefaultOwner,
-                            }
+                        Owner {
+                            owner: OwnerType::DefaultOwner,
                         }
                     }
-                    &_ => Err(())?,
                 }
+                _ => Err(())?,
             }
         };
         match result {
@@ -121,26 +302,17 @@ impl<'r> FromRequest<'r> for Customer {
         request: &'r rocket::Request<'_>,
     ) -> rocket::request::Outcome<Self, Self::Error> {
         let result: Result<Customer, ()> = try {
-            let session_token_lock = request
+            let session_store = request
                 .rocket()
-                .state::<crate::SessionTokenState>()
+                .state::<crate::SessionStoreState>()
                 .ok_or(())?;
-            let mut session_tokens = session_token_lock.lock().await;
-            let cookies = request.cookies();
-
-            let cust_session_cookie = cookies.get_private(CUST_SESSION_COOKIE_NAME).ok_or(())?;
-
-            let (session_type, expiration_time) =
-                session_tokens.get(cust_session_cookie.value()).ok_or(())?;
-            if Local::now() > expiration_time.clone() {
-                session_tokens.remove(cust_session_cookie.value());
-                Err(())?
-            } else {
-                use state::SessionType;
-                match session_type {
-                    &SessionType::Customer(customer_id) => Customer { customer_id },
-                    &_ => Err(())?,
-                }
+            let token = session_token(request, CUST_SESSION_COOKIE_NAME).ok_or(())?;
+
+            let session_type = session_store.get(&token).await.map_err(|_| ())?.ok_or(())?;
+
+            match session_type {
+                SessionType::Customer(customer_id) => Customer { customer_id },
+                _ => Err(())?,
             }
         };
         match result {
@@ -149,3 +321,52 @@ impl<'r> FromRequest<'r> for Customer {
         }
     }
 }
+
+/// Whose cart a request works with: a logged in customer's, or the guest cart a visitor
+/// who hasn't logged in keeps in the guest cart cookie
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CartOwner {
+    Customer(PostgresInt),
+    Guest(String),
+}
+
+impl CartOwner {
+    /// The visitor's guest cart, starting one if they don't have it yet. The cookie is set
+    /// again either way, so it lasts another week from the visitor's last change to the cart.
+    pub fn guest(cookies: &CookieJar<'_>) -> CartOwner {
+        let token = match cookies.get_private(GUEST_CART_COOKIE_NAME) {
+            Some(cookie) => cookie.value().to_string(),
+            None => crate::endpoints::create_session_token(),
+        };
+
+        cookies.add_private(Cookie::new(GUEST_CART_COOKIE_NAME, token.clone()));
+
+        CartOwner::Guest(token)
+    }
+
+    pub fn customer_id(&self) -> Option<PostgresInt> {
+        match self {
+            CartOwner::Customer(customer_id) => Some(*customer_id),
+            CartOwner::Guest(_) => None,
+        }
+    }
+}
+
+/// Logged in customers always have a cart, and visitors have one once they've added a book
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CartOwner {
+    type Error = ();
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        if let Some(customer) = request.guard::<Customer>().await.succeeded() {
+            return Outcome::Success(CartOwner::Customer(customer.customer_id));
+        }
+
+        match request.cookies().get_private(GUEST_CART_COOKIE_NAME) {
+            Some(cookie) => Outcome::Success(CartOwner::Guest(cookie.value().to_string())),
+            None => Outcome::Failure((http::Status::Forbidden, ())),
+        }
+    }
+}
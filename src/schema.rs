@@ -1,10 +1,10 @@
 pub mod entities {
-    use rocket::serde::Serialize;
+    use rocket::serde::{Deserialize, Serialize};
 
     pub type PostgresInt = i32;
     pub type PostgresNumeric = rust_decimal::Decimal;
 
-    pub type ISBN = PostgresInt;
+    pub use crate::isbn::Isbn;
     pub type PublisherID = PostgresInt;
 
     #[derive(Serialize, Clone, Debug)]
@@ -64,7 +64,7 @@ pub mod entities {
 
     #[derive(Serialize, Clone, Debug)]
     pub struct BookWithPublisherName {
-        pub isbn: ISBN,
+        pub isbn: Isbn,
         pub title: String,
         pub author_name: String,
         pub genre: String,
@@ -80,7 +80,7 @@ pub mod entities {
 
     impl BookWithPublisherName {
         pub fn new(
-            isbn: ISBN,
+            isbn: Isbn,
             title: String,
             author_name: String,
             genre: String,
@@ -131,7 +131,7 @@ pub mod entities {
 
     #[derive(Serialize, Clone, Debug)]
     pub struct Book {
-        pub isbn: ISBN,
+        pub isbn: Isbn,
         pub title: String,
         pub author_name: String,
         pub genre: String,
@@ -146,7 +146,7 @@ pub mod entities {
 
     impl Book {
         pub fn new(
-            isbn: ISBN,
+            isbn: Isbn,
             title: String,
             author_name: String,
             genre: String,
@@ -188,22 +188,314 @@ pub mod entities {
                 row.try_get("discontinued")?,
             ))
         }
+
+        /// Every field that differs in `new`, naming publishers with `publisher_name`
+        pub fn changes(
+            &self,
+            new: &Book,
+            publisher_name: impl Fn(PublisherID) -> String,
+        ) -> Vec<FieldChange> {
+            fn change<T: PartialEq + std::fmt::Display>(
+                field: &'static str,
+                old: &T,
+                new: &T,
+            ) -> Option<FieldChange> {
+                (old != new).then(|| FieldChange {
+                    field,
+                    old: old.to_string(),
+                    new: new.to_string(),
+                })
+            }
+
+            vec![
+                change("Title", &self.title, &new.title),
+                change("Author", &self.author_name, &new.author_name),
+                change("Genre", &self.genre, &new.genre),
+                (self.publisher != new.publisher).then(|| FieldChange {
+                    field: "Publisher",
+                    old: publisher_name(self.publisher),
+                    new: publisher_name(new.publisher),
+                }),
+                change("Pages", &self.num_pages, &new.num_pages),
+                change("Price", &self.price, &new.price),
+                change(
+                    "Author royalties",
+                    &self.author_royalties,
+                    &new.author_royalties,
+                ),
+                change(
+                    "Reorder threshold",
+                    &self.reorder_threshold,
+                    &new.reorder_threshold,
+                ),
+                change("Stock", &self.stock, &new.stock),
+                change("Discontinued", &self.discontinued, &new.discontinued),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()
+        }
     }
 
-    #[derive(Serialize, Clone, Debug, Default)]
-    pub struct Customer {
+    /// A field of a book changed by an edit or import, with its old and new values written out
+    #[derive(Serialize, Clone, Debug)]
+    pub struct FieldChange {
+        pub field: &'static str,
+        pub old: String,
+        pub new: String,
+    }
+
+    #[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum RestockStatus {
+        Pending,
+        Approved,
+        Received,
+        Cancelled,
+    }
+
+    impl RestockStatus {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                RestockStatus::Pending => "PENDING",
+                RestockStatus::Approved => "APPROVED",
+                RestockStatus::Received => "RECEIVED",
+                RestockStatus::Cancelled => "CANCELLED",
+            }
+        }
+
+        pub fn can_transition_to(&self, next: RestockStatus) -> bool {
+            use RestockStatus::*;
+
+            matches!(
+                (self, next),
+                (Pending, Approved)
+                    | (Pending, Cancelled)
+                    | (Approved, Received)
+                    | (Approved, Cancelled)
+            )
+        }
+    }
+
+    impl std::str::FromStr for RestockStatus {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<RestockStatus, String> {
+            match s {
+                "PENDING" => Ok(RestockStatus::Pending),
+                "APPROVED" => Ok(RestockStatus::Approved),
+                "RECEIVED" => Ok(RestockStatus::Received),
+                "CANCELLED" => Ok(RestockStatus::Cancelled),
+                _ => Err(format!("Unknown restock status: {s}")),
+            }
+        }
+    }
+
+    impl std::fmt::Display for RestockStatus {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.as_str())
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+    #[serde(crate = "rocket::serde")]
+    pub enum RemittanceStatus {
+        #[serde(rename = "PENDING")]
+        Pending,
+        #[serde(rename = "REMITTED")]
+        Remitted,
+        #[serde(rename = "CANCELLED")]
+        Cancelled,
+    }
+
+    impl RemittanceStatus {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                RemittanceStatus::Pending => "PENDING",
+                RemittanceStatus::Remitted => "REMITTED",
+                RemittanceStatus::Cancelled => "CANCELLED",
+            }
+        }
+
+        pub fn can_transition_to(&self, next: RemittanceStatus) -> bool {
+            use RemittanceStatus::*;
+
+            matches!((self, next), (Pending, Remitted) | (Pending, Cancelled))
+        }
+    }
+
+    impl std::str::FromStr for RemittanceStatus {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<RemittanceStatus, String> {
+            match s {
+                "PENDING" => Ok(RemittanceStatus::Pending),
+                "REMITTED" => Ok(RemittanceStatus::Remitted),
+                "CANCELLED" => Ok(RemittanceStatus::Cancelled),
+                _ => Err(format!("Unknown remittance status: {s}")),
+            }
+        }
+    }
+
+    impl std::fmt::Display for RemittanceStatus {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.as_str())
+        }
+    }
+
+    #[derive(Serialize, Clone, Debug)]
+    pub struct BookCollection {
+        pub collection_id: PostgresInt,
         pub name: String,
-        pub email: String,
-        pub default_shipping_address_id: PostgresInt,
-        pub default_payment_info_id: PostgresInt,
+        pub curator_owner_id: Option<PostgresInt>,
+        pub sort_order: PostgresInt,
+    }
+
+    impl BookCollection {
+        pub fn from_row(row: &postgres::Row) -> Result<BookCollection, postgres::error::Error> {
+            Ok(BookCollection {
+                collection_id: row.try_get("collection_id")?,
+                name: row.try_get("name")?,
+                curator_owner_id: row.try_get("curator_owner_id")?,
+                sort_order: row.try_get("sort_order")?,
+            })
+        }
+    }
+
+    #[derive(Serialize, Clone, Debug)]
+    pub struct Wishlist {
+        pub wishlist_id: PostgresInt,
+        pub customer_id: PostgresInt,
+        pub name: String,
+        /// Lets anyone with the link see the list
+        pub share_token: String,
+    }
+
+    impl Wishlist {
+        pub fn from_row(row: &postgres::Row) -> Result<Wishlist, postgres::error::Error> {
+            Ok(Wishlist {
+                wishlist_id: row.try_get("wishlist_id")?,
+                customer_id: row.try_get("customer_id")?,
+                name: row.try_get("name")?,
+                share_token: row.try_get("share_token")?,
+            })
+        }
+    }
+
+    /// Where an order is in fulfilment. New orders start out as `Processing`.
+    #[derive(Serialize, Deserialize, FromFormField, Clone, Copy, Debug, PartialEq, Eq)]
+    #[serde(crate = "rocket::serde")]
+    pub enum OrderStatus {
+        Processing,
+        Packed,
+        Shipped,
+        Delivered,
+        Cancelled,
+        Returned,
+    }
+
+    impl OrderStatus {
+        pub const ALL: [OrderStatus; 6] = [
+            OrderStatus::Processing,
+            OrderStatus::Packed,
+            OrderStatus::Shipped,
+            OrderStatus::Delivered,
+            OrderStatus::Cancelled,
+            OrderStatus::Returned,
+        ];
+
+        /// The code stored in `base.orders.order_status`
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                OrderStatus::Processing => "PR",
+                OrderStatus::Packed => "PK",
+                OrderStatus::Shipped => "SH",
+                OrderStatus::Delivered => "DL",
+                OrderStatus::Cancelled => "CA",
+                OrderStatus::Returned => "RT",
+            }
+        }
+
+        pub fn next_statuses(&self) -> Vec<OrderStatus> {
+            OrderStatus::ALL
+                .iter()
+                .copied()
+                .filter(|next| self.can_transition_to(*next))
+                .collect()
+        }
+
+        pub fn can_transition_to(&self, next: OrderStatus) -> bool {
+            use OrderStatus::*;
+
+            matches!(
+                (self, next),
+                (Processing, Packed)
+                    | (Packed, Shipped)
+                    | (Shipped, Delivered)
+                    | (Delivered, Returned)
+                    | (Processing, Cancelled)
+                    | (Packed, Cancelled)
+            )
+        }
+    }
+
+    impl std::str::FromStr for OrderStatus {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<OrderStatus, String> {
+            OrderStatus::ALL
+                .iter()
+                .copied()
+                .find(|status| status.as_str() == s)
+                .ok_or(format!("Unknown order status: {s}"))
+        }
+    }
+
+    impl std::fmt::Display for OrderStatus {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{self:?}")
+        }
+    }
+
+    /// What an emailed account token lets whoever follows the link do
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum AccountTokenPurpose {
+        PasswordReset,
+        VerifyEmail,
+    }
+
+    impl AccountTokenPurpose {
+        /// The code stored in `base.account_token.purpose`
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                AccountTokenPurpose::PasswordReset => "PASSWORD_RESET",
+                AccountTokenPurpose::VerifyEmail => "VERIFY_EMAIL",
+            }
+        }
+    }
+
+    impl std::str::FromStr for AccountTokenPurpose {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<AccountTokenPurpose, String> {
+            match s {
+                "PASSWORD_RESET" => Ok(AccountTokenPurpose::PasswordReset),
+                "VERIFY_EMAIL" => Ok(AccountTokenPurpose::VerifyEmail),
+                _ => Err(format!("Unknown account token purpose: {s}")),
+            }
+        }
     }
 }
 
 pub mod joined {
     use rocket::serde::Serialize;
 
+    use crate::import::ImportFormat;
+
     use super::{
-        entities::{Book, PostgresInt},
+        entities::{
+            Book, BookCollection, Isbn, OrderStatus, PostgresInt, PostgresNumeric, PublisherID,
+            RemittanceStatus, RestockStatus, Wishlist,
+        },
         no_id::{Address, PaymentInfo},
     };
 
@@ -219,6 +511,7 @@ pub mod joined {
         pub billing_street_address: String,
         pub billing_postal_code: String,
         pub billing_province: String,
+        pub email_verified: bool,
     }
 
     #[derive(Serialize, Clone, Debug)]
@@ -226,9 +519,12 @@ pub mod joined {
         pub order_id: PostgresInt,
         pub shipping_address: Address,
         pub tracking_number: String,
-        pub order_status: String,
+        pub order_status: OrderStatus,
         pub order_date: String,
         pub payment_info: PaymentInfo,
+        pub cancellation_reason: Option<String>,
+        pub promotion_code: Option<String>,
+        pub discount: PostgresNumeric,
     }
 
     #[derive(Serialize, Clone, Debug)]
@@ -236,10 +532,237 @@ pub mod joined {
         pub order_id: PostgresInt,
         pub shipping_address: Address,
         pub tracking_number: String,
-        pub order_status: String,
+        pub order_status: OrderStatus,
         pub order_date: String,
         pub payment_info: PaymentInfo,
         pub books: Vec<(Book, u32)>,
+        pub cancellation_reason: Option<String>,
+        pub promotion_code: Option<String>,
+        pub discount: PostgresNumeric,
+    }
+
+    #[derive(Serialize, Clone, Debug)]
+    pub struct RestockOrder {
+        pub restock_order_id: PostgresInt,
+        pub isbn: Isbn,
+        pub title: String,
+        pub quantity: PostgresInt,
+        pub price_per_unit: Option<PostgresNumeric>,
+        pub order_date: String,
+        pub order_status: RestockStatus,
+        pub publisher_remittance_id: Option<PostgresInt>,
+    }
+
+    #[derive(Serialize, Clone, Debug)]
+    pub struct OrderStatusChange {
+        pub order_status: OrderStatus,
+        pub changed_at: String,
+    }
+
+    /// An order as shown in the owner's fulfilment queue
+    #[derive(Serialize, Clone, Debug)]
+    pub struct FulfilmentOrder {
+        pub order_id: PostgresInt,
+        pub customer_name: String,
+        pub customer_email: String,
+        pub shipping_address: Address,
+        pub tracking_number: String,
+        pub order_status: OrderStatus,
+        pub next_statuses: Vec<OrderStatus>,
+        pub order_date: String,
+        pub num_books: i64,
+    }
+
+    /// A collection and its books, as shown on a shelf
+    #[derive(Serialize, Clone, Debug)]
+    pub struct CollectionWithBooks {
+        pub collection: BookCollection,
+        pub books: Vec<Book>,
+    }
+
+    /// A wishlist and its books
+    #[derive(Serialize, Clone, Debug)]
+    pub struct WishlistWithBooks {
+        pub wishlist: Wishlist,
+        pub books: Vec<Book>,
+    }
+
+    /// Royalties on one book's shipped sales over a reporting period
+    #[derive(Serialize, Clone, Debug)]
+    pub struct BookRoyalty {
+        pub isbn: Isbn,
+        pub title: String,
+        pub quantity: i64,
+        pub gross: PostgresNumeric,
+        pub royalty_rate: PostgresNumeric,
+        pub royalty_owed: PostgresNumeric,
+        pub royalty_paid: PostgresNumeric,
+    }
+
+    /// An author's royalty statement over a reporting period
+    #[derive(Serialize, Clone, Debug)]
+    pub struct AuthorRoyalties {
+        pub author_name: String,
+        pub books: Vec<BookRoyalty>,
+        pub quantity: i64,
+        pub gross: PostgresNumeric,
+        pub royalty_owed: PostgresNumeric,
+        pub royalty_paid: PostgresNumeric,
+    }
+
+    #[derive(Serialize, Clone, Debug)]
+    pub struct RoyaltyPayout {
+        pub royalty_payout_id: PostgresInt,
+        pub period_start: String,
+        pub period_end: String,
+        pub paid_at: String,
+        pub num_sales: i64,
+        pub total: PostgresNumeric,
+    }
+
+    /// One sale paid out in a royalty payout
+    #[derive(Serialize, Clone, Debug)]
+    pub struct RoyaltyPayoutLine {
+        pub order_id: PostgresInt,
+        pub isbn: Isbn,
+        pub title: String,
+        pub author_name: String,
+        pub quantity: PostgresInt,
+        pub price: PostgresNumeric,
+        pub royalty_rate: PostgresNumeric,
+        pub royalty: PostgresNumeric,
+    }
+
+    /// What a publisher's books earned the store over a period, and what the store owes them
+    #[derive(Serialize, Clone, Debug)]
+    pub struct PublisherSettlement {
+        pub publisher_id: PublisherID,
+        pub company_name: String,
+        pub bank_number: String,
+        pub quantity: i64,
+        pub gross_sales: PostgresNumeric,
+        pub royalties: PostgresNumeric,
+        pub restock_costs: PostgresNumeric,
+        pub store_margin: PostgresNumeric,
+        /// Received restock orders that no remittance covers yet
+        pub amount_due: PostgresNumeric,
+        /// Received restock orders without a price, which are left out of the costs and the
+        /// amount due
+        pub unpriced_restocks: i64,
+    }
+
+    /// A line of a publisher statement for one of their books
+    #[derive(Serialize, Clone, Debug)]
+    pub struct PublisherBookSales {
+        pub isbn: Isbn,
+        pub title: String,
+        pub quantity: i64,
+        pub gross_sales: PostgresNumeric,
+        pub royalties: PostgresNumeric,
+    }
+
+    #[derive(Serialize, Clone, Debug)]
+    pub struct PublisherRemittance {
+        pub publisher_remittance_id: PostgresInt,
+        pub publisher_id: PublisherID,
+        pub period_start: String,
+        pub period_end: String,
+        pub amount: PostgresNumeric,
+        pub bank_number: String,
+        pub remittance_status: RemittanceStatus,
+        pub created_at: String,
+        pub remitted_at: Option<String>,
+    }
+
+    /// One field changed by an edit to a book
+    #[derive(Serialize, Clone, Debug)]
+    pub struct BookEdit {
+        pub version: PostgresInt,
+        pub owner_name: Option<String>,
+        pub edited_at: String,
+        pub field_name: String,
+        pub old_value: String,
+        pub new_value: String,
+    }
+
+    /// A price a book has had, has now, or is scheduled to have
+    #[derive(Serialize, Clone, Debug)]
+    pub struct BookPrice {
+        pub book_price_id: PostgresInt,
+        pub price: PostgresNumeric,
+        pub effective_from: String,
+        pub owner_name: Option<String>,
+        /// Whether the price has yet to take effect, so can still be cancelled
+        pub scheduled: bool,
+        /// Whether this is the price the book has now
+        pub current: bool,
+    }
+
+    /// A promotion as listed for owners, with how many orders have used it
+    #[derive(Serialize, Clone, Debug)]
+    pub struct PromotionSummary {
+        pub promotion_id: PostgresInt,
+        pub code: String,
+        pub discount: String,
+        pub min_spend: PostgresNumeric,
+        pub scope: String,
+        pub starts_at: Option<String>,
+        pub ends_at: Option<String>,
+        pub max_uses: Option<PostgresInt>,
+        pub max_uses_per_customer: Option<PostgresInt>,
+        pub uses: i64,
+        pub active: bool,
+    }
+
+    /// A book's stock, and how much of it is held in carts
+    #[derive(Serialize, Clone, Debug)]
+    pub struct StockLevel {
+        pub isbn: Isbn,
+        pub stock: PostgresInt,
+        pub reserved: i64,
+        pub available: i64,
+    }
+
+    /// A book in a cart, with when its hold runs out and how many copies aren't held elsewhere
+    #[derive(Serialize, Clone, Debug)]
+    pub struct CartReservation {
+        pub isbn: Isbn,
+        /// Not set once the hold has run out
+        pub held_until: Option<String>,
+        pub available: i64,
+    }
+
+    /// What a code would take off the customer's cart
+    #[derive(Serialize, Clone, Debug)]
+    pub struct PromotionQuote {
+        pub code: String,
+        pub subtotal: PostgresNumeric,
+        pub discount: PostgresNumeric,
+        pub total: PostgresNumeric,
+    }
+
+    /// The orders placed with a promotion over a period, and what it took off them
+    #[derive(Serialize, Clone, Debug)]
+    pub struct PromotionSales {
+        pub promotion_id: PostgresInt,
+        pub code: String,
+        pub orders: i64,
+        pub gross_sales: PostgresNumeric,
+        pub discount: PostgresNumeric,
+        pub net_sales: PostgresNumeric,
+    }
+
+    #[derive(Serialize, Clone, Debug)]
+    pub struct CatalogImport {
+        pub catalog_import_id: PostgresInt,
+        pub file_name: String,
+        pub import_format: ImportFormat,
+        pub owner_name: Option<String>,
+        pub uploaded_at: String,
+        pub applied_at: Option<String>,
+        pub books_added: Option<PostgresInt>,
+        pub books_updated: Option<PostgresInt>,
+        pub records_rejected: Option<PostgresInt>,
     }
 
     impl Order {
@@ -251,6 +774,9 @@ pub mod joined {
                 order_status,
                 order_date,
                 payment_info,
+                cancellation_reason,
+                promotion_code,
+                discount,
             } = order;
             Order {
                 order_id,
@@ -260,6 +786,9 @@ pub mod joined {
                 order_date,
                 order_status,
                 payment_info,
+                cancellation_reason,
+                promotion_code,
+                discount,
             }
         }
     }
@@ -267,9 +796,11 @@ pub mod joined {
 
 pub mod no_id {
     use crate::db::query::Expiry;
-    use rocket::serde::Serialize;
+    use crate::payment::TokenisedCard;
+    use rocket::serde::{Deserialize, Serialize};
 
-    #[derive(Clone, Debug, Serialize)]
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    #[serde(crate = "rocket::serde")]
     pub struct Address {
         pub street_address: String,
         pub postal_code: String,
@@ -290,8 +821,11 @@ pub mod no_id {
     pub struct PaymentInfo {
         pub name_on_card: String,
         pub expiry: Expiry,
-        pub card_number: String,
-        pub cvv: String,
+        /// The payment provider's stand-in for the card number
+        #[serde(skip)]
+        pub token: String,
+        pub last_four: String,
+        pub brand: String,
         pub billing_address: Address,
     }
 
@@ -299,15 +833,21 @@ pub mod no_id {
         pub fn new<T: AsRef<str>>(
             name_on_card: T,
             expiry: Expiry,
-            card_number: T,
-            cvv: T,
+            card: TokenisedCard,
             billing_address: Address,
         ) -> PaymentInfo {
+            let TokenisedCard {
+                token,
+                last_four,
+                brand,
+            } = card;
+
             PaymentInfo {
                 name_on_card: name_on_card.as_ref().to_string(),
                 expiry,
-                card_number: card_number.as_ref().to_string(),
-                cvv: cvv.as_ref().to_string(),
+                token,
+                last_four,
+                brand,
                 billing_address,
             }
         }